@@ -1,56 +1,76 @@
-use std::vec::Vec;
-
 /// This module contains normal-mode ASTs.
 pub mod normal {
+  use std::borrow::Cow;
+  use std::vec::Vec;
+
+  use crate::source::Span;
+  use crate::symbol::Sym;
+  use super::grammar::{RulesTable, Rule, Word};
+
   /// This normal-mode AST is returned by the LSD LsdFile parser.
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct Module<'a> {
-    name: Option<&'a str>,
-    path: Option<&'a str>,
-    stmts: Vec<GlobalStmt<'a>>,
+    pub name: Option<Cow<'a, str>>,
+    pub path: Option<Cow<'a, str>>,
+    pub stmts: Vec<ModStmt<'a>>,
   }
 
   /// This normal-mode AST is returned by the LSD Expr parser.
-  pub enum Expr<'a> {
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct Expr<'a> {
+    pub kind: ExprKind<'a>,
+    pub span: Span,
+  }
+
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub enum ExprKind<'a> {
     Int(i64),
     Float(f64),
-    String(&'a str),
+    String(Cow<'a, str>),
     List(Vec<Expr<'a>>),
     Bool(bool),
     Null,
-    ID(&'a str),
-    PropAcc(Expr<'a>, &'a str),
-    FnCall(Expr<'a>, Vec<Expr<'a>>),
-    IndexExpr(Expr<'a>, Expr<'a>),
-    // Assign(&'a str, Expr<'a>),
-    Plus(Expr<'a>),
-    Minus(Expr<'a>),
-    Not(Expr<'a>),
-    BitNot(Expr<'a>),
-    Pow(Expr<'a>, Expr<'a>),
-    Mul(Expr<'a>, Expr<'a>),
-    Div(Expr<'a>, Expr<'a>),
-    Mod(Expr<'a>, Expr<'a>),
-    Add(Expr<'a>, Expr<'a>),
-    Sub(Expr<'a>, Expr<'a>),
-    LT(Expr<'a>, Expr<'a>),
-    LE(Expr<'a>, Expr<'a>),
-    GT(Expr<'a>, Expr<'a>),
-    GE(Expr<'a>, Expr<'a>),
-    EQ(Expr<'a>, Expr<'a>),
-    NE(Expr<'a>, Expr<'a>),
-    BitAnd(Expr<'a>, Expr<'a>),
-    BitXor(Expr<'a>, Expr<'a>),
-    BitOr(Expr<'a>, Expr<'a>),
-    And(Expr<'a>, Expr<'a>),
-    Or(Expr<'a>, Expr<'a>),
-    IfElse(Expr<'a>, Expr<'a>, Expr<'a>),
-    Pow(Expr<'a>, Expr<'a>),
-    In(Expr<'a>, Expr<'a>, bool),
-    Lambda(Vec<Param<'a>>, Expr<'a>),
+    ID(Cow<'a, str>),
+    PropAcc(Box<Expr<'a>>, Cow<'a, str>),
+    FnCall(Box<Expr<'a>>, Vec<Expr<'a>>),
+    IndexExpr(Box<Expr<'a>>, Box<Expr<'a>>),
+    // Assign(Cow<'a, str>, Expr<'a>),
+    Plus(Box<Expr<'a>>),
+    Minus(Box<Expr<'a>>),
+    Not(Box<Expr<'a>>),
+    BitNot(Box<Expr<'a>>),
+    Pow(Box<Expr<'a>>, Box<Expr<'a>>),
+    Mul(Box<Expr<'a>>, Box<Expr<'a>>),
+    Div(Box<Expr<'a>>, Box<Expr<'a>>),
+    Mod(Box<Expr<'a>>, Box<Expr<'a>>),
+    Add(Box<Expr<'a>>, Box<Expr<'a>>),
+    Sub(Box<Expr<'a>>, Box<Expr<'a>>),
+    LT(Box<Expr<'a>>, Box<Expr<'a>>),
+    LE(Box<Expr<'a>>, Box<Expr<'a>>),
+    GT(Box<Expr<'a>>, Box<Expr<'a>>),
+    GE(Box<Expr<'a>>, Box<Expr<'a>>),
+    EQ(Box<Expr<'a>>, Box<Expr<'a>>),
+    NE(Box<Expr<'a>>, Box<Expr<'a>>),
+    BitAnd(Box<Expr<'a>>, Box<Expr<'a>>),
+    BitXor(Box<Expr<'a>>, Box<Expr<'a>>),
+    BitOr(Box<Expr<'a>>, Box<Expr<'a>>),
+    And(Box<Expr<'a>>, Box<Expr<'a>>),
+    Or(Box<Expr<'a>>, Box<Expr<'a>>),
+    IfElse(Box<Expr<'a>>, Box<Expr<'a>>, Box<Expr<'a>>),
+    /// `a in b` (`true`) or `a not in b` (`false`).
+    In(Box<Expr<'a>>, Box<Expr<'a>>, bool),
+    Lambda(Vec<Param<'a>>, Box<Expr<'a>>),
+    /// A word literal, written in grammar mode between backquotes: `` `F[+F]F` ``.
+    Word(Word<'a, Sym>),
   }
 
   // Normal-mode fragments:
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub enum ModStmt<'a> {
     Import(ImportStmt<'a>),
     VarDecl(VarDecl<'a>),
@@ -58,59 +78,112 @@ pub mod normal {
     LSysDef(LSysDef<'a>),
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub enum LSysStmt<'a> {
     Stmt(Stmt<'a>),
-    AxiomDef(Word<'a, char>),
-    TableDef(Vec<Rule<'a, char>>),
-    RulesDef(Vec<Rule<'a, char>>),
-    ProductionRulesDef(Vec<Rule<'a, char>>),
-    CodingRulesDef(Vec<Rule<'a, char>>),
-    // LSysDef(LSysDef<'a, char>),
+    /// Imports at the top of a module are moved to `ModStmt::Import`.
+    Import(ImportStmt<'a>),
+    /// `set angle = 25`: sets a property of the L system.
+    SetDef(Cow<'a, str>, Expr<'a>),
+    AxiomDef(Word<'a, Sym>),
+    TableDef(RulesTable<'a, Sym>),
+    RulesDef(Vec<Rule<'a, Sym>>),
+    ProductionRulesDef(Vec<Rule<'a, Sym>>),
+    CodingRulesDef(Vec<Rule<'a, Sym>>),
+    // LSysDef(LSysDef<'a>),
+  }
+
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct Stmt<'a> {
+    pub kind: StmtKind<'a>,
+    pub span: Span,
   }
 
-  pub enum Stmt<'a> {
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub enum StmtKind<'a> {
     Expr(Expr<'a>),
-    Assign(&'a str, Expr<'a>),
+    Assign(Cow<'a, str>, Expr<'a>),
     VarDecl(VarDecl<'a>),
     FnDef(FnDef<'a>),
-    LSysDef(LSysDef<'a, char>),
-    If(Expr<'a>, Stmt<'a>, Option<Stmt<'a>>),
-    For(&'a str, Expr<'a>, Stmt<'a>),
-    While(Expr<'a>, Stmt<'a>),
+    LSysDef(LSysDef<'a>),
+    If(Expr<'a>, Box<Stmt<'a>>, Option<Box<Stmt<'a>>>),
+    For(Cow<'a, str>, Expr<'a>, Box<Stmt<'a>>),
+    While(Expr<'a>, Box<Stmt<'a>>),
     Return(Option<Expr<'a>>),
     Block(Vec<Stmt<'a>>),
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct VarDecl<'a> {
-    name: &'a str,
-    value: Option<Expr<'a>>,
+    pub name: Cow<'a, str>,
+    pub mutable: bool,
+    pub value: Option<Expr<'a>>,
+    pub span: Span,
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct FnDef<'a> {
-    name: &'a str,
-    params: Vec<Param<'a>>,
-    stmts: Vec<Stmt<'a>>,
+    pub name: Cow<'a, str>,
+    pub params: Vec<Param<'a>>,
+    pub stmts: Vec<Stmt<'a>>,
+    pub span: Span,
   }
 
-  pub struct LSysDef<'a, C> {
-    name: Option<&'a str>,
-    params: Vec<Param<'a>>,
-    axiom: Vec<Word<'a, C>>,
-    tables: Vec<RulesTable<'a, C>>,
-    // default_table: RulesTable,
-    names: Vec<&'a str>, // Vars, functions. Include tables' names?
-    stmts: Vec<LSysStmt<'a>>,
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct LSysDef<'a> {
+    pub main: bool,
+    pub name: Option<Cow<'a, str>>,
+    pub params: Vec<Param<'a>>,
+    pub stmts: Vec<LSysStmt<'a>>,
+    pub span: Span,
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct Param<'a> {
-    name: &'a str,
-    default_value: Option<Expr<'a>>,
+    pub name: Cow<'a, str>,
+    pub default_value: Option<Expr<'a>>,
+    pub span: Span,
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct ImportStmt<'a> {
-    module: &'a str,
-    alias: Option<&'a str>,
-    // symbols: Vec<&'a str>,
+    pub module: Cow<'a, str>,
+    pub alias: Option<Cow<'a, str>>,
+    /// Names imported with `from module import a, b`. Empty for `import module`.
+    pub symbols: Vec<Cow<'a, str>>,
+    pub span: Span,
+  }
+
+  impl<'a> Expr<'a> {
+    pub fn new(kind: ExprKind<'a>, span: Span) -> Self {
+      Expr {kind, span}
+    }
+
+    /// Builds a unary operation starting at `start` and ending where its operand ends.
+    pub fn unary(op: fn(Box<Expr<'a>>) -> ExprKind<'a>, start: usize, e: Expr<'a>) -> Self {
+      let span = Span::new(start, e.span.end);
+      Expr {kind: op(Box::new(e)), span}
+    }
+
+    /// Builds a binary operation spanning both of its operands.
+    pub fn binary(op: fn(Box<Expr<'a>>, Box<Expr<'a>>) -> ExprKind<'a>, l: Expr<'a>, r: Expr<'a>) -> Self {
+      let span = l.span.to(r.span);
+      Expr {kind: op(Box::new(l), Box::new(r)), span}
+    }
+  }
+
+  impl<'a> Stmt<'a> {
+    pub fn new(kind: StmtKind<'a>, span: Span) -> Self {
+      Stmt {kind, span}
+    }
   }
 }
 
@@ -118,53 +191,134 @@ pub mod normal {
 
 /// This module contains grammar-mode ASTs.
 pub mod grammar {
+  use std::borrow::Cow;
+  use std::vec::Vec;
+
+  use super::normal::{Expr, Stmt};
+
+  use crate::source::Span;
+
   /// This grammar-mode AST is returned by the LSD Rules parser.
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct RulesTable<'a, C> {
-    name: Option<&'a str>,
-    rules: Vec<Rule<'a, C>>,
+    pub name: Option<Cow<'a, str>>,
+    pub rules: Vec<Rule<'a, C>>,
+    pub span: Span,
   }
 
   /// This grammar-mode AST is returned by the LSD Word parser.
-  pub struct Word<'a, C> (Vec<Node<'a, C>>);
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct Word<'a, C> (pub Vec<Node<'a, C>>);
 
   // Grammar-mode fragments:
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub enum Rule<'a, C> {
     Production(RuleBase<'a, C>),
     Coding(RuleBase<'a, C>),
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct RuleBase<'a, C> {
-    weight: f64,
-    leftLeaf: LeftLeaf<'a, C>,
-    condition: Option<Expr<'a>>,
-    lCtx: Vec<CtxNode<'a, C>>,
-    rCtx: Vec<CtxNode<'a, C>>,
-    rightSide: Word<'a, C>,
+    pub weight: f64,
+    pub left_leaf: LeftLeaf<'a, C>,
+    pub condition: Option<Expr<'a>>,
+    pub l_ctx: Vec<CtxNode<'a, C>>,
+    pub r_ctx: Vec<CtxNode<'a, C>>,
+    pub right_side: Word<'a, C>,
+    pub span: Span,
+  }
+
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct Node<'a, C> {
+    pub kind: NodeKind<'a, C>,
+    pub span: Span,
   }
 
-  pub enum Node<'a, C> {
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub enum NodeKind<'a, C> {
     Leaf(Leaf<'a, C>),
     Branch(Vec<Node<'a, C>>),
     Expansion(Expansion<'a>),
     Block(Vec<Stmt<'a>>),
   }
 
-  pub enum CtxNode<'a, C> {
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub struct CtxNode<'a, C> {
+    pub kind: CtxNodeKind<'a, C>,
+    pub span: Span,
+  }
+
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+  pub enum CtxNodeKind<'a, C> {
     Leaf(LeftLeaf<'a, C>),
     Branch(Vec<CtxNode<'a, C>>),
   }
 
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct LeftLeaf<'a, C> {
-    symbol: C,
-    params: Option<Vec<&'a str>>,
+    pub symbol: C,
+    pub params: Option<Vec<Cow<'a, str>>>,
+    pub span: Span,
   }
+
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct Leaf<'a, C> {
-    symbol: C,
-    args: Option<Vec<Expr<'a>>>,
+    pub symbol: C,
+    pub args: Option<Vec<Expr<'a>>>,
+    pub span: Span,
   }
+
+  /// Expands a word value into the enclosing word: `@petal`, `@petals(5)` or `@lv.leaf`.
+  #[derive(Debug, Clone, PartialEq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct Expansion<'a> {
-    to: &'a str,
-    args: Option<Vec<Expr<'a>>>,
+    pub to: Cow<'a, str>,
+    pub args: Option<Vec<Expr<'a>>>,
+    pub span: Span,
+  }
+
+  impl<'a, C> Rule<'a, C> {
+    pub fn base(&self) -> &RuleBase<'a, C> {
+      match self {
+        Self::Production(base) | Self::Coding(base) => base,
+      }
+    }
+
+    pub fn span(&self) -> Span {
+      self.base().span
+    }
+  }
+
+  impl<'a, C> Node<'a, C> {
+    pub fn new(kind: NodeKind<'a, C>, span: Span) -> Self {
+      Node {kind, span}
+    }
+  }
+
+  impl<'a, C> CtxNode<'a, C> {
+    pub fn new(kind: CtxNodeKind<'a, C>, span: Span) -> Self {
+      CtxNode {kind, span}
+    }
+  }
+
+  impl<'a> Expansion<'a> {
+    /// Splits a qualified name: `@lv.leaf` gives `(Some("lv"), "leaf")`.
+    pub fn path(&self) -> (Option<&str>, &str) {
+      match self.to.rsplit_once('.') {
+        Some((module, name)) => (Some(module), name),
+        None => (None, &self.to),
+      }
+    }
   }
 }
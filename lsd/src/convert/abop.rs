@@ -0,0 +1,148 @@
+use std::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::format_lsd;
+use crate::source::Span;
+use super::{Converted, Warning, push_symbol, split_top, top_level};
+
+/// Extension of files in ABOP notation.
+pub const EXTENSION: &str = "abop";
+
+/// Converts a grammar written as in The Algorithmic Beauty of Plants to LSD. It's a quick format for grammars copied
+/// from a book, with a statement per line:
+///
+/// ```text
+/// n = 5, δ = 25.7°
+/// axiom: F
+/// F -> F[+F]F[-F]F
+/// ```
+///
+/// Settings are written `name: value` or `name = value`, and there can be several in a line, separated by commas. `n`
+/// is `iterations` and `δ` is `angle`. A line that is only a word, before the first rule, is the axiom too. Rules use
+/// `->` or `→`, and their `p1:` labels are ignored. Every character of a word is a symbol, and `#` and `//` start
+/// comments.
+pub fn convert(src: &str) -> Converted {
+  let mut out = String::new();
+  let mut rules = String::new();
+  let mut axiom = false;
+  let mut warnings = Vec::new();
+  let mut start = 0;
+  for line in src.split_inclusive('\n') {
+    let code = strip_comment(line).trim_end();
+    let span = Span::new(start + code.len() - code.trim_start().len(), start + code.len());
+    start += line.len();
+    let code = code.trim();
+    if code.is_empty() {
+      continue;
+    }
+
+    if let Some((lhs, rhs)) = split_arrow(code) {
+      writeln!(rules, "{} -> {}", predecessor(lhs), word(rhs)).unwrap();
+    } else if let Some(settings) = settings(code) {
+      for (name, value) in settings {
+        if name == "axiom" {
+          axiom = true;
+          writeln!(out, "axiom {}", word(value)).unwrap();
+        } else {
+          writeln!(out, "set {} = {}", name, value).unwrap();
+        }
+      }
+    } else if !axiom && rules.is_empty() {
+      axiom = true;
+      writeln!(out, "axiom {}", word(code)).unwrap();
+    } else {
+      warnings.push(Warning {message: "this isn't a setting, an axiom or a rule and was left out".to_string(), span});
+    }
+  }
+  if !axiom {
+    warnings.push(Warning {message: "missing axiom".to_string(), span: Span::new(src.len(), src.len())});
+  }
+  if !rules.is_empty() {
+    write!(out, "rules {{\n{}}}\n", rules).unwrap();
+  }
+  let lsd = format_lsd(&out).unwrap_or(out.clone());
+  Converted {lsd, warnings}
+}
+
+fn strip_comment(line: &str) -> &str {
+  let end = [line.find('#'), line.find("//")].into_iter().flatten().min();
+  &line[..end.unwrap_or(line.len())]
+}
+
+/// Splits a rule at its arrow, `->` or `→`.
+fn split_arrow(code: &str) -> Option<(&str, &str)> {
+  let (i, arrow) = ["->", "→"].into_iter().filter_map(|a| Some((code.find(a)?, a))).min()?;
+  Some((&code[..i], &code[i + arrow.len()..]))
+}
+
+/// Reads the settings of a line, with their LSD names. `None` if it isn't a line of settings.
+fn settings(code: &str) -> Option<Vec<(&str, &str)>> {
+  let mut res = Vec::new();
+  let mut rest = code;
+  loop {
+    let (item, next) = split_top(rest, ',').unwrap_or((rest, ""));
+    let i = top_level(item).find(|&(_, c)| c == ':' || c == '=')?.0;
+    let value = item[i + 1..].trim().trim_end_matches('°').trim_end();
+    let name = match item[..i].trim() {
+      "n" | "iterations" => "iterations",
+      "δ" | "delta" | "angle" => "angle",
+      "ω" | "axiom" => "axiom",
+      name if is_name(name) => name,
+      _ => return None,
+    };
+    if value.is_empty() {
+      return None;
+    }
+    res.push((name, value));
+    if next.is_empty() {
+      return Some(res);
+    }
+    rest = next;
+  }
+}
+
+/// Converts the left side of a rule: `p1: b < a(x) > c : x > 1`.
+fn predecessor(lhs: &str) -> String {
+  let lhs = match split_top(lhs, ':') {
+    Some((label, rest)) if label.trim().strip_prefix('p').is_some_and(|n| n.parse::<u32>().is_ok()) => rest,
+    _ => lhs,
+  };
+  let (pattern, cond) = split_top(lhs, ':').unwrap_or((lhs, ""));
+  let (left, pattern) = split_top(pattern, '<').unwrap_or(("", pattern));
+  let (pattern, right) = split_top(pattern, '>').unwrap_or((pattern, ""));
+
+  let mut res = String::new();
+  if !left.trim().is_empty() {
+    write!(res, "{} < ", word(left)).unwrap();
+  }
+  res.push_str(&word(pattern));
+  if !right.trim().is_empty() {
+    write!(res, " > {}", word(right)).unwrap();
+  }
+  if !cond.trim().is_empty() {
+    write!(res, " : {}", cond.trim()).unwrap();
+  }
+  res
+}
+
+/// Converts a word, where every character is a symbol, maybe followed by its arguments.
+fn word(w: &str) -> String {
+  let mut out = String::new();
+  let mut depth = 0usize;
+  for c in w.chars() {
+    match c {
+      '(' => {depth += 1; out.push(c)},
+      ')' => {depth = depth.saturating_sub(1); out.push(c)},
+      _ if depth > 0 => out.push(c),
+      c if c.is_whitespace() => {},
+      '[' | ']' => out.push(c),
+      c => push_symbol(&mut out, c.encode_utf8(&mut [0; 4])),
+    }
+  }
+  out
+}
+
+fn is_name(s: &str) -> bool {
+  s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,492 @@
+use std::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::format_lsd;
+use crate::source::Span;
+use super::{Converted, Warning, push_symbol, rsplit_top, split_top, top_level};
+
+/// Extension of cpfg L system files.
+pub const EXTENSION: &str = "l";
+
+/// Converts a cpfg (L-studio, vlab) L system file to LSD.
+///
+/// `#define`s become variables and every `Lsystem:` ... `endlsystem` block an L system, the first one being the main
+/// one. Homomorphism productions become coding rules and `consider:` is turned into the `ignore` setting of the
+/// symbols that aren't considered. Decomposition productions, the preprocessor and blocks of C statements can't be
+/// converted: they are left out with a warning.
+pub fn convert(src: &str) -> Converted {
+  let (code, comments) = strip_comments(src);
+  let mut c = Converter::new(&comments);
+  let mut start = 0;
+  while start <= code.len() {
+    // A backslash at the end of a line continues it in the next one.
+    let mut end = start;
+    let mut line = String::new();
+    loop {
+      let next = code[end..].find('\n').map_or(code.len(), |i| end + i);
+      let part = code[end..next].trim_end();
+      match part.strip_suffix('\\') {
+        Some(part) if next < code.len() => {
+          line.push_str(part);
+          line.push(' ');
+          end = next + 1;
+        },
+        _ => {
+          line.push_str(part);
+          end = next;
+          break;
+        },
+      }
+    }
+    c.comments_before(src, end);
+    let trim = line.len() - line.trim_start().len();
+    c.line(line.trim(), Span::new(start + trim, end.max(start + trim)));
+    start = end + 1;
+  }
+  c.finish(src.len());
+  let lsd = format_lsd(&c.out).unwrap_or(c.out.clone());
+  Converted {lsd, warnings: c.warnings}
+}
+
+/// Where productions go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+  Productions,
+  Homomorphism,
+  Decomposition,
+}
+
+struct Converter<'c> {
+  out: String,
+  warnings: Vec<Warning>,
+  /// Comments that are alone in their lines, which are kept.
+  comments: &'c [Span],
+  next_comment: usize,
+  /// The section of the L system being converted, if any.
+  section: Option<Section>,
+  /// The rules block being written.
+  rules: Option<Section>,
+  lsystems: usize,
+  /// Braces to close of a block of C statements that is being skipped.
+  skipping: usize,
+  /// Symbols used by the L system, in order.
+  alphabet: Vec<String>,
+  /// Symbols of `consider:` and where the `ignore` setting that replaces it goes in `out`.
+  consider: Option<(Vec<String>, usize)>,
+}
+
+impl<'c> Converter<'c> {
+  fn new(comments: &'c [Span]) -> Self {
+    Converter {
+      out: String::new(),
+      warnings: Vec::new(),
+      comments,
+      next_comment: 0,
+      section: None,
+      rules: None,
+      lsystems: 0,
+      skipping: 0,
+      alphabet: Vec::new(),
+      consider: None,
+    }
+  }
+
+  fn warn(&mut self, message: impl Into<String>, span: Span) {
+    self.warnings.push(Warning {message: message.into(), span});
+  }
+
+  fn comments_before(&mut self, src: &str, end: usize) {
+    while let Some(&c) = self.comments.get(self.next_comment) && c.start <= end {
+      self.next_comment += 1;
+      if self.skipping == 0 {
+        self.out.push_str(&src[c.start..c.end]);
+        self.out.push('\n');
+      }
+    }
+  }
+
+  fn line(&mut self, line: &str, span: Span) {
+    if self.skipping > 0 {
+      self.skipping = braces(self.skipping, line);
+      return;
+    }
+    if line.is_empty() {
+      return;
+    }
+    if let Some(directive) = line.strip_prefix('#') {
+      return self.directive(directive.trim_start(), span);
+    }
+    if let Some((arrow, len)) = line.find("-->").map(|i| (i, 3)).or(line.find("->").map(|i| (i, 2))) {
+      return self.production(&line[..arrow], &line[arrow + len..], span);
+    }
+
+    let (key, value) = line.split_once(':').unwrap_or((line, ""));
+    let key = key.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let value = value.trim();
+    if value.starts_with('{') || line.starts_with('{') {
+      self.warn("blocks of C statements aren't supported and were left out", span);
+      self.skipping = braces(0, line);
+      return;
+    }
+    match key.as_str() {
+      "lsystem" => self.begin(value, span),
+      "endlsystem" => self.end(),
+      "derivation length" => self.set("iterations", &expr(value), span),
+      "seed" => self.set("seed", &expr(value), span),
+      "axiom" => {
+        self.stmt(span);
+        let word = self.word(value, false);
+        writeln!(self.out, "axiom {}", word).unwrap();
+      },
+      "ignore" => {
+        let word = self.word(value, false);
+        self.set("ignore", &format!("`{}`", word), span);
+      },
+      "consider" => {
+        self.stmt(span);
+        let symbols = modules(value).into_iter().map(|m| m.name.to_string()).collect();
+        self.consider = Some((symbols, self.out.len()));
+      },
+      "homomorphism" => {
+        self.stmt(span);
+        self.section = Some(Section::Homomorphism);
+      },
+      "decomposition" => {
+        self.stmt(span);
+        self.section = Some(Section::Decomposition);
+        self.warn("decomposition productions aren't supported and were left out", span);
+      },
+      "warnings" | "no warnings" => {},
+      "maximum depth" => self.warn("`maximum depth` isn't supported and was left out", span),
+      _ => self.warn(format!("unknown statement `{}` was left out", line), span),
+    }
+  }
+
+  fn directive(&mut self, directive: &str, span: Span) {
+    let (name, rest) = directive.split_once(|c: char| !c.is_ascii_alphanumeric()).unwrap_or((directive, ""));
+    match name {
+      "define" => {
+        let rest = rest.trim_start();
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let (var, value) = rest.split_at(len);
+        if var.is_empty() || value.starts_with('(') {
+          return self.warn("macros with parameters aren't supported and were left out", span);
+        }
+        let value = if value.trim().is_empty() {"1".to_string()} else {expr(value)};
+        self.close_rules();
+        writeln!(self.out, "let {} = {}", var, value).unwrap();
+      },
+      "include" => self.warn("`#include` isn't supported: convert the included file and import it instead", span),
+      "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" | "undef" =>
+        self.warn(format!("`#{}` isn't supported: the lines in every branch were kept", name), span),
+      _ => self.warn(format!("unknown directive `#{}` was left out", name), span),
+    }
+  }
+
+  /// Opens an L system for a statement that belongs to one, and closes the rules block.
+  fn stmt(&mut self, span: Span) {
+    if self.section.is_none() {
+      self.begin("", span);
+    }
+    self.close_rules();
+  }
+
+  fn set(&mut self, name: &str, value: &str, span: Span) {
+    self.stmt(span);
+    writeln!(self.out, "set {} = {}", name, value).unwrap();
+  }
+
+  fn begin(&mut self, id: &str, span: Span) {
+    if self.section.is_some() {
+      self.warn("missing `endlsystem` before this L system", span);
+      self.end();
+    }
+    let id: String = id.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+    let id = if id.is_empty() {(self.lsystems + 1).to_string()} else {id};
+    let main = if self.lsystems == 0 {"main "} else {""};
+    writeln!(self.out, "{}lsys lsystem{} {{", main, id).unwrap();
+    self.lsystems += 1;
+    self.section = Some(Section::Productions);
+  }
+
+  fn end(&mut self) {
+    if self.section.take().is_none() {
+      return;
+    }
+    self.close_rules();
+    if let Some((consider, at)) = self.consider.take() {
+      let mut ignore = String::new();
+      for s in self.alphabet.iter().filter(|s| !consider.contains(s) && *s != "[" && *s != "]") {
+        push_symbol(&mut ignore, s);
+      }
+      if !ignore.is_empty() {
+        self.out.insert_str(at, &format!("set ignore = `{}`\n", ignore));
+      }
+    }
+    self.alphabet.clear();
+    self.out.push_str("}\n\n");
+  }
+
+  fn finish(&mut self, end: usize) {
+    if self.section.is_some() {
+      self.warn("missing `endlsystem` at the end of the file", Span::new(end, end));
+      self.end();
+    }
+  }
+
+  fn close_rules(&mut self) {
+    if self.rules.take().is_some() {
+      self.out.push_str("}\n");
+    }
+  }
+
+  /// `left < pred(params) > right : condition --> successor : probability`
+  fn production(&mut self, lhs: &str, rhs: &str, span: Span) {
+    if self.section.is_none() {
+      self.begin("", span);
+    }
+    let section = self.section.unwrap();
+    if section == Section::Decomposition {
+      return;
+    }
+
+    let mut rule = String::new();
+    let (succ, prob) = match rsplit_top(rhs, ':') {
+      Some((succ, prob)) => (succ, Some(prob.trim())),
+      None => (rhs, None),
+    };
+    if let Some(prob) = prob {
+      match prob.parse::<f64>() {
+        Ok(w) if w.is_finite() && w >= 0.0 => write!(rule, "{} | ", prob).unwrap(),
+        _ => self.warn(format!("probability `{}` isn't a number: the production has a weight of 1", prob), span),
+      }
+    }
+
+    let (preds, cond) = split_top(lhs, ':').unwrap_or((lhs, ""));
+    let (left, rest) = split_top(preds, '<').unwrap_or(("", preds));
+    let (pred, right) = split_top(rest, '>').unwrap_or((rest, ""));
+    if modules(pred).len() != 1 {
+      return self.warn(format!("the predecessor `{}` isn't a single module: the production was left out", pred.trim()), span);
+    }
+    if !is_empty(left) {
+      let left = self.word(left, true);
+      write!(rule, "{} < ", left).unwrap();
+    }
+    let pred = self.word(pred, true);
+    rule.push_str(&pred);
+    if !is_empty(right) {
+      let right = self.word(right, true);
+      write!(rule, " > {}", right).unwrap();
+    }
+
+    // `: {pre} condition {post}`
+    let mut condition = String::new();
+    let mut blocks = false;
+    let mut depth = 0usize;
+    for c in cond.chars() {
+      match c {
+        '{' => {depth += 1; blocks = true},
+        '}' => depth = depth.saturating_sub(1),
+        c if depth == 0 => condition.push(c),
+        _ => {},
+      }
+    }
+    if blocks {
+      self.warn("blocks of C statements in conditions aren't supported and were left out", span);
+    }
+    if !is_empty(&condition) {
+      write!(rule, " : {}", expr(&condition)).unwrap();
+    }
+
+    let arrow = if section == Section::Homomorphism {"=>"} else {"->"};
+    let succ = if succ.trim() == "*" {String::new()} else {self.word(succ, false)};
+    write!(rule, " {} {}", arrow, succ).unwrap();
+
+    if self.rules != Some(section) {
+      self.close_rules();
+      self.out.push_str(if section == Section::Homomorphism {"coding rules {\n"} else {"rules {\n"});
+      self.rules = Some(section);
+    }
+    self.out.push_str(rule.trim_end());
+    self.out.push('\n');
+  }
+
+  /// Converts a word. The arguments of the modules of left sides are parameters, the other ones expressions.
+  fn word(&mut self, word: &str, left: bool) -> String {
+    let mut out = String::new();
+    for m in modules(word) {
+      match m.name {
+        "[" | "]" => out.push_str(m.name),
+        name => push_symbol(&mut out, name),
+      }
+      if !self.alphabet.iter().any(|s| s == m.name) {
+        self.alphabet.push(m.name.to_string());
+      }
+      if let Some(args) = m.args {
+        let args: Vec<String> = split_args(args).map(|a| if left {a.trim().to_string()} else {expr(a)}).collect();
+        write!(out, "({})", args.join(", ")).unwrap();
+      }
+    }
+    out
+  }
+}
+
+/// Whether a context or condition is missing: empty or `*`.
+fn is_empty(s: &str) -> bool {
+  matches!(s.trim(), "" | "*")
+}
+
+/// Updates the number of braces to close with a line.
+fn braces(depth: usize, line: &str) -> usize {
+  let open = line.matches('{').count();
+  let close = line.matches('}').count();
+  (depth + open).saturating_sub(close)
+}
+
+struct CpfgModule<'w> {
+  name: &'w str,
+  args: Option<&'w str>,
+}
+
+/// Splits a word into modules. Modules are single characters, but for `@` and `?`, which take the next letter too
+/// (`@O`, `?P`), and one more if it's lowercase (`@Gs`).
+fn modules(word: &str) -> Vec<CpfgModule<'_>> {
+  let mut res = Vec::new();
+  let mut chars = word.char_indices().peekable();
+  while let Some((start, c)) = chars.next() {
+    if c.is_whitespace() {
+      continue;
+    }
+    let mut end = start + c.len_utf8();
+    if (c == '@' || c == '?') && let Some(&(_, n)) = chars.peek() && n.is_ascii_alphabetic() {
+      chars.next();
+      end += 1;
+      if n.is_ascii_uppercase() && let Some(&(_, l)) = chars.peek() && l.is_ascii_lowercase() {
+        chars.next();
+        end += 1;
+      }
+    }
+    let name = &word[start..end];
+    // Arguments: `(...)`, maybe after some spaces.
+    let rest = word[end..].trim_start();
+    let args = if rest.starts_with('(') && !matches!(name, "[" | "]") {
+      let close = closing(&rest[1..]).map_or(rest.len(), |i| i + 1);
+      let args_end = word.len() - rest.len() + close;
+      while chars.peek().is_some_and(|&(i, _)| i <= args_end) {
+        chars.next();
+      }
+      Some(&rest[1..close])
+    } else {
+      None
+    };
+    res.push(CpfgModule {name, args});
+  }
+  res
+}
+
+/// Offset of the `)` that closes a parenthesis, in what follows it.
+fn closing(s: &str) -> Option<usize> {
+  let mut depth = 0usize;
+  for (i, c) in s.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ')' if depth == 0 => return Some(i),
+      ')' => depth -= 1,
+      _ => {},
+    }
+  }
+  None
+}
+
+/// Splits arguments at the commas outside parentheses.
+fn split_args(args: &str) -> impl Iterator<Item = &str> {
+  let mut rest = Some(args);
+  std::iter::from_fn(move || {
+    let s = rest?;
+    match split_top(s, ',') {
+      Some((arg, tail)) => {rest = Some(tail); Some(arg)},
+      None => {rest = None; Some(s)},
+    }
+  })
+}
+
+/// Converts a C expression to LSD: `^` is a power in cpfg, `a ? b : c` is an `if` and `1.` is `1.0`.
+fn expr(e: &str) -> String {
+  let e = e.trim();
+  if let Some((cond, rest)) = split_top(e, '?') {
+    // The `:` of this `?`, skipping the ones of nested conditionals.
+    let mut nested = 0;
+    let colon = top_level(rest).find(|&(_, c)| match c {
+      '?' => {nested += 1; false},
+      ':' if nested > 0 => {nested -= 1; false},
+      c => c == ':',
+    });
+    if let Some((i, _)) = colon {
+      return format!("if {} then {} else {}", expr(cond), expr(&rest[..i]), expr(&rest[i + 1..]));
+    }
+  }
+
+  let mut out = String::new();
+  let mut chars = e.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    match c {
+      '^' => out.push_str("**"),
+      '(' => {
+        let inner = &e[i + 1..];
+        let close = closing(inner).unwrap_or(inner.len());
+        let args: Vec<String> = split_args(&inner[..close]).map(expr).collect();
+        write!(out, "({})", args.join(", ")).unwrap();
+        while chars.peek().is_some_and(|&(j, _)| j <= i + 1 + close) {
+          chars.next();
+        }
+      },
+      '.' if out.ends_with(|c: char| c.is_ascii_digit()) && !chars.peek().is_some_and(|&(_, n)| n.is_ascii_digit()) => {
+        let number = out.trim_end_matches(|c: char| c.is_ascii_digit());
+        if !number.ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+          out.push_str(".0");
+        } else {
+          out.push('.');
+        }
+      },
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// Replaces comments with spaces, keeping newlines so offsets don't change. Returns the code and the comments that
+/// are alone in their lines.
+fn strip_comments(src: &str) -> (String, Vec<Span>) {
+  let mut code = String::with_capacity(src.len());
+  let mut comments = Vec::new();
+  let mut rest = src;
+  while !rest.is_empty() {
+    let start = src.len() - rest.len();
+    let len = if rest.starts_with("/*") {
+      rest.find("*/").map_or(rest.len(), |i| i + 2)
+    } else if rest.starts_with("//") {
+      rest.find('\n').unwrap_or(rest.len())
+    } else {
+      let c = rest.chars().next().unwrap();
+      code.push(c);
+      rest = &rest[c.len_utf8()..];
+      continue;
+    };
+    // Comments are blanked byte by byte, so offsets don't change.
+    for c in rest[..len].chars() {
+      match c {
+        '\n' => code.push('\n'),
+        c => code.extend(std::iter::repeat_n(' ', c.len_utf8())),
+      }
+    }
+
+    let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = src[start + len..].find('\n').map_or(src.len(), |i| start + len + i);
+    if src[line_start..start].trim().is_empty() && src[start + len..line_end].trim().is_empty() {
+      comments.push(Span::new(start, start + len));
+    }
+    rest = &rest[len..];
+  }
+  (code, comments)
+}
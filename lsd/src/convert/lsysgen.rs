@@ -0,0 +1,271 @@
+use std::string::String;
+use std::vec::Vec;
+
+use crate::format_lsd;
+use crate::source::Span;
+use super::{Converted, Warning, split_top};
+
+/// Extension of LSysGen files.
+pub const EXTENSION: &str = "lsys";
+
+/// Converts a file of LSysGen, the C++ program this one is a port of, to LSD.
+///
+/// LSysGen reads LSysD, the language LSD grew from, so most of a file is kept as it is. What changes is:
+///
+/// - Rules written as statements of an L system, outside any block, are put in a `rules` block.
+/// - Rules with a tag, `grow: F -> FF`, go to the table with that name, at the end of their L system. Tags are told
+///   apart from the conditions of single-character symbols (`A : x > 1 -> B`) by being longer than one character.
+/// - Constants, `$n = 5`, become variables, and `$n` is `n`.
+/// - `if (c) stmt`, `else stmt` and `while (c) stmt` get braces around `stmt`.
+///
+/// `do ... while` loops have no LSD counterpart and are reported as warnings.
+pub fn convert(src: &str) -> Converted {
+  let mut c = Converter {out: String::new(), warnings: Vec::new(), stack: vec![Block::LSys(LSysBlock::default())]};
+  let mut start = 0;
+  let mut in_comment = false;
+  for line in src.split_inclusive('\n') {
+    let span = Span::new(start, start + line.trim_end().len());
+    start += line.len();
+    let (code, comment) = split_comment(line.trim_end(), &mut in_comment);
+    c.line(code, comment, span);
+  }
+  c.close_lsys();
+  let lsd = format_lsd(&c.out).unwrap_or(c.out.clone());
+  Converted {lsd, warnings: c.warnings}
+}
+
+#[derive(Debug, Default)]
+struct LSysBlock {
+  /// Whether a `rules` block for bare rules is open.
+  rules: bool,
+  /// Tagged rules, by tag.
+  tables: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug)]
+enum Block {
+  LSys(LSysBlock),
+  /// `rules`, `production rules`, `coding rules` or a table.
+  Rules,
+  Code,
+}
+
+struct Converter {
+  out: String,
+  warnings: Vec<Warning>,
+  /// Blocks open at the current line. The first one is the file, an implicit L system.
+  stack: Vec<Block>,
+}
+
+impl Converter {
+  fn line(&mut self, code: &str, comment: &str, span: Span) {
+    let code = code.trim();
+    let in_rules = matches!(self.stack.last(), Some(Block::Rules));
+    if !in_rules && is_rule(code) {
+      return self.rule(code, comment);
+    }
+    if let Some(Block::LSys(lsys)) = self.stack.last_mut() && lsys.rules && !code.is_empty() {
+      lsys.rules = false;
+      self.out.push_str("}\n");
+    }
+
+    let code = if in_rules || code.starts_with("axiom") {code.to_string()} else {self.statement(code, span)};
+    // A closed L system gets its tables before its `}`.
+    if code.starts_with('}') && matches!(self.stack.last(), Some(Block::LSys(_))) && self.stack.len() > 1 {
+      self.close_lsys();
+    }
+    self.push_line(&code, comment);
+    self.braces(&code);
+  }
+
+  /// Converts a statement of normal mode.
+  fn statement(&mut self, code: &str, span: Span) -> String {
+    if code.starts_with("do") && !code[2..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+      self.warn("`do ... while` loops aren't supported: rewrite them as `while` loops", span);
+    }
+    // `$n = 5`
+    let code = match code.strip_prefix('$') {
+      Some(rest) if split_top(rest, '=').is_some_and(|(name, value)| is_name(name.trim()) && !value.starts_with('=')) =>
+        format!("let {}", rest),
+      _ => code.to_string(),
+    };
+    braced(&strip_dollars(&code))
+  }
+
+  fn rule(&mut self, code: &str, comment: &str) {
+    let Some(Block::LSys(lsys)) = self.stack.last_mut() else {
+      return self.push_line(code, comment);
+    };
+    // `tag: rule`
+    if let Some((tag, rule)) = code.split_once(':') && tag.trim().len() > 1 && is_name(tag.trim()) {
+      let rule = format!("{} {}", rule.trim(), comment).trim_end().to_string();
+      match lsys.tables.iter_mut().find(|(t, _)| t == tag.trim()) {
+        Some((_, rules)) => rules.push(rule),
+        None => lsys.tables.push((tag.trim().to_string(), vec![rule])),
+      }
+      return;
+    }
+    if !lsys.rules {
+      lsys.rules = true;
+      self.out.push_str("rules {\n");
+    }
+    self.push_line(code, comment);
+  }
+
+  /// Closes the `rules` block of the innermost L system and writes its tables.
+  fn close_lsys(&mut self) {
+    let Some(Block::LSys(lsys)) = self.stack.last_mut() else {
+      return;
+    };
+    let lsys = std::mem::take(lsys);
+    if lsys.rules {
+      self.out.push_str("}\n");
+    }
+    for (tag, rules) in lsys.tables {
+      self.out.push_str(&format!("table {} {{\n", tag));
+      for rule in rules {
+        self.out.push_str(&rule);
+        self.out.push('\n');
+      }
+      self.out.push_str("}\n");
+    }
+  }
+
+  fn push_line(&mut self, code: &str, comment: &str) {
+    self.out.push_str(code);
+    if !code.is_empty() && !comment.is_empty() {
+      self.out.push(' ');
+    }
+    self.out.push_str(comment);
+    self.out.push('\n');
+  }
+
+  /// Opens and closes the blocks of a line.
+  fn braces(&mut self, code: &str) {
+    let words: Vec<&str> = code.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').filter(|w| !w.is_empty()).collect();
+    let kind = match words.as_slice() {
+      ["rules", ..] | ["production" | "coding", "rules", ..] | ["table", ..] => 0,
+      ["lsys", ..] | ["main", "lsys", ..] => 1,
+      _ => 2,
+    };
+    let mut quoted = None;
+    for c in code.chars() {
+      match (c, quoted) {
+        ('"' | '`', None) => quoted = Some(c),
+        (c, Some(q)) if c == q => quoted = None,
+        (_, Some(_)) => {},
+        ('{', None) => self.stack.push(match kind {
+          0 => Block::Rules,
+          1 => Block::LSys(LSysBlock::default()),
+          _ => Block::Code,
+        }),
+        ('}', None) if self.stack.len() > 1 => {self.stack.pop();},
+        _ => {},
+      }
+    }
+  }
+
+  fn warn(&mut self, message: impl Into<String>, span: Span) {
+    self.warnings.push(Warning {message: message.into(), span});
+  }
+}
+
+/// Splits a line into code and comment. `in_comment` tells whether the line starts inside a block comment, and is
+/// updated for the next one.
+fn split_comment<'l>(line: &'l str, in_comment: &mut bool) -> (&'l str, &'l str) {
+  if *in_comment {
+    // What follows the end of the comment in the same line is kept as it is.
+    *in_comment = !line.contains("*/");
+    return ("", line);
+  }
+  let mut quoted = None;
+  let bytes = line.as_bytes();
+  for (i, &b) in bytes.iter().enumerate() {
+    match (b, quoted) {
+      (b'"' | b'`', None) => quoted = Some(b),
+      (b, Some(q)) if b == q => quoted = None,
+      (b'/', None) if bytes.get(i + 1) == Some(&b'/') => return (&line[..i], &line[i..]),
+      (b'/', None) if bytes.get(i + 1) == Some(&b'*') => {
+        *in_comment = !line[i..].contains("*/");
+        return (&line[..i], &line[i..]);
+      },
+      _ => {},
+    }
+  }
+  (line, "")
+}
+
+/// Whether a line of an L system is a rule: it has an arrow and isn't a statement with a lambda.
+fn is_rule(code: &str) -> bool {
+  let first = code.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').next().unwrap_or_default();
+  (code.contains("->") || code.contains("=>"))
+    && !code.contains("fn(")
+    && !matches!(first, "let" | "set" | "return" | "if" | "while" | "for" | "fn" | "lsys" | "main")
+    && !code.starts_with('$')
+}
+
+fn is_name(s: &str) -> bool {
+  s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Removes the `$` of constants, outside strings and word literals.
+fn strip_dollars(code: &str) -> String {
+  let mut out = String::with_capacity(code.len());
+  let mut quoted = None;
+  let mut chars = code.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (c, quoted) {
+      ('"' | '`', None) => quoted = Some(c),
+      (c, Some(q)) if c == q => quoted = None,
+      ('$', None) if chars.peek().is_some_and(|n| n.is_ascii_alphabetic() || *n == '_') => continue,
+      _ => {},
+    }
+    out.push(c);
+  }
+  out
+}
+
+/// Puts braces around the statement of `if (c) stmt`, `else stmt` and `while (c) stmt`.
+fn braced(code: &str) -> String {
+  let (prefix, rest) = match code.strip_prefix('}') {
+    Some(rest) => ("} ", rest.trim_start()),
+    None => ("", code),
+  };
+  let (head, body) = if let Some(rest) = rest.strip_prefix("else") && !rest.trim_start().starts_with("if") {
+    ("else".to_string(), rest.trim_start())
+  } else {
+    let Some(kw) = ["if", "else if", "while"].into_iter().rev().find(|kw| rest.starts_with(kw)) else {
+      return code.to_string();
+    };
+    let after = rest[kw.len()..].trim_start();
+    if !after.starts_with('(') {
+      return code.to_string();
+    }
+    let Some(close) = top_close(after) else {
+      return code.to_string();
+    };
+    (format!("{} {}", kw, &after[..close + 1]), after[close + 1..].trim_start())
+  };
+  if body.is_empty() || body.starts_with('{') {
+    return code.to_string();
+  }
+  format!("{}{} {{{}}}", prefix, head, body)
+}
+
+/// Offset of the `)` that closes the `(` at the beginning of `s`.
+fn top_close(s: &str) -> Option<usize> {
+  let mut depth = 0usize;
+  for (i, c) in s.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ')' => {
+        depth -= 1;
+        if depth == 0 {
+          return Some(i);
+        }
+      },
+      _ => {},
+    }
+  }
+  None
+}
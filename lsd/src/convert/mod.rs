@@ -0,0 +1,132 @@
+use std::string::String;
+use std::vec::Vec;
+
+use crate::source::Span;
+
+pub mod abop;
+pub mod cpfg;
+pub mod lsysgen;
+
+/// Something of the source dialect that was left out or changed when converting it to LSD.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+  pub message: String,
+  /// Where it is in the original source.
+  pub span: Span,
+}
+
+/// LSD source converted from another dialect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Converted {
+  pub lsd: String,
+  pub warnings: Vec<Warning>,
+}
+
+/// A language of L systems that can be converted to LSD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+  /// cpfg, of L-studio and vlab.
+  Cpfg,
+  /// LSysGen, the C++ program this one is a port of.
+  LSysGen,
+  /// The notation of The Algorithmic Beauty of Plants, for quick experiments.
+  Abop,
+}
+
+impl Dialect {
+  pub const ALL: [Dialect; 3] = [Dialect::Cpfg, Dialect::LSysGen, Dialect::Abop];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Dialect::Cpfg => "cpfg",
+      Dialect::LSysGen => "lsysgen",
+      Dialect::Abop => "abop",
+    }
+  }
+
+  /// Extension of the files written in the dialect.
+  pub fn extension(self) -> &'static str {
+    match self {
+      Dialect::Cpfg => cpfg::EXTENSION,
+      Dialect::LSysGen => lsysgen::EXTENSION,
+      Dialect::Abop => abop::EXTENSION,
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|d| d.name() == name)
+  }
+
+  pub fn from_extension(ext: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|d| d.extension() == ext)
+  }
+
+  /// Guesses the dialect of a source without a file name, from lines that can't be LSD: `Lsystem:` or `derivation
+  /// length:` for cpfg, and `ω`, `δ`, `→` or `axiom:` for ABOP. `None` if there are none, which is the case of LSD
+  /// and, as it's close to it, of LSysGen. `//` comments are skipped.
+  pub fn detect(src: &str) -> Option<Self> {
+    src.lines().map(|line| line.split("//").next().unwrap_or_default().trim_start()).find_map(|line| {
+      let lower = line.to_lowercase();
+      if lower.starts_with("lsystem:") || lower.starts_with("derivation length:") {
+        Some(Dialect::Cpfg)
+      } else if line.starts_with('ω') || line.starts_with("axiom:") || line.contains(['δ', '→']) {
+        Some(Dialect::Abop)
+      } else {
+        None
+      }
+    })
+  }
+
+  pub fn convert(self, src: &str) -> Converted {
+    match self {
+      Dialect::Cpfg => cpfg::convert(src),
+      Dialect::LSysGen => lsysgen::convert(src),
+      Dialect::Abop => abop::convert(src),
+    }
+  }
+}
+
+/// Writes a symbol of a word. It's quoted unless it's a single character that is read as a symbol by itself.
+fn push_symbol(out: &mut String, name: &str) {
+  let mut chars = name.chars();
+  let bare = match (chars.next(), chars.next()) {
+    (Some(c), None) => !c.is_whitespace() && !"\"()[]{};:`<>@|".contains(c),
+    _ => false,
+  };
+  if !bare {
+    out.push('"');
+    out.push_str(name);
+    out.push('"');
+    return;
+  }
+  // `//` would be a comment.
+  if out.ends_with('/') && (name == "/" || name == "*") {
+    out.push(' ');
+  }
+  out.push_str(name);
+}
+
+/// Splits `s` at the first `c` outside parentheses and braces.
+fn split_top(s: &str, c: char) -> Option<(&str, &str)> {
+  let i = top_level(s).find(|&(_, x)| x == c)?.0;
+  Some((&s[..i], &s[i + c.len_utf8()..]))
+}
+
+/// Splits `s` at the last `c` outside parentheses and braces.
+fn rsplit_top(s: &str, c: char) -> Option<(&str, &str)> {
+  let i = top_level(s).filter(|&(_, x)| x == c).last()?.0;
+  Some((&s[..i], &s[i + c.len_utf8()..]))
+}
+
+/// Characters of `s` outside parentheses and braces, with their offsets.
+fn top_level(s: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+  let mut depth = 0usize;
+  s.char_indices().filter(move |&(_, c)| {
+    match c {
+      '(' | '{' => depth += 1,
+      ')' | '}' => depth = depth.saturating_sub(1),
+      _ => return depth == 0,
+    }
+    false
+  })
+}
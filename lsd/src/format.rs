@@ -0,0 +1,616 @@
+use std::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::ast::normal::*;
+use crate::ast::grammar::*;
+use crate::symbol::Sym;
+use crate::lexer::{Lexer, LexerMode};
+use crate::parser::LsdModuleParser;
+use crate::source::Span;
+use crate::{ParseError, parse_strict};
+
+const INDENT: &str = "  ";
+
+/// Formats a module in the canonical style, keeping its comments.
+///
+/// Statements and rules go one per line, blocks are indented two spaces and the arrows of the rules of a block are
+/// aligned. Weights of 1 are left out, as well as parentheses that aren't needed. Blank lines are kept, but never more
+/// than one in a row. The result parses to the same AST as `src`, spans aside.
+///
+/// Fails if `src` has syntax errors.
+pub fn format_lsd(src: &str) -> Result<String, Vec<ParseError<'_>>> {
+  let mut lexer = Lexer::new(src, LexerMode::Normal);
+  let module = parse_strict(&mut lexer, |mode, errors, lexer| LsdModuleParser::new().parse(mode, errors, lexer))?;
+  let mut p = Printer::new(src, lexer.comments());
+  p.module(&module);
+  Ok(p.out)
+}
+
+/// Formats an expression in the canonical style, like `format_lsd` does inside a module.
+pub fn format_expr(e: &Expr) -> String {
+  let mut p = Printer::new("", &[]);
+  p.expr(e);
+  p.out
+}
+
+struct Printer<'s> {
+  src: &'s str,
+  comments: &'s [Span],
+  /// Next comment to print.
+  next: usize,
+  /// Source offset where the last thing printed ends.
+  last: usize,
+  out: String,
+  indent: usize,
+  /// Prints statements and rules in a single line, separated by `;`, like inside words.
+  inline: bool,
+}
+
+impl<'s> Printer<'s> {
+  fn new(src: &'s str, comments: &'s [Span]) -> Self {
+    Printer {src, comments, next: 0, last: 0, out: String::new(), indent: 0, inline: false}
+  }
+
+  /// Prints something in a single line, apart, and returns it.
+  fn inline(&self, print: impl FnOnce(&mut Printer)) -> String {
+    let mut p = Printer {inline: true, ..Printer::new(self.src, &[])};
+    print(&mut p);
+    p.out
+  }
+
+  fn push_indent(&mut self) {
+    for _ in 0..self.indent {
+      self.out.push_str(INDENT);
+    }
+  }
+
+  /// Prints the comments that are before `pos`, one per line.
+  fn comments_before(&mut self, pos: usize) {
+    while let Some(&c) = self.comments.get(self.next) && c.start < pos {
+      self.next += 1;
+      self.blank_line(c.start);
+      self.push_indent();
+      self.out.push_str(&self.src[c.start..c.end]);
+      self.out.push('\n');
+      self.last = c.end;
+    }
+  }
+
+  /// Leaves a blank line if there is one between the last thing printed and `pos`.
+  fn blank_line(&mut self, pos: usize) {
+    let gap = self.src.get(self.last..pos).unwrap_or("");
+    let newlines = gap.chars().take_while(|c| c.is_whitespace()).filter(|&c| c == '\n').count();
+    if newlines > 1 && !self.out.is_empty() && !self.out.ends_with("{\n") && !self.out.ends_with("\n\n") {
+      self.out.push('\n');
+    }
+  }
+
+  /// Starts the line of something that starts at `pos`, after the comments before it.
+  fn start_line(&mut self, pos: usize) {
+    if self.inline {
+      return;
+    }
+    self.comments_before(pos);
+    self.blank_line(pos);
+    self.push_indent();
+  }
+
+  /// Ends the line of something that ends at `end`, with the comment after it if it's in the same line.
+  fn end_line(&mut self, end: usize) {
+    if self.inline {
+      self.out.push_str("; ");
+      return;
+    }
+    self.last = self.last.max(end);
+    if let Some(&c) = self.comments.get(self.next) && c.start >= end && !self.src[end..c.start].contains('\n') {
+      self.next += 1;
+      self.out.push(' ');
+      self.out.push_str(&self.src[c.start..c.end]);
+      self.last = c.end;
+    }
+    self.out.push('\n');
+  }
+
+  /// Prints a block, whose `}` is at `close` in the source. Empty blocks are printed as `{}`.
+  fn block(&mut self, close: usize, body: impl FnOnce(&mut Self)) {
+    self.out.push('{');
+    if self.inline {
+      body(self);
+      if self.out.ends_with("; ") {
+        self.out.truncate(self.out.len() - 2);
+      }
+    } else {
+      self.out.push('\n');
+      self.indent += 1;
+      body(self);
+      self.comments_before(close);
+      self.indent -= 1;
+      if self.out.ends_with("{\n") {
+        self.out.pop();
+      } else {
+        self.push_indent();
+      }
+      self.last = self.last.max(close);
+    }
+    self.out.push('}');
+  }
+
+  /// Looks for the `}` that closes a block from `from`, skipping comments.
+  fn find_close(&self, from: usize) -> usize {
+    let bytes = self.src.as_bytes();
+    let mut pos = from;
+    while pos < bytes.len() {
+      match self.comments.binary_search_by_key(&pos, |c| c.start) {
+        Ok(i) => pos = self.comments[i].end,
+        Err(_) if bytes[pos] == b'}' => return pos,
+        Err(_) => pos += 1,
+      }
+    }
+    pos
+  }
+
+
+
+  // Normal mode:
+
+  fn module(&mut self, m: &Module) {
+    for s in &m.stmts {
+      match s {
+        ModStmt::Import(i) => self.import(i),
+        ModStmt::VarDecl(d) => {
+          self.start_line(d.span.start);
+          self.var_decl(d);
+          self.end_line(d.span.end);
+        },
+        ModStmt::FnDef(d) => {
+          self.start_line(d.span.start);
+          self.fn_def(d);
+          self.end_line(d.span.end);
+        },
+        // Implicit L system: the whole file is its body.
+        ModStmt::LSysDef(d) if d.name.is_none() => d.stmts.iter().for_each(|s| self.lsys_stmt(s)),
+        ModStmt::LSysDef(d) => {
+          self.start_line(d.span.start);
+          self.lsys_def(d);
+          self.end_line(d.span.end);
+        },
+      }
+    }
+    self.comments_before(usize::MAX);
+  }
+
+  fn import(&mut self, i: &ImportStmt) {
+    self.start_line(i.span.start);
+    let is_name = i.module.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+      && i.module.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let module = if is_name {i.module.to_string()} else {quote(&i.module)};
+    if i.symbols.is_empty() {
+      write!(self.out, "import {}", module).unwrap();
+      if let Some(alias) = &i.alias {
+        write!(self.out, " as {}", alias).unwrap();
+      }
+    } else {
+      write!(self.out, "from {} import {}", module, i.symbols.join(", ")).unwrap();
+    }
+    self.end_line(i.span.end);
+  }
+
+  fn lsys_def(&mut self, d: &LSysDef) {
+    if d.main {
+      self.out.push_str("main ");
+    }
+    self.out.push_str("lsys ");
+    self.out.push_str(d.name.as_deref().unwrap_or_default());
+    if !d.params.is_empty() {
+      self.params(&d.params);
+    }
+    self.out.push(' ');
+    self.block(d.span.end - 1, |p| d.stmts.iter().for_each(|s| p.lsys_stmt(s)));
+  }
+
+  fn lsys_stmt(&mut self, s: &LSysStmt) {
+    match s {
+      LSysStmt::Stmt(s) => self.stmt(s),
+      LSysStmt::Import(i) => self.import(i),
+      LSysStmt::SetDef(name, e) => {
+        self.start_line(e.span.start);
+        write!(self.out, "set {} = ", name).unwrap();
+        self.expr(e);
+        self.end_line(e.span.end);
+      },
+      LSysStmt::AxiomDef(w) => {
+        let span = w.0.first().zip(w.0.last()).map(|(f, l)| f.span.to(l.span));
+        self.start_line(span.map_or(self.last, |s| s.start));
+        self.out.push_str("axiom ");
+        self.word(w);
+        if self.out.ends_with(' ') {
+          self.out.pop();
+        }
+        self.end_line(span.map_or(self.last, |s| s.end));
+      },
+      LSysStmt::TableDef(t) => {
+        self.start_line(t.span.start);
+        write!(self.out, "table {} ", t.name.as_deref().unwrap_or_default()).unwrap();
+        self.rules(&t.rules, t.span.end - 1);
+        self.end_line(t.span.end);
+      },
+      LSysStmt::RulesDef(rs) | LSysStmt::ProductionRulesDef(rs) | LSysStmt::CodingRulesDef(rs) => {
+        let keyword = match s {
+          LSysStmt::ProductionRulesDef(_) => "production rules",
+          LSysStmt::CodingRulesDef(_) => "coding rules",
+          _ => "rules",
+        };
+        self.start_line(rs.first().map_or(self.last, |r| r.span().start));
+        let close = self.find_close(rs.last().map_or(self.last, |r| r.span().end));
+        write!(self.out, "{} ", keyword).unwrap();
+        self.rules(rs, close);
+        self.end_line(close + 1);
+      },
+    }
+  }
+
+  fn stmt(&mut self, s: &Stmt) {
+    self.start_line(s.span.start);
+    self.stmt_kind(s);
+    self.end_line(s.span.end);
+  }
+
+  /// Prints a statement without starting or ending its line.
+  fn stmt_kind(&mut self, s: &Stmt) {
+    match &s.kind {
+      StmtKind::Expr(e) => self.expr(e),
+      StmtKind::Assign(name, e) => {
+        write!(self.out, "{} = ", name).unwrap();
+        self.expr(e);
+      },
+      StmtKind::VarDecl(d) => self.var_decl(d),
+      StmtKind::FnDef(d) => self.fn_def(d),
+      StmtKind::LSysDef(d) => self.lsys_def(d),
+      StmtKind::If(c, t, e) => {
+        self.out.push_str("if ");
+        self.expr(c);
+        self.out.push(' ');
+        self.body(t);
+        if let Some(e) = e {
+          self.out.push_str(" else ");
+          match e.kind {
+            StmtKind::If(..) => self.stmt_kind(e),
+            _ => self.body(e),
+          }
+        }
+      },
+      StmtKind::For(var, e, b) => {
+        write!(self.out, "for {} in ", var).unwrap();
+        self.expr(e);
+        self.out.push(' ');
+        self.body(b);
+      },
+      StmtKind::While(e, b) => {
+        self.out.push_str("while ");
+        self.expr(e);
+        self.out.push(' ');
+        self.body(b);
+      },
+      StmtKind::Return(e) => {
+        self.out.push_str("return");
+        if let Some(e) = e {
+          self.out.push(' ');
+          self.expr(e);
+        }
+      },
+      StmtKind::Block(_) => self.body(s),
+    }
+  }
+
+  /// Prints the block of an `if`, `for` or `while`.
+  fn body(&mut self, s: &Stmt) {
+    match &s.kind {
+      StmtKind::Block(ss) => self.block(s.span.end - 1, |p| ss.iter().for_each(|s| p.stmt(s))),
+      _ => self.stmt_kind(s),
+    }
+  }
+
+  fn var_decl(&mut self, d: &VarDecl) {
+    self.out.push_str(if d.mutable {"let mut "} else {"let "});
+    self.out.push_str(&d.name);
+    if let Some(e) = &d.value {
+      self.out.push_str(" = ");
+      self.expr(e);
+    }
+  }
+
+  fn fn_def(&mut self, d: &FnDef) {
+    write!(self.out, "fn {}", d.name).unwrap();
+    self.params(&d.params);
+    self.out.push(' ');
+    self.block(d.span.end - 1, |p| d.stmts.iter().for_each(|s| p.stmt(s)));
+  }
+
+  fn params(&mut self, ps: &[Param]) {
+    self.out.push('(');
+    for (i, p) in ps.iter().enumerate() {
+      if i > 0 {
+        self.out.push_str(", ");
+      }
+      self.out.push_str(&p.name);
+      if let Some(e) = &p.default_value {
+        self.out.push_str(" = ");
+        self.expr(e);
+      }
+    }
+    self.out.push(')');
+  }
+
+  fn exprs(&mut self, es: &[Expr]) {
+    for (i, e) in es.iter().enumerate() {
+      if i > 0 {
+        self.out.push_str(", ");
+      }
+      self.expr(e);
+    }
+  }
+
+  fn expr(&mut self, e: &Expr) {
+    use ExprKind::*;
+    match &e.kind {
+      Int(i) => write!(self.out, "{}", i).unwrap(),
+      Float(f) if f.is_nan() => self.out.push_str("NaN"),
+      Float(f) if f.is_infinite() => self.out.push_str(if *f > 0.0 {"Inf"} else {"-Inf"}),
+      // `{:?}` always writes a `.` or an exponent, so it's read back as a float.
+      Float(f) => write!(self.out, "{:?}", f).unwrap(),
+      String(s) => self.out.push_str(&quote(s)),
+      List(es) => {
+        self.out.push('[');
+        self.exprs(es);
+        self.out.push(']');
+      },
+      Bool(b) => write!(self.out, "{}", b).unwrap(),
+      Null => self.out.push_str("null"),
+      ID(id) => self.out.push_str(id),
+      PropAcc(e, prop) => {
+        self.operand(e, 0);
+        write!(self.out, ".{}", prop).unwrap();
+      },
+      FnCall(f, args) => {
+        self.operand(f, 0);
+        self.out.push('(');
+        self.exprs(args);
+        self.out.push(')');
+      },
+      IndexExpr(e, i) => {
+        self.operand(e, 0);
+        self.out.push('[');
+        self.expr(i);
+        self.out.push(']');
+      },
+      Plus(e) => self.unary("+", e),
+      Minus(e) => self.unary("-", e),
+      Not(e) => self.unary("not ", e),
+      BitNot(e) => self.unary("~", e),
+      Pow(l, r) => {
+        self.operand(l, 1);
+        self.out.push_str(" ** ");
+        self.operand(r, 2);
+      },
+      Mul(l, r) => self.binary(l, "*", r, 3),
+      Div(l, r) => self.binary(l, "/", r, 3),
+      Mod(l, r) => self.binary(l, "%", r, 3),
+      Add(l, r) => self.binary(l, "+", r, 4),
+      Sub(l, r) => self.binary(l, "-", r, 4),
+      LT(l, r) => self.binary(l, "<", r, 8),
+      LE(l, r) => self.binary(l, "<=", r, 8),
+      GT(l, r) => self.binary(l, ">", r, 8),
+      GE(l, r) => self.binary(l, ">=", r, 8),
+      EQ(l, r) => self.binary(l, "==", r, 8),
+      NE(l, r) => self.binary(l, "!=", r, 8),
+      BitAnd(l, r) => self.binary(l, "&", r, 5),
+      BitXor(l, r) => self.binary(l, "^", r, 6),
+      BitOr(l, r) => self.binary(l, "|", r, 7),
+      And(l, r) => self.binary(l, "and", r, 9),
+      Or(l, r) => self.binary(l, "or", r, 10),
+      In(l, r, true) => self.binary(l, "in", r, 8),
+      In(l, r, false) => self.binary(l, "not in", r, 8),
+      IfElse(c, t, f) => {
+        self.out.push_str("if ");
+        self.operand(c, 10);
+        self.out.push_str(" then ");
+        self.operand(t, 10);
+        self.out.push_str(" else ");
+        self.operand(f, 11);
+      },
+      Lambda(ps, e) => {
+        self.out.push_str("fn");
+        self.params(ps);
+        self.out.push_str(" -> ");
+        self.operand(e, 11);
+      },
+      Word(w) => {
+        self.out.push('`');
+        self.word(w);
+        self.out.push('`');
+      },
+    }
+  }
+
+  /// Prints an expression, in parentheses if its precedence level is above `max`.
+  fn operand(&mut self, e: &Expr, max: u8) {
+    if level(e) > max {
+      self.out.push('(');
+      self.expr(e);
+      self.out.push(')');
+    } else {
+      self.expr(e);
+    }
+  }
+
+  fn unary(&mut self, op: &str, e: &Expr) {
+    self.out.push_str(op);
+    self.operand(e, 1);
+  }
+
+  /// Prints a left-associative binary operation of the given precedence level.
+  fn binary(&mut self, l: &Expr, op: &str, r: &Expr, level: u8) {
+    self.operand(l, level);
+    write!(self.out, " {} ", op).unwrap();
+    self.operand(r, level - 1);
+  }
+
+
+
+  // Grammar mode:
+
+  /// Prints a rules block, with one rule per line and the arrows aligned.
+  fn rules(&mut self, rules: &[Rule<Sym>], close: usize) {
+    let lhs: Vec<String> = rules.iter().map(|r| self.inline(|p| p.rule_lhs(r.base()))).collect();
+    let width = if self.inline {0} else {lhs.iter().map(|l| l.chars().count()).max().unwrap_or(0)};
+    self.block(close, |p| {
+      for (r, lhs) in rules.iter().zip(lhs) {
+        p.start_line(r.span().start);
+        let arrow = match r {
+          Rule::Production(_) => "->",
+          Rule::Coding(_) => "=>",
+        };
+        write!(p.out, "{:<width$} {}", lhs, arrow, width = width).unwrap();
+        if !r.base().right_side.0.is_empty() {
+          p.out.push(' ');
+          p.word(&r.base().right_side);
+        }
+        p.end_line(r.span().end);
+      }
+    });
+  }
+
+  fn rule_lhs(&mut self, b: &RuleBase<Sym>) {
+    if b.weight != 1.0 {
+      write!(self.out, "{} | ", b.weight).unwrap();
+    }
+    if !b.l_ctx.is_empty() {
+      self.ctx_nodes(&b.l_ctx);
+      self.out.push_str(" < ");
+    }
+    self.left_leaf(&b.left_leaf);
+    if !b.r_ctx.is_empty() {
+      self.out.push_str(" > ");
+      self.ctx_nodes(&b.r_ctx);
+    }
+    if let Some(c) = &b.condition {
+      self.out.push_str(" : ");
+      self.expr(c);
+    }
+  }
+
+  fn left_leaf(&mut self, l: &LeftLeaf<Sym>) {
+    self.symbol(l.symbol);
+    if let Some(ps) = &l.params {
+      write!(self.out, "({})", ps.join(", ")).unwrap();
+    }
+  }
+
+  fn ctx_nodes(&mut self, ns: &[CtxNode<Sym>]) {
+    for n in ns {
+      match &n.kind {
+        CtxNodeKind::Leaf(l) => self.left_leaf(l),
+        CtxNodeKind::Branch(ns) => {
+          self.out.push('[');
+          self.ctx_nodes(ns);
+          self.out.push(']');
+        },
+      }
+    }
+  }
+
+  fn word(&mut self, w: &Word<Sym>) {
+    self.nodes(&w.0);
+  }
+
+  fn nodes(&mut self, ns: &[Node<Sym>]) {
+    let mut after_expansion = false;
+    for n in ns {
+      match &n.kind {
+        NodeKind::Leaf(l) => {
+          // `@a b` isn't `@ab`.
+          if after_expansion && l.symbol.as_char().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            self.out.push(' ');
+          }
+          self.symbol(l.symbol);
+          if let Some(args) = &l.args {
+            self.out.push('(');
+            self.exprs(args);
+            self.out.push(')');
+          }
+        },
+        NodeKind::Branch(ns) => {
+          self.out.push('[');
+          self.nodes(ns);
+          self.out.push(']');
+        },
+        NodeKind::Expansion(e) => {
+          write!(self.out, "@{}", e.to).unwrap();
+          if let Some(args) = &e.args {
+            self.out.push('(');
+            self.exprs(args);
+            self.out.push(')');
+          }
+        },
+        NodeKind::Block(ss) => {
+          let inline = std::mem::replace(&mut self.inline, true);
+          self.block(0, |p| ss.iter().for_each(|s| p.stmt(s)));
+          self.inline = inline;
+        },
+      }
+      after_expansion = matches!(&n.kind, NodeKind::Expansion(e) if e.args.is_none());
+    }
+  }
+
+  /// Prints a symbol, separated from the previous one if together they would be read as something else (`//` is a
+  /// comment, `@a` an expansion). Names and characters that aren't symbols by themselves are quoted.
+  fn symbol(&mut self, s: Sym) {
+    if !s.is_bare() {
+      write!(self.out, "\"{}\"", s).unwrap();
+      return;
+    }
+    if self.out.chars().next_back().is_some_and(|prev| s.glues_to(prev)) {
+      self.out.push(' ');
+    }
+    write!(self.out, "{}", s).unwrap();
+  }
+}
+
+/// Precedence level of an expression, as in the grammar: 0 binds tightest.
+fn level(e: &Expr) -> u8 {
+  use ExprKind::*;
+  match &e.kind {
+    Plus(_) | Minus(_) | Not(_) | BitNot(_) => 1,
+    Pow(..) => 2,
+    Mul(..) | Div(..) | Mod(..) => 3,
+    Add(..) | Sub(..) => 4,
+    BitAnd(..) => 5,
+    BitXor(..) => 6,
+    BitOr(..) => 7,
+    LT(..) | LE(..) | GT(..) | GE(..) | EQ(..) | NE(..) | In(..) => 8,
+    And(..) => 9,
+    Or(..) => 10,
+    IfElse(..) | Lambda(..) => 11,
+    _ => 0,
+  }
+}
+
+/// Writes a string as an LSD literal, with quotes and escapes.
+pub fn quote(s: &str) -> String {
+  let mut res = String::with_capacity(s.len() + 2);
+  res.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => res.push_str("\\\""),
+      '\\' => res.push_str("\\\\"),
+      '\n' => res.push_str("\\n"),
+      '\t' => res.push_str("\\t"),
+      '\r' => res.push_str("\\r"),
+      '\0' => res.push_str("\\0"),
+      c => res.push(c),
+    }
+  }
+  res.push('"');
+  res
+}
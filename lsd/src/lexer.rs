@@ -1,37 +1,16 @@
-// use std::string::String;
-// use regex::Regex;
-
-// static tokens = vec![
-//   Token::new("int", r"[0-9]+"),
-//   Token::new("float", r"[0-9]*\.[0-9]+"),
-//   Token::new("string", r#"(?:[^\\]|\\["])*"#),
-//   Token::new("null", r"null"),
-//   Token::new("true", r"true"),
-//   Token::new("false", r"false"),
-//   Token::new("id", r"[a-zA-Z_][a-zA-Z0-9_]*"),
-//   Token::new("+", r"\+"),
-//   Token::new("-", r"-"),
-//   Token::new("*", r"\*"),
-//   Token::new("/", r"/"),
-//   Token::new("%", r"%"),
-//   Token::new("^", r"\^"),
-//   Token::new("(", r"\("),
-//   Token::new(")", r"\)"),
-// ];
-
-// struct TokenType {
-//   pub name: String,
-//   pub re: Regex,
-// }
-
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
+use crate::source::Span;
+use crate::symbol::Sym;
+
 /// The lexer's mode.
 ///
-/// This is shared between the lexer and the parser.
-#[derive(Debug)]
+/// This is shared between the lexer and the parser: the parser switches it when entering or leaving grammar-mode
+/// fragments (rules blocks, tables, axioms, word literals...), so the next token is read in the right mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LexerMode {
   /// Normal mode: statements and expressions.
   Normal,
@@ -39,27 +18,58 @@ pub enum LexerMode {
   Grammar,
 }
 
-// impl Default for LexerMode {
-//   fn default() -> Self {
-//     Self::Normal
-//   }
-// }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexicalError {
+  /// A string literal is not closed before the end of the line.
+  UnterminatedString(usize),
+  /// A block comment is not closed before the end of the input.
+  UnterminatedComment(usize),
+  /// Unknown escape sequence inside a string literal.
+  InvalidEscape(usize),
+  /// Integer literal that does not fit in an `i64`.
+  IntegerOverflow(usize),
+  /// Malformed float literal.
+  InvalidFloat(usize),
+  /// Rule weight that is not a finite, non-negative number.
+  InvalidWeight(usize),
+  /// Quoted symbol name that is empty, has spaces or is not closed: `"Apex`.
+  InvalidSymbol(usize),
+}
 
-// impl LexerMode {
-//   pub fn new() -> Self {
-//     Self::Normal
-//   }
-// }
+impl LexicalError {
+  /// Offset in the input where the error was found.
+  pub fn offset(&self) -> usize {
+    match *self {
+      Self::UnterminatedString(o) | Self::UnterminatedComment(o) | Self::InvalidEscape(o)
+      | Self::IntegerOverflow(o) | Self::InvalidFloat(o) | Self::InvalidWeight(o) | Self::InvalidSymbol(o) => o,
+    }
+  }
 
-#[derive(PartialEq, Eq, Debug)]
-pub enum LexicalError {
-  LengthOverflow(String),
-  TruncatedInput(String),
+  /// Moves the error's offset `base` bytes forward.
+  fn rebased(self, base: usize) -> Self {
+    match self {
+      Self::UnterminatedString(o) => Self::UnterminatedString(o + base),
+      Self::UnterminatedComment(o) => Self::UnterminatedComment(o + base),
+      Self::InvalidEscape(o) => Self::InvalidEscape(o + base),
+      Self::IntegerOverflow(o) => Self::IntegerOverflow(o + base),
+      Self::InvalidFloat(o) => Self::InvalidFloat(o + base),
+      Self::InvalidWeight(o) => Self::InvalidWeight(o + base),
+      Self::InvalidSymbol(o) => Self::InvalidSymbol(o + base),
+    }
+  }
 }
 
 impl fmt::Display for LexicalError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}", self)
+    match self {
+      Self::UnterminatedString(_) => write!(f, "unterminated string literal"),
+      Self::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+      Self::InvalidEscape(_) => write!(f, "invalid escape sequence in string literal"),
+      Self::IntegerOverflow(_) => write!(f, "integer number is too big"),
+      Self::InvalidFloat(_) => write!(f, "invalid float number"),
+      Self::InvalidWeight(_) => write!(f, "invalid rule weight"),
+      Self::InvalidSymbol(_) => write!(f, "invalid symbol name"),
+    }
   }
 }
 
@@ -71,51 +81,18 @@ pub type Spanned<Token, Loc, LexicalError> = Result<(Loc, Token, Loc), LexicalEr
 // position, the token itself, and the token's ending position.
 pub(crate) type LexerItem<Token, Loc, LexicalError> = Spanned<Token, Loc, LexicalError>;
 
-// #[derive(Debug, Clone, PartialEq)]
-// #[allow(non_camel_case_types)]
-// pub enum Token<'a> {
-//   COLON,
-//
-//   // Whitespace.
-//   SPACE,
-//   HTAB,
-//   VTAB,
-//   CR,
-//   LF,
-//   FORMFEED,
-//
-//   // Digits.
-//   N_0,
-//   N_1,
-//   N_2,
-//   N_3,
-//   N_4,
-//   N_5,
-//   N_6,
-//   N_7,
-//   N_8,
-//   N_9,
-//
-//   // Other.
-//   OTHER(&'a [u8]),
-//
-//   // Returned when the lexer is in literal mode.
-//   LITERAL(&'a [u8]),
-// }
-
-/// Normal-mode token types.
-#[derive(Debug, Clone, Eq)]
-// #[allow(non_camel_case_types)]
+/// Token types of both lexer modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
   // Identifiers
   Id,
-  AtId,
+  AtId,     // Normal + Rule modes
   Accessor,
   // LSysId, //$koch, @koch
 
   // Literal values
-  Int,
-  Float,
+  Int,      // Normal + Rule modes (rule weights)
+  Float,    // Normal + Rule modes (rule weights)
   String,
   Null,
   True,
@@ -147,7 +124,7 @@ pub enum TokenType {
 
   // Logic operators
   BitAnd,
-  BitOr,
+  BitOr,    // Normal + Rule modes (rule weights)
   BitXor,
   BitNot,
 
@@ -164,83 +141,50 @@ pub enum TokenType {
   Comma,
   Colon,    // Normal + Rule modes
   QM, //?
-  XM, //!
-  // At, //@
   Arrow,    // Normal + Rule modes
   DArrow,   // Normal + Rule modes
+  Backquote, // Normal + Rule modes
 
   // Separators
-  SemiColon,
-  NewLine,
+  SemiColon, // Normal + Rule modes
+  NewLine,   // Normal + Rule modes
 
   // Keywords
-  // And,
+  As,
   Axiom,
   Coding,
   // Do,
   Else,
   For,
-  // From,
+  From,
   Fn,
   If,
-  // Import,
+  Import,
   In,
   Inf,
   Let,
   Lsys,
   Main,
-  // Mut,
+  Mut,
   NaN,
-  // Not,
-  Null,
-  // Or,
   Production,
   Return,
   Rules,
   Set,
   Table,
-  // Then,
+  Then,
   While,
 
-//   Other,
-// }
-//
-// /// Grammar-mode token types.
-// #[derive(Debug, Clone, Eq)]
-// // #[allow(non_camel_case_types)]
-// pub enum GrammarToken {
-
+  // Grammar mode
   Symbol,
-  // AtID,
-  // Arrow,
-  // DArrow,
-  // Colon,
-  // Pipe,
-  // LAngle,
-  // RAngle,
-  // LParen,
-  // RParen,
-  // LBranch,
-  // RBranch,
-  // LBlock,
-  // RBlock,
-  //
-  // SemiColon,
-  // NewLine,
-  //
+
   Other,
 }
 
-// #[derive(Debug, Clone, Eq)]
-// pub enum TokenType {
-//   Normal(NormalToken),
-//   Rule(GrammarToken),
-// }
-
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token<'a> {
   pub ttype: TokenType,
-  pub bytes: &'a [u8],
+  pub text: &'a str,
 }
 
 impl fmt::Display for Token<'_> {
@@ -250,130 +194,429 @@ impl fmt::Display for Token<'_> {
 }
 
 impl<'a> Token<'a> {
-  pub fn new(ttype: TokenType, bytes: &'a [u8]) {
-    Token {ttype: ttype, bytes: bytes}
+  pub fn new(ttype: TokenType, text: &'a str) -> Self {
+    Token {ttype, text}
+  }
+
+  pub fn as_str(&self) -> &'a str {
+    self.text
   }
 
   pub fn as_bytes(&self) -> &'a [u8] {
-    self.bytes
+    self.text.as_bytes()
   }
 }
 
+/// Returns the keyword token type for a normal-mode identifier, if it is one.
+fn keyword(id: &str) -> Option<TokenType> {
+  Some(match id {
+    "and" => TokenType::And,
+    "as" => TokenType::As,
+    "axiom" => TokenType::Axiom,
+    "coding" => TokenType::Coding,
+    "else" => TokenType::Else,
+    "false" => TokenType::False,
+    "fn" => TokenType::Fn,
+    "for" => TokenType::For,
+    "from" => TokenType::From,
+    "if" => TokenType::If,
+    "import" => TokenType::Import,
+    "in" => TokenType::In,
+    "Inf" => TokenType::Inf,
+    "let" => TokenType::Let,
+    "lsys" => TokenType::Lsys,
+    "main" => TokenType::Main,
+    "mut" => TokenType::Mut,
+    "NaN" => TokenType::NaN,
+    "not" => TokenType::Not,
+    "null" => TokenType::Null,
+    "or" => TokenType::Or,
+    "production" => TokenType::Production,
+    "return" => TokenType::Return,
+    "rules" => TokenType::Rules,
+    "set" => TokenType::Set,
+    "table" => TokenType::Table,
+    "then" => TokenType::Then,
+    "true" => TokenType::True,
+    "while" => TokenType::While,
+    _ => return None,
+  })
+}
+
+fn is_id_start(c: u8) -> bool {c.is_ascii_alphabetic() || c == b'_'}
+fn is_id_char(c: u8) -> bool {c.is_ascii_alphanumeric() || c == b'_'}
+
+/// Removes the quotes of a string literal and resolves its escape sequences.
+///
+/// The literal must have been accepted by the lexer.
+pub fn unescape(literal: &str) -> Cow<'_, str> {
+  let inner = &literal[1..literal.len() - 1];
+  if !inner.contains('\\') {
+    return Cow::Borrowed(inner);
+  }
+  let mut res = String::with_capacity(inner.len());
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      res.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => res.push('\n'),
+      Some('t') => res.push('\t'),
+      Some('r') => res.push('\r'),
+      Some('0') => res.push('\0'),
+      Some('u') => {
+        let hex: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+        res.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+      },
+      Some(c) => res.push(c),
+      None => {},
+    }
+  }
+  Cow::Owned(res)
+}
+
+/// The symbol of a grammar-mode `Symbol` token: a character or a quoted name like `"Apex"`.
+///
+/// The token must have been accepted by the lexer.
+pub fn symbol(token: &str) -> Sym {
+  Sym::new(token.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(token))
+}
+
 #[derive(Debug)]
 pub struct Lexer<'input> {
-  input: &'input [u8],
+  input: &'input str,
 
   // Offset into the original input.
   offset: usize,
 
+  // Offset of the input in its `SourceMap`, added to every reported location.
+  base: usize,
+
+  // Brackets opened and not yet closed. Newlines are ignored while the innermost one is a parenthesis or a square
+  // bracket.
+  brackets: Vec<u8>,
+
+  // Type of the last token returned, used to tell rule weights from symbols in grammar mode.
+  last: Option<TokenType>,
+
+  // Comments skipped so far.
+  comments: Vec<Span>,
+
   // The lexer's mode.  This is behind a `Rc<RefCell>` so that it
   // is also accessible to the parser.
   pub mode: Rc<RefCell<LexerMode>>,
 }
 
 impl<'input> Lexer<'input> {
-  pub fn new(input: &'input [u8], mode: LexerMode) -> Self {
+  pub fn new(input: &'input str, mode: LexerMode) -> Self {
+    Self::with_base(input, mode, 0)
+  }
+
+  /// Creates a lexer whose locations start at `base`, the offset of the input in its `SourceMap`.
+  pub fn with_base(input: &'input str, mode: LexerMode, base: usize) -> Self {
     Lexer {
       input,
       offset: 0,
+      base,
+      brackets: Vec::new(),
+      last: None,
+      comments: Vec::new(),
       mode: Rc::new(RefCell::new(mode)),
     }
   }
 
-  fn normal_mode_next(&mut self) {
-    // TODO: Generar lexer
-    if self.input.len() < count {
-      return Some(Err(LexicalError::TruncatedInput(format!(
-        "Expected {} octets, got {}",
-        count,
-        self.input.len()
-      ))));
-    } else {
-      (count, LITERAL(&self.input[..count]))
+  /// Current offset into the input, `base` included.
+  pub fn offset(&self) -> usize {
+    self.base + self.offset
+  }
+
+  /// Spans of the comments skipped so far, in order. They are only complete once the whole input has been read.
+  pub fn comments(&self) -> &[Span] {
+    &self.comments
+  }
+
+  fn rest(&self) -> &'input [u8] {
+    &self.input.as_bytes()[self.offset..]
+  }
+
+  fn at(&self, i: usize) -> Option<u8> {
+    self.rest().get(i).copied()
+  }
+
+  /// Skips spaces and comments. Newlines are skipped too when inside parentheses or brackets.
+  fn skip_ignored(&mut self) -> Result<(), LexicalError> {
+    loop {
+      let skip_nl = matches!(self.brackets.last(), Some(b'(') | Some(b'['));
+      match (self.at(0), self.at(1)) {
+        (Some(b' ' | b'\t' | 0x0b | 0x0c), _) => self.offset += 1,
+        (Some(b'\n' | b'\r'), _) if skip_nl => self.offset += 1,
+        (Some(b'\\'), Some(b'\n')) if *self.mode.borrow() == LexerMode::Normal => self.offset += 2,
+        (Some(b'/'), Some(b'/')) => {
+          let len = self.rest().iter().position(|&c| c == b'\n' || c == b'\r').unwrap_or(self.rest().len());
+          self.comments.push(Span::new(self.offset(), self.offset() + len));
+          self.offset += len;
+        },
+        (Some(b'/'), Some(b'*')) => {
+          let start = self.offset();
+          self.skip_block_comment()?;
+          self.comments.push(Span::new(start, self.offset()));
+        },
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  /// Skips a block comment, which can be nested.
+  fn skip_block_comment(&mut self) -> Result<(), LexicalError> {
+    let start = self.offset;
+    let mut depth = 0;
+    loop {
+      match (self.at(0), self.at(1)) {
+        (Some(b'/'), Some(b'*')) => {depth += 1; self.offset += 2;},
+        (Some(b'*'), Some(b'/')) => {
+          depth -= 1;
+          self.offset += 2;
+          if depth == 0 {
+            return Ok(());
+          }
+        },
+        (Some(_), _) => self.offset += 1,
+        (None, _) => return Err(LexicalError::UnterminatedComment(start)),
+      }
+    }
+  }
+
+  /// Length of the identifier at the beginning of the remaining input.
+  fn id_len(&self, from: usize) -> usize {
+    from + self.rest()[from..].iter().take_while(|&&c| is_id_char(c)).count()
+  }
+
+  /// Like `id_len`, but also takes qualified names like `lv.leaf`.
+  fn path_len(&self, from: usize) -> usize {
+    let rest = self.rest();
+    let mut len = self.id_len(from);
+    while rest.get(len) == Some(&b'.') && rest.get(len + 1).copied().is_some_and(is_id_start) {
+      len = self.id_len(len + 1);
+    }
+    len
+  }
+
+  /// Length and type of the number at the beginning of the remaining input.
+  fn number(&self) -> Result<(usize, TokenType), LexicalError> {
+    let rest = self.rest();
+    let digits = |from: usize| from + rest[from..].iter().take_while(|c| c.is_ascii_digit()).count();
+    let mut len = digits(0);
+    let mut ttype = TokenType::Int;
+    if rest.get(len) == Some(&b'.') && rest.get(len + 1).is_some_and(u8::is_ascii_digit) {
+      len = digits(len + 1);
+      ttype = TokenType::Float;
+    }
+    if let Some(b'e' | b'E') = rest.get(len) {
+      let sign = matches!(rest.get(len + 1), Some(b'+' | b'-')) as usize;
+      if rest.get(len + 1 + sign).is_some_and(u8::is_ascii_digit) {
+        len = digits(len + 1 + sign);
+        ttype = TokenType::Float;
+      } else {
+        return Err(LexicalError::InvalidFloat(self.offset));
+      }
     }
+    if rest.get(len).is_some_and(|&c| is_id_start(c)) {
+      return Err(LexicalError::InvalidFloat(self.offset));
+    }
+    Ok((len, ttype))
   }
 
-  fn symbol_next(&mut self) {
-    // TODO: Generar lexer
-    match *self.input.first()? as char {
-      ':' => (1, COLON),
-
-      // Whitespace.
-      ' ' => (1, SPACE),
-      '\t' => (1, HTAB),
-      '\u{0b}' => (1, VTAB),
-      '\u{0d}' => (1, CR),
-      '\u{0a}' => (1, LF),
-      '\u{0c}' => (1, FORMFEED),
-
-      // Digits.
-      '0' => (1, N_0),
-      '1' => (1, N_1),
-      '2' => (1, N_2),
-      '3' => (1, N_3),
-      '4' => (1, N_4),
-      '5' => (1, N_5),
-      '6' => (1, N_6),
-      '7' => (1, N_7),
-      '8' => (1, N_8),
-      '9' => (1, N_9),
-
-      // Other.
-      _ => (1, OTHER(&self.input[..1])),
+  /// Length of the string literal at the beginning of the remaining input, quotes included.
+  fn string(&self) -> Result<usize, LexicalError> {
+    let rest = self.rest();
+    let mut i = 1;
+    loop {
+      match rest.get(i) {
+        Some(b'"') => return Ok(i + 1),
+        Some(b'\\') => {
+          match rest.get(i + 1) {
+            Some(b'\\' | b'"' | b'\'' | b'n' | b't' | b'r' | b'0') => i += 2,
+            Some(b'u') if rest.get(i + 2) == Some(&b'{') => {
+              let hex = rest[i + 3..].iter().take_while(|c| c.is_ascii_hexdigit()).count();
+              let code = std::str::from_utf8(&rest[i + 3..i + 3 + hex]).ok()
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .and_then(char::from_u32);
+              if hex == 0 || rest.get(i + 3 + hex) != Some(&b'}') || code.is_none() {
+                return Err(LexicalError::InvalidEscape(self.offset + i));
+              }
+              i += 4 + hex;
+            },
+            _ => return Err(LexicalError::InvalidEscape(self.offset + i)),
+          }
+        },
+        Some(b'\n' | b'\r') | None => return Err(LexicalError::UnterminatedString(self.offset)),
+        Some(_) => i += 1,
+      }
     }
   }
+
+  /// Tokens that are read the same way in both modes.
+  fn common_next(&self) -> Option<(usize, TokenType)> {
+    let rest = self.rest();
+    Some(match (rest[0], rest.get(1).copied()) {
+      (b'\r', Some(b'\n')) => (2, TokenType::NewLine),
+      (b'\n' | b'\r', _) => (1, TokenType::NewLine),
+      (b';', _) => (1, TokenType::SemiColon),
+      (b'-', Some(b'>')) => (2, TokenType::Arrow),
+      (b'=', Some(b'>')) => (2, TokenType::DArrow),
+      (b'(', _) => (1, TokenType::LParen),
+      (b')', _) => (1, TokenType::RParen),
+      (b'[', _) => (1, TokenType::LBracket),
+      (b']', _) => (1, TokenType::RBracket),
+      (b'{', _) => (1, TokenType::LBrace),
+      (b'}', _) => (1, TokenType::RBrace),
+      (b':', _) => (1, TokenType::Colon),
+      (b'`', _) => (1, TokenType::Backquote),
+      (b'@', Some(c)) if is_id_start(c) => (self.path_len(1), TokenType::AtId),
+      _ => return None,
+    })
+  }
+
+  fn normal_mode_next(&self) -> Result<(usize, TokenType), LexicalError> {
+    if let Some(res) = self.common_next() {
+      return Ok(res);
+    }
+    let rest = self.rest();
+    let two = rest.get(1).copied();
+    Ok(match (rest[0], two) {
+      (c, _) if is_id_start(c) => {
+        let len = self.id_len(0);
+        (len, keyword(&self.input[self.offset..self.offset + len]).unwrap_or(TokenType::Id))
+      },
+      (c, _) if c.is_ascii_digit() => self.number()?,
+      (b'.', Some(c)) if c.is_ascii_digit() => self.number()?,
+      (b'.', Some(c)) if is_id_start(c) => (self.id_len(1), TokenType::Accessor),
+      (b'.', _) => (1, TokenType::Dot),
+      (b'"', _) => (self.string()?, TokenType::String),
+      (b'*', Some(b'*')) => (2, TokenType::Pow),
+      (b'=', Some(b'=')) => (2, TokenType::EQ),
+      (b'!', Some(b'=')) => (2, TokenType::NE),
+      (b'<', Some(b'=')) => (2, TokenType::LE),
+      (b'>', Some(b'=')) => (2, TokenType::GE),
+      (b'&', Some(b'&')) => (2, TokenType::And),
+      (b'|', Some(b'|')) => (2, TokenType::Or),
+      (b'+', _) => (1, TokenType::Add),
+      (b'-', _) => (1, TokenType::Sub),
+      (b'*', _) => (1, TokenType::Mul),
+      (b'/', _) => (1, TokenType::Div),
+      (b'%', _) => (1, TokenType::Mod),
+      (b'=', _) => (1, TokenType::Assign),
+      (b'<', _) => (1, TokenType::LT),
+      (b'>', _) => (1, TokenType::GT),
+      (b'!', _) => (1, TokenType::Not),
+      (b'&', _) => (1, TokenType::BitAnd),
+      (b'|', _) => (1, TokenType::BitOr),
+      (b'^', _) => (1, TokenType::BitXor),
+      (b'~', _) => (1, TokenType::BitNot),
+      (b',', _) => (1, TokenType::Comma),
+      (b'?', _) => (1, TokenType::QM),
+      _ => (self.char_len(), TokenType::Other),
+    })
+  }
+
+  fn symbol_next(&self) -> Result<(usize, TokenType), LexicalError> {
+    if let Some(res) = self.common_next() {
+      return Ok(res);
+    }
+    let rest = self.rest();
+    Ok(match rest[0] {
+      b'<' => (1, TokenType::LT),
+      b'>' => (1, TokenType::GT),
+      // A number followed by a bar at the start of a rule is its weight: `0.5 | F -> F+F`. Otherwise digits are
+      // symbols.
+      c if (c.is_ascii_digit() || c == b'.') && self.weight_len().is_some() => {
+        let (len, ttype) = self.number().map_err(|_| LexicalError::InvalidWeight(self.offset))?;
+        if Some(len) != self.weight_len() {
+          return Err(LexicalError::InvalidWeight(self.offset));
+        }
+        (len, ttype)
+      },
+      b'|' if matches!(self.last, Some(TokenType::Int | TokenType::Float)) => (1, TokenType::BitOr),
+      b'"' => (self.quoted_symbol()?, TokenType::Symbol),
+      _ => (self.char_len(), TokenType::Symbol),
+    })
+  }
+
+  /// If the remaining input starts with a rule weight, returns the length of its number.
+  fn weight_len(&self) -> Option<usize> {
+    if !matches!(self.last, None | Some(TokenType::NewLine | TokenType::SemiColon | TokenType::LBrace)) {
+      return None;
+    }
+    let rest = self.rest();
+    let len = rest.iter().take_while(|&&c| c.is_ascii_alphanumeric() || c == b'.').count();
+    let spaces = rest[len..].iter().take_while(|&&c| c == b' ' || c == b'\t').count();
+    (rest.get(len + spaces) == Some(&b'|')).then_some(len)
+  }
+
+  /// Length of the quoted symbol name at the beginning of the remaining input, quotes included.
+  fn quoted_symbol(&self) -> Result<usize, LexicalError> {
+    let rest = &self.input[self.offset + 1..];
+    let len = rest.find(|c: char| c == '"' || c.is_whitespace()).unwrap_or(rest.len());
+    if len == 0 || !rest[len..].starts_with('"') {
+      return Err(LexicalError::InvalidSymbol(self.offset));
+    }
+    Ok(len + 2)
+  }
+
+  /// Length in bytes of the character at the beginning of the remaining input.
+  fn char_len(&self) -> usize {
+    self.input[self.offset..].chars().next().map_or(1, char::len_utf8)
+  }
 }
 
 impl<'input> Iterator for Lexer<'input> {
   type Item = LexerItem<Token<'input>, usize, LexicalError>;
 
   fn next(&mut self) -> Option<Self::Item> {
-    use self::Token::*;
-
-    // let limit = self.input.len();
-    // let mut cursor: usize = 0;
-    // let mut marker: usize;
-    // let mut ctxmarker: usize;
-    let mut mode = self.mode.borrow_mut();
-    let yyinput = self.input;
-    let mut yycursor = 0usize;
-    let tokenType = // re2rust $INPUT -o $OUTPUT --no-unsafe --api simple
-      /*!re2c
-         re2c:YYCTYPE = "u8";
-
-         [ \t]+ {}
-         "//" .* {}
-         "/*" .* "*/" {}
-         [\n\r] {return Some(TokenType::NewLine)}
-         [a-zA-Z_] [a-zA-Z_0-9]* {return Some(TokenType::Id)}
-         [0-9] [0-9]* {return Some(TokenType::Int)}
-         * {return None}
-       */
-
-    // TODO: Generar cualquier token que no empiece por: [a-zA-Z0-9+-:=/&%#·, caracteres de escape y quizá alguna cosa más].
-    // Ignorar espacios y comentarios.
-    let (len, token) = match mode {
-
-      LexerMode::Normal => {
-        self::normal_mode_next()
+    if let Err(err) = self.skip_ignored() {
+      self.offset = self.input.len();
+      return Some(Err(err.rebased(self.base)));
+    }
+    if self.offset >= self.input.len() {
+      return None;
+    }
+
+    let mode = *self.mode.borrow();
+    let res = match mode {
+      LexerMode::Normal => self.normal_mode_next(),
+      LexerMode::Grammar => self.symbol_next(),
+    };
+    let (len, ttype) = match res {
+      Ok(res) => res,
+      Err(err) => {
+        // Skip the rest of the line so lexing can go on after the error.
+        let len = self.rest().iter().position(|&c| c == b'\n' || c == b'\r').unwrap_or(self.rest().len());
+        self.offset += len.max(1);
+        return Some(Err(err.rebased(self.base)));
       },
-      LexerMode::Grammar => {
-        self::symbol_next()
-      }
     };
 
-    self.input = &self.input[len..];
+    match ttype {
+      TokenType::LParen => self.brackets.push(b'('),
+      TokenType::LBracket => self.brackets.push(b'['),
+      TokenType::LBrace => self.brackets.push(b'{'),
+      TokenType::RParen | TokenType::RBracket | TokenType::RBrace => {self.brackets.pop();},
+      _ => {},
+    }
 
     let start = self.offset;
     let end = start + len;
-    self.offset += len;
+    self.offset = end;
+    self.last = Some(ttype);
 
-    Some(Ok((start, token, end)))
+    Some(Ok((self.base + start, Token::new(ttype, &self.input[start..end]), self.base + end)))
   }
 }
 
-impl<'input> From<&'input [u8]> for Lexer<'input> {
-  fn from(i: &'input [u8]) -> Lexer<'input> {
-    Lexer::new(i)
+impl<'input> From<&'input str> for Lexer<'input> {
+  fn from(i: &'input str) -> Lexer<'input> {
+    Lexer::new(i, LexerMode::Normal)
   }
 }
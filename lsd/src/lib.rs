@@ -1,43 +1,98 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::vec::Vec;
 
 pub mod lexer;
 pub mod ast;
+pub mod source;
+pub mod symbol;
+pub mod visit;
+/// Conversion of L systems written in other dialects to LSD.
+pub mod convert;
+/// Canonical formatting of LSD source.
+mod format;
+/// `into_owned()` conversions of ASTs into `'static` ones, which can outlive their source.
+mod owned;
 
 #[cfg(test)]
 mod test;
 
-use lalrpop_util::lalrpop_mod;
+use lalrpop_util::{lalrpop_mod, ErrorRecovery};
 
-lalrpop_mod!(grammar);
-use grammar::LsdModuleParser;
-use grammar::LsdExprParser;
-use grammar::LsdWordParser;
-use grammar::LsdRulesParser;
+use lexer::{Lexer, LexerMode, LexicalError, Token};
+use source::SourceFile;
+use ast::normal::{Module, Expr};
+use ast::grammar::{Word, Rule};
 
-pub fn parse_lsd_module<'input>(&'input input) -> Option<Module<'input>> {
-  let lexer = Lexer::new(input, LexerMode::Normal);
+pub use format::{format_expr, format_lsd, quote};
+pub use symbol::{Sym, Symbol};
+
+/// Error returned by the LSD parsers. Lexical errors are wrapped in `ParseError::User`.
+pub type ParseError<'a> = lalrpop_util::ParseError<usize, Token<'a>, LexicalError>;
+
+lalrpop_mod!(#[allow(unused_imports, clippy::all)] parser);
+use parser::LsdModuleParser;
+use parser::LsdExprParser;
+use parser::LsdWordParser;
+use parser::LsdRulesParser;
+
+/// Runs a parser, returning what it parsed (if it could get to the end) and every syntax error found.
+fn parse_recovering<'a, T>(
+  lexer: &mut Lexer<'a>,
+  parse: impl FnOnce(&Rc<RefCell<LexerMode>>, &mut Vec<ErrorRecovery<usize, Token<'a>, LexicalError>>, &mut Lexer<'a>) -> Result<T, ParseError<'a>>,
+) -> (Option<T>, Vec<ParseError<'a>>) {
   let mode = Rc::clone(&lexer.mode);
+  let mut recovered = Vec::new();
+  let res = parse(&mode, &mut recovered, lexer);
 
-  return LsdModuleParser::new().parse(&mode, lexer); //.unwrap();
+  let mut errors: Vec<ParseError> = recovered.into_iter().map(|r| r.error).collect();
+  let ast = res.map_err(|e| errors.push(e)).ok();
+  (ast, errors)
 }
 
-pub fn parse_expr<'input>(&'input input) -> Option<Expr<'input>> {
-  let lexer = Lexer::new(input, LexerMode::Normal);
-  let mode = Rc::clone(&lexer.mode);
+/// Like `parse_recovering`, but fails if there is any syntax error.
+fn parse_strict<'a, T>(
+  lexer: &mut Lexer<'a>,
+  parse: impl FnOnce(&Rc<RefCell<LexerMode>>, &mut Vec<ErrorRecovery<usize, Token<'a>, LexicalError>>, &mut Lexer<'a>) -> Result<T, ParseError<'a>>,
+) -> Result<T, Vec<ParseError<'a>>> {
+  match parse_recovering(lexer, parse) {
+    (Some(ast), errors) if errors.is_empty() => Ok(ast),
+    (_, errors) => Err(errors),
+  }
+}
 
-  return LsdExprParser::new().parse(&mode, lexer); //.unwrap();
+/// Parses a module, going on after syntax errors.
+///
+/// Returns the (maybe partial) module, which is `None` only if the parser couldn't recover from an error, and every
+/// syntax error found.
+pub fn parse_lsd_module(input: &str) -> (Option<Module<'_>>, Vec<ParseError<'_>>) {
+  parse_recovering(&mut Lexer::new(input, LexerMode::Normal), |mode, errors, lexer| {
+    LsdModuleParser::new().parse(mode, errors, lexer)
+  })
 }
 
-pub fn parse_word<'input>(&'input input) -> Option<Vec<Node<'input>>> {
-  let lexer = Lexer::new(input, LexerMode::Rule);
-  let mode = Rc::clone(&lexer.mode);
+/// Parses a file registered in a `SourceMap`, like `parse_lsd_module`. Spans in the result are global offsets of the
+/// map.
+pub fn parse_lsd_file(file: &SourceFile) -> (Option<Module<'_>>, Vec<ParseError<'_>>) {
+  parse_recovering(&mut Lexer::with_base(&file.src, LexerMode::Normal, file.start), |mode, errors, lexer| {
+    LsdModuleParser::new().parse(mode, errors, lexer)
+  })
+}
 
-  return LsdWordParser::new().parse(&mode, lexer); //.unwrap();
+pub fn parse_expr(input: &str) -> Result<Expr<'_>, Vec<ParseError<'_>>> {
+  parse_strict(&mut Lexer::new(input, LexerMode::Normal), |mode, errors, lexer| {
+    LsdExprParser::new().parse(mode, errors, lexer)
+  })
 }
 
-pub fn parse_rules<'input>(&'input input) -> Option<Vec<Rule<'input>>> {
-  let lexer = Lexer::new(input, LexerMode::Rule);
-  let mode = Rc::clone(&lexer.mode);
+pub fn parse_word(input: &str) -> Result<Word<'_, Sym>, Vec<ParseError<'_>>> {
+  parse_strict(&mut Lexer::new(input, LexerMode::Grammar), |mode, errors, lexer| {
+    LsdWordParser::new().parse(mode, errors, lexer)
+  })
+}
 
-  return LsdRulesParser::new().parse(&mode, lexer); //.unwrap();
+pub fn parse_rules(input: &str) -> Result<Vec<Rule<'_, Sym>>, Vec<ParseError<'_>>> {
+  parse_strict(&mut Lexer::new(input, LexerMode::Grammar), |mode, errors, lexer| {
+    LsdRulesParser::new().parse(mode, errors, lexer)
+  })
 }
@@ -0,0 +1,257 @@
+use std::borrow::Cow;
+use std::vec::Vec;
+
+use crate::ast::normal::*;
+use crate::ast::grammar::*;
+
+fn own(s: Cow<'_, str>) -> Cow<'static, str> {
+  Cow::Owned(s.into_owned())
+}
+
+fn own_all<T, U>(v: Vec<T>, f: impl FnMut(T) -> U) -> Vec<U> {
+  v.into_iter().map(f).collect()
+}
+
+fn own_box(e: Expr<'_>) -> Box<Expr<'static>> {
+  Box::new(e.into_owned())
+}
+
+fn own_args(args: Option<Vec<Expr<'_>>>) -> Option<Vec<Expr<'static>>> {
+  args.map(|args| own_all(args, Expr::into_owned))
+}
+
+// Normal mode:
+
+impl Module<'_> {
+  pub fn into_owned(self) -> Module<'static> {
+    Module {
+      name: self.name.map(own),
+      path: self.path.map(own),
+      stmts: own_all(self.stmts, ModStmt::into_owned),
+    }
+  }
+}
+
+impl Expr<'_> {
+  pub fn into_owned(self) -> Expr<'static> {
+    Expr {kind: self.kind.into_owned(), span: self.span}
+  }
+}
+
+impl ExprKind<'_> {
+  pub fn into_owned(self) -> ExprKind<'static> {
+    use ExprKind::*;
+    match self {
+      Int(i) => Int(i),
+      Float(f) => Float(f),
+      String(s) => String(own(s)),
+      List(es) => List(own_all(es, Expr::into_owned)),
+      Bool(b) => Bool(b),
+      Null => Null,
+      ID(id) => ID(own(id)),
+      PropAcc(e, prop) => PropAcc(own_box(*e), own(prop)),
+      FnCall(f, args) => FnCall(own_box(*f), own_all(args, Expr::into_owned)),
+      IndexExpr(e, i) => IndexExpr(own_box(*e), own_box(*i)),
+      Plus(e) => Plus(own_box(*e)),
+      Minus(e) => Minus(own_box(*e)),
+      Not(e) => Not(own_box(*e)),
+      BitNot(e) => BitNot(own_box(*e)),
+      Pow(l, r) => Pow(own_box(*l), own_box(*r)),
+      Mul(l, r) => Mul(own_box(*l), own_box(*r)),
+      Div(l, r) => Div(own_box(*l), own_box(*r)),
+      Mod(l, r) => Mod(own_box(*l), own_box(*r)),
+      Add(l, r) => Add(own_box(*l), own_box(*r)),
+      Sub(l, r) => Sub(own_box(*l), own_box(*r)),
+      LT(l, r) => LT(own_box(*l), own_box(*r)),
+      LE(l, r) => LE(own_box(*l), own_box(*r)),
+      GT(l, r) => GT(own_box(*l), own_box(*r)),
+      GE(l, r) => GE(own_box(*l), own_box(*r)),
+      EQ(l, r) => EQ(own_box(*l), own_box(*r)),
+      NE(l, r) => NE(own_box(*l), own_box(*r)),
+      BitAnd(l, r) => BitAnd(own_box(*l), own_box(*r)),
+      BitXor(l, r) => BitXor(own_box(*l), own_box(*r)),
+      BitOr(l, r) => BitOr(own_box(*l), own_box(*r)),
+      And(l, r) => And(own_box(*l), own_box(*r)),
+      Or(l, r) => Or(own_box(*l), own_box(*r)),
+      IfElse(c, t, e) => IfElse(own_box(*c), own_box(*t), own_box(*e)),
+      In(l, r, b) => In(own_box(*l), own_box(*r), b),
+      Lambda(ps, e) => Lambda(own_all(ps, Param::into_owned), own_box(*e)),
+      Word(w) => Word(w.into_owned()),
+    }
+  }
+}
+
+impl ModStmt<'_> {
+  pub fn into_owned(self) -> ModStmt<'static> {
+    match self {
+      ModStmt::Import(i) => ModStmt::Import(i.into_owned()),
+      ModStmt::VarDecl(d) => ModStmt::VarDecl(d.into_owned()),
+      ModStmt::FnDef(d) => ModStmt::FnDef(d.into_owned()),
+      ModStmt::LSysDef(d) => ModStmt::LSysDef(d.into_owned()),
+    }
+  }
+}
+
+impl LSysStmt<'_> {
+  pub fn into_owned(self) -> LSysStmt<'static> {
+    use LSysStmt::*;
+    match self {
+      Stmt(s) => Stmt(s.into_owned()),
+      Import(i) => Import(i.into_owned()),
+      SetDef(name, e) => SetDef(own(name), e.into_owned()),
+      AxiomDef(w) => AxiomDef(w.into_owned()),
+      TableDef(t) => TableDef(t.into_owned()),
+      RulesDef(rs) => RulesDef(own_all(rs, Rule::into_owned)),
+      ProductionRulesDef(rs) => ProductionRulesDef(own_all(rs, Rule::into_owned)),
+      CodingRulesDef(rs) => CodingRulesDef(own_all(rs, Rule::into_owned)),
+    }
+  }
+}
+
+impl Stmt<'_> {
+  pub fn into_owned(self) -> Stmt<'static> {
+    Stmt {kind: self.kind.into_owned(), span: self.span}
+  }
+}
+
+impl StmtKind<'_> {
+  pub fn into_owned(self) -> StmtKind<'static> {
+    use StmtKind::*;
+    let own_stmt = |s: Box<Stmt<'_>>| Box::new(s.into_owned());
+    match self {
+      Expr(e) => Expr(e.into_owned()),
+      Assign(name, e) => Assign(own(name), e.into_owned()),
+      VarDecl(d) => VarDecl(d.into_owned()),
+      FnDef(d) => FnDef(d.into_owned()),
+      LSysDef(d) => LSysDef(d.into_owned()),
+      If(c, t, e) => If(c.into_owned(), own_stmt(t), e.map(own_stmt)),
+      For(var, e, s) => For(own(var), e.into_owned(), own_stmt(s)),
+      While(c, s) => While(c.into_owned(), own_stmt(s)),
+      Return(e) => Return(e.map(|e| e.into_owned())),
+      Block(ss) => Block(own_all(ss, Stmt::into_owned)),
+    }
+  }
+}
+
+impl VarDecl<'_> {
+  pub fn into_owned(self) -> VarDecl<'static> {
+    VarDecl {name: own(self.name), mutable: self.mutable, value: self.value.map(Expr::into_owned), span: self.span}
+  }
+}
+
+impl FnDef<'_> {
+  pub fn into_owned(self) -> FnDef<'static> {
+    FnDef {
+      name: own(self.name),
+      params: own_all(self.params, Param::into_owned),
+      stmts: own_all(self.stmts, Stmt::into_owned),
+      span: self.span,
+    }
+  }
+}
+
+impl LSysDef<'_> {
+  pub fn into_owned(self) -> LSysDef<'static> {
+    LSysDef {
+      main: self.main,
+      name: self.name.map(own),
+      params: own_all(self.params, Param::into_owned),
+      stmts: own_all(self.stmts, LSysStmt::into_owned),
+      span: self.span,
+    }
+  }
+}
+
+impl Param<'_> {
+  pub fn into_owned(self) -> Param<'static> {
+    Param {name: own(self.name), default_value: self.default_value.map(Expr::into_owned), span: self.span}
+  }
+}
+
+impl ImportStmt<'_> {
+  pub fn into_owned(self) -> ImportStmt<'static> {
+    ImportStmt {
+      module: own(self.module),
+      alias: self.alias.map(own),
+      symbols: own_all(self.symbols, own),
+      span: self.span,
+    }
+  }
+}
+
+// Grammar mode:
+
+impl<C> RulesTable<'_, C> {
+  pub fn into_owned(self) -> RulesTable<'static, C> {
+    RulesTable {name: self.name.map(own), rules: own_all(self.rules, Rule::into_owned), span: self.span}
+  }
+}
+
+impl<C> Word<'_, C> {
+  pub fn into_owned(self) -> Word<'static, C> {
+    Word(own_all(self.0, Node::into_owned))
+  }
+}
+
+impl<C> Rule<'_, C> {
+  pub fn into_owned(self) -> Rule<'static, C> {
+    match self {
+      Rule::Production(base) => Rule::Production(base.into_owned()),
+      Rule::Coding(base) => Rule::Coding(base.into_owned()),
+    }
+  }
+}
+
+impl<C> RuleBase<'_, C> {
+  pub fn into_owned(self) -> RuleBase<'static, C> {
+    RuleBase {
+      weight: self.weight,
+      left_leaf: self.left_leaf.into_owned(),
+      condition: self.condition.map(Expr::into_owned),
+      l_ctx: own_all(self.l_ctx, CtxNode::into_owned),
+      r_ctx: own_all(self.r_ctx, CtxNode::into_owned),
+      right_side: self.right_side.into_owned(),
+      span: self.span,
+    }
+  }
+}
+
+impl<C> Node<'_, C> {
+  pub fn into_owned(self) -> Node<'static, C> {
+    let kind = match self.kind {
+      NodeKind::Leaf(leaf) => NodeKind::Leaf(leaf.into_owned()),
+      NodeKind::Branch(nodes) => NodeKind::Branch(own_all(nodes, Node::into_owned)),
+      NodeKind::Expansion(exp) => NodeKind::Expansion(exp.into_owned()),
+      NodeKind::Block(stmts) => NodeKind::Block(own_all(stmts, Stmt::into_owned)),
+    };
+    Node {kind, span: self.span}
+  }
+}
+
+impl<C> CtxNode<'_, C> {
+  pub fn into_owned(self) -> CtxNode<'static, C> {
+    let kind = match self.kind {
+      CtxNodeKind::Leaf(leaf) => CtxNodeKind::Leaf(leaf.into_owned()),
+      CtxNodeKind::Branch(nodes) => CtxNodeKind::Branch(own_all(nodes, CtxNode::into_owned)),
+    };
+    CtxNode {kind, span: self.span}
+  }
+}
+
+impl<C> LeftLeaf<'_, C> {
+  pub fn into_owned(self) -> LeftLeaf<'static, C> {
+    LeftLeaf {symbol: self.symbol, params: self.params.map(|ps| own_all(ps, own)), span: self.span}
+  }
+}
+
+impl<C> Leaf<'_, C> {
+  pub fn into_owned(self) -> Leaf<'static, C> {
+    Leaf {symbol: self.symbol, args: own_args(self.args), span: self.span}
+  }
+}
+
+impl Expansion<'_> {
+  pub fn into_owned(self) -> Expansion<'static> {
+    Expansion {to: own(self.to), args: own_args(self.args), span: self.span}
+  }
+}
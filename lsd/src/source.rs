@@ -0,0 +1,148 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+/// A range of bytes in the source code.
+///
+/// Offsets are global to a `SourceMap`: every file added to it takes a different range of offsets, so a span alone is
+/// enough to know which file it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
+impl Span {
+  pub fn new(start: usize, end: usize) -> Self {
+    Span {start, end}
+  }
+
+  /// Returns the smallest span covering both `self` and `other`.
+  pub fn to(self, other: Span) -> Span {
+    Span {start: self.start.min(other.start), end: self.end.max(other.end)}
+  }
+
+  pub fn len(&self) -> usize {
+    self.end - self.start
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.start == self.end
+  }
+
+  pub fn contains(&self, offset: usize) -> bool {
+    self.start <= offset && offset <= self.end
+  }
+}
+
+impl fmt::Display for Span {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}..{}", self.start, self.end)
+  }
+}
+
+/// A line and column in a file, both starting at 1. Columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineCol {
+  pub line: usize,
+  pub col: usize,
+}
+
+impl fmt::Display for LineCol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.line, self.col)
+  }
+}
+
+/// A source file registered in a `SourceMap`.
+#[derive(Debug)]
+pub struct SourceFile {
+  pub path: PathBuf,
+  pub src: String,
+  /// Global offset of the first byte of this file.
+  pub start: usize,
+  /// Local offsets where each line starts.
+  line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+  fn new(path: PathBuf, src: String, start: usize) -> Self {
+    let line_starts = std::iter::once(0)
+      .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+      .collect();
+    SourceFile {path, src, start, line_starts}
+  }
+
+  /// Global offset right after the last byte of this file.
+  pub fn end(&self) -> usize {
+    self.start + self.src.len()
+  }
+
+  /// Line and column of a global offset inside this file.
+  pub fn line_col(&self, offset: usize) -> LineCol {
+    let local = offset.saturating_sub(self.start).min(self.src.len());
+    let line = self.line_starts.partition_point(|&start| start <= local) - 1;
+    let line_start = self.line_starts[line];
+    let col = self.src.get(line_start..local).map_or(local - line_start, |s| s.chars().count());
+    LineCol {line: line + 1, col: col + 1}
+  }
+
+  /// Text of a line (starting at 1), without its line break.
+  pub fn line(&self, line: usize) -> Option<&str> {
+    let start = *self.line_starts.get(line.checked_sub(1)?)?;
+    let end = self.line_starts.get(line).map_or(self.src.len(), |&end| end);
+    Some(self.src[start..end].trim_end_matches(['\n', '\r']))
+  }
+
+  /// Text covered by a span of this file.
+  pub fn snippet(&self, span: Span) -> Option<&str> {
+    self.src.get(span.start.checked_sub(self.start)?..span.end.checked_sub(self.start)?)
+  }
+}
+
+/// Keeps every parsed file so spans can be mapped back to their file, line and column.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+  files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+  pub fn new() -> Self {
+    SourceMap {files: Vec::new()}
+  }
+
+  /// Adds a file and returns it. Its global offsets start right after the previous file's ones.
+  pub fn add_file(&mut self, path: impl AsRef<Path>, src: String) -> &SourceFile {
+    // Leave a one-byte gap so the end of a file is not the start of the next one.
+    let start = self.files.last().map_or(0, |f| f.end() + 1);
+    self.files.push(SourceFile::new(path.as_ref().to_path_buf(), src, start));
+    self.files.last().unwrap()
+  }
+
+  pub fn files(&self) -> &[SourceFile] {
+    &self.files
+  }
+
+  /// File a global offset belongs to.
+  pub fn file_at(&self, offset: usize) -> Option<&SourceFile> {
+    let i = self.files.partition_point(|f| f.start <= offset).checked_sub(1)?;
+    let file = &self.files[i];
+    (offset <= file.end()).then_some(file)
+  }
+
+  /// File, line and column of a global offset.
+  pub fn lookup(&self, offset: usize) -> Option<(&Path, LineCol)> {
+    let file = self.file_at(offset)?;
+    Some((file.path.as_path(), file.line_col(offset)))
+  }
+
+  /// Formats the start of a span as `path:line:col`.
+  pub fn describe(&self, span: Span) -> String {
+    match self.lookup(span.start) {
+      Some((path, lc)) => format!("{}:{}", path.display(), lc),
+      None => format!("<unknown>:{}", span),
+    }
+  }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::vec::Vec;
+
+/// A grammar-mode symbol: a single character like `F` or `·`, or a name like `Apex` or `Internode'`, written
+/// `"Apex"` in LSD source.
+///
+/// Symbols are interned, so they are `Copy` and compare as integers. Single characters are stored as themselves and
+/// never touch the interner.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Sym(u32);
+
+/// Names interned so far. A name's symbol is `FIRST_NAME` plus its index here.
+#[derive(Default)]
+struct Interner {
+  names: Vec<&'static str>,
+  ids: HashMap<&'static str, u32>,
+}
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(Default::default);
+
+/// Past the last Unicode scalar value.
+const FIRST_NAME: u32 = 0x11_0000;
+
+impl Sym {
+  /// Interns a symbol. A name of a single character gives the same symbol as the character.
+  ///
+  /// # Panics
+  ///
+  /// If `name` is empty.
+  pub fn new(name: &str) -> Self {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+      (None, _) => panic!("empty symbol name"),
+      (Some(c), None) => Sym::from(c),
+      _ => {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(&id) = interner.ids.get(name) {
+          return Sym(id);
+        }
+        // Interned names live as long as the program.
+        let name: &'static str = Box::leak(name.into());
+        let id = FIRST_NAME + interner.names.len() as u32;
+        interner.names.push(name);
+        interner.ids.insert(name, id);
+        Sym(id)
+      },
+    }
+  }
+
+  /// The character of a single-character symbol.
+  pub fn as_char(self) -> Option<char> {
+    char::from_u32(self.0).filter(|_| self.0 < FIRST_NAME)
+  }
+
+  /// The name of a multi-character symbol.
+  pub fn as_name(self) -> Option<&'static str> {
+    let i = self.0.checked_sub(FIRST_NAME)?;
+    Some(INTERNER.lock().unwrap().names[i as usize])
+  }
+
+  /// Whether the symbol is written in words as it is. Names, and characters with a meaning of their own in words like
+  /// `[` or `(`, are quoted.
+  pub fn is_bare(self) -> bool {
+    self.as_char().is_some_and(|c| !c.is_whitespace() && !"\"()[]{};:`<>".contains(c))
+  }
+
+  /// Whether the symbol has to be separated from the character before it in a word, so they aren't read together:
+  /// `//` is a comment, and `@a` an expansion.
+  pub fn glues_to(self, prev: char) -> bool {
+    matches!((prev, self.as_char()), ('/', Some('/' | '*')) | ('@', Some('a'..='z' | 'A'..='Z' | '_')))
+  }
+
+  /// Runs `f` on the symbol as a string, without allocating.
+  pub fn with_str<R>(self, f: impl FnOnce(&str) -> R) -> R {
+    match self.as_char() {
+      Some(c) => f(c.encode_utf8(&mut [0; 4])),
+      None => f(self.as_name().unwrap()),
+    }
+  }
+}
+
+/// A symbol of an alphabet of words. Words read from LSD source are made of `Sym`s, which can be any symbol, but an
+/// L system built through the library can have its own alphabet, like an enum, whose symbols are checked at compile
+/// time. Its symbols are written and parsed as the `Sym`s they stand for.
+pub trait Symbol: Clone + Eq + Hash + fmt::Display + FromStr {
+  /// The `Sym` of the symbol, to use it where any symbol is taken, like in streamed words. By default, the one named
+  /// as the symbol is displayed, which must not be empty.
+  fn to_sym(&self) -> Sym {
+    Sym::new(&self.to_string())
+  }
+
+  /// The symbol of the alphabet for a `Sym`, if there is one. By default, the one parsed from its name.
+  fn from_sym(sym: Sym) -> Option<Self> {
+    sym.with_str(|name| name.parse().ok())
+  }
+}
+
+impl Symbol for Sym {
+  fn to_sym(&self) -> Sym {*self}
+  fn from_sym(sym: Sym) -> Option<Self> {Some(sym)}
+}
+
+impl Symbol for char {
+  fn to_sym(&self) -> Sym {Sym::from(*self)}
+  fn from_sym(sym: Sym) -> Option<Self> {sym.as_char()}
+}
+
+/// Error of parsing an empty string as a `Sym`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmptySymbol;
+
+impl fmt::Display for EmptySymbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("empty symbol name")
+  }
+}
+
+/// Interns a name, like `Sym::new`, without quotes.
+impl FromStr for Sym {
+  type Err = EmptySymbol;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() {Err(EmptySymbol)} else {Ok(Sym::new(s))}
+  }
+}
+
+impl From<char> for Sym {
+  fn from(c: char) -> Self {
+    Sym(c as u32)
+  }
+}
+
+impl PartialEq<char> for Sym {
+  fn eq(&self, c: &char) -> bool {
+    self.0 == *c as u32
+  }
+}
+
+impl fmt::Display for Sym {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.with_str(|s| f.write_str(s))
+  }
+}
+
+impl fmt::Debug for Sym {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.as_char() {
+      Some(c) => write!(f, "Sym({:?})", c),
+      None => write!(f, "Sym({:?})", self.as_name().unwrap()),
+    }
+  }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Sym {
+  fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    self.with_str(|name| s.serialize_str(name))
+  }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Sym {
+  fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    let name = String::deserialize(d)?;
+    if name.is_empty() {
+      return Err(serde::de::Error::invalid_length(0, &"a non-empty symbol"));
+    }
+    Ok(Sym::new(&name))
+  }
+}
+
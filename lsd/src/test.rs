@@ -1,49 +1,590 @@
-use std::rc::Rc;
+use std::borrow::Cow;
 
-use super::lexer::Lexer;
-use super::{MainParser, RuleParser};
+use super::lexer::{Lexer, LexerMode, LexicalError, TokenType};
+use super::ast::normal::*;
+use super::ast::grammar::*;
+use super::source::{Span, SourceMap, LineCol};
+use super::symbol::{EmptySymbol, Sym, Symbol};
+use super::convert::Dialect;
+use super::visit::{self, Visitor, VisitorMut, Fold};
+use super::{ParseError, parse_lsd_module, parse_lsd_file, parse_expr, parse_word, parse_rules, format_lsd};
+
+fn parse_ok(input: &str) -> Module<'_> {
+  let (module, errors) = parse_lsd_module(input);
+  assert_eq!(errors, vec![]);
+  module.unwrap()
+}
+
+fn token_types(input: &str, mode: LexerMode) -> Vec<TokenType> {
+  Lexer::new(input, mode).map(|t| t.unwrap().1.ttype).collect()
+}
 
 #[test]
-fn main() {
-  // Values are encoded by a length, a colon, and then the value.
-  // The value doesn't need to be followed by whitespace, but it
-  // may.
-  let input = &b"3:foo5:xyzzy"[..];
+fn normal_mode_tokens() {
+  use TokenType::*;
 
-  let lexer = Lexer::new(input);
-  let mode = Rc::clone(&lexer.mode);
+  assert_eq!(
+    token_types("let mut x = 1.5e3 ** -2 // comment\nx.y != \"a\\\"b\" /* a /* nested */ comment */", LexerMode::Normal),
+    vec![Let, Mut, Id, Assign, Float, Pow, Sub, Int, NewLine, Id, Accessor, NE, String],
+  );
+  assert_eq!(
+    token_types("f(1,\n  2) -> => and or not Inf NaN", LexerMode::Normal),
+    vec![Id, LParen, Int, Comma, Int, RParen, Arrow, DArrow, And, Or, Not, Inf, NaN],
+  );
+}
 
-  let values = ListParser::new().parse(&mode, lexer).unwrap();
+#[test]
+fn grammar_mode_tokens() {
+  use TokenType::*;
 
   assert_eq!(
-    &values[..],
-    &[
-      Value {
-        value: b"foo".to_vec()
-      },
-      Value {
-        value: b"xyzzy".to_vec()
-      },
-    ][..]
+    token_types("0.5 | A < F(x) > [+]B : -> F|·@leaf", LexerMode::Grammar),
+    vec![Float, BitOr, Symbol, LT, Symbol, LParen, Symbol, RParen, GT, LBracket, Symbol, RBracket, Symbol, Colon,
+         Arrow, Symbol, Symbol, Symbol, AtId],
   );
+}
 
-  // Whitespace can also occur in the value.
-  let input = &b"3:f   5:    y"[..];
+#[test]
+fn symbols() {
+  assert_eq!(Sym::new("Apex"), Sym::new("Apex"));
+  assert_ne!(Sym::new("Apex"), Sym::new("Apex'"));
+  assert_eq!(Sym::new("F"), Sym::from('F'));
+  assert_eq!(Sym::new("Internode'").as_name(), Some("Internode'"));
+  assert_eq!(Sym::new("·").as_char(), Some('·'));
+  assert_eq!(Sym::new("F_1").to_string(), "F_1");
+  assert_eq!("Apex".parse(), Ok(Sym::new("Apex")));
+  assert_eq!("".parse::<Sym>(), Err(EmptySymbol));
 
-  let lexer = Lexer::new(input);
-  let mode = Rc::clone(&lexer.mode);
+  let word = parse_word("\"Apex\"(1)·[\"Internode'\"\"F\"]").unwrap();
+  struct Leaves(Vec<String>);
+  impl<'ast> Visitor<'ast> for Leaves {
+    fn visit_leaf(&mut self, l: &'ast Leaf<'ast, Sym>) {self.0.push(l.symbol.to_string()); visit::walk_leaf(self, l)}
+  }
+  let mut leaves = Leaves(Vec::new());
+  leaves.visit_word(&word);
+  assert_eq!(leaves.0, ["Apex", "·", "Internode'", "F"]);
+  assert_eq!(word.0[0].span, Span::new(0, 9));
 
-  let values = ListParser::new().parse(&mode, lexer).unwrap();
+  let rules = parse_rules("\"Apex\"(x) > \"F_1\" -> \"Apex\"(x + 1)").unwrap();
+  assert_eq!(rules[0].base().left_leaf.symbol, Sym::new("Apex"));
+  assert_eq!(format_lsd("axiom \"Apex\"\"F\"·[\"F_1\"]\n").unwrap(), "axiom \"Apex\"F·[\"F_1\"]\n");
 
+  let errors: Vec<LexicalError> = Lexer::new("\"Apex\n\"\"\n\"two words\"", LexerMode::Grammar)
+    .filter_map(Result::err)
+    .collect();
+  assert_eq!(errors, vec![LexicalError::InvalidSymbol(0), LexicalError::InvalidSymbol(6), LexicalError::InvalidSymbol(9)]);
+}
+
+#[test]
+fn alphabets() {
+  use std::fmt;
+
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  enum Plant {Apex, Internode}
+
+  impl fmt::Display for Plant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      f.write_str(match self {Plant::Apex => "A", Plant::Internode => "Internode"})
+    }
+  }
+
+  impl std::str::FromStr for Plant {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+      match s {"A" => Ok(Plant::Apex), "Internode" => Ok(Plant::Internode), _ => Err(())}
+    }
+  }
+
+  impl Symbol for Plant {}
+
+  assert_eq!(Plant::Apex.to_sym(), Sym::from('A'));
+  assert_eq!(Plant::from_sym(Sym::new("Internode")), Some(Plant::Internode));
+  assert_eq!(Plant::from_sym(Sym::from('F')), None);
+  assert_eq!('F'.to_sym(), Sym::from('F'));
+  assert_eq!(char::from_sym(Sym::new("Apex")), None);
+  assert_eq!(Sym::from_sym(Sym::new("Apex")).map(|s| s.to_sym()), Some(Sym::new("Apex")));
+}
+
+#[test]
+fn lexical_errors() {
+  let errors: Vec<LexicalError> = Lexer::new("\"abc\n\"\\q\"\n/* open", LexerMode::Normal)
+    .filter_map(Result::err)
+    .collect();
   assert_eq!(
-    &values[..],
-    &[
-      Value {
-        value: b"f  ".to_vec()
-      },
-      Value {
-        value: b"    y".to_vec()
-      },
-    ][..]
+    errors,
+    vec![LexicalError::UnterminatedString(0), LexicalError::InvalidEscape(6), LexicalError::UnterminatedComment(10)],
   );
 }
+
+#[test]
+fn expressions() {
+  let int = |i, start| Box::new(Expr::new(ExprKind::Int(i), Span::new(start, start + 1)));
+  assert_eq!(
+    parse_expr("1 + 2 * 3"),
+    Ok(Expr::new(
+      ExprKind::Add(int(1, 0), Box::new(Expr::new(ExprKind::Mul(int(2, 4), int(3, 8)), Span::new(4, 9)))),
+      Span::new(0, 9),
+    )),
+  );
+  assert_eq!(parse_expr("\"a\\tb\"").map(|e| e.kind), Ok(ExprKind::String(Cow::Owned("a\tb".to_string()))));
+  assert!(matches!(parse_expr("if x then f(1, 2) else y.z").map(|e| e.kind), Ok(ExprKind::IfElse(..))));
+  assert!(matches!(parse_expr("fn(x, y = 2) -> x * y").map(|e| e.kind), Ok(ExprKind::Lambda(..))));
+  assert!(matches!(parse_expr("x not in [1, 2]").map(|e| e.kind), Ok(ExprKind::In(_, _, false))));
+  assert!(matches!(parse_expr("(1 + 2) * 3").map(|e| e.kind), Ok(ExprKind::Mul(..))));
+  assert!(matches!(
+    parse_expr("99999999999999999999").unwrap_err()[..],
+    [ParseError::User {error: LexicalError::IntegerOverflow(0)}],
+  ));
+}
+
+#[test]
+fn words() {
+  let word = parse_word("F(1, x + 1)[+F]@petal(3)").unwrap();
+  assert_eq!(word.0.len(), 3);
+  assert!(matches!(&word.0[0].kind, NodeKind::Leaf(Leaf {symbol, args: Some(args), ..}) if *symbol == 'F' && args.len() == 2));
+  assert!(matches!(&word.0[1].kind, NodeKind::Branch(nodes) if nodes.len() == 2));
+  assert!(matches!(&word.0[2].kind, NodeKind::Expansion(Expansion {to, args: Some(_), ..}) if to == "petal"));
+  assert_eq!(word.0.iter().map(|n| n.span).collect::<Vec<_>>(), vec![Span::new(0, 11), Span::new(11, 15), Span::new(15, 24)]);
+}
+
+#[test]
+fn rules() {
+  let rules = parse_rules("0.5 | A < B(x) > [C]D : x > 1 -> B(x - 1){y = x}C\nB => ").unwrap();
+  assert_eq!(rules.len(), 2);
+  match &rules[0] {
+    Rule::Production(rule) => {
+      assert_eq!(rule.weight, 0.5);
+      assert_eq!(rule.left_leaf, LeftLeaf {symbol: 'B'.into(), params: Some(vec![Cow::Borrowed("x")]), span: Span::new(10, 14)});
+      assert_eq!(rule.l_ctx.len(), 1);
+      assert_eq!(rule.r_ctx.len(), 2);
+      assert!(rule.condition.is_some());
+      assert_eq!(rule.right_side.0.len(), 3);
+    },
+    _ => panic!("expected a production rule"),
+  }
+  assert!(matches!(&rules[1], Rule::Coding(rule) if rule.right_side.0.is_empty()));
+}
+
+#[test]
+fn implicit_lsystem() {
+  let module = parse_ok("
+    set angle = 60
+    axiom F--F--F
+    rules {
+      F -> F+F--F+F
+    }
+    let petals = `[+F]` * 5
+  ");
+  assert_eq!(module.stmts.len(), 1);
+  match &module.stmts[0] {
+    ModStmt::LSysDef(def) => {
+      assert!(def.main);
+      assert_eq!(def.stmts.len(), 4);
+      assert!(matches!(&def.stmts[1], LSysStmt::AxiomDef(word) if word.0.len() == 7));
+      assert!(matches!(&def.stmts[2], LSysStmt::RulesDef(rules) if rules.len() == 1));
+    },
+    _ => panic!("expected an L system"),
+  }
+}
+
+#[test]
+fn module_definitions() {
+  let module = parse_ok("
+    fn size(n) {
+      if n > 1 {return n * size(n - 1)} else {return 1}
+    }
+
+    main lsys koch {
+      axiom F
+      table t1 {F -> F+F-F}
+    }
+
+    lsys bush(angle = 25, depth) {
+      axiom F
+    }
+  ");
+  assert_eq!(module.stmts.len(), 3);
+  assert!(matches!(&module.stmts[0], ModStmt::FnDef(def) if def.name == "size"));
+  match &module.stmts[1] {
+    ModStmt::LSysDef(def) => {
+      assert!(def.main);
+      assert_eq!(def.name.as_deref(), Some("koch"));
+      assert!(matches!(&def.stmts[1], LSysStmt::TableDef(RulesTable {name: Some(name), rules, ..}) if name == "t1" && rules.len() == 1));
+    },
+    _ => panic!("expected an L system"),
+  }
+  match &module.stmts[2] {
+    ModStmt::LSysDef(def) => {
+      assert!(!def.main);
+      assert_eq!(def.params.iter().map(|p| &*p.name).collect::<Vec<_>>(), vec!["angle", "depth"]);
+      assert!(def.params[0].default_value.is_some());
+    },
+    _ => panic!("expected an L system"),
+  }
+}
+
+#[test]
+fn source_map() {
+  let mut map = SourceMap::new();
+  map.add_file("a.lsd", "axiom F\n".to_string());
+  map.add_file("b.lsd", "set angle = 90\naxiom F+F\nrules {F -> FF}\n".to_string());
+  let file = &map.files()[1];
+  assert_eq!(file.start, 9);
+  let module = parse_lsd_file(file).0.unwrap();
+
+  let def = match &module.stmts[0] {
+    ModStmt::LSysDef(def) => def,
+    _ => panic!("expected an L system"),
+  };
+  let rule = match &def.stmts[2] {
+    LSysStmt::RulesDef(rules) => &rules[0],
+    _ => panic!("expected a rules block"),
+  };
+  assert_eq!(map.file_at(rule.span().start).unwrap().snippet(rule.span()), Some("F -> FF"));
+  assert_eq!(map.lookup(rule.span().start), Some((std::path::Path::new("b.lsd"), LineCol {line: 3, col: 8})));
+  assert_eq!(map.describe(def.span), "b.lsd:1:1");
+}
+
+#[test]
+fn error_recovery() {
+  let (module, errors) = parse_lsd_module("
+    set angle = )
+    axiom F(
+    )]
+    rules {
+      F -> F+F
+      G : -> G
+      H -> H
+    }
+    let x = 1
+  ");
+  assert_eq!(errors.len(), 3);
+  assert!(errors.iter().all(|e| matches!(e, ParseError::UnrecognizedToken {..})));
+  let def = match &module.unwrap().stmts[0] {
+    ModStmt::LSysDef(def) => def.clone(),
+    _ => panic!("expected an L system"),
+  };
+  assert_eq!(def.stmts.len(), 2);
+  assert!(matches!(&def.stmts[0], LSysStmt::RulesDef(rules) if rules.len() == 2));
+  assert!(matches!(&def.stmts[1], LSysStmt::Stmt(Stmt {kind: StmtKind::VarDecl(_), ..})));
+
+  let (module, errors) = parse_lsd_module("fn f() {\n  let = 2\n}\nlsys a {axiom F}");
+  assert_eq!(errors.len(), 1);
+  assert_eq!(module.unwrap().stmts.len(), 2);
+}
+
+#[test]
+fn imports() {
+  let module = parse_ok("
+    import \"plants/leaves\" as lv
+    from shapes import koch, dragon
+    axiom F@lv.leaf(2)
+  ");
+  assert_eq!(module.stmts.len(), 3);
+  match &module.stmts[0] {
+    ModStmt::Import(import) => {
+      assert_eq!(import.module, "plants/leaves");
+      assert_eq!(import.alias.as_deref(), Some("lv"));
+      assert!(import.symbols.is_empty());
+    },
+    _ => panic!("expected an import"),
+  }
+  assert!(matches!(&module.stmts[1], ModStmt::Import(ImportStmt {alias: None, symbols, ..}) if symbols == &["koch", "dragon"]));
+  let def = match &module.stmts[2] {
+    ModStmt::LSysDef(def) => def,
+    _ => panic!("expected an L system"),
+  };
+  match &def.stmts[0] {
+    LSysStmt::AxiomDef(word) => match &word.0[1].kind {
+      NodeKind::Expansion(exp) => assert_eq!(exp.path(), (Some("lv"), "leaf")),
+      _ => panic!("expected an expansion"),
+    },
+    _ => panic!("expected an axiom"),
+  }
+}
+
+#[test]
+fn owned_ast() {
+  let module = {
+    let src = String::from("import leaves as lv\nlsys a(x = 1) {axiom F(x)@lv.leaf\nrules {F -> FF}}");
+    parse_ok(&src).into_owned()
+  };
+  let handle = std::thread::spawn(move || module.stmts.len());
+  assert_eq!(handle.join().unwrap(), 2);
+}
+
+#[test]
+fn visitors() {
+  struct Ids<'a>(Vec<&'a str>);
+  impl<'a> Visitor<'a> for Ids<'a> {
+    fn visit_expr(&mut self, e: &'a Expr<'a>) {
+      if let ExprKind::ID(id) = &e.kind {
+        self.0.push(id);
+      }
+      visit::walk_expr(self, e);
+    }
+  }
+
+  struct Rename;
+  impl<'a> VisitorMut<'a> for Rename {
+    fn visit_left_leaf_mut(&mut self, l: &mut LeftLeaf<'a, Sym>) {
+      l.symbol = 'G'.into();
+    }
+  }
+
+  struct ConstFold;
+  impl<'a> Fold<'a> for ConstFold {
+    fn fold_expr(&mut self, e: Expr<'a>) -> Expr<'a> {
+      let e = visit::fold_expr(self, e);
+      match &e.kind {
+        ExprKind::Add(l, r) => match (&l.kind, &r.kind) {
+          (ExprKind::Int(l), ExprKind::Int(r)) => Expr::new(ExprKind::Int(l + r), e.span),
+          _ => e,
+        },
+        _ => e,
+      }
+    }
+  }
+
+  let src = "let a = 1 + 2\nlsys l(x = a) {axiom F(x)\nrules {F(y) : y > b -> F(1 + 1, y)[+F]}}";
+  let mut module = parse_ok(src);
+
+  let mut ids = Ids(vec![]);
+  ids.visit_module(&module);
+  assert_eq!(ids.0, ["a", "x", "y", "b", "y"]);
+
+  Rename.visit_module_mut(&mut module);
+  let module = ConstFold.fold_module(module);
+  let ModStmt::VarDecl(d) = &module.stmts[0] else {panic!()};
+  assert!(matches!(d.value.as_ref().unwrap().kind, ExprKind::Int(3)));
+  let ModStmt::LSysDef(d) = &module.stmts[1] else {panic!()};
+  let LSysStmt::RulesDef(rules) = &d.stmts[1] else {panic!()};
+  let base = rules[0].base();
+  assert_eq!(base.left_leaf.symbol, 'G');
+  let NodeKind::Leaf(leaf) = &base.right_side.0[0].kind else {panic!()};
+  assert!(matches!(leaf.args.as_ref().unwrap()[0].kind, ExprKind::Int(2)));
+}
+
+#[test]
+fn format() {
+  // Spans change with formatting, the rest of the AST must not.
+  struct ClearSpans;
+  impl<'a> VisitorMut<'a> for ClearSpans {
+    fn visit_import_mut(&mut self, i: &mut ImportStmt<'a>) {i.span = Span::default()}
+    fn visit_left_leaf_mut(&mut self, l: &mut LeftLeaf<'a, Sym>) {l.span = Span::default()}
+    fn visit_var_decl_mut(&mut self, d: &mut VarDecl<'a>) {d.span = Span::default(); visit::walk_var_decl_mut(self, d)}
+    fn visit_fn_def_mut(&mut self, d: &mut FnDef<'a>) {d.span = Span::default(); visit::walk_fn_def_mut(self, d)}
+    fn visit_lsys_def_mut(&mut self, d: &mut LSysDef<'a>) {d.span = Span::default(); visit::walk_lsys_def_mut(self, d)}
+    fn visit_param_mut(&mut self, p: &mut Param<'a>) {p.span = Span::default(); visit::walk_param_mut(self, p)}
+    fn visit_stmt_mut(&mut self, s: &mut Stmt<'a>) {s.span = Span::default(); visit::walk_stmt_mut(self, s)}
+    fn visit_expr_mut(&mut self, e: &mut Expr<'a>) {e.span = Span::default(); visit::walk_expr_mut(self, e)}
+    fn visit_rules_table_mut(&mut self, t: &mut RulesTable<'a, Sym>) {t.span = Span::default(); visit::walk_rules_table_mut(self, t)}
+    fn visit_node_mut(&mut self, n: &mut Node<'a, Sym>) {n.span = Span::default(); visit::walk_node_mut(self, n)}
+    fn visit_leaf_mut(&mut self, l: &mut Leaf<'a, Sym>) {l.span = Span::default(); visit::walk_leaf_mut(self, l)}
+    fn visit_expansion_mut(&mut self, e: &mut Expansion<'a>) {e.span = Span::default(); visit::walk_expansion_mut(self, e)}
+    fn visit_ctx_node_mut(&mut self, n: &mut CtxNode<'a, Sym>) {n.span = Span::default(); visit::walk_ctx_node_mut(self, n)}
+    fn visit_rule_mut(&mut self, r: &mut Rule<'a, Sym>) {
+      match r {
+        Rule::Production(b) | Rule::Coding(b) => b.span = Span::default(),
+      }
+      visit::walk_rule_mut(self, r);
+    }
+  }
+  let ast = |src| {
+    let mut module = parse_ok(src);
+    ClearSpans.visit_module_mut(&mut module);
+    module
+  };
+
+  let src = "// Koch curve
+import \"plants/leaves\" as lv
+from shapes import koch,   star
+
+
+let   a=(1+2)*3   // three
+fn size(n){if n>1{return n*size(n-1)}else{return 1}}
+main lsys koch(angle=25,   depth) {
+    axiom F  -F--F @lv.leaf b
+    rules {
+        1 | F->F+F-F
+        0.5|A<B(x)>[C]D:x>1->B(x-1){y=x;z=2}C // contexts
+        // inner
+        B=>
+    }
+    table t {  }
+    while not (x in y) {x = if a then (fn(q) -> q+1) else `F/ /G`}
+}
+";
+  let formatted = format_lsd(src).unwrap();
+  assert_eq!(formatted, "// Koch curve
+import \"plants/leaves\" as lv
+from shapes import koch, star
+
+let a = (1 + 2) * 3 // three
+fn size(n) {
+  if n > 1 {
+    return n * size(n - 1)
+  } else {
+    return 1
+  }
+}
+main lsys koch(angle = 25, depth) {
+  axiom F-F--F@lv.leaf b
+  rules {
+    F                             -> F+F-F
+    0.5 | A < B(x) > [C]D : x > 1 -> B(x - 1){y = x; z = 2}C // contexts
+    // inner
+    B                             =>
+  }
+  table t {}
+  while not (x in y) {
+    x = if a then (fn(q) -> q + 1) else `F/ /G`
+  }
+}
+");
+  assert_eq!(ast(&formatted), ast(src));
+  assert_eq!(format_lsd(&formatted).unwrap(), formatted);
+
+  let implicit = "axiom  F\nrules {F -> F[+F]F}\n";
+  assert_eq!(format_lsd(implicit).unwrap(), "axiom F\nrules {\n  F -> F[+F]F\n}\n");
+  assert!(format_lsd("axiom F\nrules {F -> }}").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_roundtrip() {
+  let module = parse_ok("set angle = 25.5\nlet w = `F[+F]`\naxiom F@w\nrules {0.5 | A < B(x) : x > 1 -> B(x - 1)[+A]}");
+  let json = serde_json::to_string(&module).unwrap();
+  let back: Module<'static> = serde_json::from_str(&json).unwrap();
+  assert_eq!(back, module);
+}
+
+#[test]
+fn cpfg() {
+  let src = "/* Bush */
+#define R 1.5
+#include <colors.h>
+
+Lsystem: 1
+derivation length: 5
+consider: FA[]
+axiom: A(1)
+A(s) : s < 3 --> F(s)[+A(s*R)][-A(s^2)]  // grows
+B < A(s) > F : * --> A(s > 1 ? 1. : 2)
+F(x) --> F(x)@O(1) : 0.5
+C : {t = 1;} t > 0 --> {.F.}
+homomorphism
+@O(r) --> ;(2)@o(r)
+decomposition
+A --> BB
+endlsystem
+";
+  let converted = super::convert::cpfg::convert(src);
+  assert_eq!(converted.lsd, "/* Bush */
+let R = 1.5
+main lsys lsystem1 {
+  set iterations = 5
+  set ignore = `+-B\"@O\"C\"{\".\"}\"\";\"\"@o\"`
+  axiom A(1)
+  rules {
+    A(s) : s < 3 -> F(s)[+A(s * R)][-A(s ** 2)]
+    B < A(s) > F -> A(if s > 1 then 1.0 else 2)
+    0.5 | F(x)   -> F(x)\"@O\"(1)
+    C : t > 0    -> \"{\".F.\"}\"
+  }
+  coding rules {
+    \"@O\"(r) => \";\"(2)\"@o\"(r)
+  }
+}
+");
+  let warnings: Vec<(&str, &str)> = converted.warnings.iter()
+    .map(|w| (w.message.as_str(), &src[w.span.start..w.span.end]))
+    .collect();
+  assert_eq!(warnings, vec![
+    ("`#include` isn't supported: convert the included file and import it instead", "#include <colors.h>"),
+    ("blocks of C statements in conditions aren't supported and were left out", "C : {t = 1;} t > 0 --> {.F.}"),
+    ("decomposition productions aren't supported and were left out", "decomposition"),
+  ]);
+  assert!(parse_lsd_module(&converted.lsd).1.is_empty());
+}
+
+#[test]
+fn lsysgen() {
+  let src = "// Weed
+$N = 4
+set iterations = $N
+axiom A
+A -> F[+A][-A]FA // grows
+F -> FF
+thin: F -> F
+if ($N > 3) $N = 3
+lsys Leaf {
+  axiom L
+  grow: L -> LL
+}
+";
+  let converted = super::convert::lsysgen::convert(src);
+  assert_eq!(converted.lsd, "// Weed
+let N = 4
+set iterations = N
+axiom A
+rules {
+  A -> F[+A][-A]FA // grows
+  F -> FF
+}
+if N > 3 {
+  N = 3
+}
+lsys Leaf {
+  axiom L
+  table grow {
+    L -> LL
+  }
+}
+table thin {
+  F -> F
+}
+");
+  assert!(converted.warnings.is_empty());
+  assert!(parse_lsd_module(&converted.lsd).1.is_empty());
+
+  let src = "$i = 0\ndo $i = $i + 1 while ($i < 3)\n";
+  let converted = super::convert::lsysgen::convert(src);
+  let warnings: Vec<&str> = converted.warnings.iter().map(|w| &src[w.span.start..w.span.end]).collect();
+  assert_eq!(warnings, vec!["do $i = $i + 1 while ($i < 3)"]);
+}
+
+#[test]
+fn abop() {
+  let src = "# Figure 1.24 (d)
+n = 7, δ = 20°
+X
+X -> F[+X]F[-X]+X
+p2: F → FF
+b < a(x) > c : x > 1 -> a(x + 1){b}
+what is this
+";
+  let converted = super::convert::abop::convert(src);
+  assert_eq!(converted.lsd, "set iterations = 7
+set angle = 20
+axiom X
+rules {
+  X                    -> F[+X]F[-X]+X
+  F                    -> FF
+  b < a(x) > c : x > 1 -> a(x + 1)\"{\"b\"}\"
+}
+");
+  let warnings: Vec<&str> = converted.warnings.iter().map(|w| &src[w.span.start..w.span.end]).collect();
+  assert_eq!(warnings, vec!["what is this"]);
+  assert!(parse_lsd_module(&converted.lsd).1.is_empty());
+
+  let converted = super::convert::abop::convert("axiom: F, angle: 90\nF -> F+F-F-F+F\n");
+  assert_eq!(converted.lsd, "axiom F\nset angle = 90\nrules {\n  F -> F+F-F-F+F\n}\n");
+  assert!(converted.warnings.is_empty());
+
+  assert_eq!(Dialect::detect(src), Some(Dialect::Abop));
+  assert_eq!(Dialect::detect("axiom: F, angle: 90\nF -> F+F-F-F+F\n"), Some(Dialect::Abop));
+  assert_eq!(Dialect::detect("Lsystem: 1\nderivation length: 3\n"), Some(Dialect::Cpfg));
+  // LSD, aunque los comentarios tengan flechas
+  assert_eq!(Dialect::detect("// F → FF\naxiom F\nrules {F -> FF}\n"), None);
+}
@@ -0,0 +1,549 @@
+use crate::ast::normal::*;
+use crate::ast::grammar::*;
+use crate::symbol::Sym;
+
+/// Traverses an AST by reference.
+///
+/// Every method calls its `walk_*` function by default, which visits the children of the node. Overriding a method
+/// and calling `walk_*` from it visits a node and goes on with its children; not calling it skips them.
+pub trait Visitor<'ast> {
+  fn visit_module(&mut self, m: &'ast Module<'ast>) {walk_module(self, m)}
+  fn visit_mod_stmt(&mut self, s: &'ast ModStmt<'ast>) {walk_mod_stmt(self, s)}
+  fn visit_import(&mut self, _i: &'ast ImportStmt<'ast>) {}
+  fn visit_var_decl(&mut self, d: &'ast VarDecl<'ast>) {walk_var_decl(self, d)}
+  fn visit_fn_def(&mut self, d: &'ast FnDef<'ast>) {walk_fn_def(self, d)}
+  fn visit_lsys_def(&mut self, d: &'ast LSysDef<'ast>) {walk_lsys_def(self, d)}
+  fn visit_lsys_stmt(&mut self, s: &'ast LSysStmt<'ast>) {walk_lsys_stmt(self, s)}
+  fn visit_param(&mut self, p: &'ast Param<'ast>) {walk_param(self, p)}
+  fn visit_stmt(&mut self, s: &'ast Stmt<'ast>) {walk_stmt(self, s)}
+  fn visit_expr(&mut self, e: &'ast Expr<'ast>) {walk_expr(self, e)}
+  fn visit_rules_table(&mut self, t: &'ast RulesTable<'ast, Sym>) {walk_rules_table(self, t)}
+  fn visit_rule(&mut self, r: &'ast Rule<'ast, Sym>) {walk_rule(self, r)}
+  fn visit_word(&mut self, w: &'ast Word<'ast, Sym>) {walk_word(self, w)}
+  fn visit_node(&mut self, n: &'ast Node<'ast, Sym>) {walk_node(self, n)}
+  fn visit_leaf(&mut self, l: &'ast Leaf<'ast, Sym>) {walk_leaf(self, l)}
+  fn visit_expansion(&mut self, e: &'ast Expansion<'ast>) {walk_expansion(self, e)}
+  fn visit_ctx_node(&mut self, n: &'ast CtxNode<'ast, Sym>) {walk_ctx_node(self, n)}
+  fn visit_left_leaf(&mut self, _l: &'ast LeftLeaf<'ast, Sym>) {}
+}
+
+/// Traverses an AST by mutable reference. See `Visitor`.
+pub trait VisitorMut<'a> {
+  fn visit_module_mut(&mut self, m: &mut Module<'a>) {walk_module_mut(self, m)}
+  fn visit_mod_stmt_mut(&mut self, s: &mut ModStmt<'a>) {walk_mod_stmt_mut(self, s)}
+  fn visit_import_mut(&mut self, _i: &mut ImportStmt<'a>) {}
+  fn visit_var_decl_mut(&mut self, d: &mut VarDecl<'a>) {walk_var_decl_mut(self, d)}
+  fn visit_fn_def_mut(&mut self, d: &mut FnDef<'a>) {walk_fn_def_mut(self, d)}
+  fn visit_lsys_def_mut(&mut self, d: &mut LSysDef<'a>) {walk_lsys_def_mut(self, d)}
+  fn visit_lsys_stmt_mut(&mut self, s: &mut LSysStmt<'a>) {walk_lsys_stmt_mut(self, s)}
+  fn visit_param_mut(&mut self, p: &mut Param<'a>) {walk_param_mut(self, p)}
+  fn visit_stmt_mut(&mut self, s: &mut Stmt<'a>) {walk_stmt_mut(self, s)}
+  fn visit_expr_mut(&mut self, e: &mut Expr<'a>) {walk_expr_mut(self, e)}
+  fn visit_rules_table_mut(&mut self, t: &mut RulesTable<'a, Sym>) {walk_rules_table_mut(self, t)}
+  fn visit_rule_mut(&mut self, r: &mut Rule<'a, Sym>) {walk_rule_mut(self, r)}
+  fn visit_word_mut(&mut self, w: &mut Word<'a, Sym>) {walk_word_mut(self, w)}
+  fn visit_node_mut(&mut self, n: &mut Node<'a, Sym>) {walk_node_mut(self, n)}
+  fn visit_leaf_mut(&mut self, l: &mut Leaf<'a, Sym>) {walk_leaf_mut(self, l)}
+  fn visit_expansion_mut(&mut self, e: &mut Expansion<'a>) {walk_expansion_mut(self, e)}
+  fn visit_ctx_node_mut(&mut self, n: &mut CtxNode<'a, Sym>) {walk_ctx_node_mut(self, n)}
+  fn visit_left_leaf_mut(&mut self, _l: &mut LeftLeaf<'a, Sym>) {}
+}
+
+/// Rebuilds an AST, taking it by value. By default every node is rebuilt from its folded children, so overriding a
+/// single method is enough to replace some kind of node (for example, to fold constant expressions).
+pub trait Fold<'a> {
+  fn fold_module(&mut self, m: Module<'a>) -> Module<'a> {fold_module(self, m)}
+  fn fold_mod_stmt(&mut self, s: ModStmt<'a>) -> ModStmt<'a> {fold_mod_stmt(self, s)}
+  fn fold_import(&mut self, i: ImportStmt<'a>) -> ImportStmt<'a> {i}
+  fn fold_var_decl(&mut self, d: VarDecl<'a>) -> VarDecl<'a> {fold_var_decl(self, d)}
+  fn fold_fn_def(&mut self, d: FnDef<'a>) -> FnDef<'a> {fold_fn_def(self, d)}
+  fn fold_lsys_def(&mut self, d: LSysDef<'a>) -> LSysDef<'a> {fold_lsys_def(self, d)}
+  fn fold_lsys_stmt(&mut self, s: LSysStmt<'a>) -> LSysStmt<'a> {fold_lsys_stmt(self, s)}
+  fn fold_param(&mut self, p: Param<'a>) -> Param<'a> {fold_param(self, p)}
+  fn fold_stmt(&mut self, s: Stmt<'a>) -> Stmt<'a> {fold_stmt(self, s)}
+  fn fold_expr(&mut self, e: Expr<'a>) -> Expr<'a> {fold_expr(self, e)}
+  fn fold_rules_table(&mut self, t: RulesTable<'a, Sym>) -> RulesTable<'a, Sym> {fold_rules_table(self, t)}
+  fn fold_rule(&mut self, r: Rule<'a, Sym>) -> Rule<'a, Sym> {fold_rule(self, r)}
+  fn fold_word(&mut self, w: Word<'a, Sym>) -> Word<'a, Sym> {fold_word(self, w)}
+  fn fold_node(&mut self, n: Node<'a, Sym>) -> Node<'a, Sym> {fold_node(self, n)}
+  fn fold_leaf(&mut self, l: Leaf<'a, Sym>) -> Leaf<'a, Sym> {fold_leaf(self, l)}
+  fn fold_expansion(&mut self, e: Expansion<'a>) -> Expansion<'a> {fold_expansion(self, e)}
+  fn fold_ctx_node(&mut self, n: CtxNode<'a, Sym>) -> CtxNode<'a, Sym> {fold_ctx_node(self, n)}
+  fn fold_left_leaf(&mut self, l: LeftLeaf<'a, Sym>) -> LeftLeaf<'a, Sym> {l}
+}
+
+
+
+// Visitor:
+
+pub fn walk_module<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, m: &'ast Module<'ast>) {
+  m.stmts.iter().for_each(|s| v.visit_mod_stmt(s));
+}
+
+pub fn walk_mod_stmt<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, s: &'ast ModStmt<'ast>) {
+  match s {
+    ModStmt::Import(i) => v.visit_import(i),
+    ModStmt::VarDecl(d) => v.visit_var_decl(d),
+    ModStmt::FnDef(d) => v.visit_fn_def(d),
+    ModStmt::LSysDef(d) => v.visit_lsys_def(d),
+  }
+}
+
+pub fn walk_var_decl<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, d: &'ast VarDecl<'ast>) {
+  if let Some(e) = &d.value {
+    v.visit_expr(e);
+  }
+}
+
+pub fn walk_fn_def<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, d: &'ast FnDef<'ast>) {
+  d.params.iter().for_each(|p| v.visit_param(p));
+  d.stmts.iter().for_each(|s| v.visit_stmt(s));
+}
+
+pub fn walk_lsys_def<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, d: &'ast LSysDef<'ast>) {
+  d.params.iter().for_each(|p| v.visit_param(p));
+  d.stmts.iter().for_each(|s| v.visit_lsys_stmt(s));
+}
+
+pub fn walk_lsys_stmt<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, s: &'ast LSysStmt<'ast>) {
+  match s {
+    LSysStmt::Stmt(s) => v.visit_stmt(s),
+    LSysStmt::Import(i) => v.visit_import(i),
+    LSysStmt::SetDef(_, e) => v.visit_expr(e),
+    LSysStmt::AxiomDef(w) => v.visit_word(w),
+    LSysStmt::TableDef(t) => v.visit_rules_table(t),
+    LSysStmt::RulesDef(rs) | LSysStmt::ProductionRulesDef(rs) | LSysStmt::CodingRulesDef(rs) => {
+      rs.iter().for_each(|r| v.visit_rule(r));
+    },
+  }
+}
+
+pub fn walk_param<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, p: &'ast Param<'ast>) {
+  if let Some(e) = &p.default_value {
+    v.visit_expr(e);
+  }
+}
+
+pub fn walk_stmt<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, s: &'ast Stmt<'ast>) {
+  match &s.kind {
+    StmtKind::Expr(e) | StmtKind::Assign(_, e) => v.visit_expr(e),
+    StmtKind::VarDecl(d) => v.visit_var_decl(d),
+    StmtKind::FnDef(d) => v.visit_fn_def(d),
+    StmtKind::LSysDef(d) => v.visit_lsys_def(d),
+    StmtKind::If(c, t, e) => {
+      v.visit_expr(c);
+      v.visit_stmt(t);
+      if let Some(e) = e {
+        v.visit_stmt(e);
+      }
+    },
+    StmtKind::For(_, e, s) | StmtKind::While(e, s) => {
+      v.visit_expr(e);
+      v.visit_stmt(s);
+    },
+    StmtKind::Return(e) => {
+      if let Some(e) = e {
+        v.visit_expr(e);
+      }
+    },
+    StmtKind::Block(ss) => ss.iter().for_each(|s| v.visit_stmt(s)),
+  }
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, e: &'ast Expr<'ast>) {
+  use ExprKind::*;
+  match &e.kind {
+    Int(_) | Float(_) | String(_) | Bool(_) | Null | ID(_) => {},
+    List(es) => es.iter().for_each(|e| v.visit_expr(e)),
+    PropAcc(e, _) | Plus(e) | Minus(e) | Not(e) | BitNot(e) => v.visit_expr(e),
+    FnCall(f, args) => {
+      v.visit_expr(f);
+      args.iter().for_each(|a| v.visit_expr(a));
+    },
+    IndexExpr(l, r) | Pow(l, r) | Mul(l, r) | Div(l, r) | Mod(l, r) | Add(l, r) | Sub(l, r) | LT(l, r) | LE(l, r)
+    | GT(l, r) | GE(l, r) | EQ(l, r) | NE(l, r) | BitAnd(l, r) | BitXor(l, r) | BitOr(l, r) | And(l, r) | Or(l, r)
+    | In(l, r, _) => {
+      v.visit_expr(l);
+      v.visit_expr(r);
+    },
+    IfElse(c, t, e) => {
+      v.visit_expr(c);
+      v.visit_expr(t);
+      v.visit_expr(e);
+    },
+    Lambda(ps, e) => {
+      ps.iter().for_each(|p| v.visit_param(p));
+      v.visit_expr(e);
+    },
+    Word(w) => v.visit_word(w),
+  }
+}
+
+pub fn walk_rules_table<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, t: &'ast RulesTable<'ast, Sym>) {
+  t.rules.iter().for_each(|r| v.visit_rule(r));
+}
+
+pub fn walk_rule<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, r: &'ast Rule<'ast, Sym>) {
+  let base = r.base();
+  base.l_ctx.iter().for_each(|n| v.visit_ctx_node(n));
+  v.visit_left_leaf(&base.left_leaf);
+  base.r_ctx.iter().for_each(|n| v.visit_ctx_node(n));
+  if let Some(c) = &base.condition {
+    v.visit_expr(c);
+  }
+  v.visit_word(&base.right_side);
+}
+
+pub fn walk_word<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, w: &'ast Word<'ast, Sym>) {
+  w.0.iter().for_each(|n| v.visit_node(n));
+}
+
+pub fn walk_node<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, n: &'ast Node<'ast, Sym>) {
+  match &n.kind {
+    NodeKind::Leaf(l) => v.visit_leaf(l),
+    NodeKind::Branch(ns) => ns.iter().for_each(|n| v.visit_node(n)),
+    NodeKind::Expansion(e) => v.visit_expansion(e),
+    NodeKind::Block(ss) => ss.iter().for_each(|s| v.visit_stmt(s)),
+  }
+}
+
+pub fn walk_leaf<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, l: &'ast Leaf<'ast, Sym>) {
+  l.args.iter().flatten().for_each(|a| v.visit_expr(a));
+}
+
+pub fn walk_expansion<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, e: &'ast Expansion<'ast>) {
+  e.args.iter().flatten().for_each(|a| v.visit_expr(a));
+}
+
+pub fn walk_ctx_node<'ast, V: Visitor<'ast> + ?Sized>(v: &mut V, n: &'ast CtxNode<'ast, Sym>) {
+  match &n.kind {
+    CtxNodeKind::Leaf(l) => v.visit_left_leaf(l),
+    CtxNodeKind::Branch(ns) => ns.iter().for_each(|n| v.visit_ctx_node(n)),
+  }
+}
+
+
+
+// VisitorMut:
+
+pub fn walk_module_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, m: &mut Module<'a>) {
+  m.stmts.iter_mut().for_each(|s| v.visit_mod_stmt_mut(s));
+}
+
+pub fn walk_mod_stmt_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, s: &mut ModStmt<'a>) {
+  match s {
+    ModStmt::Import(i) => v.visit_import_mut(i),
+    ModStmt::VarDecl(d) => v.visit_var_decl_mut(d),
+    ModStmt::FnDef(d) => v.visit_fn_def_mut(d),
+    ModStmt::LSysDef(d) => v.visit_lsys_def_mut(d),
+  }
+}
+
+pub fn walk_var_decl_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, d: &mut VarDecl<'a>) {
+  if let Some(e) = &mut d.value {
+    v.visit_expr_mut(e);
+  }
+}
+
+pub fn walk_fn_def_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, d: &mut FnDef<'a>) {
+  d.params.iter_mut().for_each(|p| v.visit_param_mut(p));
+  d.stmts.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+}
+
+pub fn walk_lsys_def_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, d: &mut LSysDef<'a>) {
+  d.params.iter_mut().for_each(|p| v.visit_param_mut(p));
+  d.stmts.iter_mut().for_each(|s| v.visit_lsys_stmt_mut(s));
+}
+
+pub fn walk_lsys_stmt_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, s: &mut LSysStmt<'a>) {
+  match s {
+    LSysStmt::Stmt(s) => v.visit_stmt_mut(s),
+    LSysStmt::Import(i) => v.visit_import_mut(i),
+    LSysStmt::SetDef(_, e) => v.visit_expr_mut(e),
+    LSysStmt::AxiomDef(w) => v.visit_word_mut(w),
+    LSysStmt::TableDef(t) => v.visit_rules_table_mut(t),
+    LSysStmt::RulesDef(rs) | LSysStmt::ProductionRulesDef(rs) | LSysStmt::CodingRulesDef(rs) => {
+      rs.iter_mut().for_each(|r| v.visit_rule_mut(r));
+    },
+  }
+}
+
+pub fn walk_param_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, p: &mut Param<'a>) {
+  if let Some(e) = &mut p.default_value {
+    v.visit_expr_mut(e);
+  }
+}
+
+pub fn walk_stmt_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, s: &mut Stmt<'a>) {
+  match &mut s.kind {
+    StmtKind::Expr(e) | StmtKind::Assign(_, e) => v.visit_expr_mut(e),
+    StmtKind::VarDecl(d) => v.visit_var_decl_mut(d),
+    StmtKind::FnDef(d) => v.visit_fn_def_mut(d),
+    StmtKind::LSysDef(d) => v.visit_lsys_def_mut(d),
+    StmtKind::If(c, t, e) => {
+      v.visit_expr_mut(c);
+      v.visit_stmt_mut(t);
+      if let Some(e) = e {
+        v.visit_stmt_mut(e);
+      }
+    },
+    StmtKind::For(_, e, s) | StmtKind::While(e, s) => {
+      v.visit_expr_mut(e);
+      v.visit_stmt_mut(s);
+    },
+    StmtKind::Return(e) => {
+      if let Some(e) = e {
+        v.visit_expr_mut(e);
+      }
+    },
+    StmtKind::Block(ss) => ss.iter_mut().for_each(|s| v.visit_stmt_mut(s)),
+  }
+}
+
+pub fn walk_expr_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, e: &mut Expr<'a>) {
+  use ExprKind::*;
+  match &mut e.kind {
+    Int(_) | Float(_) | String(_) | Bool(_) | Null | ID(_) => {},
+    List(es) => es.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+    PropAcc(e, _) | Plus(e) | Minus(e) | Not(e) | BitNot(e) => v.visit_expr_mut(e),
+    FnCall(f, args) => {
+      v.visit_expr_mut(f);
+      args.iter_mut().for_each(|a| v.visit_expr_mut(a));
+    },
+    IndexExpr(l, r) | Pow(l, r) | Mul(l, r) | Div(l, r) | Mod(l, r) | Add(l, r) | Sub(l, r) | LT(l, r) | LE(l, r)
+    | GT(l, r) | GE(l, r) | EQ(l, r) | NE(l, r) | BitAnd(l, r) | BitXor(l, r) | BitOr(l, r) | And(l, r) | Or(l, r)
+    | In(l, r, _) => {
+      v.visit_expr_mut(l);
+      v.visit_expr_mut(r);
+    },
+    IfElse(c, t, e) => {
+      v.visit_expr_mut(c);
+      v.visit_expr_mut(t);
+      v.visit_expr_mut(e);
+    },
+    Lambda(ps, e) => {
+      ps.iter_mut().for_each(|p| v.visit_param_mut(p));
+      v.visit_expr_mut(e);
+    },
+    Word(w) => v.visit_word_mut(w),
+  }
+}
+
+pub fn walk_rules_table_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, t: &mut RulesTable<'a, Sym>) {
+  t.rules.iter_mut().for_each(|r| v.visit_rule_mut(r));
+}
+
+pub fn walk_rule_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, r: &mut Rule<'a, Sym>) {
+  let base = match r {
+    Rule::Production(base) | Rule::Coding(base) => base,
+  };
+  base.l_ctx.iter_mut().for_each(|n| v.visit_ctx_node_mut(n));
+  v.visit_left_leaf_mut(&mut base.left_leaf);
+  base.r_ctx.iter_mut().for_each(|n| v.visit_ctx_node_mut(n));
+  if let Some(c) = &mut base.condition {
+    v.visit_expr_mut(c);
+  }
+  v.visit_word_mut(&mut base.right_side);
+}
+
+pub fn walk_word_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, w: &mut Word<'a, Sym>) {
+  w.0.iter_mut().for_each(|n| v.visit_node_mut(n));
+}
+
+pub fn walk_node_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, n: &mut Node<'a, Sym>) {
+  match &mut n.kind {
+    NodeKind::Leaf(l) => v.visit_leaf_mut(l),
+    NodeKind::Branch(ns) => ns.iter_mut().for_each(|n| v.visit_node_mut(n)),
+    NodeKind::Expansion(e) => v.visit_expansion_mut(e),
+    NodeKind::Block(ss) => ss.iter_mut().for_each(|s| v.visit_stmt_mut(s)),
+  }
+}
+
+pub fn walk_leaf_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, l: &mut Leaf<'a, Sym>) {
+  l.args.iter_mut().flatten().for_each(|a| v.visit_expr_mut(a));
+}
+
+pub fn walk_expansion_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, e: &mut Expansion<'a>) {
+  e.args.iter_mut().flatten().for_each(|a| v.visit_expr_mut(a));
+}
+
+pub fn walk_ctx_node_mut<'a, V: VisitorMut<'a> + ?Sized>(v: &mut V, n: &mut CtxNode<'a, Sym>) {
+  match &mut n.kind {
+    CtxNodeKind::Leaf(l) => v.visit_left_leaf_mut(l),
+    CtxNodeKind::Branch(ns) => ns.iter_mut().for_each(|n| v.visit_ctx_node_mut(n)),
+  }
+}
+
+
+
+// Fold:
+
+fn fold_all<T>(v: Vec<T>, f: impl FnMut(T) -> T) -> Vec<T> {
+  v.into_iter().map(f).collect()
+}
+
+pub fn fold_module<'a, F: Fold<'a> + ?Sized>(f: &mut F, m: Module<'a>) -> Module<'a> {
+  Module {stmts: fold_all(m.stmts, |s| f.fold_mod_stmt(s)), ..m}
+}
+
+pub fn fold_mod_stmt<'a, F: Fold<'a> + ?Sized>(f: &mut F, s: ModStmt<'a>) -> ModStmt<'a> {
+  match s {
+    ModStmt::Import(i) => ModStmt::Import(f.fold_import(i)),
+    ModStmt::VarDecl(d) => ModStmt::VarDecl(f.fold_var_decl(d)),
+    ModStmt::FnDef(d) => ModStmt::FnDef(f.fold_fn_def(d)),
+    ModStmt::LSysDef(d) => ModStmt::LSysDef(f.fold_lsys_def(d)),
+  }
+}
+
+pub fn fold_var_decl<'a, F: Fold<'a> + ?Sized>(f: &mut F, d: VarDecl<'a>) -> VarDecl<'a> {
+  VarDecl {value: d.value.map(|e| f.fold_expr(e)), ..d}
+}
+
+pub fn fold_fn_def<'a, F: Fold<'a> + ?Sized>(f: &mut F, d: FnDef<'a>) -> FnDef<'a> {
+  FnDef {
+    params: fold_all(d.params, |p| f.fold_param(p)),
+    stmts: fold_all(d.stmts, |s| f.fold_stmt(s)),
+    ..d
+  }
+}
+
+pub fn fold_lsys_def<'a, F: Fold<'a> + ?Sized>(f: &mut F, d: LSysDef<'a>) -> LSysDef<'a> {
+  LSysDef {
+    params: fold_all(d.params, |p| f.fold_param(p)),
+    stmts: fold_all(d.stmts, |s| f.fold_lsys_stmt(s)),
+    ..d
+  }
+}
+
+pub fn fold_lsys_stmt<'a, F: Fold<'a> + ?Sized>(f: &mut F, s: LSysStmt<'a>) -> LSysStmt<'a> {
+  match s {
+    LSysStmt::Stmt(s) => LSysStmt::Stmt(f.fold_stmt(s)),
+    LSysStmt::Import(i) => LSysStmt::Import(f.fold_import(i)),
+    LSysStmt::SetDef(name, e) => LSysStmt::SetDef(name, f.fold_expr(e)),
+    LSysStmt::AxiomDef(w) => LSysStmt::AxiomDef(f.fold_word(w)),
+    LSysStmt::TableDef(t) => LSysStmt::TableDef(f.fold_rules_table(t)),
+    LSysStmt::RulesDef(rs) => LSysStmt::RulesDef(fold_all(rs, |r| f.fold_rule(r))),
+    LSysStmt::ProductionRulesDef(rs) => LSysStmt::ProductionRulesDef(fold_all(rs, |r| f.fold_rule(r))),
+    LSysStmt::CodingRulesDef(rs) => LSysStmt::CodingRulesDef(fold_all(rs, |r| f.fold_rule(r))),
+  }
+}
+
+pub fn fold_param<'a, F: Fold<'a> + ?Sized>(f: &mut F, p: Param<'a>) -> Param<'a> {
+  Param {default_value: p.default_value.map(|e| f.fold_expr(e)), ..p}
+}
+
+pub fn fold_stmt<'a, F: Fold<'a> + ?Sized>(f: &mut F, s: Stmt<'a>) -> Stmt<'a> {
+  let mut fold_box = |s: Box<Stmt<'a>>| Box::new(f.fold_stmt(*s));
+  let kind = match s.kind {
+    StmtKind::If(c, t, e) => {
+      let (t, e) = (fold_box(t), e.map(&mut fold_box));
+      StmtKind::If(f.fold_expr(c), t, e)
+    },
+    StmtKind::For(var, e, s) => {
+      let s = fold_box(s);
+      StmtKind::For(var, f.fold_expr(e), s)
+    },
+    StmtKind::While(e, s) => {
+      let s = fold_box(s);
+      StmtKind::While(f.fold_expr(e), s)
+    },
+    StmtKind::Expr(e) => StmtKind::Expr(f.fold_expr(e)),
+    StmtKind::Assign(name, e) => StmtKind::Assign(name, f.fold_expr(e)),
+    StmtKind::VarDecl(d) => StmtKind::VarDecl(f.fold_var_decl(d)),
+    StmtKind::FnDef(d) => StmtKind::FnDef(f.fold_fn_def(d)),
+    StmtKind::LSysDef(d) => StmtKind::LSysDef(f.fold_lsys_def(d)),
+    StmtKind::Return(e) => StmtKind::Return(e.map(|e| f.fold_expr(e))),
+    StmtKind::Block(ss) => StmtKind::Block(fold_all(ss, |s| f.fold_stmt(s))),
+  };
+  Stmt {kind, span: s.span}
+}
+
+pub fn fold_expr<'a, F: Fold<'a> + ?Sized>(f: &mut F, e: Expr<'a>) -> Expr<'a> {
+  use ExprKind::*;
+  let mut b = |e: Box<Expr<'a>>| Box::new(f.fold_expr(*e));
+  let kind = match e.kind {
+    kind @ (Int(_) | Float(_) | String(_) | Bool(_) | Null | ID(_)) => kind,
+    List(es) => List(es.into_iter().map(|e| *b(Box::new(e))).collect()),
+    PropAcc(e, prop) => PropAcc(b(e), prop),
+    FnCall(fun, args) => FnCall(b(fun), args.into_iter().map(|e| *b(Box::new(e))).collect()),
+    IndexExpr(l, r) => IndexExpr(b(l), b(r)),
+    Plus(e) => Plus(b(e)),
+    Minus(e) => Minus(b(e)),
+    Not(e) => Not(b(e)),
+    BitNot(e) => BitNot(b(e)),
+    Pow(l, r) => Pow(b(l), b(r)),
+    Mul(l, r) => Mul(b(l), b(r)),
+    Div(l, r) => Div(b(l), b(r)),
+    Mod(l, r) => Mod(b(l), b(r)),
+    Add(l, r) => Add(b(l), b(r)),
+    Sub(l, r) => Sub(b(l), b(r)),
+    LT(l, r) => LT(b(l), b(r)),
+    LE(l, r) => LE(b(l), b(r)),
+    GT(l, r) => GT(b(l), b(r)),
+    GE(l, r) => GE(b(l), b(r)),
+    EQ(l, r) => EQ(b(l), b(r)),
+    NE(l, r) => NE(b(l), b(r)),
+    BitAnd(l, r) => BitAnd(b(l), b(r)),
+    BitXor(l, r) => BitXor(b(l), b(r)),
+    BitOr(l, r) => BitOr(b(l), b(r)),
+    And(l, r) => And(b(l), b(r)),
+    Or(l, r) => Or(b(l), b(r)),
+    IfElse(c, t, e) => IfElse(b(c), b(t), b(e)),
+    In(l, r, yes) => In(b(l), b(r), yes),
+    Lambda(ps, e) => {
+      let ps = fold_all(ps, |p| f.fold_param(p));
+      Lambda(ps, Box::new(f.fold_expr(*e)))
+    },
+    Word(w) => Word(f.fold_word(w)),
+  };
+  Expr {kind, span: e.span}
+}
+
+pub fn fold_rules_table<'a, F: Fold<'a> + ?Sized>(f: &mut F, t: RulesTable<'a, Sym>) -> RulesTable<'a, Sym> {
+  RulesTable {rules: fold_all(t.rules, |r| f.fold_rule(r)), ..t}
+}
+
+pub fn fold_rule<'a, F: Fold<'a> + ?Sized>(f: &mut F, r: Rule<'a, Sym>) -> Rule<'a, Sym> {
+  let mut fold_base = |base: RuleBase<'a, Sym>| RuleBase {
+    l_ctx: fold_all(base.l_ctx, |n| f.fold_ctx_node(n)),
+    left_leaf: f.fold_left_leaf(base.left_leaf),
+    r_ctx: fold_all(base.r_ctx, |n| f.fold_ctx_node(n)),
+    condition: base.condition.map(|c| f.fold_expr(c)),
+    right_side: f.fold_word(base.right_side),
+    ..base
+  };
+  match r {
+    Rule::Production(base) => Rule::Production(fold_base(base)),
+    Rule::Coding(base) => Rule::Coding(fold_base(base)),
+  }
+}
+
+pub fn fold_word<'a, F: Fold<'a> + ?Sized>(f: &mut F, w: Word<'a, Sym>) -> Word<'a, Sym> {
+  Word(fold_all(w.0, |n| f.fold_node(n)))
+}
+
+pub fn fold_node<'a, F: Fold<'a> + ?Sized>(f: &mut F, n: Node<'a, Sym>) -> Node<'a, Sym> {
+  let kind = match n.kind {
+    NodeKind::Leaf(l) => NodeKind::Leaf(f.fold_leaf(l)),
+    NodeKind::Branch(ns) => NodeKind::Branch(fold_all(ns, |n| f.fold_node(n))),
+    NodeKind::Expansion(e) => NodeKind::Expansion(f.fold_expansion(e)),
+    NodeKind::Block(ss) => NodeKind::Block(fold_all(ss, |s| f.fold_stmt(s))),
+  };
+  Node {kind, span: n.span}
+}
+
+pub fn fold_leaf<'a, F: Fold<'a> + ?Sized>(f: &mut F, l: Leaf<'a, Sym>) -> Leaf<'a, Sym> {
+  Leaf {args: l.args.map(|args| fold_all(args, |a| f.fold_expr(a))), ..l}
+}
+
+pub fn fold_expansion<'a, F: Fold<'a> + ?Sized>(f: &mut F, e: Expansion<'a>) -> Expansion<'a> {
+  Expansion {args: e.args.map(|args| fold_all(args, |a| f.fold_expr(a))), ..e}
+}
+
+pub fn fold_ctx_node<'a, F: Fold<'a> + ?Sized>(f: &mut F, n: CtxNode<'a, Sym>) -> CtxNode<'a, Sym> {
+  let kind = match n.kind {
+    CtxNodeKind::Leaf(l) => CtxNodeKind::Leaf(f.fold_left_leaf(l)),
+    CtxNodeKind::Branch(ns) => CtxNodeKind::Branch(fold_all(ns, |n| f.fold_ctx_node(n))),
+  };
+  CtxNode {kind, span: n.span}
+}
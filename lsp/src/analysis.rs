@@ -0,0 +1,497 @@
+use std::cmp::Reverse;
+use std::path::PathBuf;
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::ast::normal::*;
+use lsd::ast::grammar::*;
+use lsd::source::Span;
+use lsd::Sym;
+use lsd::visit::{self, Visitor};
+use lsp_types::{
+  CompletionItem, CompletionItemKind, DocumentSymbol, Hover, HoverContents, Location, MarkupContent, MarkupKind,
+  NumberOrString, Position, Range, SymbolKind, Url,
+};
+use lsysgen::common::errors::{Diagnostic, Severity};
+use lsysgen::common::module::{ModuleId, ModuleLoader};
+
+use crate::lines::LineIndex;
+
+const KEYWORDS: &[&str] = &[
+  "and", "as", "axiom", "coding", "else", "false", "fn", "for", "from", "if", "import", "in", "let", "lsys", "main",
+  "mut", "not", "null", "or", "production", "return", "rules", "set", "table", "then", "true", "while",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+  Var,
+  Fn,
+  LSys,
+  Param,
+  Table,
+}
+
+impl DefKind {
+  fn describe(self) -> &'static str {
+    match self {
+      DefKind::Var => "variable",
+      DefKind::Fn => "function",
+      DefKind::LSys => "L system",
+      DefKind::Param => "parameter",
+      DefKind::Table => "rules table",
+    }
+  }
+}
+
+/// A name defined in the document.
+#[derive(Debug, Clone)]
+pub struct Def {
+  pub name: String,
+  pub kind: DefKind,
+  pub span: Span,
+  /// Where the name can be used: the block, function, rule or L system it's defined in.
+  pub scope: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RefKind {
+  /// A name used in an expression, maybe qualified with a module (`lv.leaf`).
+  Name(String),
+  /// `@name`.
+  Expansion(String),
+  /// A symbol of a word or of the left side of a rule.
+  Symbol(Sym),
+}
+
+/// Something in the document that hover and go-to-definition work on.
+#[derive(Debug, Clone)]
+struct Ref {
+  kind: RefKind,
+  span: Span,
+  /// L system it's in.
+  lsys: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct RuleInfo {
+  symbol: Sym,
+  lsys: Option<usize>,
+  table: Option<String>,
+  span: Span,
+}
+
+/// What the server knows about an open document. It's rebuilt on every change.
+pub struct Analysis {
+  uri: Url,
+  text: String,
+  lines: LineIndex,
+  loader: ModuleLoader,
+  id: ModuleId,
+  module: Option<Module<'static>>,
+  defs: Vec<Def>,
+  refs: Vec<Ref>,
+  rules: Vec<RuleInfo>,
+}
+
+impl Analysis {
+  pub fn new(uri: Url, text: String) -> Self {
+    let path = uri.to_file_path().unwrap_or_else(|_| PathBuf::from(uri.path()));
+    // The document is the first file of the loader, so its spans are offsets in `text`.
+    let mut loader = ModuleLoader::new();
+    let id = loader.load_source(&path, text.clone());
+    let module = lsd::parse_lsd_module(&text).0.map(Module::into_owned);
+
+    let mut index = Indexer::new(text.len());
+    if let Some(module) = &module {
+      index.visit_module(module);
+    }
+    Analysis {
+      lines: LineIndex::new(&text),
+      uri,
+      text,
+      loader,
+      id,
+      module,
+      defs: index.defs,
+      refs: index.refs,
+      rules: index.rules,
+    }
+  }
+
+  fn range(&self, span: Span) -> Range {
+    self.lines.range(&self.text, span)
+  }
+
+  /// Syntax and module errors of the document, converted to LSP diagnostics.
+  pub fn diagnostics(&self) -> Vec<lsp_types::Diagnostic> {
+    let file = self.loader.module(self.id).file;
+    let map = self.loader.source_map();
+    let in_doc = |span: Span| map.file_at(span.start).is_some_and(|f| std::ptr::eq(f, &map.files()[file]));
+    self.loader.errors().diagnostics().iter()
+      .filter(|d| d.primary_span().is_none_or(in_doc))
+      .map(|d| self.lsp_diagnostic(d, in_doc))
+      .collect()
+  }
+
+  fn lsp_diagnostic(&self, d: &Diagnostic, in_doc: impl Fn(Span) -> bool) -> lsp_types::Diagnostic {
+    let mut message = d.message.clone();
+    for note in &d.notes {
+      message.push_str("\nnote: ");
+      message.push_str(note);
+    }
+    if let Some(help) = &d.help {
+      message.push_str("\nhelp: ");
+      message.push_str(help);
+    }
+    let related = d.labels.iter()
+      .filter(|l| !l.primary && !l.message.is_empty() && in_doc(l.span))
+      .map(|l| lsp_types::DiagnosticRelatedInformation {
+        location: Location::new(self.uri.clone(), self.range(l.span)),
+        message: l.message.clone(),
+      })
+      .collect::<Vec<_>>();
+    lsp_types::Diagnostic {
+      range: self.range(d.primary_span().unwrap_or_default()),
+      severity: Some(match d.severity {
+        Severity::Error => lsp_types::DiagnosticSeverity::ERROR,
+        Severity::Warning => lsp_types::DiagnosticSeverity::WARNING,
+        Severity::Note => lsp_types::DiagnosticSeverity::INFORMATION,
+      }),
+      code: d.code.map(|c| NumberOrString::String(c.to_string())),
+      source: Some("lsd".to_string()),
+      message,
+      related_information: (!related.is_empty()).then_some(related),
+      ..Default::default()
+    }
+  }
+
+  /// The innermost reference at a position. A reference that ends right before the position is taken if there is
+  /// none under it.
+  fn ref_at(&self, pos: Position) -> Option<&Ref> {
+    let offset = self.lines.offset(&self.text, pos);
+    self.refs.iter()
+      .filter(|r| r.span.contains(offset))
+      .min_by_key(|r| (r.span.end == offset, r.span.len()))
+  }
+
+  /// The definition a name used at `at` refers to: the one in the innermost scope and, among those, the last one
+  /// before `at`.
+  fn resolve(&self, name: &str, at: usize) -> Option<&Def> {
+    self.defs.iter()
+      .filter(|d| d.name == name && d.scope.contains(at))
+      .max_by_key(|d| {
+        let before = d.span.start <= at;
+        // Closest before `at` or, if there is none, first after it.
+        let closeness = if before {d.span.start as i64} else {-(d.span.start as i64)};
+        (Reverse(d.scope.len()), before, closeness)
+      })
+  }
+
+  /// Looks for a name in the imported modules. Returns where it's defined and its first line.
+  fn resolve_import(&self, name: &str) -> Option<(Location, String)> {
+    let (id, name) = self.loader.resolve(self.id, name)?;
+    let module = self.loader.module(id);
+    let file = &self.loader.source_map().files()[module.file];
+    let span = self.loader.ast(id)?.stmts.iter().find_map(|s| match s {
+      ModStmt::VarDecl(d) if d.name == name => Some(d.span),
+      ModStmt::FnDef(d) if d.name == name => Some(d.span),
+      ModStmt::LSysDef(d) if d.name.as_deref() == Some(name) => Some(d.span),
+      _ => None,
+    })?;
+    let span = Span::new(span.start - file.start, span.end - file.start);
+    let uri = Url::from_file_path(module.path.canonicalize().ok()?).ok()?;
+    let range = LineIndex::new(&file.src).range(&file.src, span);
+    Some((Location::new(uri, range), first_line(&file.src, span).to_string()))
+  }
+
+  pub fn hover(&self, pos: Position) -> Option<Hover> {
+    let r = self.ref_at(pos)?;
+    let value = match &r.kind {
+      RefKind::Symbol(c) => {
+        let rules: Vec<&RuleInfo> = self.rules.iter()
+          .filter(|rule| rule.symbol == *c && (r.lsys.is_none() || rule.lsys == r.lsys))
+          .collect();
+        if rules.is_empty() {
+          format!("`{}` isn't rewritten by any rule", c)
+        } else {
+          let mut value = format!("`{}` is rewritten by:\n```lsd\n", c);
+          for rule in rules {
+            value.push_str(&self.text[rule.span.start..rule.span.end]);
+            if let Some(table) = &rule.table {
+              value.push_str(&format!("  // table {}", table));
+            }
+            value.push('\n');
+          }
+          value.push_str("```");
+          value
+        }
+      },
+      RefKind::Name(name) | RefKind::Expansion(name) => match self.resolve(name, r.span.start) {
+        Some(def) => format!("{} `{}`\n```lsd\n{}\n```", def.kind.describe(), name, first_line(&self.text, def.span)),
+        None => {
+          let (loc, line) = self.resolve_import(name)?;
+          let file = loc.uri.path_segments().and_then(|mut s| s.next_back()).unwrap_or_default().to_string();
+          format!("`{}`, defined in `{}`\n```lsd\n{}\n```", name, file, line)
+        },
+      },
+    };
+    Some(Hover {
+      contents: HoverContents::Markup(MarkupContent {kind: MarkupKind::Markdown, value}),
+      range: Some(self.range(r.span)),
+    })
+  }
+
+  pub fn definition(&self, pos: Position) -> Option<Location> {
+    let r = self.ref_at(pos)?;
+    match &r.kind {
+      RefKind::Name(name) | RefKind::Expansion(name) => match self.resolve(name, r.span.start) {
+        Some(def) => Some(Location::new(self.uri.clone(), self.range(def.span))),
+        None => self.resolve_import(name).map(|(loc, _)| loc),
+      },
+      // The first rule that rewrites the symbol.
+      RefKind::Symbol(c) => self.rules.iter()
+        .find(|rule| rule.symbol == *c && (r.lsys.is_none() || rule.lsys == r.lsys))
+        .map(|rule| Location::new(self.uri.clone(), self.range(rule.span))),
+    }
+  }
+
+  /// Keywords and the names that can be used at a position. After `@`, only names.
+  pub fn completions(&self, pos: Position) -> Vec<CompletionItem> {
+    let offset = self.lines.offset(&self.text, pos);
+    let after_at = self.text[..offset].trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_').ends_with('@');
+
+    let mut items: Vec<CompletionItem> = Vec::new();
+    let mut push = |label: &str, kind, detail: &str| {
+      if !items.iter().any(|i| i.label == label) {
+        items.push(CompletionItem {
+          label: label.to_string(),
+          kind: Some(kind),
+          detail: (!detail.is_empty()).then(|| detail.to_string()),
+          ..Default::default()
+        });
+      }
+    };
+    for def in self.defs.iter().filter(|d| d.scope.contains(offset) && d.kind != DefKind::Table) {
+      let kind = match def.kind {
+        DefKind::Fn => CompletionItemKind::FUNCTION,
+        DefKind::LSys => CompletionItemKind::MODULE,
+        _ => CompletionItemKind::VARIABLE,
+      };
+      push(&def.name, kind, def.kind.describe());
+    }
+    for import in &self.loader.module(self.id).imports {
+      if import.symbols.is_empty() {
+        push(&import.name, CompletionItemKind::MODULE, "module");
+      }
+      for symbol in &import.symbols {
+        push(symbol, CompletionItemKind::VARIABLE, &import.name);
+      }
+    }
+    if !after_at {
+      for kw in KEYWORDS {
+        push(kw, CompletionItemKind::KEYWORD, "");
+      }
+    }
+    items
+  }
+
+  /// L systems, tables, rules, functions and variables of the document, nested as in the source.
+  pub fn symbols(&self) -> Vec<DocumentSymbol> {
+    let Some(module) = &self.module else {
+      return Vec::new();
+    };
+    module.stmts.iter().flat_map(|s| match s {
+      ModStmt::Import(_) => Vec::new(),
+      ModStmt::VarDecl(d) => vec![self.symbol(&d.name, SymbolKind::VARIABLE, d.span, None, Vec::new())],
+      ModStmt::FnDef(d) => vec![self.symbol(&d.name, SymbolKind::FUNCTION, d.span, None, Vec::new())],
+      // An implicit L system is the whole document.
+      ModStmt::LSysDef(d) if d.name.is_none() => self.lsys_symbols(d),
+      ModStmt::LSysDef(d) => vec![self.lsys_symbol(d)],
+    }).collect()
+  }
+
+  fn lsys_symbol(&self, d: &LSysDef) -> DocumentSymbol {
+    let detail = d.main.then(|| "main".to_string());
+    self.symbol(d.name.as_deref().unwrap_or_default(), SymbolKind::CLASS, d.span, detail, self.lsys_symbols(d))
+  }
+
+  fn lsys_symbols(&self, d: &LSysDef) -> Vec<DocumentSymbol> {
+    let mut symbols = Vec::new();
+    for s in &d.stmts {
+      match s {
+        LSysStmt::Stmt(Stmt {kind: StmtKind::LSysDef(d), ..}) => symbols.push(self.lsys_symbol(d)),
+        LSysStmt::Stmt(Stmt {kind: StmtKind::FnDef(d), ..}) =>
+          symbols.push(self.symbol(&d.name, SymbolKind::FUNCTION, d.span, None, Vec::new())),
+        LSysStmt::Stmt(Stmt {kind: StmtKind::VarDecl(d), ..}) =>
+          symbols.push(self.symbol(&d.name, SymbolKind::VARIABLE, d.span, None, Vec::new())),
+        LSysStmt::TableDef(t) => {
+          let rules = self.rule_symbols(&t.rules, None);
+          symbols.push(self.symbol(t.name.as_deref().unwrap_or_default(), SymbolKind::NAMESPACE, t.span, None, rules));
+        },
+        LSysStmt::RulesDef(rs) => symbols.extend(self.rule_symbols(rs, None)),
+        LSysStmt::ProductionRulesDef(rs) => symbols.extend(self.rule_symbols(rs, Some("production"))),
+        LSysStmt::CodingRulesDef(rs) => symbols.extend(self.rule_symbols(rs, Some("coding"))),
+        _ => {},
+      }
+    }
+    symbols
+  }
+
+  fn rule_symbols(&self, rules: &[Rule<Sym>], detail: Option<&str>) -> Vec<DocumentSymbol> {
+    rules.iter().map(|r| {
+      let name = self.text[r.span().start..r.span().end].split_whitespace().collect::<Vec<_>>().join(" ");
+      self.symbol(&name, SymbolKind::OPERATOR, r.span(), detail.map(str::to_string), Vec::new())
+    }).collect()
+  }
+
+  #[allow(deprecated)]
+  fn symbol(&self, name: &str, kind: SymbolKind, span: Span, detail: Option<String>, children: Vec<DocumentSymbol>) -> DocumentSymbol {
+    DocumentSymbol {
+      name: name.to_string(),
+      detail,
+      kind,
+      tags: None,
+      deprecated: None,
+      range: self.range(span),
+      selection_range: self.range(span),
+      children: (!children.is_empty()).then_some(children),
+    }
+  }
+}
+
+fn first_line(text: &str, span: Span) -> &str {
+  text[span.start..span.end].lines().next().unwrap_or_default().trim_end()
+}
+
+/// Length of a symbol as written in the source: names are quoted.
+fn symbol_len(s: Sym) -> usize {
+  s.as_name().map_or_else(|| s.as_char().map_or(0, char::len_utf8), |name| name.len() + 2)
+}
+
+/// Collects definitions, references and rules.
+struct Indexer {
+  defs: Vec<Def>,
+  refs: Vec<Ref>,
+  rules: Vec<RuleInfo>,
+  scopes: Vec<Span>,
+  /// L systems being visited, innermost last.
+  lsys: Vec<usize>,
+  lsys_count: usize,
+  table: Option<String>,
+}
+
+impl Indexer {
+  fn new(len: usize) -> Self {
+    Indexer {
+      defs: Vec::new(),
+      refs: Vec::new(),
+      rules: Vec::new(),
+      scopes: vec![Span::new(0, len)],
+      lsys: Vec::new(),
+      lsys_count: 0,
+      table: None,
+    }
+  }
+
+  fn def(&mut self, name: &str, kind: DefKind, span: Span) {
+    let scope = *self.scopes.last().unwrap();
+    self.defs.push(Def {name: name.to_string(), kind, span, scope});
+  }
+
+  fn reference(&mut self, kind: RefKind, span: Span) {
+    self.refs.push(Ref {kind, span, lsys: self.lsys.last().copied()});
+  }
+
+  fn scoped(&mut self, scope: Span, f: impl FnOnce(&mut Self)) {
+    self.scopes.push(scope);
+    f(self);
+    self.scopes.pop();
+  }
+}
+
+impl<'ast> Visitor<'ast> for Indexer {
+  fn visit_var_decl(&mut self, d: &'ast VarDecl<'ast>) {
+    visit::walk_var_decl(self, d);
+    self.def(&d.name, DefKind::Var, d.span);
+  }
+
+  fn visit_fn_def(&mut self, d: &'ast FnDef<'ast>) {
+    self.def(&d.name, DefKind::Fn, d.span);
+    self.scoped(d.span, |v| visit::walk_fn_def(v, d));
+  }
+
+  fn visit_lsys_def(&mut self, d: &'ast LSysDef<'ast>) {
+    if let Some(name) = &d.name {
+      self.def(name, DefKind::LSys, d.span);
+    }
+    self.lsys.push(self.lsys_count);
+    self.lsys_count += 1;
+    self.scoped(d.span, |v| visit::walk_lsys_def(v, d));
+    self.lsys.pop();
+  }
+
+  fn visit_param(&mut self, p: &'ast Param<'ast>) {
+    visit::walk_param(self, p);
+    self.def(&p.name, DefKind::Param, p.span);
+  }
+
+  fn visit_stmt(&mut self, s: &'ast Stmt<'ast>) {
+    match &s.kind {
+      StmtKind::Block(_) => self.scoped(s.span, |v| visit::walk_stmt(v, s)),
+      StmtKind::For(var, ..) => self.scoped(s.span, |v| {
+        v.def(var, DefKind::Var, s.span);
+        visit::walk_stmt(v, s);
+      }),
+      _ => visit::walk_stmt(self, s),
+    }
+  }
+
+  fn visit_expr(&mut self, e: &'ast Expr<'ast>) {
+    match &e.kind {
+      ExprKind::ID(name) => self.reference(RefKind::Name(name.to_string()), e.span),
+      // `module.name`: the reference is the name after the dot.
+      ExprKind::PropAcc(m, prop) if matches!(m.kind, ExprKind::ID(_)) => {
+        if let ExprKind::ID(module) = &m.kind {
+          self.reference(RefKind::Name(format!("{}.{}", module, prop)), Span::new(e.span.end - prop.len(), e.span.end));
+        }
+      },
+      ExprKind::Lambda(..) => return self.scoped(e.span, |v| visit::walk_expr(v, e)),
+      _ => {},
+    }
+    visit::walk_expr(self, e);
+  }
+
+  fn visit_rules_table(&mut self, t: &'ast RulesTable<'ast, Sym>) {
+    if let Some(name) = &t.name {
+      self.def(name, DefKind::Table, t.span);
+    }
+    self.table = t.name.as_deref().map(str::to_string);
+    visit::walk_rules_table(self, t);
+    self.table = None;
+  }
+
+  fn visit_rule(&mut self, r: &'ast Rule<'ast, Sym>) {
+    let base = r.base();
+    self.rules.push(RuleInfo {symbol: base.left_leaf.symbol, lsys: self.lsys.last().copied(), table: self.table.clone(), span: r.span()});
+    self.scoped(r.span(), |v| {
+      for p in base.left_leaf.params.iter().flatten() {
+        v.def(p, DefKind::Param, base.left_leaf.span);
+      }
+      visit::walk_rule(v, r);
+    });
+  }
+
+  fn visit_leaf(&mut self, l: &'ast Leaf<'ast, Sym>) {
+    self.reference(RefKind::Symbol(l.symbol), Span::new(l.span.start, l.span.start + symbol_len(l.symbol)));
+    visit::walk_leaf(self, l);
+  }
+
+  fn visit_left_leaf(&mut self, l: &'ast LeftLeaf<'ast, Sym>) {
+    self.reference(RefKind::Symbol(l.symbol), Span::new(l.span.start, l.span.start + symbol_len(l.symbol)));
+  }
+
+  fn visit_expansion(&mut self, e: &'ast Expansion<'ast>) {
+    self.reference(RefKind::Expansion(e.to.to_string()), Span::new(e.span.start, e.span.start + 1 + e.to.len()));
+    visit::walk_expansion(self, e);
+  }
+}
@@ -0,0 +1,49 @@
+use std::vec::Vec;
+
+use lsd::source::Span;
+use lsp_types::{Position, Range};
+
+/// Converts byte offsets of a document to LSP positions and back. LSP columns count UTF-16 code units.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+  /// Offsets where each line starts.
+  line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+  pub fn new(text: &str) -> Self {
+    let line_starts = std::iter::once(0)
+      .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+      .collect();
+    LineIndex {line_starts}
+  }
+
+  pub fn position(&self, text: &str, offset: usize) -> Position {
+    let offset = offset.min(text.len());
+    let line = self.line_starts.partition_point(|&s| s <= offset) - 1;
+    let start = self.line_starts[line];
+    let col = text.get(start..offset).map_or(0, |s| s.encode_utf16().count());
+    Position::new(line as u32, col as u32)
+  }
+
+  pub fn range(&self, text: &str, span: Span) -> Range {
+    Range::new(self.position(text, span.start), self.position(text, span.end))
+  }
+
+  /// Byte offset of a position. Positions past the end of a line are moved to its end.
+  pub fn offset(&self, text: &str, pos: Position) -> usize {
+    let Some(&start) = self.line_starts.get(pos.line as usize) else {
+      return text.len();
+    };
+    let line = &text[start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+      if units >= pos.character as usize {
+        return start + i;
+      }
+      units += c.len_utf16();
+    }
+    start + line.len()
+  }
+}
@@ -0,0 +1,15 @@
+mod analysis;
+mod lines;
+mod server;
+
+#[cfg(test)]
+mod test;
+
+fn main() {
+  let (conn, io_threads) = lsp_server::Connection::stdio();
+  let res = server::run(conn).and_then(|()| Ok(io_threads.join()?));
+  if let Err(e) = res {
+    eprintln!("error: {}", e);
+    std::process::exit(1);
+  }
+}
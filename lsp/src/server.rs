@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::string::String;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+  DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as LspRequest};
+use lsp_types::{
+  CompletionOptions, CompletionResponse, DocumentSymbolResponse, GotoDefinitionResponse, HoverProviderCapability,
+  OneOf, PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+use crate::analysis::Analysis;
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+pub fn capabilities() -> ServerCapabilities {
+  ServerCapabilities {
+    text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+    hover_provider: Some(HoverProviderCapability::Simple(true)),
+    definition_provider: Some(OneOf::Left(true)),
+    completion_provider: Some(CompletionOptions {trigger_characters: Some(vec!["@".to_string()]), ..Default::default()}),
+    document_symbol_provider: Some(OneOf::Left(true)),
+    ..Default::default()
+  }
+}
+
+/// Initializes the connection and serves requests until the client asks to shut down.
+pub fn run(conn: Connection) -> Result<()> {
+  conn.initialize(serde_json::to_value(capabilities())?)?;
+  Server {conn, docs: HashMap::new()}.main_loop()
+}
+
+struct Server {
+  conn: Connection,
+  /// Open documents.
+  docs: HashMap<Url, Analysis>,
+}
+
+impl Server {
+  fn main_loop(&mut self) -> Result<()> {
+    while let Ok(msg) = self.conn.receiver.recv() {
+      match msg {
+        Message::Request(req) => {
+          if self.conn.handle_shutdown(&req)? {
+            return Ok(());
+          }
+          let resp = self.request(req);
+          self.conn.sender.send(resp.into())?;
+        },
+        Message::Notification(not) => self.notification(not)?,
+        Message::Response(_) => {},
+      }
+    }
+    Ok(())
+  }
+
+  fn request(&self, req: Request) -> Response {
+    match req.method.as_str() {
+      HoverRequest::METHOD => self.handle::<HoverRequest>(req, |s, p| {
+        let pos = p.text_document_position_params;
+        s.docs.get(&pos.text_document.uri)?.hover(pos.position)
+      }),
+      GotoDefinition::METHOD => self.handle::<GotoDefinition>(req, |s, p| {
+        let pos = p.text_document_position_params;
+        s.docs.get(&pos.text_document.uri)?.definition(pos.position).map(GotoDefinitionResponse::Scalar)
+      }),
+      Completion::METHOD => self.handle::<Completion>(req, |s, p| {
+        let pos = p.text_document_position;
+        Some(CompletionResponse::Array(s.docs.get(&pos.text_document.uri)?.completions(pos.position)))
+      }),
+      DocumentSymbolRequest::METHOD => self.handle::<DocumentSymbolRequest>(req, |s, p| {
+        Some(DocumentSymbolResponse::Nested(s.docs.get(&p.text_document.uri)?.symbols()))
+      }),
+      _ => Response::new_err(req.id, ErrorCode::MethodNotFound as i32, format!("unknown method `{}`", req.method)),
+    }
+  }
+
+  fn handle<R: LspRequest>(&self, req: Request, f: impl FnOnce(&Self, R::Params) -> R::Result) -> Response {
+    match serde_json::from_value(req.params) {
+      Ok(params) => Response::new_ok(req.id, f(self, params)),
+      Err(e) => Response::new_err(req.id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+  }
+
+  fn notification(&mut self, not: Notification) -> Result<()> {
+    match not.method.as_str() {
+      DidOpenTextDocument::METHOD => {
+        let p: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+        self.update(p.text_document.uri, p.text_document.text, Some(p.text_document.version))
+      },
+      DidChangeTextDocument::METHOD => {
+        let p: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+        // The whole text is sent on every change.
+        match p.content_changes.into_iter().next_back() {
+          Some(change) => self.update(p.text_document.uri, change.text, Some(p.text_document.version)),
+          None => Ok(()),
+        }
+      },
+      DidCloseTextDocument::METHOD => {
+        let p: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+        self.docs.remove(&p.text_document.uri);
+        self.publish(p.text_document.uri, Vec::new(), None)
+      },
+      _ => Ok(()),
+    }
+  }
+
+  /// Analyzes a document again and publishes its diagnostics.
+  fn update(&mut self, uri: Url, text: String, version: Option<i32>) -> Result<()> {
+    let analysis = Analysis::new(uri.clone(), text);
+    let diagnostics = analysis.diagnostics();
+    self.docs.insert(uri.clone(), analysis);
+    self.publish(uri, diagnostics, version)
+  }
+
+  fn publish(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>, version: Option<i32>) -> Result<()> {
+    let params = PublishDiagnosticsParams {uri, diagnostics, version};
+    let not = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+    self.conn.sender.send(not.into())?;
+    Ok(())
+  }
+}
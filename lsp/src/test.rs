@@ -0,0 +1,117 @@
+use lsp_server::{Connection, Message, Notification, Request, RequestId};
+use lsp_types::{CompletionItemKind, HoverContents, Position, SymbolKind, Url};
+use serde_json::json;
+
+use super::analysis::Analysis;
+use super::server;
+
+const SRC: &str = "fn size(n) {return n * 2}
+let len = size(3)
+
+main lsys koch(angle = 25) {
+  axiom F@plant
+  table t {
+    F -> F+F-F
+  }
+  rules {
+    F(x) : x > len -> F(x / 3)G
+    G -> @plant
+  }
+}
+
+lsys plant {axiom X}
+";
+
+fn doc() -> Url {
+  Url::parse("file:///tmp/koch.lsd").unwrap()
+}
+
+/// Position of the `n`th occurrence of `pat` in `SRC`, plus `delta` characters. Everything is in one line.
+fn pos(pat: &str, n: usize, delta: u32) -> Position {
+  let offset = SRC.match_indices(pat).nth(n).unwrap().0;
+  let line = SRC[..offset].matches('\n').count() as u32;
+  let col = (offset - SRC[..offset].rfind('\n').map_or(0, |i| i + 1)) as u32;
+  Position::new(line, col + delta)
+}
+
+fn hover_text(a: &Analysis, pos: Position) -> String {
+  match a.hover(pos).unwrap().contents {
+    HoverContents::Markup(m) => m.value,
+    c => panic!("unexpected hover contents {:?}", c),
+  }
+}
+
+#[test]
+fn analysis() {
+  let a = Analysis::new(doc(), SRC.to_string());
+  assert_eq!(a.diagnostics(), vec![]);
+
+  // Symbols show the rules that rewrite them, in tables too.
+  let hover = hover_text(&a, pos("F+F", 0, 2));
+  assert!(hover.contains("F -> F+F-F  // table t"), "{}", hover);
+  assert!(hover.contains("F(x) : x > len -> F(x / 3)G"), "{}", hover);
+  assert!(hover_text(&a, pos("X", 0, 0)).contains("isn't rewritten"));
+  assert!(hover_text(&a, pos("len", 1, 0)).starts_with("variable `len`"));
+
+  // Names, expansions and rule parameters.
+  let def = |p| a.definition(p).unwrap().range.start;
+  assert_eq!(def(pos("size(3)", 0, 1)), Position::new(0, 0));
+  assert_eq!(def(pos("@plant", 1, 2)), Position::new(14, 0));
+  assert_eq!(def(pos("x / 3", 0, 0)), pos("F(x)", 0, 0));
+  assert_eq!(def(pos("n * 2", 0, 0)), pos("n)", 0, 0));
+
+  let completions = a.completions(pos("@plant", 1, 1));
+  assert!(completions.iter().any(|c| c.label == "plant" && c.kind == Some(CompletionItemKind::MODULE)));
+  assert!(completions.iter().all(|c| c.kind != Some(CompletionItemKind::KEYWORD)));
+  assert!(a.completions(pos("rules", 0, 0)).iter().any(|c| c.label == "axiom"));
+
+  let symbols = a.symbols();
+  let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+  assert_eq!(names, ["size", "len", "koch", "plant"]);
+  let koch = symbols[2].children.as_ref().unwrap();
+  assert_eq!(koch[0].kind, SymbolKind::NAMESPACE);
+  assert_eq!(koch[0].children.as_ref().unwrap()[0].name, "F -> F+F-F");
+  assert_eq!(koch[1].name, "F(x) : x > len -> F(x / 3)G");
+
+  // Named symbols.
+  let a = Analysis::new(doc(), "axiom \"Apex\"\nrules {\"Apex\" -> F\"Apex\"}\n".to_string());
+  assert!(hover_text(&a, Position::new(0, 10)).starts_with("`Apex` is rewritten by"));
+}
+
+#[test]
+fn json_rpc_session() {
+  let (server_conn, client) = Connection::memory();
+  let server = std::thread::spawn(move || server::run(server_conn).unwrap());
+
+  let request = |id: i32, method: &str, params| Message::Request(Request::new(RequestId::from(id), method.to_string(), params));
+  let notify = |method: &str, params| Message::Notification(Notification::new(method.to_string(), params));
+  let response = || match client.receiver.recv().unwrap() {
+    Message::Response(resp) => resp.result.unwrap(),
+    msg => panic!("expected a response, got {:?}", msg),
+  };
+
+  client.sender.send(request(1, "initialize", json!({"capabilities": {}}))).unwrap();
+  assert_eq!(response()["capabilities"]["hoverProvider"], json!(true));
+  client.sender.send(notify("initialized", json!({}))).unwrap();
+
+  let open = json!({"textDocument": {"uri": doc(), "languageId": "lsd", "version": 1, "text": "axiom F\nrules {F -> }}"}});
+  client.sender.send(notify("textDocument/didOpen", open)).unwrap();
+  let Message::Notification(diags) = client.receiver.recv().unwrap() else {panic!("expected diagnostics")};
+  assert_eq!(diags.method, "textDocument/publishDiagnostics");
+  assert_eq!(diags.params["diagnostics"][0]["range"]["start"], json!({"line": 1, "character": 13}));
+
+  let change = json!({"textDocument": {"uri": doc(), "version": 2}, "contentChanges": [{"text": SRC}]});
+  client.sender.send(notify("textDocument/didChange", change)).unwrap();
+  let Message::Notification(diags) = client.receiver.recv().unwrap() else {panic!("expected diagnostics")};
+  assert_eq!(diags.params["diagnostics"], json!([]));
+
+  let at = pos("size(3)", 0, 0);
+  let params = json!({"textDocument": {"uri": doc()}, "position": at});
+  client.sender.send(request(2, "textDocument/definition", params)).unwrap();
+  assert_eq!(response()["range"]["start"], json!({"line": 0, "character": 0}));
+
+  client.sender.send(request(3, "shutdown", json!(null))).unwrap();
+  response();
+  client.sender.send(notify("exit", json!(null))).unwrap();
+  server.join().unwrap();
+}
@@ -0,0 +1,405 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::Sym;
+use lsd::convert::Dialect;
+use lsd::source::{SourceMap, Span};
+use lsysgen::common::Value;
+use lsysgen::common::builder::LSystemBuilder;
+use lsysgen::common::lsystem::{Arg, LSystem};
+use lsysgen::common::errors::{Diagnostic, ErrorHandler};
+use lsysgen::common::module::{ModuleId, ModuleLoader};
+use lsysgen::common::tree::{save_tree, source_hash, Graph, GraphFormat, Tree, TreeFile, TreeMeta};
+
+use crate::cliargs::{Command, ConvertArgs, FmtArgs, GraphArgs, RunArgs, StatsArgs, USAGE};
+
+/// Name of the standard input, when it's read as FILE.
+const STDIN: &str = "<stdin>";
+
+/// Runs a command and returns the exit code of the program.
+pub fn run(cmd: Command) -> i32 {
+  match cmd {
+    Command::Help => {
+      println!("{}", USAGE);
+      0
+    },
+    Command::List(args) => list(&args),
+    Command::Run(args) => run_lsystem(args),
+    Command::Fmt(args) => fmt(&args),
+    Command::Convert(args) => convert(&args),
+    Command::Stats(args) => stats(&args),
+    Command::Graph(args) => graph(&args),
+  }
+}
+
+fn load(args: &RunArgs) -> Option<(ModuleLoader, ModuleId)> {
+  let mut loader = ModuleLoader::new();
+  for dir in &args.include {
+    loader.add_search_path(dir);
+  }
+  let dialect = match args.from.as_deref().map(dialect).transpose() {
+    Ok(dialect) => dialect,
+    Err(diag) => {
+      report(&loader, diag);
+      return None;
+    },
+  };
+  // Sin `--from`, la entrada estándar se lee como LSD, salvo que se vea que es de otro dialecto
+  let mut as_lsd = false;
+  let id = if args.file == Path::new("-") || dialect.is_some() {
+    match read_input(&args.file) {
+      Ok((path, src)) => Some(match dialect.or_else(|| Dialect::detect(&src)) {
+        Some(dialect) => loader.load_source_as(path, src, dialect),
+        None => {
+          as_lsd = true;
+          loader.load_source(path, src)
+        },
+      }),
+      Err(diag) => {
+        report(&loader, diag);
+        return None;
+      },
+    }
+  } else {
+    loader.load_file(&args.file)
+  };
+  loader.errors().emit(loader.source_map());
+  match id {
+    Some(id) if !loader.errors().has_errors() => Some((loader, id)),
+    _ => {
+      if as_lsd && loader.errors().has_errors() {
+        report(&loader, Diagnostic::note("the standard input was read as LSD")
+          .with_help(format!("if it's written in another dialect, give it with `--from`: {}", dialect_names())));
+      }
+      None
+    },
+  }
+}
+
+/// Reads FILE or, if it's `-`, the standard input. Returns the name of the file too.
+fn read_input(path: &Path) -> Result<(PathBuf, String), Diagnostic> {
+  if path == Path::new("-") {
+    let mut src = String::new();
+    match std::io::stdin().read_to_string(&mut src) {
+      Ok(_) => Ok((PathBuf::from(STDIN), src)),
+      Err(e) => Err(Diagnostic::error(format!("couldn't read the standard input: {}", e)).with_code("M0004")),
+    }
+  } else {
+    match std::fs::read_to_string(path) {
+      Ok(src) => Ok((path.to_path_buf(), src)),
+      Err(e) => Err(Diagnostic::error(format!("couldn't read `{}`: {}", path.display(), e)).with_code("M0004")),
+    }
+  }
+}
+
+fn dialect(name: &str) -> Result<Dialect, Diagnostic> {
+  Dialect::from_name(name).ok_or_else(|| Diagnostic::error(format!("unknown dialect `{}`", name))
+    .with_note(format!("dialects are {}", dialect_names())))
+}
+
+fn dialect_names() -> String {
+  Dialect::ALL.map(|d| format!("`{}`", d.name())).join(", ")
+}
+
+fn report(loader: &ModuleLoader, diag: Diagnostic) {
+  let mut err = ErrorHandler::new();
+  err.push(diag);
+  err.emit(loader.source_map());
+}
+
+fn list(args: &RunArgs) -> i32 {
+  let (loader, id) = match load(args) {
+    Some(res) => res,
+    None => return 1,
+  };
+  for decl in &loader.module(id).lsystems {
+    let params: Vec<String> = decl.params.iter()
+      .map(|p| if p.optional {format!("{}?", p.name)} else {p.name.clone()})
+      .collect();
+    let main = if decl.main {" (main)"} else {""};
+    println!("{}({}){}", decl.display_name(), params.join(", "), main);
+  }
+  0
+}
+
+/// Derives an L system and prints the word it gets to, or writes it to `--output`.
+fn run_lsystem(args: RunArgs) -> i32 {
+  let (loader, id, lsystem) = match derive(&args) {
+    Some(res) => res,
+    None => return 1,
+  };
+  if let Some(path) = &args.save_tree && let Err(e) = save_tree(path, lsystem.current(), &meta(&loader, id, &lsystem)) {
+    report(&loader, Diagnostic::error(format!("couldn't write `{}`: {}", path.display(), e)));
+    return 1;
+  }
+  let word = lsystem.current().to_string();
+  match &args.output {
+    Some(path) => match std::fs::write(path, word + "\n") {
+      Ok(()) => 0,
+      Err(e) => {
+        report(&loader, Diagnostic::error(format!("couldn't write `{}`: {}", path.display(), e)));
+        1
+      },
+    },
+    None => {
+      println!("{}", word);
+      0
+    },
+  }
+}
+
+/// Loads, builds and derives the L system chosen by the options of `run`, and returns it with the module it was loaded
+/// from. Errors are printed, and `None` is returned if there are any.
+fn derive(args: &RunArgs) -> Option<(ModuleLoader, ModuleId, LSystem<Sym>)> {
+  let (loader, id) = load(args)?;
+  let decl = match loader.choose_lsystem(id, args.system.as_deref()) {
+    Ok(decl) => decl,
+    Err(diag) => {
+      report(&loader, diag);
+      return None;
+    },
+  };
+  let cli_args = args.args.iter().map(|(name, val)| Arg {name: name.clone(), value: parse_value(val)}).collect();
+  let bound = match decl.bind(cli_args) {
+    Ok(bound) => bound,
+    Err(diag) => {
+      report(&loader, diag);
+      return None;
+    },
+  };
+
+  let mut builder = LSystemBuilder::new(&loader);
+  let lsystem = builder.build(decl, bound);
+  builder.errors().emit(loader.source_map());
+  let mut lsystem = lsystem?;
+  if let Some(iterations) = args.iterations {
+    lsystem = lsystem.with_iterations(iterations);
+  }
+  if let Some(seed) = args.seed {
+    lsystem = lsystem.with_seed(seed);
+  }
+  lsystem.derive();
+  if lsystem.errors().has_errors() {
+    lsystem.errors().emit(loader.source_map());
+    return None;
+  }
+  Some((loader, id, lsystem))
+}
+
+/// Formats files in place or, with `--check`, prints the ones that aren't formatted.
+fn fmt(args: &FmtArgs) -> i32 {
+  let mut code = 0;
+  for path in &args.files {
+    let mut map = SourceMap::new();
+    let mut err = ErrorHandler::new();
+    let src = match std::fs::read_to_string(path) {
+      Ok(src) => src,
+      Err(e) => {
+        err.push(Diagnostic::error(format!("couldn't read `{}`: {}", path.display(), e)).with_code("M0004"));
+        err.emit(&map);
+        code = 1;
+        continue;
+      },
+    };
+    map.add_file(path, src);
+    let file = &map.files()[0];
+    let formatted = match lsd::format_lsd(&file.src) {
+      Ok(formatted) => formatted,
+      Err(errors) => {
+        err.extend(errors);
+        err.emit(&map);
+        code = 1;
+        continue;
+      },
+    };
+
+    if formatted == file.src {
+      continue;
+    }
+    if args.check {
+      println!("{}", path.display());
+      code = 1;
+    } else if let Err(e) = std::fs::write(path, formatted) {
+      err.push(Diagnostic::error(format!("couldn't write `{}`: {}", path.display(), e)));
+      err.emit(&map);
+      code = 1;
+    }
+  }
+  code
+}
+
+/// Converts a file of another dialect and writes the LSD source. Conversion warnings are printed, but don't make it
+/// fail.
+fn convert(args: &ConvertArgs) -> i32 {
+  let mut map = SourceMap::new();
+  let mut err = ErrorHandler::new();
+  let dialect = match &args.from {
+    Some(name) => dialect(name),
+    None => args.file.extension().and_then(|e| e.to_str()).and_then(Dialect::from_extension)
+      .ok_or_else(|| Diagnostic::error(format!("can't tell the dialect of `{}`: use `--from`", args.file.display()))),
+  };
+  let (dialect, (path, src)) = match dialect.and_then(|dialect| Ok((dialect, read_input(&args.file)?))) {
+    Ok(input) => input,
+    Err(diag) => {
+      err.push(diag);
+      err.emit(&map);
+      return 1;
+    },
+  };
+
+  let converted = dialect.convert(&src);
+  let start = map.add_file(path, src).start;
+  for w in converted.warnings {
+    err.push(Diagnostic::warning(w.message)
+      .with_code("C0001")
+      .with_primary(Span::new(start + w.span.start, start + w.span.end), ""));
+  }
+  err.emit(&map);
+  match &args.output {
+    Some(path) => match std::fs::write(path, converted.lsd) {
+      Ok(()) => 0,
+      Err(e) => {
+        let mut err = ErrorHandler::new();
+        err.push(Diagnostic::error(format!("couldn't write `{}`: {}", path.display(), e)));
+        err.emit(&map);
+        1
+      },
+    },
+    None => {
+      print!("{}", converted.lsd);
+      0
+    },
+  }
+}
+
+/// Prints the statistics of a word, read as text or from a tree file or derived, and saves it with `--save-tree`.
+fn stats(args: &StatsArgs) -> i32 {
+  let mut map = SourceMap::new();
+  let mut err = ErrorHandler::new();
+  let res = match &args.derive {
+    Some(run) => derived_word(run),
+    None => read_tree(&mut map, args.word.as_deref(), args.load_tree.as_deref()),
+  };
+  let (tree, meta) = match res {
+    Ok(res) => res,
+    Err(errors) => {
+      err.extend(errors);
+      err.emit(&map);
+      return 1;
+    },
+  };
+  if let Some(path) = &args.save_tree && let Err(e) = save_tree(path, &tree, &meta) {
+    err.push(Diagnostic::error(format!("couldn't write `{}`: {}", path.display(), e)));
+    err.emit(&map);
+    return 1;
+  }
+  if args.load_tree.is_some() || args.derive.is_some() {
+    println!("iteration:     {}", meta.iteration);
+    println!("seed:          {}", meta.seed);
+    println!("source hash:   {:016x}", meta.source_hash);
+  }
+  println!("{}", tree.stats());
+  0
+}
+
+/// Exports a word, read as text or from a tree file or derived, as a graph.
+fn graph(args: &GraphArgs) -> i32 {
+  let mut map = SourceMap::new();
+  let mut err = ErrorHandler::new();
+  let format = match (&args.format, &args.output) {
+    (Some(name), _) => GraphFormat::from_name(name)
+      .ok_or_else(|| Diagnostic::error(format!("unknown graph format `{}`", name))),
+    (None, Some(path)) => Ok(path.extension().and_then(|e| e.to_str()).and_then(GraphFormat::from_extension)
+      .unwrap_or(GraphFormat::Dot)),
+    (None, None) => Ok(GraphFormat::Dot),
+  };
+  let res = format.map_err(|diag| vec![diag]).and_then(|format| {
+    let word = match &args.derive {
+      Some(run) => derived_word(run)?,
+      None => read_tree(&mut map, args.word.as_deref(), args.load_tree.as_deref())?,
+    };
+    Ok((format, word))
+  });
+  let (format, (tree, _)) = match res {
+    Ok(res) => res,
+    Err(errors) => {
+      err.extend(errors);
+      err.emit(&map);
+      return 1;
+    },
+  };
+  let graph = Graph::new(&tree);
+  let res = match &args.output {
+    Some(path) => std::fs::File::create(path).and_then(|file| graph.write(format, file))
+      .map_err(|e| format!("couldn't write `{}`: {}", path.display(), e)),
+    None => graph.write(format, std::io::stdout().lock()).map_err(|e| format!("couldn't write the graph: {}", e)),
+  };
+  match res {
+    Ok(()) => 0,
+    Err(msg) => {
+      err.push(Diagnostic::error(msg));
+      err.emit(&map);
+      1
+    },
+  }
+}
+
+/// Derives the word of `stats` or `graph`. Its errors are printed as they are found, so none are returned.
+fn derived_word(args: &RunArgs) -> Result<(Tree, TreeMeta), Vec<Diagnostic>> {
+  let (loader, id, lsystem) = derive(args).ok_or_else(Vec::new)?;
+  Ok((lsystem.current().clone(), meta(&loader, id, &lsystem)))
+}
+
+/// What is saved with a derived word: its iteration, the seed and the hash of the module of the L system.
+fn meta(loader: &ModuleLoader, id: ModuleId, lsystem: &LSystem<Sym>) -> TreeMeta {
+  let src = &loader.source_map().files()[loader.module(id).file].src;
+  TreeMeta {seed: lsystem.seed(), iteration: lsystem.iteration() as u64, source_hash: source_hash(src)}
+}
+
+/// Reads the word of `stats` or `graph`, from the tree file `load_tree` if there is one, or else from the text of
+/// `word`.
+fn read_tree(map: &mut SourceMap, word: Option<&Path>, load_tree: Option<&Path>) -> Result<(Tree, TreeMeta), Vec<Diagnostic>> {
+  match load_tree {
+    Some(path) => TreeFile::open(path)
+      .and_then(|file| Ok((file.to_word::<Tree>()?, *file.meta())))
+      .map_err(|e| vec![Diagnostic::error(format!("couldn't read `{}`: {}", path.display(), e)).with_code("M0004")]),
+    None => read_word(map, word.expect("WORD is given without `--load-tree`")),
+  }
+}
+
+/// Reads a word written as text, which is saved as iteration 0 of its own source.
+fn read_word(map: &mut SourceMap, path: &Path) -> Result<(Tree, TreeMeta), Vec<Diagnostic>> {
+  let (path, src) = read_input(path).map_err(|diag| vec![diag])?;
+  let file = map.add_file(path, src);
+  // Los errores tienen posiciones relativas a la palabra, sin los espacios de alrededor
+  let start = file.start + file.src.len() - file.src.trim_start().len();
+  let meta = TreeMeta {source_hash: source_hash(&file.src), ..TreeMeta::default()};
+  match file.src.trim().parse::<Tree>() {
+    Ok(tree) => Ok((tree, meta)),
+    Err(errors) => {
+      let rebase = |span: Span| Span::new(start + span.start, start + span.end);
+      Err(errors.into_iter().map(|mut diag| {
+        diag.labels.iter_mut().for_each(|l| l.span = rebase(l.span));
+        diag
+      }).collect())
+    },
+  }
+}
+
+/// Reads an argument given in the command line: a number, a boolean or, otherwise, a string.
+fn parse_value(s: &str) -> Value {
+  if let Ok(i) = s.parse::<i64>() {
+    Value::Int(i)
+  } else if let Ok(f) = s.parse::<f64>() {
+    Value::Float(f)
+  } else {
+    match s {
+      "true" => Value::Bool(true),
+      "false" => Value::Bool(false),
+      "null" => Value::Null,
+      _ => Value::String(s.to_string()),
+    }
+  }
+}
@@ -0,0 +1,283 @@
+use std::path::PathBuf;
+use std::string::String;
+use std::vec::Vec;
+
+pub const USAGE: &str = "\
+usage: lsys [run] FILE [options]
+       lsys list FILE [-I DIR]...
+       lsys fmt [--check] FILE...
+       lsys convert [--from DIALECT] FILE [-o OUT]
+       lsys stats (WORD | --load-tree TREE | --derive FILE [options]) [--save-tree OUT]
+       lsys graph (WORD | --load-tree TREE | --derive FILE [options]) [--format FORMAT] [-o OUT]
+
+run derives the L system and prints the word it gets to.
+
+FILE can also be a file of another dialect, which is converted to LSD first: a cpfg (L-studio) `.l` file, a LSysGen
+`.lsys` file or an `.abop` file, with a grammar written as in The Algorithmic Beauty of Plants. If FILE is `-`, it's
+read from the standard input, as LSD unless it's clearly cpfg or ABOP: give `--from` for other dialects.
+
+stats prints the symbols and the shape of a derived word read from the file WORD, written like `F[+F]F(1.5, 2)`, from
+a binary tree file or from deriving an L system with `--derive` and the options of run. graph reads its word in the
+same ways, and writes its modules and how they follow each other as a graph.
+
+options:
+  --from DIALECT          dialect of FILE, `cpfg`, `lsysgen` or `abop` (by default, given by its extension)
+  -s, --system NAME       L system to run, instead of the main one
+  -a, --arg [NAME=]VALUE  argument for a parameterized L system (can be repeated)
+  -I, --include DIR       also look for imported modules in DIR (can be repeated)
+  -n, --iterations N      derive N iterations, instead of the ones set by the L system
+  --seed SEED             seed of the random numbers, instead of the one set by the L system
+  -o, --output OUT        write the derived word to OUT instead of the standard output
+  --save-tree OUT         also save the derived word to the tree file OUT, with its iteration and seed
+  -h, --help              show this help
+
+fmt options:
+  --check                 don't write the files, fail if any of them isn't formatted
+
+convert options:
+  -o, --output OUT        write the LSD source to OUT instead of the standard output
+
+stats options:
+  --load-tree TREE        read the word from the tree file TREE, instead of WORD
+  --derive FILE           derive the word from an L system of FILE, instead of reading WORD
+  --save-tree OUT         save the word to the tree file OUT, which is faster to read
+
+graph options:
+  --load-tree TREE        read the word from the tree file TREE, instead of WORD
+  --derive FILE           derive the word from an L system of FILE, instead of reading WORD
+  -f, --format FORMAT     `dot`, `graphml` or `json` (by default, given by the extension of OUT, or `dot`)
+  -o, --output OUT        write the graph to OUT instead of the standard output";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  Run(RunArgs),
+  /// Lists the L systems defined in a file.
+  List(RunArgs),
+  /// Formats LSD files in place.
+  Fmt(FmtArgs),
+  /// Converts a file of another dialect to LSD.
+  Convert(ConvertArgs),
+  /// Prints statistics of a word read from a file.
+  Stats(StatsArgs),
+  /// Exports a word read from a file as a graph.
+  Graph(GraphArgs),
+  Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunArgs {
+  pub file: PathBuf,
+  pub system: Option<String>,
+  /// Arguments given with `--arg`, with their name if they have one.
+  pub args: Vec<(Option<String>, String)>,
+  pub include: Vec<PathBuf>,
+  /// Name of the dialect of the file, if it isn't LSD or given by its extension.
+  pub from: Option<String>,
+  /// Number of iterations to derive, instead of the ones of the L system.
+  pub iterations: Option<usize>,
+  pub seed: Option<u64>,
+  pub output: Option<PathBuf>,
+  /// Tree file to save the derived word to.
+  pub save_tree: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FmtArgs {
+  pub files: Vec<PathBuf>,
+  /// Only check whether the files are formatted.
+  pub check: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConvertArgs {
+  pub file: PathBuf,
+  /// Name of the dialect of the file, if it isn't given by its extension.
+  pub from: Option<String>,
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StatsArgs {
+  /// File with the word as text.
+  pub word: Option<PathBuf>,
+  /// Tree file to read the word from instead.
+  pub load_tree: Option<PathBuf>,
+  /// L system to derive the word from instead, with the options that choose it.
+  pub derive: Option<RunArgs>,
+  pub save_tree: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GraphArgs {
+  /// File with the word as text.
+  pub word: Option<PathBuf>,
+  /// Tree file to read the word from instead.
+  pub load_tree: Option<PathBuf>,
+  /// L system to derive the word from instead, with the options that choose it.
+  pub derive: Option<RunArgs>,
+  pub format: Option<String>,
+  pub output: Option<PathBuf>,
+}
+
+/// Parses the command line arguments, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+  let mut args = args.into_iter().peekable();
+  let list = match args.peek().map(String::as_str) {
+    Some("run") => {args.next(); false},
+    Some("list") => {args.next(); true},
+    Some("fmt") => {args.next(); return parse_fmt(args)},
+    Some("convert") => {args.next(); return parse_convert(args)},
+    Some("stats") => {args.next(); return parse_stats(args)},
+    Some("graph") => {args.next(); return parse_graph(args)},
+    _ => false,
+  };
+
+  let mut run = RunArgs::default();
+  let mut file = None;
+  while let Some(arg) = args.next() {
+    if lsystem_option(&mut run, &arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "-o" | "--output" => run.output = Some(PathBuf::from(value(&mut args, &arg)?)),
+      "--save-tree" => run.save_tree = Some(PathBuf::from(value(&mut args, &arg)?)),
+      opt if opt.starts_with('-') && opt.len() > 1 => return Err(format!("unknown option `{}`", opt)),
+      _ if file.is_some() => return Err(format!("unexpected argument `{}`", arg)),
+      _ => file = Some(PathBuf::from(arg)),
+    }
+  }
+
+  run.file = file.ok_or("missing input file")?;
+  Ok(if list {Command::List(run)} else {Command::Run(run)})
+}
+
+/// Reads an option that chooses, binds or derives an L system, of `run` or of the commands that take `--derive`.
+/// Returns whether `arg` was one.
+fn lsystem_option(run: &mut RunArgs, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool, String> {
+  match arg {
+    "-s" | "--system" => run.system = Some(value(args, arg)?),
+    "-a" | "--arg" => {
+      let val = value(args, arg)?;
+      run.args.push(match val.split_once('=') {
+        Some((name, val)) if is_name(name) => (Some(name.to_string()), val.to_string()),
+        _ => (None, val),
+      });
+    },
+    "-I" | "--include" => run.include.push(PathBuf::from(value(args, arg)?)),
+    "--from" => run.from = Some(value(args, arg)?),
+    "-n" | "--iterations" => {
+      let val = value(args, arg)?;
+      run.iterations = Some(val.parse().map_err(|_| format!("`{}` isn't a number of iterations", val))?);
+    },
+    "--seed" => {
+      let val = value(args, arg)?;
+      run.seed = Some(val.parse().map_err(|_| format!("`{}` isn't a seed", val))?);
+    },
+    _ => return Ok(false),
+  }
+  Ok(true)
+}
+
+/// The value of an option, which is the next argument.
+fn value(args: &mut impl Iterator<Item = String>, opt: &str) -> Result<String, String> {
+  args.next().ok_or_else(|| format!("missing value for `{}`", opt))
+}
+
+/// Checks that a command reads its word from only one place, and that the options of `--derive` are given with it.
+fn word_source(word: &Option<PathBuf>, load_tree: &Option<PathBuf>, derive: &Option<RunArgs>, run: &RunArgs)
+  -> Result<(), String> {
+  match [word.is_some(), load_tree.is_some(), derive.is_some()].into_iter().filter(|&given| given).count() {
+    0 => Err("missing input file".to_string()),
+    1 if derive.is_none() && run != &RunArgs::default() =>
+      Err("`-s`, `-a`, `-I`, `--from`, `-n` and `--seed` are options of `--derive`".to_string()),
+    1 => Ok(()),
+    _ => Err("give only one of WORD, `--load-tree` and `--derive`".to_string()),
+  }
+}
+
+fn parse_fmt(args: impl Iterator<Item = String>) -> Result<Command, String> {
+  let mut fmt = FmtArgs::default();
+  for arg in args {
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "--check" => fmt.check = true,
+      opt if opt.starts_with('-') && opt.len() > 1 => return Err(format!("unknown option `{}`", opt)),
+      _ => fmt.files.push(PathBuf::from(arg)),
+    }
+  }
+  if fmt.files.is_empty() {
+    return Err("missing input file".to_string());
+  }
+  Ok(Command::Fmt(fmt))
+}
+
+fn parse_convert(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+  let mut convert = ConvertArgs::default();
+  let mut file = None;
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "--from" => convert.from = Some(value(&mut args, &arg)?),
+      "-o" | "--output" => convert.output = Some(PathBuf::from(value(&mut args, &arg)?)),
+      opt if opt.starts_with('-') && opt.len() > 1 => return Err(format!("unknown option `{}`", opt)),
+      _ if file.is_some() => return Err(format!("unexpected argument `{}`", arg)),
+      _ => file = Some(PathBuf::from(arg)),
+    }
+  }
+  convert.file = file.ok_or("missing input file")?;
+  Ok(Command::Convert(convert))
+}
+
+fn parse_stats(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+  let mut stats = StatsArgs::default();
+  let mut run = RunArgs::default();
+  while let Some(arg) = args.next() {
+    if lsystem_option(&mut run, &arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "--load-tree" => stats.load_tree = Some(PathBuf::from(value(&mut args, &arg)?)),
+      "--save-tree" => stats.save_tree = Some(PathBuf::from(value(&mut args, &arg)?)),
+      "--derive" => stats.derive = Some(RunArgs {file: PathBuf::from(value(&mut args, &arg)?), ..RunArgs::default()}),
+      opt if opt.starts_with('-') && opt.len() > 1 => return Err(format!("unknown option `{}`", opt)),
+      _ if stats.word.is_some() => return Err(format!("unexpected argument `{}`", arg)),
+      _ => stats.word = Some(PathBuf::from(arg)),
+    }
+  }
+  word_source(&stats.word, &stats.load_tree, &stats.derive, &run)?;
+  if let Some(derive) = &mut stats.derive {
+    *derive = RunArgs {file: std::mem::take(&mut derive.file), ..run};
+  }
+  Ok(Command::Stats(stats))
+}
+
+fn parse_graph(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+  let mut graph = GraphArgs::default();
+  let mut run = RunArgs::default();
+  while let Some(arg) = args.next() {
+    if lsystem_option(&mut run, &arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "-h" | "--help" => return Ok(Command::Help),
+      "--load-tree" => graph.load_tree = Some(PathBuf::from(value(&mut args, &arg)?)),
+      "--derive" => graph.derive = Some(RunArgs {file: PathBuf::from(value(&mut args, &arg)?), ..RunArgs::default()}),
+      "-f" | "--format" => graph.format = Some(value(&mut args, &arg)?),
+      "-o" | "--output" => graph.output = Some(PathBuf::from(value(&mut args, &arg)?)),
+      opt if opt.starts_with('-') && opt.len() > 1 => return Err(format!("unknown option `{}`", opt)),
+      _ if graph.word.is_some() => return Err(format!("unexpected argument `{}`", arg)),
+      _ => graph.word = Some(PathBuf::from(arg)),
+    }
+  }
+  word_source(&graph.word, &graph.load_tree, &graph.derive, &run)?;
+  if let Some(derive) = &mut graph.derive {
+    *derive = RunArgs {file: std::mem::take(&mut derive.file), ..run};
+  }
+  Ok(Command::Graph(graph))
+}
+
+fn is_name(s: &str) -> bool {
+  s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -1,3 +1,16 @@
+mod cli;
+mod cliargs;
+
+#[cfg(test)]
+mod test;
+
 fn main() {
-    println!("Hello, world!");
+  let code = match cliargs::parse(std::env::args().skip(1)) {
+    Ok(cmd) => cli::run(cmd),
+    Err(msg) => {
+      eprintln!("error: {}\n\n{}", msg, cliargs::USAGE);
+      2
+    },
+  };
+  std::process::exit(code);
 }
@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use super::cliargs::{parse, Command, ConvertArgs, FmtArgs, GraphArgs, RunArgs, StatsArgs};
+
+fn args(line: &str) -> Result<Command, String> {
+  parse(line.split_whitespace().map(String::from))
+}
+
+#[test]
+fn run_args() {
+  let file = |name: &str| RunArgs {file: PathBuf::from(name), ..RunArgs::default()};
+  assert_eq!(args("koch.lsd"), Ok(Command::Run(file("koch.lsd"))));
+  assert_eq!(args("run koch.lsd"), Ok(Command::Run(file("koch.lsd"))));
+  assert_eq!(args("run -"), Ok(Command::Run(file("-"))));
+  let include = vec![PathBuf::from("lib")];
+  assert_eq!(args("list koch.lsd -I lib"), Ok(Command::List(RunArgs {include, ..file("koch.lsd")})));
+  let line = "-s tree koch.lsd -a 3 --arg angle=25.5 -a x=y=z -n 4 -o out.txt -I a --include b";
+  assert_eq!(args(line), Ok(Command::Run(RunArgs {
+    system: Some("tree".to_string()),
+    args: vec![
+      (None, "3".to_string()),
+      (Some("angle".to_string()), "25.5".to_string()),
+      (Some("x".to_string()), "y=z".to_string()),
+    ],
+    include: vec![PathBuf::from("a"), PathBuf::from("b")],
+    iterations: Some(4),
+    output: Some(PathBuf::from("out.txt")),
+    ..file("koch.lsd")
+  })));
+  let trees = RunArgs {seed: Some(7), save_tree: Some(PathBuf::from("out.tree")), ..file("koch.lsd")};
+  assert_eq!(args("koch.lsd --seed 7 --save-tree out.tree"), Ok(Command::Run(trees)));
+  // Un valor que no empieza por un nombre es posicional
+  let positional = vec![(None, "1=2".to_string())];
+  assert_eq!(args("koch.lsd -a 1=2"), Ok(Command::Run(RunArgs {args: positional, ..file("koch.lsd")})));
+  assert_eq!(args("koch.lsd --help"), Ok(Command::Help));
+  assert_eq!(args("list -h"), Ok(Command::Help));
+
+  assert_eq!(args(""), Err("missing input file".to_string()));
+  assert_eq!(args("list"), Err("missing input file".to_string()));
+  assert_eq!(args("a.lsd b.lsd"), Err("unexpected argument `b.lsd`".to_string()));
+  assert_eq!(args("koch.lsd --verbose"), Err("unknown option `--verbose`".to_string()));
+  assert_eq!(args("koch.lsd -s"), Err("missing value for `-s`".to_string()));
+  assert_eq!(args("koch.lsd --arg"), Err("missing value for `--arg`".to_string()));
+  assert_eq!(args("koch.lsd -n -1"), Err("`-1` isn't a number of iterations".to_string()));
+  assert_eq!(args("koch.lsd -n many"), Err("`many` isn't a number of iterations".to_string()));
+  assert_eq!(args("koch.lsd -o"), Err("missing value for `-o`".to_string()));
+  assert_eq!(args("koch.lsd --seed -7"), Err("`-7` isn't a seed".to_string()));
+  assert_eq!(args("koch.lsd --save-tree"), Err("missing value for `--save-tree`".to_string()));
+}
+
+#[test]
+fn from_args() {
+  let from = |file: &str, dialect: &str| {
+    RunArgs {file: PathBuf::from(file), from: Some(dialect.to_string()), ..RunArgs::default()}
+  };
+  assert_eq!(args("- --from abop"), Ok(Command::Run(from("-", "abop"))));
+  assert_eq!(args("list --from cpfg plant.txt"), Ok(Command::List(from("plant.txt", "cpfg"))));
+  // El nombre del dialecto se comprueba al cargar el fichero
+  assert_eq!(args("run plant.txt --from abc"), Ok(Command::Run(from("plant.txt", "abc"))));
+  assert_eq!(args("plant.txt --from"), Err("missing value for `--from`".to_string()));
+  assert_eq!(args("--from abop"), Err("missing input file".to_string()));
+}
+
+#[test]
+fn fmt_args() {
+  let files = |names: &[&str], check: bool| {
+    Ok(Command::Fmt(FmtArgs {files: names.iter().map(PathBuf::from).collect(), check}))
+  };
+  assert_eq!(args("fmt koch.lsd"), files(&["koch.lsd"], false));
+  assert_eq!(args("fmt a.lsd --check b.lsd -"), files(&["a.lsd", "b.lsd", "-"], true));
+  assert_eq!(args("fmt --check -h"), Ok(Command::Help));
+
+  assert_eq!(args("fmt"), Err("missing input file".to_string()));
+  assert_eq!(args("fmt --check"), Err("missing input file".to_string()));
+  assert_eq!(args("fmt koch.lsd --write"), Err("unknown option `--write`".to_string()));
+}
+
+#[test]
+fn convert_args() {
+  let file = |name: &str| ConvertArgs {file: PathBuf::from(name), ..ConvertArgs::default()};
+  assert_eq!(args("convert plant.l"), Ok(Command::Convert(file("plant.l"))));
+  assert_eq!(args("convert --from abop - -o plant.lsd"), Ok(Command::Convert(ConvertArgs {
+    from: Some("abop".to_string()),
+    output: Some(PathBuf::from("plant.lsd")),
+    ..file("-")
+  })));
+  assert_eq!(args("convert plant.l --help"), Ok(Command::Help));
+
+  assert_eq!(args("convert"), Err("missing input file".to_string()));
+  assert_eq!(args("convert -o plant.lsd"), Err("missing input file".to_string()));
+  assert_eq!(args("convert a.l b.l"), Err("unexpected argument `b.l`".to_string()));
+  assert_eq!(args("convert plant.l --from"), Err("missing value for `--from`".to_string()));
+  assert_eq!(args("convert plant.l --output"), Err("missing value for `--output`".to_string()));
+  assert_eq!(args("convert plant.l -n 3"), Err("unknown option `-n`".to_string()));
+}
+
+#[test]
+fn stats_args() {
+  let path = |name: &str| Some(PathBuf::from(name));
+  assert_eq!(args("stats word.txt"), Ok(Command::Stats(StatsArgs {word: path("word.txt"), ..StatsArgs::default()})));
+  assert_eq!(args("stats --load-tree a.tree --save-tree b.tree"), Ok(Command::Stats(StatsArgs {
+    load_tree: path("a.tree"),
+    save_tree: path("b.tree"),
+    ..StatsArgs::default()
+  })));
+  // Las opciones de la derivación pueden ir antes o después de `--derive`
+  let line = "stats -n 5 --derive koch.lsd -s tree -a 2 --from abop -I lib --seed 3 --save-tree b.tree";
+  assert_eq!(args(line), Ok(Command::Stats(StatsArgs {
+    derive: Some(RunArgs {
+      file: PathBuf::from("koch.lsd"),
+      system: Some("tree".to_string()),
+      args: vec![(None, "2".to_string())],
+      include: vec![PathBuf::from("lib")],
+      from: Some("abop".to_string()),
+      iterations: Some(5),
+      seed: Some(3),
+      ..RunArgs::default()
+    }),
+    save_tree: path("b.tree"),
+    ..StatsArgs::default()
+  })));
+  assert_eq!(args("stats --help"), Ok(Command::Help));
+
+  let both = Err("give only one of WORD, `--load-tree` and `--derive`".to_string());
+  assert_eq!(args("stats"), Err("missing input file".to_string()));
+  assert_eq!(args("stats word.txt --load-tree a.tree"), both);
+  assert_eq!(args("stats --derive koch.lsd --load-tree a.tree"), both);
+  assert_eq!(args("stats a.txt b.txt"), Err("unexpected argument `b.txt`".to_string()));
+  let without_derive = Err("`-s`, `-a`, `-I`, `--from`, `-n` and `--seed` are options of `--derive`".to_string());
+  assert_eq!(args("stats word.txt -n 3"), without_derive);
+  assert_eq!(args("stats --derive koch.lsd -o out.txt"), Err("unknown option `-o`".to_string()));
+  assert_eq!(args("stats --derive"), Err("missing value for `--derive`".to_string()));
+  assert_eq!(args("stats --save-tree"), Err("missing value for `--save-tree`".to_string()));
+}
+
+#[test]
+fn graph_args() {
+  let path = |name: &str| Some(PathBuf::from(name));
+  assert_eq!(args("graph word.txt"), Ok(Command::Graph(GraphArgs {word: path("word.txt"), ..GraphArgs::default()})));
+  assert_eq!(args("graph --load-tree a.tree -f graphml -o a.xml"), Ok(Command::Graph(GraphArgs {
+    load_tree: path("a.tree"),
+    format: Some("graphml".to_string()),
+    output: path("a.xml"),
+    ..GraphArgs::default()
+  })));
+  // `-o` es la salida del grafo, no de la derivación
+  assert_eq!(args("graph --derive koch.lsd -n 3 --format json -o koch.json"), Ok(Command::Graph(GraphArgs {
+    derive: Some(RunArgs {file: PathBuf::from("koch.lsd"), iterations: Some(3), ..RunArgs::default()}),
+    format: Some("json".to_string()),
+    output: path("koch.json"),
+    ..GraphArgs::default()
+  })));
+  assert_eq!(args("graph -h"), Ok(Command::Help));
+
+  assert_eq!(args("graph"), Err("missing input file".to_string()));
+  let both = Err("give only one of WORD, `--load-tree` and `--derive`".to_string());
+  assert_eq!(args("graph --load-tree a.tree --derive koch.lsd"), both);
+  assert_eq!(args("graph a.txt b.txt"), Err("unexpected argument `b.txt`".to_string()));
+  let without_derive = Err("`-s`, `-a`, `-I`, `--from`, `-n` and `--seed` are options of `--derive`".to_string());
+  assert_eq!(args("graph --load-tree a.tree -s tree"), without_derive);
+  assert_eq!(args("graph word.txt --save-tree a.tree"), Err("unknown option `--save-tree`".to_string()));
+  assert_eq!(args("graph word.txt -f"), Err("missing value for `-f`".to_string()));
+}
@@ -0,0 +1,171 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const ALGAE: &str = "fn grow(x) {return x + 1}
+
+main lsys algae(n = 2) {
+  set iterations = n
+  axiom A
+  rules {
+    A -> AB
+    B -> A
+  }
+}
+
+lsys count {
+  set iterations = 5
+  axiom F(0)
+  rules {
+    F(x) : x < 3 -> F(grow(x))G
+  }
+}
+";
+
+/// A path for a file of a test, removed when it's dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+  fn new(name: &str) -> Self {
+    TempFile(std::env::temp_dir().join(format!("lsys-test-{}-{}", std::process::id(), name)))
+  }
+
+  fn with(name: &str, src: &str) -> Self {
+    let file = Self::new(name);
+    std::fs::write(&file.0, src).unwrap();
+    file
+  }
+
+  fn path(&self) -> &str {
+    self.0.to_str().unwrap()
+  }
+
+  fn read(&self) -> String {
+    std::fs::read_to_string(&self.0).unwrap()
+  }
+}
+
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.0);
+  }
+}
+
+/// Runs lsys with `stdin` as its standard input.
+fn lsys(args: &[&str], stdin: &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_lsys"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  // Puede acabar sin leer la entrada, si falla antes
+  let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+  child.wait_with_output().unwrap()
+}
+
+fn stdout(out: &Output) -> String {
+  assert!(out.status.success(), "lsys failed: {}", String::from_utf8_lossy(&out.stderr));
+  String::from_utf8(out.stdout.clone()).unwrap()
+}
+
+#[test]
+fn run() {
+  let src = TempFile::with("algae.lsd", ALGAE);
+  assert_eq!(stdout(&lsys(&[src.path()], "")), "ABA\n");
+  assert_eq!(stdout(&lsys(&["run", src.path(), "-a", "n=4"], "")), "ABAABABA\n");
+  assert_eq!(stdout(&lsys(&[src.path(), "-n", "3"], "")), "ABAAB\n");
+  // Las condiciones y las funciones del módulo se evalúan al derivar
+  assert_eq!(stdout(&lsys(&[src.path(), "-s", "count"], "")), "F(3)GGG\n");
+  assert_eq!(stdout(&lsys(&["-", "-n", "1"], ALGAE)), "AB\n");
+
+  let out = TempFile::new("algae.txt");
+  assert_eq!(stdout(&lsys(&[src.path(), "-o", out.path()], "")), "");
+  assert_eq!(out.read(), "ABA\n");
+
+  let res = lsys(&["-"], "lsys broken {\n  set iterations = 2\n  set speed = 1\n}\n");
+  assert!(!res.status.success());
+  let err = String::from_utf8(res.stderr).unwrap();
+  assert!(err.contains("warning[B0004]: unknown setting `speed` is ignored"), "{}", err);
+  assert!(err.contains("`broken` has no axiom"), "{}", err);
+}
+
+#[test]
+fn abop_input() {
+  let abop = "n = 2, δ = 90°\nω: F\nF → F+F\n";
+  assert_eq!(stdout(&lsys(&["-"], abop)), "F+F+F+F\n");
+
+  // Sin nada que sea solo de ABOP, la entrada se lee como LSD
+  let plain = "n = 2\nF\nF -> F+F\n";
+  let res = lsys(&["-"], plain);
+  assert!(!res.status.success());
+  let err = String::from_utf8(res.stderr).unwrap();
+  assert!(err.contains("the standard input was read as LSD"), "{}", err);
+  assert!(err.contains("give it with `--from`"), "{}", err);
+  assert_eq!(stdout(&lsys(&["-", "--from", "abop", "-n", "1"], plain)), "F+F\n");
+
+  let src = TempFile::with("plain.abop", plain);
+  assert_eq!(stdout(&lsys(&[src.path()], "")), "F+F+F+F\n");
+  let res = lsys(&["-", "--from", "abc"], plain);
+  assert!(String::from_utf8(res.stderr).unwrap().contains("unknown dialect `abc`"));
+}
+
+#[test]
+fn stats() {
+  let src = TempFile::with("stats.lsd", ALGAE);
+  let out = stdout(&lsys(&["stats", "--derive", src.path(), "-a", "3", "-n", "4"], ""));
+  assert!(out.starts_with("iteration:     4\n"), "{}", out);
+  // ABAABABA
+  assert!(out.contains("symbols:\n  A           5\n  B           3\n"), "{}", out);
+  let out = stdout(&lsys(&["stats", "--derive", src.path(), "-s", "count"], ""));
+  assert!(out.contains("iteration:     5\n") && out.contains("  G           3\n"), "{}", out);
+
+  let word = TempFile::with("stats.txt", "ABA\n");
+  let out = stdout(&lsys(&["stats", word.path()], ""));
+  assert!(out.starts_with("nodes:         3\n"), "{}", out);
+}
+
+#[test]
+fn save_tree() {
+  let src = TempFile::with("save.lsd", ALGAE);
+  let tree = TempFile::new("algae.tree");
+  let out = lsys(&[src.path(), "-n", "3", "--seed", "7", "--save-tree", tree.path()], "");
+  assert_eq!(stdout(&out), "ABAAB\n");
+  let loaded = stdout(&lsys(&["stats", "--load-tree", tree.path()], ""));
+  assert!(loaded.starts_with("iteration:     3\nseed:          7\n"), "{}", loaded);
+  // El hash es el del módulo del que sale la palabra
+  let derived = stdout(&lsys(&["stats", "--derive", src.path(), "-n", "3", "--seed", "7"], ""));
+  assert_eq!(loaded, derived);
+
+  let src = TempFile::with("seeded.lsd", "set iterations = 2\nset seed = 42\naxiom F\nrules {\n  F -> FF\n}\n");
+  let tree = TempFile::new("seeded.tree");
+  stdout(&lsys(&["stats", "--derive", src.path(), "--save-tree", tree.path()], ""));
+  let loaded = stdout(&lsys(&["stats", "--load-tree", tree.path()], ""));
+  assert!(loaded.starts_with("iteration:     2\nseed:          42\n"), "{}", loaded);
+  assert!(loaded.contains("symbols:\n  F           4\n"), "{}", loaded);
+}
+
+#[test]
+fn graph() {
+  let src = TempFile::with("graph.lsd", ALGAE);
+  let dot = stdout(&lsys(&["graph", "--derive", src.path(), "-n", "2"], ""));
+  assert_eq!(dot, "digraph tree {
+  n0 [label=\"A\"];
+  n1 [label=\"B\"];
+  n2 [label=\"A\"];
+  n0 -> n1;
+  n1 -> n2;
+}
+");
+
+  // El formato sale de la extensión del fichero
+  let out = TempFile::new("graph.json");
+  assert_eq!(stdout(&lsys(&["graph", "--derive", src.path(), "-s", "count", "-n", "1", "-o", out.path()], "")), "");
+  let json = out.read();
+  assert!(json.starts_with("{\"directed\": true, \"nodes\": [\n"), "{}", json);
+  assert!(json.contains("{\"id\": 0, \"position\": 0, \"symbol\": \"F\", \"values\": [1]}"), "{}", json);
+
+  let res = lsys(&["graph", "--derive", src.path(), "-f", "svg"], "");
+  assert!(String::from_utf8(res.stderr).unwrap().contains("unknown graph format `svg`"));
+}
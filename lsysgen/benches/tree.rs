@@ -0,0 +1,79 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use lsd::Sym;
+use lsysgen::common::Value;
+use lsysgen::common::tree::{CompactTree, InstanceWord, NodeRef, Tree};
+
+/// Keeps count of the bytes in use on the heap.
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    IN_USE.fetch_add(layout.size(), Ordering::Relaxed);
+    unsafe {System.alloc(layout)}
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    unsafe {System.dealloc(ptr, layout)}
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const NODES: usize = 10_000_000;
+
+/// A word like the ones of a bush, `F(1.5)[+(25.7)X]F(1.5)[+(25.7)X]...`, where 2 of every 5 nodes have a value.
+fn build<W: InstanceWord + Default>() -> W {
+  let mut word = W::default();
+  while word.len() < NODES {
+    word.push_leaf(Sym::from('F'), [Value::Float(1.5)]);
+    word.open_branch();
+    word.push_leaf(Sym::from('+'), [Value::Float(25.7)]);
+    word.push_leaf(Sym::from('X'), []);
+    word.close_branch().unwrap();
+  }
+  word
+}
+
+/// Adds up every value, as an interpretation of the word would read them.
+fn read(word: &impl InstanceWord) -> f64 {
+  let mut sum = 0.0;
+  for node in word.nodes() {
+    if let NodeRef::Leaf(_, values) = node {
+      for v in values {
+        if let Value::Float(f) = v {
+          sum += f;
+        }
+      }
+    }
+  }
+  sum
+}
+
+fn bench<W: InstanceWord + Default>(name: &str) {
+  let before = IN_USE.load(Ordering::Relaxed);
+  let start = Instant::now();
+  let word: W = build();
+  let built = start.elapsed();
+  let bytes = IN_USE.load(Ordering::Relaxed) - before;
+
+  let start = Instant::now();
+  black_box(read(black_box(&word)));
+  let read = start.elapsed();
+  println!("{:<12} {:>9.1} MiB {:>6.1} B/node {:>10.2?} build {:>10.2?} read", name, bytes as f64 / (1 << 20) as f64,
+    bytes as f64 / word.len() as f64, built, read);
+}
+
+/// Memory and time taken by the storages of instance words. Run it with `cargo bench --bench tree`.
+fn main() {
+  println!("{} nodes", NODES);
+  bench::<Tree>("Tree");
+  bench::<CompactTree>("CompactTree");
+}
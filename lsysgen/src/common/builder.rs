@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::Sym;
+use lsd::ast::grammar;
+use lsd::ast::normal::{Expr, FnDef, LSysDef, LSysStmt, ModStmt, Module, Stmt, StmtKind};
+use lsd::source::Span;
+use lsd::visit::{self, Visitor};
+
+use crate::common::tree::{node::context, Tree};
+use crate::deriving::{Rule, Table};
+use super::errors::{Diagnostic, ErrorHandler, EvalError};
+use super::expr::{qualified_name, ExpressionEvaluator};
+use super::lsystem::{LSystem, LSystemDecl};
+use super::module::{ModuleId, ModuleLoader};
+use super::settings::Settings2D;
+use super::values::{Function, Parameter, Scope, Value};
+
+/// Names that can be given to `set`.
+const SETTINGS: [&str; 6] = ["iterations", "seed", "angle", "step", "width", "tables"];
+
+/// Builds the L systems of the modules of a loader from their LSD code.
+///
+/// The top-level variables and functions of the module are evaluated first, with the names it imports, then the
+/// parameters of the L system and its statements, in order. Imported names are kept in the scope as they are written,
+/// like `lv.leaf`. Axioms and the words that rules expand are evaluated when they are read, while the
+/// arguments and the conditions of rules are evaluated when deriving, in the scope left by the last statement.
+#[derive(Debug)]
+pub struct LSystemBuilder<'l> {
+  loader: &'l ModuleLoader,
+  ee: ExpressionEvaluator,
+  err: ErrorHandler,
+  /// Top-level scope of every module evaluated so far.
+  modules: HashMap<ModuleId, Scope>,
+}
+
+/// An L system while its statements are read.
+struct Parts {
+  axiom: Option<Tree<context::Instance>>,
+  iterations: usize,
+  seed: u64,
+  settings: Settings2D,
+  table_func: Option<Rc<Function>>,
+  rules: Table<Sym>,
+  coding_rules: Table<Sym>,
+  tables: Vec<(String, Table<Sym>)>,
+}
+
+impl<'l> LSystemBuilder<'l> {
+  pub fn new(loader: &'l ModuleLoader) -> Self {
+    LSystemBuilder {loader, ee: ExpressionEvaluator::new(), err: ErrorHandler::new(), modules: HashMap::new()}
+  }
+
+  /// Builds a declared L system with the values of its parameters, as given by `LSystemDecl::bind`. Parameters
+  /// without a value take their default one.
+  ///
+  /// Every error and warning found is recorded in `errors`, and `None` is returned if there are errors.
+  pub fn build(&mut self, decl: &LSystemDecl, args: Vec<(String, Option<Value>)>) -> Option<LSystem<Sym>> {
+    let errors = self.err.error_count();
+    let module = self.loader.ast(decl.module);
+    let def = match module.as_ref().and_then(|module| find_def(module, decl.span)) {
+      Some(def) => def,
+      None => {
+        self.err.push(Diagnostic::error(format!("`{}` can't be read from its module", decl.display_name()))
+          .with_code("B0001")
+          .with_primary(decl.span, ""));
+        return None;
+      },
+    };
+
+    let mut scope = self.module_scope(decl.module);
+    for ((name, val), param) in args.into_iter().zip(&def.params) {
+      let val = match (val, &param.default_value) {
+        (Some(val), _) => Some(val),
+        (None, Some(default)) => self.eval(default, &scope),
+        (None, None) => None,
+      };
+      scope.set(name, val.unwrap_or(Value::Null));
+    }
+
+    let mut parts = Parts {
+      axiom: None,
+      iterations: 0,
+      seed: 0,
+      settings: Settings2D::default(),
+      table_func: None,
+      rules: Table::new(),
+      coding_rules: Table::new(),
+      tables: Vec::new(),
+    };
+    for stmt in &def.stmts {
+      self.lsys_stmt(stmt, &mut scope, &mut parts);
+    }
+
+    if !def.stmts.iter().any(|s| matches!(s, LSysStmt::AxiomDef(_))) {
+      self.err.push(Diagnostic::error(format!("`{}` has no axiom", decl.display_name()))
+        .with_code("B0002")
+        .with_primary(def.span, "")
+        .with_help("add one with `axiom ...`"));
+    }
+    if self.err.error_count() > errors {
+      return None;
+    }
+    let mut lsystem = LSystem::new(decl.display_name(), parts.axiom?)
+      .with_rules(parts.rules)
+      .with_coding_rules(parts.coding_rules)
+      .with_iterations(parts.iterations)
+      .with_seed(parts.seed)
+      .with_settings(parts.settings)
+      .with_scope(scope);
+    for (name, table) in parts.tables {
+      lsystem = lsystem.with_table(name, table);
+    }
+    if let Some(func) = parts.table_func {
+      lsystem = lsystem.with_table_func(func);
+    }
+    Some(lsystem)
+  }
+
+  pub fn errors(&self) -> &ErrorHandler {&self.err}
+
+  /// The top-level variables and functions of a module, and the names it imports.
+  fn module_scope(&mut self, id: ModuleId) -> Scope {
+    if let Some(scope) = self.modules.get(&id) {
+      return scope.clone();
+    }
+    let scope = match self.loader.ast(id) {
+      Some(module) => self.globals(id, &module),
+      None => Scope::new(),
+    };
+    self.modules.insert(id, scope.clone());
+    scope
+  }
+
+  fn globals(&mut self, id: ModuleId, module: &Module) -> Scope {
+    let mut scope = Scope::new();
+    let mut names = Names::default();
+    names.visit_module(module);
+    for name in names.0 {
+      // El cargador no deja importar módulos en ciclo, así que la recursión acaba
+      let val = match self.loader.resolve(id, &name) {
+        Some((from, local)) if !scope.has(&name) => self.module_scope(from).get(local).cloned(),
+        _ => None,
+      };
+      if let Some(val) = val {
+        scope.set(name, val);
+      }
+    }
+    for stmt in &module.stmts {
+      match stmt {
+        ModStmt::VarDecl(d) => {
+          let val = d.value.as_ref().map_or(Some(Value::Null), |e| self.eval(e, &scope));
+          scope.set(d.name.to_string(), val.unwrap_or(Value::Null));
+        },
+        ModStmt::FnDef(d) => self.fn_def(d, &mut scope),
+        ModStmt::Import(_) | ModStmt::LSysDef(_) => {},
+      }
+    }
+    scope
+  }
+
+  fn lsys_stmt(&mut self, stmt: &LSysStmt, scope: &mut Scope, parts: &mut Parts) {
+    match stmt {
+      LSysStmt::Stmt(s) => self.stmt(s, scope),
+      // Los módulos importados ya los ha cargado el cargador
+      LSysStmt::Import(_) => {},
+      LSysStmt::SetDef(name, e) => {
+        if let Some(val) = self.eval(e, scope) {
+          self.set(name, val, e.span, parts);
+        }
+      },
+      LSysStmt::AxiomDef(word) => parts.axiom = self.err.check(self.ee.eval_word(word, scope)),
+      LSysStmt::TableDef(table) => {
+        let mut rules = Table::new();
+        for rule in &table.rules {
+          if let Some(rule) = self.rule(rule, scope) {
+            rules.push(rule);
+          }
+        }
+        parts.tables.push((table.name.as_deref().unwrap_or_default().to_string(), rules));
+      },
+      LSysStmt::RulesDef(rules) | LSysStmt::ProductionRulesDef(rules) => {
+        for r in rules {
+          if let Some(rule) = self.rule(r, scope) {
+            match r {
+              grammar::Rule::Production(_) => parts.rules.push(rule),
+              grammar::Rule::Coding(_) => parts.coding_rules.push(rule),
+            }
+          }
+        }
+      },
+      LSysStmt::CodingRulesDef(rules) => {
+        for r in rules {
+          if let Some(rule) = self.rule(r, scope) {
+            parts.coding_rules.push(rule);
+          }
+        }
+      },
+    }
+  }
+
+  fn stmt(&mut self, stmt: &Stmt, scope: &mut Scope) {
+    match &stmt.kind {
+      StmtKind::VarDecl(d) => {
+        let val = d.value.as_ref().map_or(Some(Value::Null), |e| self.eval(e, scope));
+        scope.set(d.name.to_string(), val.unwrap_or(Value::Null));
+      },
+      StmtKind::Assign(name, e) => {
+        if !scope.has(name) {
+          self.err.push(EvalError::UndefinedVariable(name.to_string(), stmt.span));
+        } else if let Some(val) = self.eval(e, scope) {
+          scope.set(name.to_string(), val);
+        }
+      },
+      StmtKind::FnDef(d) => self.fn_def(d, scope),
+      StmtKind::Expr(e) => {self.eval(e, scope);},
+      // Cada L system anidado se construye por separado
+      StmtKind::LSysDef(_) => {},
+      StmtKind::If(..) | StmtKind::For(..) | StmtKind::While(..) | StmtKind::Return(_) | StmtKind::Block(_) => {
+        self.err.push(EvalError::Unsupported("control flow statements", stmt.span));
+      },
+    }
+  }
+
+  /// Defines a function whose body is a single `return` statement.
+  fn fn_def(&mut self, d: &FnDef, scope: &mut Scope) {
+    let body = match d.stmts.as_slice() {
+      [Stmt {kind: StmtKind::Return(Some(e)), ..}] | [Stmt {kind: StmtKind::Expr(e), ..}] => e,
+      _ => {
+        self.err.push(EvalError::Unsupported("functions with more than a `return` statement", d.span));
+        return;
+      },
+    };
+    let params = d.params.iter().map(|p| Parameter::new(p.name.to_string())).collect();
+    let fun = Function::new(params, body.clone().into_owned(), d.span);
+    scope.set(d.name.to_string(), Value::Function(Rc::new(fun)));
+  }
+
+  fn set(&mut self, name: &str, val: Value, span: Span, parts: &mut Parts) {
+    let number = match val {
+      Value::Int(i) => Some(i as f64),
+      Value::Float(f) => Some(f),
+      _ => None,
+    };
+    match (name, &val) {
+      ("iterations", Value::Int(n)) if *n >= 0 => parts.iterations = *n as usize,
+      ("seed", Value::Int(n)) if *n >= 0 => parts.seed = *n as u64,
+      ("angle", _) if number.is_some() => parts.settings.angle = number.unwrap(),
+      ("step", _) if number.is_some() => parts.settings.step = number.unwrap(),
+      ("width", _) if number.is_some() => parts.settings.width = number.unwrap(),
+      ("tables", Value::Function(func)) => parts.table_func = Some(func.clone()),
+      _ if SETTINGS.contains(&name) => {
+        let expected = match name {
+          "iterations" | "seed" => "an integer that isn't negative",
+          "tables" => "a function",
+          _ => "a number",
+        };
+        self.err.push(Diagnostic::error(format!("`{}` must be {}, not {}", name, expected, val.type_name()))
+          .with_code("B0003")
+          .with_primary(span, ""));
+      },
+      // Otros dialectos dan valores que aquí no se usan, como `ignore`
+      _ => self.err.push(Diagnostic::warning(format!("unknown setting `{}` is ignored", name))
+        .with_code("B0004")
+        .with_primary(span, "")
+        .with_note(format!("settings are {}", SETTINGS.join(", ")))),
+    }
+  }
+
+  /// Reads a rule of the grammar. Its expansions are evaluated now, in `scope`.
+  fn rule(&mut self, rule: &grammar::Rule<Sym>, scope: &Scope) -> Option<Rule<Sym>> {
+    let base = rule.base();
+    if !base.l_ctx.is_empty() || !base.r_ctx.is_empty() {
+      self.err.push(EvalError::Unsupported("context-sensitive rules", base.span));
+      return None;
+    }
+    let right_side = self.err.check(self.ee.right_side(&base.right_side, scope))?;
+    let mut res = Rule::new(base.left_leaf.symbol, right_side)
+      .with_params(base.left_leaf.params.iter().flatten().map(|p| p.to_string()))
+      .with_span(base.span);
+    if let Some(cond) = &base.condition {
+      res = res.with_condition(cond.clone().into_owned());
+    }
+    Some(res)
+  }
+
+  fn eval(&mut self, e: &Expr, scope: &Scope) -> Option<Value> {
+    self.err.check(self.ee.eval(e, scope))
+  }
+}
+
+/// Names used in a module that can be imported: `koch`, `lv.leaf` or the ones expanded with `@lv.leaf`.
+#[derive(Default)]
+struct Names(Vec<String>);
+
+impl<'ast> Visitor<'ast> for Names {
+  fn visit_expr(&mut self, e: &'ast Expr<'ast>) {
+    match qualified_name(e) {
+      Some(name) => self.0.push(name),
+      None => visit::walk_expr(self, e),
+    }
+  }
+
+  fn visit_expansion(&mut self, e: &'ast grammar::Expansion<'ast>) {
+    self.0.push(e.to.to_string());
+    visit::walk_expansion(self, e);
+  }
+}
+
+/// The definition of an L system of a module, maybe nested in another one, from its span.
+fn find_def<'m>(module: &'m Module<'m>, span: Span) -> Option<&'m LSysDef<'m>> {
+  fn nested<'m>(def: &'m LSysDef<'m>, span: Span) -> Option<&'m LSysDef<'m>> {
+    if def.span == span {
+      return Some(def);
+    }
+    def.stmts.iter().find_map(|s| match s {
+      LSysStmt::Stmt(Stmt {kind: StmtKind::LSysDef(d), ..}) => nested(d, span),
+      _ => None,
+    })
+  }
+  module.stmts.iter().find_map(|s| match s {
+    ModStmt::LSysDef(d) => nested(d, span),
+    _ => None,
+  })
+}
@@ -0,0 +1,449 @@
+use std::fmt;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+use std::string::String;
+use std::vec::Vec;
+
+use lalrpop_util::ParseError;
+
+use lsd::lexer::{LexicalError, Token, TokenType};
+use lsd::source::{SourceMap, Span};
+
+use super::operators::MAX_WORD_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Note,
+  Warning,
+  Error,
+}
+
+/// A message attached to a span of the source code.
+///
+/// Primary labels point at the cause of the diagnostic and are underlined with `^`; secondary ones give context and
+/// are underlined with `-`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+  pub span: Span,
+  pub message: String,
+  pub primary: bool,
+}
+
+/// An error or warning found while parsing, evaluating or deriving an L system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub code: Option<&'static str>,
+  pub message: String,
+  pub labels: Vec<Label>,
+  pub notes: Vec<String>,
+  pub help: Option<String>,
+}
+
+/// Errors found while evaluating LSD code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+  UndefinedVariable(String, Span),
+  /// An operator was applied to values of the wrong types: `(operator, operand types, span)`.
+  TypeMismatch(&'static str, Vec<&'static str>, Span),
+  WrongArgCount {expected: usize, found: usize, call: Span, def: Option<Span>},
+  DivisionByZero(Span),
+  NotCallable(Span),
+  /// The result of an integer operation doesn't fit in 64 bits.
+  Overflow(Span),
+  /// Code that the evaluator can't run yet: `(what it is, span)`.
+  Unsupported(&'static str, Span),
+  /// A word with open branches was repeated, concatenated or expanded into another one.
+  UnbalancedWord(Span),
+  /// A word was repeated more times than `operators::MAX_WORD_LEN` allows.
+  WordTooLong(Span),
+}
+
+/// Errors found while deriving an L system.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeriveError {
+  UnknownTable(String, Span),
+  /// A rule was chosen, but the node it rewrites doesn't have as many values as the rule has parameters.
+  ArgMismatch {rule: Span, expected: usize, found: usize},
+  /// The right side of a rule closes a branch it didn't open, or leaves one open.
+  UnbalancedBranch(Span),
+  /// The table function chose different tables for the iterations of an L system derived as a stream.
+  TableChange(Span),
+  Eval(EvalError),
+}
+
+/// Collects the diagnostics of a compilation or a derivation, so every error is reported and not only the first one.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorHandler {
+  diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Note => write!(f, "note"),
+      Self::Warning => write!(f, "warning"),
+      Self::Error => write!(f, "error"),
+    }
+  }
+}
+
+impl Severity {
+  fn color(&self) -> &'static str {
+    match self {
+      Self::Note => CYAN,
+      Self::Warning => YELLOW,
+      Self::Error => RED,
+    }
+  }
+}
+
+// Códigos ANSI para colorear la salida
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+
+impl Diagnostic {
+  pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+    Diagnostic {severity, code: None, message: message.into(), labels: Vec::new(), notes: Vec::new(), help: None}
+  }
+
+  pub fn error(message: impl Into<String>) -> Self {Self::new(Severity::Error, message)}
+  pub fn warning(message: impl Into<String>) -> Self {Self::new(Severity::Warning, message)}
+  pub fn note(message: impl Into<String>) -> Self {Self::new(Severity::Note, message)}
+
+  pub fn with_code(mut self, code: &'static str) -> Self {
+    self.code = Some(code);
+    self
+  }
+
+  pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+    self.labels.push(Label {span, message: message.into(), primary: true});
+    self
+  }
+
+  pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+    self.labels.push(Label {span, message: message.into(), primary: false});
+    self
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+
+  pub fn with_help(mut self, help: impl Into<String>) -> Self {
+    self.help = Some(help.into());
+    self
+  }
+
+  pub fn is_error(&self) -> bool {
+    self.severity == Severity::Error
+  }
+
+  /// Span of the first primary label, if any.
+  pub fn primary_span(&self) -> Option<Span> {
+    self.labels.iter().find(|l| l.primary).map(|l| l.span)
+  }
+
+  /// Renders this diagnostic with snippets of the source code, like:
+  ///
+  /// ```text
+  /// error[P0002]: unexpected `)`
+  ///  --> plant.lsd:3:8
+  ///   |
+  /// 3 | axiom F)
+  ///   |        ^ expected one of ...
+  /// ```
+  pub fn render(&self, map: &SourceMap, color: bool) -> String {
+    let paint = |style: &'static str| if color {style} else {""};
+    let reset = paint(RESET);
+    let mut out = String::new();
+
+    let code = self.code.map_or(String::new(), |c| format!("[{}]", c));
+    let _ = writeln!(out, "{}{}{}{}: {}{}{}", paint(self.severity.color()), self.severity, code, reset, paint(BOLD),
+                     self.message, reset);
+
+    // Lines are rendered in source order, but the location shown is the primary label's one.
+    let mut labels: Vec<&Label> = self.labels.iter().collect();
+    labels.sort_by_key(|l| (l.span.start, !l.primary));
+    let gutter = labels.iter()
+      .filter_map(|l| map.lookup(l.span.start))
+      .map(|(_, lc)| lc.line.to_string().len())
+      .max()
+      .unwrap_or(0);
+    let pad = " ".repeat(gutter);
+
+    if let Some(span) = self.primary_span().or(labels.first().map(|l| l.span)) {
+      let _ = writeln!(out, "{}{}-->{} {}", pad, paint(BLUE), reset, map.describe(span));
+    }
+    if !labels.is_empty() {
+      let _ = writeln!(out, "{} {}|{}", pad, paint(BLUE), reset);
+    }
+    let mut last_file = None;
+    for label in labels {
+      let file = match map.file_at(label.span.start) {
+        Some(file) => file,
+        None => continue,
+      };
+      if last_file.is_some_and(|start| start != file.start) {
+        let _ = writeln!(out, "{}{}::>{} {}", pad, paint(BLUE), reset, map.describe(label.span));
+      }
+      last_file = Some(file.start);
+
+      let start = file.line_col(label.span.start);
+      let end = file.line_col(label.span.end);
+      let text = file.line(start.line).unwrap_or("");
+      // Multiline spans are only underlined up to the end of their first line.
+      let width = if end.line == start.line {end.col.saturating_sub(start.col)} else {text.chars().count() + 1 - start.col};
+      let (mark, style) = if label.primary {('^', self.severity.color())} else {('-', BLUE)};
+      let _ = writeln!(out, "{}{:>w$} |{} {}", paint(BLUE), start.line, reset, text, w = gutter);
+      let _ = writeln!(out, "{} {}|{} {}{}{}{}{}", pad, paint(BLUE), reset, " ".repeat(start.col - 1), paint(style),
+                       mark.to_string().repeat(width.max(1)), if label.message.is_empty() {String::new()} else {format!(" {}", label.message)},
+                       reset);
+    }
+    for note in &self.notes {
+      let _ = writeln!(out, "{} {}={} {}note{}: {}", pad, paint(BLUE), reset, paint(BOLD), reset, note);
+    }
+    if let Some(help) = &self.help {
+      let _ = writeln!(out, "{} {}={} {}help{}: {}", pad, paint(BLUE), reset, paint(BOLD), reset, help);
+    }
+    out
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.code {
+      Some(code) => write!(f, "{}[{}]: {}", self.severity, code, self.message),
+      None => write!(f, "{}: {}", self.severity, self.message),
+    }
+  }
+}
+
+impl From<LexicalError> for Diagnostic {
+  fn from(err: LexicalError) -> Self {
+    let at = Span::new(err.offset(), err.offset() + 1);
+    let diag = Diagnostic::error(err.to_string());
+    match err {
+      LexicalError::UnterminatedString(_) => diag.with_code("L0001")
+        .with_primary(at, "string starts here")
+        .with_help("close the string with `\"` before the end of the line"),
+      LexicalError::UnterminatedComment(_) => diag.with_code("L0002")
+        .with_primary(at, "comment starts here")
+        .with_note("block comments can be nested, so every `/*` needs its own `*/`"),
+      LexicalError::InvalidEscape(_) => diag.with_code("L0003")
+        .with_primary(at, "unknown escape")
+        .with_help("valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"`, `\\'` and `\\u{...}`"),
+      LexicalError::IntegerOverflow(_) => diag.with_code("L0004")
+        .with_primary(at, "doesn't fit in a 64-bit integer")
+        .with_help("use a float number instead"),
+      LexicalError::InvalidFloat(_) => diag.with_code("L0005").with_primary(at, ""),
+      LexicalError::InvalidWeight(_) => diag.with_code("L0006")
+        .with_primary(at, "")
+        .with_note("weights must be finite and not negative"),
+      LexicalError::InvalidSymbol(_) => diag.with_code("L0007")
+        .with_primary(at, "symbol name starts here")
+        .with_help("names of symbols are quoted and have no spaces, like `\"Apex\"`"),
+    }
+  }
+}
+
+/// Describes a token for an error message.
+fn describe_token(token: &Token) -> String {
+  match token.ttype {
+    TokenType::NewLine => "end of line".to_string(),
+    _ => format!("`{}`", token.text),
+  }
+}
+
+/// Spelling of a terminal of the LSD grammar, as named by LALRPOP.
+fn spelling(terminal: &str) -> String {
+  let punct = match terminal {
+    "Assign" => "=", "Add" => "+", "Sub" => "-", "Mul" => "*", "Div" => "/", "Mod" => "%", "Pow" => "**",
+    "EQ" => "==", "NE" => "!=", "LT" => "<", "LE" => "<=", "GT" => ">", "GE" => ">=",
+    "BitAnd" => "&", "BitOr" => "|", "BitXor" => "^", "BitNot" => "~",
+    "LParen" => "(", "RParen" => ")", "LBracket" => "[", "RBracket" => "]", "LBrace" => "{", "RBrace" => "}",
+    "Dot" => ".", "Comma" => ",", "Colon" => ":", "QM" => "?", "Arrow" => "->", "DArrow" => "=>", "Backquote" => "`",
+    "SemiColon" => ";",
+    "Id" => return "identifier".to_string(),
+    "AtId" => return "`@name`".to_string(),
+    "Accessor" => return "`.name`".to_string(),
+    "Int" => return "integer".to_string(),
+    "Float" => return "float".to_string(),
+    "String" => return "string".to_string(),
+    "NewLine" => return "end of line".to_string(),
+    "GmSymbol" => return "symbol".to_string(),
+    "Other" => return "unknown character".to_string(),
+    // Palabras clave
+    _ => return format!("`{}`", terminal.to_lowercase()),
+  };
+  format!("`{}`", punct)
+}
+
+/// Formats the tokens expected by the parser, whose names come quoted from LALRPOP.
+fn describe_expected(expected: &[String]) -> Option<String> {
+  let names: Vec<String> = expected.iter().map(|e| spelling(e.trim_matches('"'))).collect();
+  match names.as_slice() {
+    [] => None,
+    [one] => Some(format!("expected {}", one)),
+    _ => Some(format!("expected one of {}", names.join(", "))),
+  }
+}
+
+impl From<ParseError<usize, Token<'_>, LexicalError>> for Diagnostic {
+  fn from(err: ParseError<usize, Token<'_>, LexicalError>) -> Self {
+    match err {
+      ParseError::InvalidToken {location} => Diagnostic::error("invalid token")
+        .with_code("P0001")
+        .with_primary(Span::new(location, location + 1), ""),
+      ParseError::UnrecognizedToken {token: (l, token, r), expected} => {
+        let diag = Diagnostic::error(format!("unexpected {}", describe_token(&token))).with_code("P0002");
+        match describe_expected(&expected) {
+          Some(exp) => diag.with_primary(Span::new(l, r), exp),
+          None => diag.with_primary(Span::new(l, r), ""),
+        }
+      },
+      ParseError::UnrecognizedEof {location, expected} => {
+        let diag = Diagnostic::error("unexpected end of file").with_code("P0003");
+        match describe_expected(&expected) {
+          Some(exp) => diag.with_primary(Span::new(location, location), exp),
+          None => diag.with_primary(Span::new(location, location), ""),
+        }
+      },
+      ParseError::ExtraToken {token: (l, token, r)} => Diagnostic::error(format!("extra {}", describe_token(&token)))
+        .with_code("P0004")
+        .with_primary(Span::new(l, r), "nothing was expected here"),
+      ParseError::User {error} => error.into(),
+    }
+  }
+}
+
+impl From<EvalError> for Diagnostic {
+  fn from(err: EvalError) -> Self {
+    match err {
+      EvalError::UndefinedVariable(name, span) => Diagnostic::error(format!("undefined variable `{}`", name))
+        .with_code("E0001")
+        .with_primary(span, "not found in this scope")
+        .with_help(format!("declare it first with `let {} = ...`", name)),
+      EvalError::TypeMismatch(op, types, span) => Diagnostic::error(format!("`{}` can't be applied to {}", op, types.join(" and ")))
+        .with_code("E0002")
+        .with_primary(span, ""),
+      EvalError::WrongArgCount {expected, found, call, def} => {
+        let diag = Diagnostic::error(format!("this function takes {} arguments but {} were given", expected, found))
+          .with_code("E0003")
+          .with_primary(call, "");
+        match def {
+          Some(def) => diag.with_secondary(def, "function defined here"),
+          None => diag,
+        }
+      },
+      EvalError::DivisionByZero(span) => Diagnostic::error("division by zero")
+        .with_code("E0004")
+        .with_primary(span, ""),
+      EvalError::NotCallable(span) => Diagnostic::error("this value is not a function")
+        .with_code("E0005")
+        .with_primary(span, ""),
+      EvalError::Overflow(span) => Diagnostic::error("integer overflow")
+        .with_code("E0006")
+        .with_primary(span, "doesn't fit in a 64-bit integer")
+        .with_help("use float numbers instead"),
+      EvalError::Unsupported(what, span) => Diagnostic::error(format!("{} can't be evaluated yet", what))
+        .with_code("E0007")
+        .with_primary(span, ""),
+      EvalError::UnbalancedWord(span) => Diagnostic::error("unbalanced branches in word")
+        .with_code("E0008")
+        .with_primary(span, "")
+        .with_note("every `[` must be closed by a `]` in the same word"),
+      EvalError::WordTooLong(span) => Diagnostic::error("word too long")
+        .with_code("E0009")
+        .with_primary(span, "")
+        .with_note(format!("words repeated with `*` can't have more than {} nodes", MAX_WORD_LEN)),
+    }
+  }
+}
+
+impl From<DeriveError> for Diagnostic {
+  fn from(err: DeriveError) -> Self {
+    match err {
+      DeriveError::UnknownTable(name, span) => Diagnostic::error(format!("unknown rules table `{}`", name))
+        .with_code("D0001")
+        .with_primary(span, "chosen here"),
+      DeriveError::ArgMismatch {rule, expected, found} => {
+        Diagnostic::error(format!("a node with {} values can't be rewritten by a rule with {} parameters", found, expected))
+          .with_code("D0002")
+          .with_primary(rule, "chosen for the node")
+      },
+      DeriveError::UnbalancedBranch(span) => Diagnostic::error("unbalanced branch in derived word")
+        .with_code("D0003")
+        .with_primary(span, "")
+        .with_note("every `[` must be closed by a `]` in the same word"),
+      DeriveError::TableChange(span) => Diagnostic::error("the rules table changes between iterations")
+        .with_code("D0004")
+        .with_primary(span, "chooses the tables")
+        .with_note("words derived as a stream are rewritten with the same table in every iteration"),
+      DeriveError::Eval(err) => err.into(),
+    }
+  }
+}
+
+impl From<EvalError> for DeriveError {
+  fn from(err: EvalError) -> Self {
+    DeriveError::Eval(err)
+  }
+}
+
+impl ErrorHandler {
+  pub fn new() -> Self {
+    ErrorHandler {diagnostics: Vec::new()}
+  }
+
+  pub fn push(&mut self, diagnostic: impl Into<Diagnostic>) {
+    self.diagnostics.push(diagnostic.into());
+  }
+
+  pub fn extend<D: Into<Diagnostic>>(&mut self, diagnostics: impl IntoIterator<Item = D>) {
+    self.diagnostics.extend(diagnostics.into_iter().map(Into::into));
+  }
+
+  /// Keeps the value of a result, or records its error and returns `None` so the caller can go on.
+  pub fn check<T, E: Into<Diagnostic>>(&mut self, res: Result<T, E>) -> Option<T> {
+    res.map_err(|e| self.push(e)).ok()
+  }
+
+  pub fn diagnostics(&self) -> &[Diagnostic] {
+    &self.diagnostics
+  }
+
+  pub fn error_count(&self) -> usize {
+    self.diagnostics.iter().filter(|d| d.is_error()).count()
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.diagnostics.iter().any(Diagnostic::is_error)
+  }
+
+  pub fn clear(&mut self) {
+    self.diagnostics.clear();
+  }
+
+  /// Renders every diagnostic, followed by a summary line if there are errors.
+  pub fn render(&self, map: &SourceMap, color: bool) -> String {
+    let mut out: String = self.diagnostics.iter().map(|d| d.render(map, color) + "\n").collect();
+    match self.error_count() {
+      0 => {},
+      1 => out += "aborting due to 1 previous error\n",
+      n => out += &format!("aborting due to {} previous errors\n", n),
+    }
+    out
+  }
+
+  /// Prints every diagnostic to stderr, colored unless it is not a terminal or `NO_COLOR` is set.
+  pub fn emit(&self, map: &SourceMap) {
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    eprint!("{}", self.render(map, color));
+  }
+}
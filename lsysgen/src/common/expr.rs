@@ -0,0 +1,303 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use lsd::Sym;
+use lsd::ast::grammar;
+use lsd::ast::normal::{Expr, ExprKind};
+use lsd::source::Span;
+
+use super::errors::EvalError;
+use super::operators;
+use super::tree::Tree;
+use super::tree::node::{context, Node, NodeContent};
+use super::values::{self, Function, Parameter, Scope, Value};
+
+/// Evaluates LSD expressions: the arguments of words, the conditions of rules and the code that builds values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpressionEvaluator;
+
+impl ExpressionEvaluator {
+  pub fn new() -> Self {
+    ExpressionEvaluator
+  }
+
+  pub fn eval(&self, e: &Expr, scope: &Scope) -> Result<Value, EvalError> {
+    use ExprKind::*;
+    Ok(match &e.kind {
+      Int(i) => Value::Int(*i),
+      Float(f) => Value::Float(*f),
+      String(s) => Value::String(s.to_string()),
+      Bool(b) => Value::Bool(*b),
+      Null => Value::Null,
+      ID(name) => match scope.get(name) {
+        Some(val) => val.clone(),
+        None => return Err(EvalError::UndefinedVariable(name.to_string(), e.span)),
+      },
+
+      Plus(a) => match self.eval(a, scope)? {
+        val @ (Value::Int(_) | Value::Float(_)) => val,
+        val => return Err(mismatch("+", &[&val], e.span)),
+      },
+      Minus(a) => {
+        let a = self.eval(a, scope)?;
+        check("-", operators::neg(&a), &[&a], e.span)?
+      },
+      Not(a) => match self.eval(a, scope)? {
+        Value::Bool(b) => Value::Bool(!b),
+        val => return Err(mismatch("not", &[&val], e.span)),
+      },
+      BitNot(a) => match self.eval(a, scope)? {
+        Value::Int(i) => Value::Int(!i),
+        val => return Err(mismatch("~", &[&val], e.span)),
+      },
+
+      Pow(a, b) => self.binary("**", a, b, scope, e.span, operators::pow)?,
+      Mul(a, b) => self.binary("*", a, b, scope, e.span, operators::mul)?,
+      Div(a, b) | Mod(a, b) => {
+        let (op, f): (_, fn(&Value, &Value) -> Value) = match &e.kind {
+          Div(..) => ("/", operators::div),
+          _ => ("%", operators::rem),
+        };
+        let (a, b) = (self.eval(a, scope)?, self.eval(b, scope)?);
+        if matches!((&a, &b), (Value::Int(_), Value::Int(0))) {
+          return Err(EvalError::DivisionByZero(e.span));
+        }
+        check(op, f(&a, &b), &[&a, &b], e.span)?
+      },
+      Add(a, b) => self.binary("+", a, b, scope, e.span, operators::add)?,
+      Sub(a, b) => self.binary("-", a, b, scope, e.span, operators::sub)?,
+      BitAnd(a, b) => self.binary("&", a, b, scope, e.span, |a, b| operators::bit_op(a, b, |x, y| x & y))?,
+      BitXor(a, b) => self.binary("^", a, b, scope, e.span, |a, b| operators::bit_op(a, b, |x, y| x ^ y))?,
+      BitOr(a, b) => self.binary("|", a, b, scope, e.span, |a, b| operators::bit_op(a, b, |x, y| x | y))?,
+
+      EQ(a, b) => Value::Bool(operators::eq(&self.eval(a, scope)?, &self.eval(b, scope)?)),
+      NE(a, b) => Value::Bool(!operators::eq(&self.eval(a, scope)?, &self.eval(b, scope)?)),
+      LT(a, b) => self.compare("<", a, b, scope, e.span, Ordering::is_lt)?,
+      LE(a, b) => self.compare("<=", a, b, scope, e.span, Ordering::is_le)?,
+      GT(a, b) => self.compare(">", a, b, scope, e.span, Ordering::is_gt)?,
+      GE(a, b) => self.compare(">=", a, b, scope, e.span, Ordering::is_ge)?,
+
+      // `and` y `or` no evalúan el segundo operando si el primero ya decide el resultado
+      And(a, b) | Or(a, b) => {
+        let (op, short) = if matches!(e.kind, And(..)) {("and", false)} else {("or", true)};
+        match self.eval(a, scope)? {
+          Value::Bool(x) if x == short => Value::Bool(x),
+          Value::Bool(_) => match self.eval(b, scope)? {
+            Value::Bool(y) => Value::Bool(y),
+            val => return Err(mismatch(op, &[&Value::Bool(!short), &val], e.span)),
+          },
+          val => return Err(mismatch(op, &[&val], e.span)),
+        }
+      },
+      IfElse(cond, a, b) => match self.eval(cond, scope)? {
+        Value::Bool(true) => self.eval(a, scope)?,
+        Value::Bool(false) => self.eval(b, scope)?,
+        val => return Err(mismatch("if", &[&val], cond.span)),
+      },
+
+      FnCall(f, args) => {
+        let fun = match self.eval(f, scope)? {
+          Value::Function(fun) => fun,
+          _ => return Err(EvalError::NotCallable(f.span)),
+        };
+        let args = args.iter().map(|a| self.eval(a, scope)).collect::<Result<Vec<_>, _>>()?;
+        fun.call(&args, scope, e.span, self)?
+      },
+      Lambda(params, body) => {
+        let params = params.iter().map(|p| Parameter::new(p.name.to_string())).collect();
+        Value::Function(Rc::new(Function::new(params, (**body).clone().into_owned(), e.span)))
+      },
+
+      List(_) => return Err(EvalError::Unsupported("lists", e.span)),
+      // `lv.leaf`: un nombre de un módulo importado, que está en el ámbito con el prefijo
+      PropAcc(..) => match qualified_name(e) {
+        Some(name) => match scope.get(&name) {
+          Some(val) => val.clone(),
+          None => return Err(EvalError::UndefinedVariable(name, e.span)),
+        },
+        None => return Err(EvalError::Unsupported("properties", e.span)),
+      },
+      IndexExpr(..) => return Err(EvalError::Unsupported("indexing", e.span)),
+      In(..) => return Err(EvalError::Unsupported("`in`", e.span)),
+      Word(word) => Value::Word(values::Word::Instance(self.eval_word(word, scope)?)),
+    })
+  }
+
+  /// Builds an instance word, like an axiom or a backquoted literal, evaluating the arguments of its leaves and
+  /// splicing the words it expands.
+  pub fn eval_word(&self, word: &grammar::Word<Sym>, scope: &Scope) -> Result<Tree<context::Instance>, EvalError> {
+    let mut res = Tree::new();
+    self.instance_nodes(&word.0, scope, &mut res)?;
+    Ok(res)
+  }
+
+  /// Builds the right side of a rule. The arguments of its leaves stay as expressions, to be evaluated when the rule
+  /// is applied, but the words it expands are evaluated now, so they can't use the parameters of the rule.
+  pub fn right_side(&self, word: &grammar::Word<Sym>, scope: &Scope) -> Result<Tree<context::RightSide>, EvalError> {
+    let mut res = Tree::new();
+    self.right_side_nodes(&word.0, scope, &mut res)?;
+    Ok(res)
+  }
+
+  fn instance_nodes(&self, nodes: &[grammar::Node<Sym>], scope: &Scope, res: &mut Tree<context::Instance>)
+    -> Result<(), EvalError> {
+    for node in nodes {
+      match &node.kind {
+        grammar::NodeKind::Leaf(leaf) => {
+          let values = leaf.args.iter().flatten().map(|a| self.eval(a, scope)).collect::<Result<_, _>>()?;
+          res.add_leaf(NodeContent {character: leaf.symbol, context: context::Instance {values}});
+        },
+        grammar::NodeKind::Branch(nodes) => {
+          res.open_branch();
+          self.instance_nodes(nodes, scope, res)?;
+          res.close_branch().map_err(|_| EvalError::UnbalancedWord(node.span))?;
+        },
+        grammar::NodeKind::Expansion(exp) => {
+          let tree = match self.expand(exp, scope)? {
+            values::Word::Instance(tree) => tree,
+            values::Word::RightSide(tree) => self.instance(&tree, scope, exp.span)?,
+          };
+          res.append(tree).map_err(|_| EvalError::UnbalancedWord(exp.span))?;
+        },
+        grammar::NodeKind::Block(_) => return Err(EvalError::Unsupported("blocks", node.span)),
+      }
+    }
+    Ok(())
+  }
+
+  fn right_side_nodes(&self, nodes: &[grammar::Node<Sym>], scope: &Scope, res: &mut Tree<context::RightSide>)
+    -> Result<(), EvalError> {
+    for node in nodes {
+      match &node.kind {
+        grammar::NodeKind::Leaf(leaf) => {
+          let args = leaf.args.iter().flatten().map(|a| a.clone().into_owned()).collect();
+          res.add_leaf(NodeContent {character: leaf.symbol, context: context::RightSide {args}});
+        },
+        grammar::NodeKind::Branch(nodes) => {
+          res.open_branch();
+          self.right_side_nodes(nodes, scope, res)?;
+          res.close_branch().map_err(|_| EvalError::UnbalancedWord(node.span))?;
+        },
+        grammar::NodeKind::Expansion(exp) => {
+          let tree = match self.expand(exp, scope)? {
+            values::Word::RightSide(tree) => tree,
+            values::Word::Instance(tree) => right_side_of(&tree, exp.span)?,
+          };
+          res.append(tree).map_err(|_| EvalError::UnbalancedWord(exp.span))?;
+        },
+        grammar::NodeKind::Block(_) => return Err(EvalError::Unsupported("blocks", node.span)),
+      }
+    }
+    Ok(())
+  }
+
+  /// The word a `@name` or `@name(args)` expansion stands for.
+  fn expand(&self, exp: &grammar::Expansion, scope: &Scope) -> Result<values::Word, EvalError> {
+    let val = match scope.get(&exp.to) {
+      Some(val) => val.clone(),
+      None => return Err(EvalError::UndefinedVariable(exp.to.to_string(), exp.span)),
+    };
+    let val = match (&exp.args, val) {
+      (None, val) => val,
+      (Some(args), Value::Function(fun)) => {
+        let args = args.iter().map(|a| self.eval(a, scope)).collect::<Result<Vec<_>, _>>()?;
+        fun.call(&args, scope, exp.span, self)?
+      },
+      (Some(_), _) => return Err(EvalError::NotCallable(exp.span)),
+    };
+    match val {
+      Value::Word(word) => Ok(word),
+      val => Err(mismatch("@", &[&val], exp.span)),
+    }
+  }
+
+  /// `tree` with the arguments of its leaves evaluated in `scope`.
+  fn instance(&self, tree: &Tree<context::RightSide>, scope: &Scope, span: Span)
+    -> Result<Tree<context::Instance>, EvalError> {
+    let mut res = Tree::new();
+    for node in tree.iter() {
+      match node {
+        Node::BranchStart(_) => res.open_branch(),
+        Node::BranchEnd(_) => res.close_branch().map_err(|_| EvalError::UnbalancedWord(span))?,
+        Node::Leaf(content) => {
+          let values = content.context.args.iter().map(|a| self.eval(a, scope)).collect::<Result<_, _>>()?;
+          res.add_leaf(NodeContent {character: content.character, context: context::Instance {values}});
+        },
+      }
+    }
+    Ok(res)
+  }
+
+  fn binary(&self, op: &'static str, a: &Expr, b: &Expr, scope: &Scope, span: Span,
+            f: impl Fn(&Value, &Value) -> Value) -> Result<Value, EvalError> {
+    let (a, b) = (self.eval(a, scope)?, self.eval(b, scope)?);
+    check(op, f(&a, &b), &[&a, &b], span)
+  }
+
+  fn compare(&self, op: &'static str, a: &Expr, b: &Expr, scope: &Scope, span: Span,
+             f: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let (a, b) = (self.eval(a, scope)?, self.eval(b, scope)?);
+    match operators::cmp(&a, &b) {
+      Some(ord) => Ok(Value::Bool(f(ord))),
+      None => Err(mismatch(op, &[&a, &b], span)),
+    }
+  }
+}
+
+/// The result of an operator, which is `Value::Error` if it can't be applied to its operands, if they are integers and
+/// the result doesn't fit in one, if they are words with open branches, or if a word is repeated too many times.
+/// The name an identifier or a chain of property accesses on one stands for, like `lv.leaf` or `a.b.c`.
+pub fn qualified_name(e: &Expr) -> Option<String> {
+  match &e.kind {
+    ExprKind::ID(name) => Some(name.to_string()),
+    ExprKind::PropAcc(e, prop) => Some(qualified_name(e)? + "." + prop),
+    _ => None,
+  }
+}
+
+fn check(op: &'static str, res: Value, operands: &[&Value], span: Span) -> Result<Value, EvalError> {
+  match res {
+    Value::Error if operands.iter().all(|v| matches!(v, Value::Int(_))) => Err(EvalError::Overflow(span)),
+    Value::Error if op == "*" && matches!(operands, [Value::Word(w), Value::Int(n)] | [Value::Int(n), Value::Word(w)]
+                                           if w.is_balanced() && *n >= 0) => Err(EvalError::WordTooLong(span)),
+    Value::Error if operands.iter().any(|v| matches!(v, Value::Word(w) if !w.is_balanced())) => {
+      Err(EvalError::UnbalancedWord(span))
+    },
+    Value::Error => Err(mismatch(op, operands, span)),
+    res => Ok(res),
+  }
+}
+
+/// `tree` as the right side of a rule, with its values written as literals.
+fn right_side_of(tree: &Tree<context::Instance>, span: Span) -> Result<Tree<context::RightSide>, EvalError> {
+  let mut res = Tree::new();
+  for node in tree.iter() {
+    match node {
+      Node::BranchStart(_) => res.open_branch(),
+      Node::BranchEnd(_) => res.close_branch().map_err(|_| EvalError::UnbalancedWord(span))?,
+      Node::Leaf(content) => {
+        let args = content.context.values.iter().map(|v| literal(v, span)).collect::<Result<_, _>>()?;
+        res.add_leaf(NodeContent {character: content.character, context: context::RightSide {args}});
+      },
+    }
+  }
+  Ok(res)
+}
+
+/// An expression that evaluates to `val`.
+fn literal(val: &Value, span: Span) -> Result<Expr<'static>, EvalError> {
+  let kind = match val {
+    Value::Int(i) => ExprKind::Int(*i),
+    Value::Float(f) => ExprKind::Float(*f),
+    Value::Bool(b) => ExprKind::Bool(*b),
+    Value::String(s) => ExprKind::String(s.clone().into()),
+    Value::Null => ExprKind::Null,
+    _ => return Err(EvalError::Unsupported("words with values that aren't numbers, booleans or strings", span)),
+  };
+  Ok(Expr::new(kind, span))
+}
+
+fn mismatch(op: &'static str, operands: &[&Value], span: Span) -> EvalError {
+  EvalError::TypeMismatch(op, operands.iter().map(|v| v.type_name()).collect(), span)
+}
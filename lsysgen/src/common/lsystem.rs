@@ -1,12 +1,27 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::string::String;
 use std::vec::Vec;
 use std::collections::HashMap;
 
+use lsd::Symbol;
+use lsd::ast::normal::LSysDef;
+use lsd::source::Span;
+
 use crate::common::tree::*;
-use super::values::Scope;
+use crate::deriving::{Derivator, StreamDeriver, Table};
+use super::values::{Function, Scope, Value};
+use super::errors::{DeriveError, Diagnostic, ErrorHandler, EvalError};
+use super::expr::ExpressionEvaluator;
+use super::module::ModuleId;
+use super::settings::Settings2D;
 
+/// An L system over the alphabet `T`: `Sym` for the ones written in LSD, or any other `Symbol` for the ones built
+/// through the library, with `new` and the `with_*` methods.
 #[derive(Debug, Clone)]
-pub struct LSystem<T> {
+pub struct LSystem<T: Symbol> {
   scope: Scope,
 
   name: String,
@@ -15,15 +30,333 @@ pub struct LSystem<T> {
   coding_rules: Table<T>,
 
   axiom: Tree<node::context::Instance, T>,
-  target_iterations: i32,
+  target_iterations: usize,
+  /// Seed of the random numbers of stochastic rules. It's saved with the words written to tree files.
+  seed: u64,
   settings_2d: Settings2D,
-  ignore_chars: Vec<T>,
-  table_func: Function,
+  /// Gives the name of the table of every iteration, from its number. Without it, the default table is used.
+  table_func: Option<Rc<Function>>,
+  /// Where `derive_to` writes the words of the iterations. Without it, the temporary directory of the system is used.
+  spill_dir: Option<PathBuf>,
 
   current_iter: usize,
   current_tree: Tree<node::context::Instance, T>,
-  encoded_trees: Vec<Tree<node::context::Instance, T>>,
+  /// IDs and origins of the nodes of the current word, if they're followed.
+  lineage: Option<Lineage>,
+  /// Where the nodes of the previous word went in the last step, if lineage is followed.
+  last_diff: Option<TreeDiff>,
 
   err: ErrorHandler,
   derivator: Derivator<T>,
 }
+
+impl<T: Symbol> LSystem<T> {
+  /// An L system without rules, which derives `axiom` into itself, for no iterations.
+  pub fn new(name: impl Into<String>, axiom: Tree<node::context::Instance, T>) -> Self {
+    LSystem {
+      scope: Scope::new(),
+      name: name.into(),
+      tables: HashMap::new(),
+      default_table: Table::new(),
+      coding_rules: Table::new(),
+      current_tree: axiom.clone(),
+      axiom,
+      target_iterations: 0,
+      seed: 0,
+      settings_2d: Settings2D::default(),
+      table_func: None,
+      spill_dir: None,
+      current_iter: 0,
+      lineage: None,
+      last_diff: None,
+      err: ErrorHandler::new(),
+      derivator: Derivator::new(),
+    }
+  }
+
+  /// Sets the table used when there's no table function.
+  pub fn with_rules(mut self, table: Table<T>) -> Self {
+    self.default_table = table;
+    self
+  }
+
+  pub fn with_table(mut self, name: impl Into<String>, table: Table<T>) -> Self {
+    self.tables.insert(name.into(), table);
+    self
+  }
+
+  /// Sets the rules that turn derived words into the ones that are drawn.
+  pub fn with_coding_rules(mut self, table: Table<T>) -> Self {
+    self.coding_rules = table;
+    self
+  }
+
+  /// Sets the function that chooses the table of every iteration: it takes the number of the iteration, from 0, and
+  /// returns the name of a table.
+  pub fn with_table_func(mut self, func: Rc<Function>) -> Self {
+    self.table_func = Some(func);
+    self
+  }
+
+  pub fn with_iterations(mut self, iterations: usize) -> Self {
+    self.target_iterations = iterations;
+    self
+  }
+
+  pub fn with_seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+
+  pub fn with_settings(mut self, settings: Settings2D) -> Self {
+    self.settings_2d = settings;
+    self
+  }
+
+  /// Follows the nodes across iterations: every step records a `TreeDiff`, and the lineage of the current word is
+  /// kept up to date.
+  pub fn with_lineage(mut self) -> Self {
+    self.lineage = Some(Lineage::new(self.axiom.len()));
+    self
+  }
+
+  /// Sets the directory where `derive_to` writes the words of the iterations.
+  pub fn with_spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    self.spill_dir = Some(dir.into());
+    self
+  }
+
+  /// Sets the variables and functions that rules can use, replacing the ones defined before.
+  pub fn with_scope(mut self, scope: Scope) -> Self {
+    self.scope = scope;
+    self
+  }
+
+  /// Defines a variable that rules can use.
+  pub fn with_var(mut self, name: impl Into<String>, val: Value) -> Self {
+    self.scope.set(name.into(), val);
+    self
+  }
+
+  pub fn name(&self) -> &str {&self.name}
+  pub fn axiom(&self) -> &Tree<node::context::Instance, T> {&self.axiom}
+  pub fn iterations(&self) -> usize {self.target_iterations}
+  pub fn seed(&self) -> u64 {self.seed}
+  pub fn settings(&self) -> &Settings2D {&self.settings_2d}
+  pub fn scope(&self) -> &Scope {&self.scope}
+
+  /// The number of iterations derived so far.
+  pub fn iteration(&self) -> usize {self.current_iter}
+  pub fn current(&self) -> &Tree<node::context::Instance, T> {&self.current_tree}
+  pub fn errors(&self) -> &ErrorHandler {&self.err}
+  /// The lineage of the current word, if the L system was built `with_lineage`.
+  pub fn lineage(&self) -> Option<&Lineage> {self.lineage.as_ref()}
+  /// The diff of the last step, if the L system was built `with_lineage` and it has been derived.
+  pub fn last_diff(&self) -> Option<&TreeDiff> {self.last_diff.as_ref()}
+
+  /// Derives the current word once. If it fails, the word stays as it was, the errors are recorded in `errors` and
+  /// `false` is returned.
+  pub fn step(&mut self) -> bool {
+    let res = match self.table(self.current_iter) {
+      Ok(table) if self.lineage.is_some() => self.derivator.step_with_diff(table, &self.current_tree, &self.scope)
+        .map(|(tree, diff)| (tree, Some(diff))),
+      Ok(table) => self.derivator.step(table, &self.current_tree, &self.scope).map(|tree| (tree, None)),
+      Err(err) => Err(vec![err]),
+    };
+    match res {
+      Ok((tree, diff)) => {
+        if let (Some(lineage), Some(diff)) = (&mut self.lineage, &diff) {
+          *lineage = lineage.next(diff);
+        }
+        self.last_diff = diff;
+        self.current_tree = tree;
+        self.current_iter += 1;
+        true
+      },
+      Err(errors) => {
+        self.err.extend(errors);
+        false
+      },
+    }
+  }
+
+  /// Derives the current word until it gets to the number of iterations of the L system, or to an error.
+  pub fn derive(&mut self) -> &Tree<node::context::Instance, T> {
+    while self.current_iter < self.target_iterations && self.step() {}
+    &self.current_tree
+  }
+
+  /// Derives the current word until it gets to the number of iterations of the L system, like `derive`, but with a
+  /// `StreamDeriver`: the words of the iterations are written to files and the last one is sent to `sink`, so they
+  /// don't have to fit in memory. The current word stays as it is.
+  ///
+  /// Every iteration must use the same table. If the derivation fails, the errors are recorded in `errors` and
+  /// `false` is returned; `Err` is only returned for errors of the files or of `sink`.
+  pub fn derive_to(&mut self, sink: &mut impl WordSink<T>) -> io::Result<bool> {
+    let iterations = self.target_iterations.saturating_sub(self.current_iter);
+    let table = match self.stream_table(iterations) {
+      Ok(table) => table,
+      Err(err) => {
+        self.err.push(err);
+        return Ok(false);
+      },
+    };
+    let globals = Rc::new(self.scope.clone());
+    let derivator = &self.derivator;
+    let mut failure = None;
+    let mut deriver = StreamDeriver::new(|symbol: T, values: &[Value], out: &mut dyn WordSink<T>| {
+      match derivator.successor_of(table, &symbol, values, &globals) {
+        Ok(Some(successor)) => successor.nodes().try_for_each(|node| out.node(node.into())).map(|_| true),
+        Ok(None) => Ok(false),
+        Err(err) => {
+          failure = Some(err);
+          Err(io::Error::new(io::ErrorKind::InvalidData, "the word can't be derived"))
+        },
+      }
+    });
+    if let Some(dir) = &self.spill_dir {
+      deriver = deriver.spill_dir(dir);
+    }
+    let res = deriver.derive(&self.current_tree, iterations, sink);
+    match failure {
+      Some(err) => {
+        self.err.push(err);
+        Ok(false)
+      },
+      None => res.map(|_| true),
+    }
+  }
+
+  /// Goes back to the axiom.
+  pub fn reset(&mut self) {
+    self.current_tree = self.axiom.clone();
+    self.current_iter = 0;
+    if self.lineage.is_some() {
+      self.lineage = Some(Lineage::new(self.axiom.len()));
+    }
+    self.last_diff = None;
+    self.err.clear();
+  }
+
+  /// The current word with the coding rules applied.
+  pub fn encoded(&self) -> Result<Tree<node::context::Instance, T>, Vec<DeriveError>> {
+    self.derivator.step(&self.coding_rules, &self.current_tree, &self.scope)
+  }
+
+  /// The table of the next `iterations` iterations, which must be the same for all of them.
+  fn stream_table(&self, iterations: usize) -> Result<&Table<T>, DeriveError> {
+    let table = self.table(self.current_iter)?;
+    for i in self.current_iter + 1..self.current_iter + iterations {
+      if !std::ptr::eq(self.table(i)?, table) {
+        return Err(DeriveError::TableChange(self.table_func.as_ref().map(|f| f.span()).unwrap_or_default()));
+      }
+    }
+    Ok(table)
+  }
+
+  /// The table of an iteration.
+  fn table(&self, iteration: usize) -> Result<&Table<T>, DeriveError> {
+    let func = match &self.table_func {
+      Some(func) => func,
+      None => return Ok(&self.default_table),
+    };
+    let name = match func.call(&[Value::Int(iteration as i64)], &self.scope, func.span(), &ExpressionEvaluator::new())? {
+      Value::String(name) => name,
+      val => return Err(EvalError::TypeMismatch("table", vec![val.type_name()], func.span()).into()),
+    };
+    self.tables.get(&name).ok_or(DeriveError::UnknownTable(name, func.span()))
+  }
+}
+
+impl<T: Symbol> fmt::Display for LSystem<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "LSystem({})", self.name)
+  }
+}
+
+/// An L system defined in a module, before it's built.
+#[derive(Debug, Clone)]
+pub struct LSystemDecl {
+  pub module: ModuleId,
+  /// `None` for the implicit L system of a file without `lsys` blocks.
+  pub name: Option<String>,
+  pub main: bool,
+  pub params: Vec<DeclParam>,
+  pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeclParam {
+  pub name: String,
+  /// Whether it has a default value, so it can be left out.
+  pub optional: bool,
+}
+
+/// An argument for a parameterized L system: `bush(30)` or `lsys run bush -a angle=30`.
+#[derive(Debug, Clone)]
+pub struct Arg {
+  pub name: Option<String>,
+  pub value: Value,
+}
+
+impl LSystemDecl {
+  pub fn from_def(module: ModuleId, def: &LSysDef) -> Self {
+    LSystemDecl {
+      module,
+      name: def.name.as_deref().map(str::to_string),
+      main: def.main,
+      params: def.params.iter()
+        .map(|p| DeclParam {name: p.name.to_string(), optional: p.default_value.is_some()})
+        .collect(),
+      span: def.span,
+    }
+  }
+
+  pub fn display_name(&self) -> &str {
+    self.name.as_deref().unwrap_or("<main>")
+  }
+
+  /// Matches arguments with the parameters of this L system. Positional arguments go first, then named ones.
+  ///
+  /// Returns the value of every parameter, or `None` for the ones left to their default value.
+  pub fn bind(&self, args: Vec<Arg>) -> Result<Vec<(String, Option<Value>)>, Diagnostic> {
+    let mut values: Vec<Option<Value>> = vec![None; self.params.len()];
+    let mut named = false;
+    for (i, arg) in args.into_iter().enumerate() {
+      let idx = match &arg.name {
+        None if named => return Err(self.arg_error("positional argument after a named one")),
+        None if i >= self.params.len() => {
+          return Err(self.arg_error(format!("`{}` takes {} arguments but more were given", self.display_name(),
+                                             self.params.len())));
+        },
+        None => i,
+        Some(name) => {
+          named = true;
+          match self.params.iter().position(|p| p.name == *name) {
+            Some(idx) => idx,
+            None => return Err(self.arg_error(format!("`{}` has no parameter `{}`", self.display_name(), name))),
+          }
+        },
+      };
+      if values[idx].is_some() {
+        return Err(self.arg_error(format!("parameter `{}` given more than once", self.params[idx].name)));
+      }
+      values[idx] = Some(arg.value);
+    }
+
+    for (param, value) in self.params.iter().zip(&values) {
+      if value.is_none() && !param.optional {
+        return Err(self.arg_error(format!("missing argument for parameter `{}`", param.name)));
+      }
+    }
+    Ok(self.params.iter().map(|p| p.name.clone()).zip(values).collect())
+  }
+
+  fn arg_error(&self, message: impl Into<String>) -> Diagnostic {
+    let params: Vec<&str> = self.params.iter().map(|p| p.name.as_str()).collect();
+    Diagnostic::error(message)
+      .with_code("A0001")
+      .with_secondary(self.span, "L system defined here")
+      .with_note(format!("parameters: ({})", params.join(", ")))
+  }
+}
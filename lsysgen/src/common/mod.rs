@@ -1,9 +1,15 @@
 mod values;
 pub mod tree;
-mod lsystem;
+pub mod lsystem;
+pub mod operators;
+pub mod errors;
+pub mod module;
+pub mod expr;
+pub mod settings;
+pub mod builder;
 
 pub use values::Parameter;
 pub use values::Function;
 pub use values::Value;
 pub use values::Scope;
-
+pub use values::Word;
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::convert::Dialect;
+use lsd::ast::normal::{LSysDef, LSysStmt, Module, ModStmt, ImportStmt, Stmt, StmtKind};
+use lsd::source::{SourceMap, Span};
+use lsd::ParseError;
+
+use super::errors::{Diagnostic, ErrorHandler};
+use super::lsystem::LSystemDecl;
+
+/// Environment variable with extra directories to look for modules in, separated like `PATH`.
+pub const LSD_PATH_VAR: &str = "LSD_PATH";
+
+/// Extension of LSD files. It can be omitted in imports.
+pub const LSD_EXTENSION: &str = "lsd";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(usize);
+
+/// An import already resolved to a loaded module.
+#[derive(Debug, Clone)]
+pub struct Import {
+  pub module: ModuleId,
+  /// Name the module is accessed with: the alias, or the file name without extension.
+  pub name: String,
+  /// Names imported with `from module import ...`.
+  pub symbols: Vec<String>,
+  pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct LoadedModule {
+  pub path: PathBuf,
+  /// Index of the module's file in the loader's `SourceMap`.
+  pub file: usize,
+  pub imports: Vec<Import>,
+  /// Names defined at the top level of the module.
+  pub exports: Vec<String>,
+  /// Every L system defined in the module, including the ones nested in other L systems.
+  pub lsystems: Vec<LSystemDecl>,
+}
+
+/// Loads modules and, recursively, the modules they import.
+///
+/// Every module is loaded only once, even if it's imported from several places. Imports are looked for, in this
+/// order, in the directory of the importing file, in the search paths added with `add_search_path` and in the
+/// directories in `LSD_PATH`.
+#[derive(Debug)]
+pub struct ModuleLoader {
+  search_paths: Vec<PathBuf>,
+  env_paths: Vec<PathBuf>,
+  map: SourceMap,
+  modules: Vec<LoadedModule>,
+  cache: HashMap<PathBuf, ModuleId>,
+  /// Modules being loaded, to detect cyclic imports.
+  loading: Vec<PathBuf>,
+  err: ErrorHandler,
+}
+
+impl Default for ModuleLoader {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ModuleLoader {
+  pub fn new() -> Self {
+    let env_paths = std::env::var_os(LSD_PATH_VAR)
+      .map(|paths| std::env::split_paths(&paths).collect())
+      .unwrap_or_default();
+    ModuleLoader {
+      search_paths: Vec::new(),
+      env_paths,
+      map: SourceMap::new(),
+      modules: Vec::new(),
+      cache: HashMap::new(),
+      loading: Vec::new(),
+      err: ErrorHandler::new(),
+    }
+  }
+
+  pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+    self.search_paths.push(path.into());
+  }
+
+  pub fn source_map(&self) -> &SourceMap {&self.map}
+  pub fn errors(&self) -> &ErrorHandler {&self.err}
+  pub fn module(&self, id: ModuleId) -> &LoadedModule {&self.modules[id.0]}
+
+  /// Loads a file and every module it imports. Returns `None` if the file can't be read; other errors are
+  /// collected in `errors()`.
+  pub fn load_file(&mut self, path: impl AsRef<Path>) -> Option<ModuleId> {
+    let path = path.as_ref();
+    match std::fs::read_to_string(path) {
+      Ok(src) => Some(self.load(path, src, None)),
+      Err(e) => {
+        self.err.push(Diagnostic::error(format!("couldn't read `{}`: {}", path.display(), e)).with_code("M0004"));
+        None
+      },
+    }
+  }
+
+  /// Loads a module from a string, as if it was read from `path`. Its imports are looked for next to `path`.
+  pub fn load_source(&mut self, path: impl AsRef<Path>, src: String) -> ModuleId {
+    self.load(path.as_ref(), src, None)
+  }
+
+  /// Like `load_source`, but the source is written in `dialect`, whatever the extension of `path`.
+  pub fn load_source_as(&mut self, path: impl AsRef<Path>, src: String, dialect: Dialect) -> ModuleId {
+    self.load(path.as_ref(), src, Some(dialect))
+  }
+
+  fn load(&mut self, path: &Path, src: String, dialect: Option<Dialect>) -> ModuleId {
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if let Some(&id) = self.cache.get(&key) {
+      return id;
+    }
+
+    // Files of other dialects are converted to LSD first.
+    let dialect = dialect.or_else(|| path.extension().and_then(|e| e.to_str()).and_then(Dialect::from_extension));
+    let (src_path, src) = match dialect {
+      Some(dialect) => self.convert(dialect, path, src),
+      None => (path.to_path_buf(), src),
+    };
+    self.map.add_file(src_path, src);
+    let file = self.map.files().len() - 1;
+    let id = ModuleId(self.modules.len());
+    let (stmts, errors) = collect_stmts(&self.map, file, id);
+    self.err.extend(errors);
+    self.check_main(&stmts.lsystems);
+
+    self.modules.push(LoadedModule {
+      path: path.to_path_buf(),
+      file,
+      imports: Vec::new(),
+      exports: stmts.exports,
+      lsystems: stmts.lsystems,
+    });
+    self.cache.insert(key.clone(), id);
+    self.loading.push(key);
+
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut imports = Vec::new();
+    for import in stmts.imports {
+      if let Some(import) = self.import(&dir, import) {
+        imports.push(import);
+      }
+    }
+    self.modules[id.0].imports = imports;
+
+    self.loading.pop();
+    id
+  }
+
+  /// Converts a file of another dialect and returns the name and the source of the LSD file to load instead. The
+  /// original file is kept in the map, for the conversion warnings to point to it.
+  fn convert(&mut self, dialect: Dialect, path: &Path, src: String) -> (PathBuf, String) {
+    let converted = dialect.convert(&src);
+    let start = self.map.add_file(path, src).start;
+    for w in converted.warnings {
+      self.err.push(Diagnostic::warning(w.message)
+        .with_code("C0001")
+        .with_primary(Span::new(start + w.span.start, start + w.span.end), ""));
+    }
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(LSD_EXTENSION);
+    (PathBuf::from(name), converted.lsd)
+  }
+
+  fn import(&mut self, dir: &Path, import: PendingImport) -> Option<Import> {
+    let path = match self.find(dir, &import.module) {
+      Some(path) => path,
+      None => {
+        self.err.push(Diagnostic::error(format!("module `{}` not found", import.module))
+          .with_code("M0001")
+          .with_primary(import.span, "imported here")
+          .with_note(format!("looked in `{}`, the search paths and `{}`", dir.display(), LSD_PATH_VAR)));
+        return None;
+      },
+    };
+
+    let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+    if let Some(i) = self.loading.iter().position(|p| *p == key) {
+      let chain: Vec<String> = self.loading[i..].iter().chain([&key]).map(|p| p.display().to_string()).collect();
+      self.err.push(Diagnostic::error(format!("cyclic import of module `{}`", import.module))
+        .with_code("M0002")
+        .with_primary(import.span, "imported here")
+        .with_note(format!("import chain: {}", chain.join(" -> "))));
+      return None;
+    }
+
+    let module = self.load_file(&path)?;
+    for symbol in &import.symbols {
+      if !self.modules[module.0].exports.contains(symbol) {
+        self.err.push(Diagnostic::error(format!("`{}` is not defined in module `{}`", symbol, import.module))
+          .with_code("M0003")
+          .with_primary(import.span, ""));
+      }
+    }
+    let name = import.alias.unwrap_or_else(|| {
+      Path::new(&import.module).file_stem().map_or(import.module.clone(), |s| s.to_string_lossy().into_owned())
+    });
+    Some(Import {module, name, symbols: import.symbols, span: import.span})
+  }
+
+  /// Checks that there is at most one `main` L system.
+  fn check_main(&mut self, lsystems: &[LSystemDecl]) {
+    let mains: Vec<&LSystemDecl> = lsystems.iter().filter(|d| d.main).collect();
+    if let [first, rest @ ..] = mains.as_slice() && !rest.is_empty() {
+      let diag = rest.iter().fold(
+        Diagnostic::error("more than one main L system").with_code("M0005").with_secondary(first.span, "first main L system"),
+        |diag, d| diag.with_primary(d.span, "also main"),
+      );
+      self.err.push(diag.with_help("remove `main` from all of them but one"));
+    }
+  }
+
+  /// Chooses an L system of a module by name, or its main L system if `name` is `None`.
+  ///
+  /// A module with a single L system doesn't need to mark it as `main`.
+  pub fn choose_lsystem(&self, id: ModuleId, name: Option<&str>) -> Result<&LSystemDecl, Diagnostic> {
+    let lsystems = &self.module(id).lsystems;
+    let names = || {
+      let names: Vec<&str> = lsystems.iter().filter_map(|d| d.name.as_deref()).collect();
+      format!("available L systems: {}", names.join(", "))
+    };
+    match name {
+      Some(name) => lsystems.iter()
+        .find(|d| d.name.as_deref() == Some(name))
+        .ok_or_else(|| Diagnostic::error(format!("no L system named `{}`", name)).with_code("M0006").with_note(names())),
+      None => match lsystems.iter().find(|d| d.main) {
+        Some(decl) => Ok(decl),
+        None if lsystems.len() == 1 => Ok(&lsystems[0]),
+        None if lsystems.is_empty() => Err(Diagnostic::error("the module defines no L system").with_code("M0007")),
+        None => Err(Diagnostic::error("no main L system").with_code("M0007")
+          .with_note(names())
+          .with_help("mark one of them as `main lsys` or choose one by name")),
+      },
+    }
+  }
+
+  /// Looks for the file of an imported module.
+  fn find(&self, dir: &Path, module: &str) -> Option<PathBuf> {
+    let mut rel = PathBuf::from(module);
+    if rel.extension().is_none() {
+      rel.set_extension(LSD_EXTENSION);
+    }
+    if rel.is_absolute() {
+      return rel.is_file().then_some(rel);
+    }
+    std::iter::once(dir)
+      .chain(self.search_paths.iter().map(PathBuf::as_path))
+      .chain(self.env_paths.iter().map(PathBuf::as_path))
+      .map(|d| d.join(&rel))
+      .find(|p| p.is_file())
+  }
+
+  /// Parses a loaded module. Syntax errors were already reported when it was loaded.
+  pub fn ast(&self, id: ModuleId) -> Option<Module<'_>> {
+    lsd::parse_lsd_file(&self.map.files()[self.module(id).file]).0
+  }
+
+  /// Resolves a name used in a module, like `lv.leaf` in an expression or in `@lv.leaf`, or `koch` after
+  /// `from shapes import koch`.
+  ///
+  /// Returns the module where the name is defined and its name there, or `None` if it doesn't come from an import.
+  pub fn resolve<'n>(&self, from: ModuleId, name: &'n str) -> Option<(ModuleId, &'n str)> {
+    let imports = &self.module(from).imports;
+    match name.split_once('.') {
+      Some((module, rest)) => {
+        let import = imports.iter().find(|i| i.symbols.is_empty() && i.name == module)?;
+        // `a.b.c`: `b` may be imported by `a` too.
+        self.resolve(import.module, rest).or(Some((import.module, rest)))
+      },
+      None => imports.iter()
+        .find(|i| i.symbols.iter().any(|s| s == name))
+        .map(|i| (i.module, name)),
+    }
+  }
+}
+
+/// An import read from the source, before looking for its module.
+struct PendingImport {
+  module: String,
+  alias: Option<String>,
+  symbols: Vec<String>,
+  span: Span,
+}
+
+struct ModuleStmts {
+  imports: Vec<PendingImport>,
+  exports: Vec<String>,
+  lsystems: Vec<LSystemDecl>,
+}
+
+/// Parses a file of the map and takes what the loader needs from it.
+fn collect_stmts(map: &SourceMap, file: usize, id: ModuleId) -> (ModuleStmts, Vec<ParseError<'_>>) {
+  let (module, errors) = lsd::parse_lsd_file(&map.files()[file]);
+  let mut stmts = ModuleStmts {imports: Vec::new(), exports: Vec::new(), lsystems: Vec::new()};
+
+  for stmt in module.iter().flat_map(|m| m.stmts.iter()) {
+    match stmt {
+      ModStmt::Import(i) => stmts.imports.push(pending(i)),
+      ModStmt::VarDecl(d) => stmts.exports.push(d.name.to_string()),
+      ModStmt::FnDef(d) => stmts.exports.push(d.name.to_string()),
+      ModStmt::LSysDef(d) => {
+        stmts.exports.extend(d.name.as_deref().map(str::to_string));
+        collect_lsystems(d, id, &mut stmts);
+      },
+    }
+  }
+  (stmts, errors)
+}
+
+/// Adds an L system and the ones defined inside it. Imports inside an L system are loaded with the module too.
+fn collect_lsystems(def: &LSysDef, id: ModuleId, stmts: &mut ModuleStmts) {
+  stmts.lsystems.push(LSystemDecl::from_def(id, def));
+  for s in &def.stmts {
+    match s {
+      LSysStmt::Import(i) => stmts.imports.push(pending(i)),
+      LSysStmt::Stmt(Stmt {kind: StmtKind::LSysDef(d), ..}) => collect_lsystems(d, id, stmts),
+      _ => {},
+    }
+  }
+}
+
+fn pending(i: &ImportStmt) -> PendingImport {
+  PendingImport {
+    module: i.module.to_string(),
+    alias: i.alias.as_deref().map(str::to_string),
+    symbols: i.symbols.iter().map(|s| s.to_string()).collect(),
+    span: i.span,
+  }
+}
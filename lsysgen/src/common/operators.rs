@@ -0,0 +1,123 @@
+use super::values::Value;
+
+/// Most nodes a word repeated with `*` can have, so `` `F` * 1000000000000 `` fails instead of taking all the memory.
+pub const MAX_WORD_LEN: usize = 1 << 24;
+
+/// `a + b`: numeric addition, string concatenation and word concatenation.
+pub fn add(a: &Value, b: &Value) -> Value {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => x.checked_add(*y).map(Value::Int).unwrap_or(Value::Error),
+    (Value::Int(x), Value::Float(y)) => Value::Float(*x as f64 + y),
+    (Value::Float(x), Value::Int(y)) => Value::Float(x + *y as f64),
+    (Value::Float(x), Value::Float(y)) => Value::Float(x + y),
+    (Value::String(x), Value::String(y)) => Value::String(format!("{}{}", x, y)),
+    (Value::Word(x), Value::Word(y)) => x.concat(y).map(Value::Word).unwrap_or(Value::Error),
+    _ => Value::Error,
+  }
+}
+
+/// `a - b`.
+pub fn sub(a: &Value, b: &Value) -> Value {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => x.checked_sub(*y).map(Value::Int).unwrap_or(Value::Error),
+    _ => float_op(a, b, |x, y| x - y),
+  }
+}
+
+/// `a * b`: numeric product and word repetition (`word * n` or `n * word`). A repeated word can't be longer than
+/// `MAX_WORD_LEN`.
+pub fn mul(a: &Value, b: &Value) -> Value {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => x.checked_mul(*y).map(Value::Int).unwrap_or(Value::Error),
+    (Value::Int(x), Value::Float(y)) => Value::Float(*x as f64 * y),
+    (Value::Float(x), Value::Int(y)) => Value::Float(x * *y as f64),
+    (Value::Float(x), Value::Float(y)) => Value::Float(x * y),
+    (Value::Word(w), Value::Int(n)) | (Value::Int(n), Value::Word(w)) => match usize::try_from(*n) {
+      Ok(n) if w.len().checked_mul(n).is_some_and(|len| len <= MAX_WORD_LEN) => {
+        w.repeat(n).map(Value::Word).unwrap_or(Value::Error)
+      },
+      _ => Value::Error,
+    },
+    _ => Value::Error,
+  }
+}
+
+/// `a / b`. The result is always a float, also for two integers.
+pub fn div(a: &Value, b: &Value) -> Value {
+  float_op(a, b, |x, y| x / y)
+}
+
+/// `a % b`, the remainder of the Euclidean division, which is never negative.
+pub fn rem(a: &Value, b: &Value) -> Value {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => x.checked_rem_euclid(*y).map(Value::Int).unwrap_or(Value::Error),
+    _ => float_op(a, b, |x, y| x.rem_euclid(y)),
+  }
+}
+
+/// `a ** b`. Integers stay integers if the exponent isn't negative.
+pub fn pow(a: &Value, b: &Value) -> Value {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) if *y >= 0 => match u32::try_from(*y) {
+      Ok(y) => x.checked_pow(y).map(Value::Int).unwrap_or(Value::Error),
+      Err(_) => Value::Error,
+    },
+    _ => float_op(a, b, f64::powf),
+  }
+}
+
+/// `-a`.
+pub fn neg(a: &Value) -> Value {
+  match a {
+    Value::Int(x) => x.checked_neg().map(Value::Int).unwrap_or(Value::Error),
+    Value::Float(x) => Value::Float(-x),
+    _ => Value::Error,
+  }
+}
+
+/// `a == b`. Numbers are compared by value, whatever their type; values of other types are equal if they are of the
+/// same type and hold the same thing.
+pub fn eq(a: &Value, b: &Value) -> bool {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => x == y,
+    (Value::Int(x), Value::Float(y)) | (Value::Float(y), Value::Int(x)) => *x as f64 == *y,
+    (Value::Float(x), Value::Float(y)) => x == y,
+    (Value::Bool(x), Value::Bool(y)) => x == y,
+    (Value::String(x), Value::String(y)) => x == y,
+    (Value::Null, Value::Null) => true,
+    _ => false,
+  }
+}
+
+/// Compares two numbers or two strings, for `<`, `<=`, `>` and `>=`. `None` if they can't be compared.
+pub fn cmp(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+    (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+    _ => number(a)?.partial_cmp(&number(b)?),
+  }
+}
+
+/// `a & b`, `a | b` and `a ^ b` on integers.
+pub fn bit_op(a: &Value, b: &Value, op: fn(i64, i64) -> i64) -> Value {
+  match (a, b) {
+    (Value::Int(x), Value::Int(y)) => Value::Int(op(*x, *y)),
+    _ => Value::Error,
+  }
+}
+
+/// The value of a number as a float.
+fn number(a: &Value) -> Option<f64> {
+  match a {
+    Value::Int(x) => Some(*x as f64),
+    Value::Float(x) => Some(*x),
+    _ => None,
+  }
+}
+
+fn float_op(a: &Value, b: &Value, op: impl Fn(f64, f64) -> f64) -> Value {
+  match (number(a), number(b)) {
+    (Some(x), Some(y)) => Value::Float(op(x, y)),
+    _ => Value::Error,
+  }
+}
@@ -0,0 +1,16 @@
+/// How the 2D turtle draws the words of an L system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings2D {
+  /// Angle turned by `+` and `-`, in degrees.
+  pub angle: f64,
+  /// Length of a step of `F` and `f`.
+  pub step: f64,
+  /// Width of the lines.
+  pub width: f64,
+}
+
+impl Default for Settings2D {
+  fn default() -> Self {
+    Settings2D {angle: 90.0, step: 1.0, width: 1.0}
+  }
+}
@@ -0,0 +1,131 @@
+use std::vec::Vec;
+
+use lsd::Sym;
+
+use crate::common::Value;
+use super::node::context;
+use super::{InstanceWord, NodeRef, Tree, TreeError};
+
+/// Other end of a branch that is still open.
+const OPEN: u32 = u32::MAX;
+
+/// An instance word stored as a structure of arrays: a symbol per node, where the values of each node start in a
+/// single pool of values, and the positions of the branches on their own. A node without values takes 8 bytes,
+/// instead of a `Node` with an empty `Vec`.
+///
+/// It can hold up to `u32::MAX` nodes and values.
+#[derive(Debug, Clone)]
+pub struct CompactTree {
+  /// The symbol of every node. Branch starts and ends have `[` and `]`.
+  symbols: Vec<Sym>,
+  /// Where the values of every node start in `values`, plus where the ones of the last node end.
+  offsets: Vec<u32>,
+  values: Vec<Value>,
+  /// Every branch start and end, by position, with the position of its other end.
+  links: Vec<(u32, u32)>,
+  /// Indices in `links` of the branches that are still open.
+  open_branches: Vec<usize>,
+}
+
+impl Default for CompactTree {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl CompactTree {
+  pub fn new() -> Self {
+    CompactTree {
+      symbols: Vec::new(),
+      offsets: vec![0],
+      values: Vec::new(),
+      links: Vec::new(),
+      open_branches: Vec::new(),
+    }
+  }
+
+  fn link(&self, k: usize) -> NodeRef<'_> {
+    match self.links[k] {
+      (_, OPEN) => NodeRef::BranchStart(0),
+      (i, j) if j > i => NodeRef::BranchStart(j as usize),
+      (_, j) => NodeRef::BranchEnd(j as usize),
+    }
+  }
+
+  fn leaf(&self, i: usize) -> NodeRef<'_> {
+    NodeRef::Leaf(self.symbols[i], &self.values[self.offsets[i] as usize..self.offsets[i + 1] as usize])
+  }
+
+  fn push(&mut self, symbol: Sym) {
+    self.symbols.push(symbol);
+    self.offsets.push(self.values.len() as u32);
+  }
+}
+
+impl InstanceWord for CompactTree {
+  fn len(&self) -> usize {self.symbols.len()}
+
+  fn node(&self, i: usize) -> NodeRef<'_> {
+    match self.links.binary_search_by_key(&(i as u32), |l| l.0) {
+      Ok(k) => self.link(k),
+      Err(_) => self.leaf(i),
+    }
+  }
+
+  /// Goes through the branches along with the nodes, without looking them up.
+  fn nodes(&self) -> impl Iterator<Item = NodeRef<'_>> {
+    let mut k = 0;
+    (0..self.len()).map(move |i| match self.links.get(k) {
+      Some(&(pos, _)) if pos as usize == i => {
+        k += 1;
+        self.link(k - 1)
+      },
+      _ => self.leaf(i),
+    })
+  }
+
+  fn push_leaf(&mut self, symbol: Sym, values: impl IntoIterator<Item = Value>) {
+    self.values.extend(values);
+    self.push(symbol);
+  }
+
+  fn open_branch(&mut self) {
+    self.open_branches.push(self.links.len());
+    self.links.push((self.symbols.len() as u32, OPEN));
+    self.push(Sym::from('['));
+  }
+
+  fn close_branch(&mut self) -> Result<(), TreeError> {
+    let k = self.open_branches.pop().ok_or(TreeError::NoOpenBranch)?;
+    let i = self.symbols.len() as u32;
+    self.links[k].1 = i;
+    self.links.push((i, self.links[k].0));
+    self.push(Sym::from(']'));
+    Ok(())
+  }
+}
+
+/// Copies a word from one storage to another.
+fn copy<W: InstanceWord + Default>(word: &impl InstanceWord) -> W {
+  let mut res = W::default();
+  for node in word.nodes() {
+    match node {
+      NodeRef::BranchStart(_) => res.open_branch(),
+      NodeRef::BranchEnd(_) => res.close_branch().expect("branches end after they start"),
+      NodeRef::Leaf(symbol, values) => res.push_leaf(symbol, values.iter().cloned()),
+    }
+  }
+  res
+}
+
+impl From<&Tree<context::Instance, Sym>> for CompactTree {
+  fn from(tree: &Tree<context::Instance, Sym>) -> Self {
+    copy(tree)
+  }
+}
+
+impl From<&CompactTree> for Tree<context::Instance, Sym> {
+  fn from(tree: &CompactTree) -> Self {
+    copy(tree)
+  }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::ops::Range;
+use std::vec::Vec;
+
+use lsd::Symbol;
+
+use crate::common::operators;
+use super::{InstanceWord, NodeRef};
+
+/// What a derivation step did to a word: for every node of the source, the range of the target that replaced it.
+/// A node that no rule rewrote is kept, and replaced by a copy of itself. The derivator builds it, with
+/// `StreamDeriver::step`, and `TreeDiff::between` finds one for any two words.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TreeDiff {
+  targets: Vec<Range<usize>>,
+  kept: Vec<bool>,
+}
+
+/// Why a node can't be added to a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffError {
+  /// The target doesn't start where the previous one ended.
+  Gap(Range<usize>),
+  /// A kept node has to be copied once, but its target has another length. It happens when a rule writes nodes and
+  /// then says it didn't apply.
+  NotACopy(Range<usize>),
+}
+
+/// How many nodes a step kept, and how many it took away and added by rewriting them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DiffSummary {
+  pub kept: usize,
+  /// Source nodes that were rewritten, or erased.
+  pub deleted: usize,
+  /// Target nodes that come from rewritten nodes.
+  pub inserted: usize,
+}
+
+impl TreeDiff {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// A diff from `source` to `target`, which keeps as many nodes as it can: the ones of a longest common subsequence
+  /// of both words, found with the algorithm of Myers. It takes `O((n + m) d)` time, where `d` is the number of nodes
+  /// that aren't kept, so it's fast for words that are much alike.
+  ///
+  /// Target nodes that aren't kept come from the source node before them that isn't kept either. If there is none,
+  /// the kept node before them, or the one after them at the start, is taken as rewritten into itself and them.
+  /// Nothing can come from an empty source, so then the diff is empty.
+  pub fn between<S: Symbol>(source: &impl InstanceWord<S>, target: &impl InstanceWord<S>) -> TreeDiff {
+    let (n, m) = (source.len(), target.len());
+    let common = common_nodes(n, m, |i, j| same_node(source.node(i), target.node(j)));
+    let mut res = TreeDiff::new();
+    let (mut i, mut j) = (0, 0);
+    for (si, tj) in common.into_iter().chain([(n, m)]) {
+      if si > i {
+        res.targets.push(j..tj);
+        res.kept.push(false);
+        res.targets.extend((i + 1..si).map(|_| tj..tj));
+        res.kept.extend((i + 1..si).map(|_| false));
+        j = tj;
+      } else if tj > j && !res.targets.is_empty() {
+        let last = res.targets.len() - 1;
+        res.targets[last].end = tj;
+        res.kept[last] = false;
+        j = tj;
+      }
+      if si < n {
+        res.targets.push(j..tj + 1);
+        res.kept.push(j == tj);
+      }
+      (i, j) = (si + 1, tj + 1);
+    }
+    res
+  }
+
+  /// Adds the next source node, replaced by `target`. Fails if `target` doesn't start where the previous one ended,
+  /// or if the node is kept and `target` isn't a single node.
+  pub fn push(&mut self, target: Range<usize>, kept: bool) -> Result<(), DiffError> {
+    if target.start != self.target_len() || target.end < target.start {
+      return Err(DiffError::Gap(target));
+    }
+    if kept && target.len() != 1 {
+      return Err(DiffError::NotACopy(target));
+    }
+    self.targets.push(target);
+    self.kept.push(kept);
+    Ok(())
+  }
+
+  pub fn source_len(&self) -> usize {self.targets.len()}
+  pub fn target_len(&self) -> usize {self.targets.last().map_or(0, |t| t.end)}
+
+  /// Nodes of the target that replaced the source node at `i`. It's empty if the node was erased.
+  pub fn target(&self, i: usize) -> Range<usize> {self.targets[i].clone()}
+  pub fn is_kept(&self, i: usize) -> bool {self.kept[i]}
+
+  /// Position of the source node that the target node at `j` comes from.
+  pub fn source(&self, j: usize) -> Option<usize> {
+    let i = self.targets.partition_point(|t| t.end <= j);
+    (i < self.targets.len()).then_some(i)
+  }
+
+  /// Every source node, with its target range.
+  pub fn iter(&self) -> impl Iterator<Item = (usize, Range<usize>)> + '_ {
+    self.targets.iter().cloned().enumerate()
+  }
+
+  pub fn summary(&self) -> DiffSummary {
+    let kept = self.kept.iter().filter(|&&k| k).count();
+    DiffSummary {kept, deleted: self.source_len() - kept, inserted: self.target_len() - kept}
+  }
+}
+
+impl fmt::Display for DiffError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Gap(target) => write!(f, "target {:?} doesn't follow the previous one", target),
+      Self::NotACopy(target) => write!(f, "kept node replaced by {} nodes", target.len()),
+    }
+  }
+}
+
+impl fmt::Display for DiffSummary {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} kept, {} deleted, {} inserted", self.kept, self.deleted, self.inserted)
+  }
+}
+
+/// Lineage IDs of the nodes of a word, which tell the same node apart across iterations, to animate growth. A node
+/// keeps its ID while no rule rewrites it, and the nodes that replace it get new ones, with it as their origin.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Lineage {
+  ids: Vec<u64>,
+  /// ID of the node of the previous iteration that every node comes from.
+  origins: Vec<Option<u64>>,
+  /// First ID not given yet.
+  next: u64,
+}
+
+impl Lineage {
+  /// IDs of the axiom, which comes from nowhere.
+  pub fn new(len: usize) -> Self {
+    Lineage {ids: (0..len as u64).collect(), origins: vec![None; len], next: len as u64}
+  }
+
+  pub fn len(&self) -> usize {self.ids.len()}
+  pub fn is_empty(&self) -> bool {self.ids.is_empty()}
+  pub fn id(&self, i: usize) -> u64 {self.ids[i]}
+  /// The ID of the node that the one at `i` comes from: its own one if it was kept.
+  pub fn origin(&self, i: usize) -> Option<u64> {self.origins[i]}
+
+  /// Position of the node with an ID. It takes linear time.
+  pub fn position(&self, id: u64) -> Option<usize> {
+    self.ids.iter().position(|&i| i == id)
+  }
+
+  /// IDs of the word derived with `diff` from this one.
+  ///
+  /// # Panics
+  ///
+  /// If `diff` isn't for a word of this length.
+  pub fn next(&self, diff: &TreeDiff) -> Lineage {
+    assert_eq!(diff.source_len(), self.len(), "the diff is for another word");
+    let mut res = Lineage {
+      ids: Vec::with_capacity(diff.target_len()),
+      origins: Vec::with_capacity(diff.target_len()),
+      next: self.next,
+    };
+    for (i, target) in diff.iter() {
+      let id = self.ids[i];
+      if diff.is_kept(i) {
+        res.ids.push(id);
+        res.origins.push(Some(id));
+      } else {
+        res.ids.extend(res.next..res.next + target.len() as u64);
+        res.origins.extend(target.clone().map(|_| Some(id)));
+        res.next += target.len() as u64;
+      }
+    }
+    res
+  }
+}
+
+/// Whether two nodes are the same, but for where their branches end.
+fn same_node<S: PartialEq>(a: NodeRef<S>, b: NodeRef<S>) -> bool {
+  match (a, b) {
+    (NodeRef::BranchStart(_), NodeRef::BranchStart(_)) | (NodeRef::BranchEnd(_), NodeRef::BranchEnd(_)) => true,
+    (NodeRef::Leaf(a, va), NodeRef::Leaf(b, vb)) => {
+      a == b && va.len() == vb.len() && va.iter().zip(vb).all(|(x, y)| x.type_name() == y.type_name() && operators::eq(x, y))
+    },
+    _ => false,
+  }
+}
+
+/// Positions of the nodes of a longest common subsequence of two words of lengths `n` and `m`, in order, by the
+/// greedy algorithm of Myers. `eq(i, j)` tells whether the node `i` of the first word is the node `j` of the second.
+fn common_nodes(n: usize, m: usize, eq: impl Fn(usize, usize) -> bool) -> Vec<(usize, usize)> {
+  let max = (n + m) as isize;
+  // `v[k]` es hasta dónde llega en la primera palabra el mejor camino de la diagonal `k = x - y`
+  let mut v = vec![0isize; 2 * max as usize + 3];
+  let at = |k: isize| (k + max + 1) as usize;
+  // Las diagonales `-d..=d` de `v` antes de cada paso `d`, para volver atrás
+  let mut trace = Vec::new();
+  let mut steps = 0;
+  'search: for d in 0..=max {
+    trace.push(v[at(-d)..=at(d)].to_vec());
+    for k in (-d..=d).step_by(2) {
+      let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {v[at(k + 1)]} else {v[at(k - 1)] + 1};
+      let mut y = x - k;
+      while x < n as isize && y < m as isize && eq(x as usize, y as usize) {
+        x += 1;
+        y += 1;
+      }
+      v[at(k)] = x;
+      if x >= n as isize && y >= m as isize {
+        steps = d;
+        break 'search;
+      }
+    }
+  }
+
+  let mut res = Vec::new();
+  let (mut x, mut y) = (n as isize, m as isize);
+  for d in (1..=steps).rev() {
+    let prev = &trace[d as usize];
+    let get = |k: isize| prev[(k + d) as usize];
+    let k = x - y;
+    let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {k + 1} else {k - 1};
+    let prev_x = get(prev_k);
+    let prev_y = prev_x - prev_k;
+    while x > prev_x && y > prev_y {
+      x -= 1;
+      y -= 1;
+      res.push((x as usize, y as usize));
+    }
+    (x, y) = (prev_x, prev_y);
+  }
+  while x > 0 && y > 0 {
+    x -= 1;
+    y -= 1;
+    res.push((x as usize, y as usize));
+  }
+  res.reverse();
+  res
+}
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::Sym;
+use memmap2::Mmap;
+
+use crate::common::Value;
+use super::{InstanceWord, NodeRef};
+
+/// Tree files start with it.
+const MAGIC: &[u8; 8] = b"LSYSTREE";
+pub const VERSION: u32 = 1;
+/// Magic, version, flags, the metadata and the length of every section.
+const HEADER: usize = 80;
+/// Symbols of a node from this code on are names, from the table of names of the file. Below it, they are
+/// characters, as `Sym` does.
+const FIRST_NAME: u32 = 0x11_0000;
+/// Other end of a branch that is still open.
+const OPEN: u32 = u32::MAX;
+/// Size of a value: a tag, the length of a string and the value itself, or where the string starts.
+const VALUE: usize = 16;
+
+const NULL: u32 = 0;
+const INT: u32 = 1;
+const FLOAT: u32 = 2;
+const BOOL: u32 = 3;
+const STRING: u32 = 4;
+
+/// What a derived word comes from, saved with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TreeMeta {
+  /// Seed of the random numbers of stochastic rules.
+  pub seed: u64,
+  pub iteration: u64,
+  /// Hash of the source of the L system, from `source_hash`, to know whether the file is out of date.
+  pub source_hash: u64,
+}
+
+/// Hash of a source to store in `TreeMeta`. It's FNV-1a, which doesn't change between versions of Rust like the
+/// hasher of the standard library may.
+pub fn source_hash(src: &str) -> u64 {
+  src.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+#[derive(Debug)]
+pub enum FileError {
+  Io(io::Error),
+  /// The file doesn't start like a tree file.
+  NotATreeFile,
+  /// The file was written by another version of the format.
+  Version(u32),
+  /// The sections of the file are out of its bounds or have invalid data.
+  Corrupt,
+  /// The word has more than `u32::MAX` nodes or values.
+  TooLarge,
+  /// The node at the position has a value that can't be saved, like a function.
+  Unsaveable(usize),
+}
+
+impl fmt::Display for FileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Io(err) => write!(f, "{}", err),
+      Self::NotATreeFile => write!(f, "not a tree file"),
+      Self::Version(v) => write!(f, "tree file of version {}, expected {}", v, VERSION),
+      Self::Corrupt => write!(f, "corrupt tree file"),
+      Self::TooLarge => write!(f, "the word is too large to be saved"),
+      Self::Unsaveable(i) => write!(f, "node {} has a value that can't be saved", i),
+    }
+  }
+}
+
+impl From<io::Error> for FileError {
+  fn from(err: io::Error) -> Self {
+    FileError::Io(err)
+  }
+}
+
+/// Where every section of a file starts, in bytes.
+///
+/// After the header come the code of the symbol of every node, where its values start, every branch start and end
+/// with its other end like in `CompactTree`, the values, the table of names of symbols and the strings of both.
+/// Everything is little endian.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+  nodes: usize,
+  values: usize,
+  links: usize,
+  names: usize,
+  symbols_at: usize,
+  offsets_at: usize,
+  links_at: usize,
+  values_at: usize,
+  names_at: usize,
+  strings_at: usize,
+  end: usize,
+}
+
+impl Layout {
+  fn new(nodes: usize, values: usize, links: usize, names: usize, strings: usize) -> Option<Self> {
+    let symbols_at = HEADER;
+    let offsets_at = symbols_at.checked_add(nodes.checked_mul(4)?)?;
+    let links_at = offsets_at.checked_add(nodes.checked_add(1)?.checked_mul(4)?)?;
+    let values_at = links_at.checked_add(links.checked_mul(8)?)?;
+    let names_at = values_at.checked_add(values.checked_mul(VALUE)?)?;
+    let strings_at = names_at.checked_add(names.checked_mul(8)?)?;
+    let end = strings_at.checked_add(strings)?;
+    Some(Layout {nodes, values, links, names, symbols_at, offsets_at, links_at, values_at, names_at, strings_at, end})
+  }
+}
+
+/// Writes a word in the binary format read by `TreeFile`.
+pub fn write_tree(out: impl Write, word: &impl InstanceWord, meta: &TreeMeta) -> Result<(), FileError> {
+  // Primera pasada: cuenta valores, ramas y cadenas, y numera los nombres
+  let mut names = Vec::new();
+  let mut codes = HashMap::new();
+  let (mut values, mut links, mut strings) = (0usize, 0usize, 0usize);
+  for (i, node) in word.nodes().enumerate() {
+    match node {
+      NodeRef::Leaf(symbol, vals) => {
+        if symbol.as_char().is_none() && !codes.contains_key(&symbol) {
+          codes.insert(symbol, FIRST_NAME + names.len() as u32);
+          names.push(symbol);
+        }
+        values += vals.len();
+        for val in vals {
+          match val {
+            Value::String(s) => strings += s.len(),
+            Value::Null | Value::Int(_) | Value::Float(_) | Value::Bool(_) => {},
+            _ => return Err(FileError::Unsaveable(i)),
+          }
+        }
+      },
+      _ => links += 1,
+    }
+  }
+  if word.len() >= u32::MAX as usize || values > u32::MAX as usize {
+    return Err(FileError::TooLarge);
+  }
+  let name_bytes: usize = names.iter().map(|name| name.with_str(str::len)).sum();
+
+  let mut out = BufWriter::new(out);
+  out.write_all(MAGIC)?;
+  out.write_all(&VERSION.to_le_bytes())?;
+  out.write_all(&0u32.to_le_bytes())?;
+  for n in [meta.seed, meta.iteration, meta.source_hash] {
+    out.write_all(&n.to_le_bytes())?;
+  }
+  for n in [word.len(), values, links, names.len(), name_bytes + strings] {
+    out.write_all(&(n as u64).to_le_bytes())?;
+  }
+
+  for node in word.nodes() {
+    let code = match node {
+      NodeRef::BranchStart(_) => '[' as u32,
+      NodeRef::BranchEnd(_) => ']' as u32,
+      NodeRef::Leaf(symbol, _) => symbol.as_char().map_or_else(|| codes[&symbol], u32::from),
+    };
+    out.write_all(&code.to_le_bytes())?;
+  }
+  let mut offset = 0u32;
+  out.write_all(&offset.to_le_bytes())?;
+  for node in word.nodes() {
+    if let NodeRef::Leaf(_, vals) = node {
+      offset += vals.len() as u32;
+    }
+    out.write_all(&offset.to_le_bytes())?;
+  }
+  for (i, node) in word.nodes().enumerate() {
+    let other = match node {
+      NodeRef::BranchStart(end) if end > i => end as u32,
+      NodeRef::BranchStart(_) => OPEN,
+      NodeRef::BranchEnd(start) => start as u32,
+      NodeRef::Leaf(..) => continue,
+    };
+    out.write_all(&(i as u32).to_le_bytes())?;
+    out.write_all(&other.to_le_bytes())?;
+  }
+  let mut pos = name_bytes as u64;
+  for node in word.nodes() {
+    let NodeRef::Leaf(_, vals) = node else {continue};
+    for val in vals {
+      let (tag, len, data) = match val {
+        Value::Int(i) => (INT, 0, *i as u64),
+        Value::Float(f) => (FLOAT, 0, f.to_bits()),
+        Value::Bool(b) => (BOOL, 0, *b as u64),
+        Value::String(s) => {
+          let start = pos;
+          pos += s.len() as u64;
+          (STRING, s.len() as u32, start)
+        },
+        _ => (NULL, 0, 0),
+      };
+      out.write_all(&tag.to_le_bytes())?;
+      out.write_all(&len.to_le_bytes())?;
+      out.write_all(&data.to_le_bytes())?;
+    }
+  }
+  let mut pos = 0u32;
+  for name in &names {
+    let len = name.with_str(str::len) as u32;
+    out.write_all(&pos.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    pos += len;
+  }
+  for name in &names {
+    name.with_str(|s| out.write_all(s.as_bytes()))?;
+  }
+  for node in word.nodes() {
+    let NodeRef::Leaf(_, vals) = node else {continue};
+    for val in vals {
+      if let Value::String(s) = val {
+        out.write_all(s.as_bytes())?;
+      }
+    }
+  }
+  out.flush()?;
+  Ok(())
+}
+
+/// Saves a word to a file, which `TreeFile::open` reads.
+pub fn save_tree(path: impl AsRef<Path>, word: &impl InstanceWord, meta: &TreeMeta) -> Result<(), FileError> {
+  write_tree(File::create(path)?, word, meta)
+}
+
+/// A word saved by `write_tree`, read in place from its bytes, which are usually a file mapped in memory. Opening it
+/// checks the whole file but copies nothing, except the names of the symbols; values are decoded when they are read.
+pub struct TreeFile<B = Mmap> {
+  bytes: B,
+  meta: TreeMeta,
+  layout: Layout,
+  /// Symbols of the table of names.
+  names: Vec<Sym>,
+}
+
+/// A node of a `TreeFile`.
+#[derive(Debug, Clone)]
+pub enum FileNode<'f> {
+  BranchStart(usize),
+  BranchEnd(usize),
+  Leaf(Sym, FileValues<'f>),
+}
+
+/// The values of a node of a `TreeFile`, decoded one by one.
+#[derive(Debug, Clone)]
+pub struct FileValues<'f> {
+  values: &'f [u8],
+  strings: &'f [u8],
+}
+
+impl TreeFile {
+  /// Maps a tree file in memory.
+  pub fn open(path: impl AsRef<Path>) -> Result<Self, FileError> {
+    let file = File::open(path)?;
+    // SAFETY: el archivo no debe cambiar mientras está mapeado, como con cualquier mmap
+    let map = unsafe {Mmap::map(&file)?};
+    TreeFile::from_bytes(map)
+  }
+}
+
+impl<B: AsRef<[u8]>> TreeFile<B> {
+  pub fn from_bytes(bytes: B) -> Result<Self, FileError> {
+    let b = bytes.as_ref();
+    if b.len() < 12 || &b[..8] != MAGIC {
+      return Err(FileError::NotATreeFile);
+    }
+    match u32_at(b, 8) {
+      VERSION if b.len() >= HEADER => {},
+      VERSION => return Err(FileError::Corrupt),
+      v => return Err(FileError::Version(v)),
+    }
+    let meta = TreeMeta {seed: u64_at(b, 16), iteration: u64_at(b, 24), source_hash: u64_at(b, 32)};
+    let count = |pos| usize::try_from(u64_at(b, pos)).map_err(|_| FileError::Corrupt);
+    let layout = Layout::new(count(40)?, count(48)?, count(56)?, count(64)?, count(72)?).ok_or(FileError::Corrupt)?;
+    if layout.end != b.len() {
+      return Err(FileError::Corrupt);
+    }
+
+    let strings = &b[layout.strings_at..];
+    let string = |pos: usize, len: usize| pos.checked_add(len)
+      .and_then(|end| strings.get(pos..end))
+      .and_then(|s| std::str::from_utf8(s).ok());
+    let mut names = Vec::with_capacity(layout.names);
+    for k in 0..layout.names {
+      let at = layout.names_at + k * 8;
+      let name = string(u32_at(b, at) as usize, u32_at(b, at + 4) as usize).filter(|s| !s.is_empty());
+      names.push(Sym::new(name.ok_or(FileError::Corrupt)?));
+    }
+    let mut prev = 0;
+    for i in 0..=layout.nodes {
+      let offset = u32_at(b, layout.offsets_at + i * 4) as usize;
+      if offset < prev || (i == 0 && offset != 0) || (i == layout.nodes && offset != layout.values) {
+        return Err(FileError::Corrupt);
+      }
+      prev = offset;
+    }
+    // Las ramas van en orden y cada inicio apunta a su final, que apunta a él; solo los nodos de las ramas tienen `[`
+    // y `]`, y no tienen valores
+    let mut k = 0;
+    let mut open: Vec<(usize, u32)> = Vec::new();
+    for i in 0..layout.nodes {
+      let code = u32_at(b, layout.symbols_at + i * 4);
+      if char::from_u32(code).is_none() && (code < FIRST_NAME || (code - FIRST_NAME) as usize >= names.len()) {
+        return Err(FileError::Corrupt);
+      }
+      let link = (k < layout.links).then(|| (u32_at(b, layout.links_at + k * 8), u32_at(b, layout.links_at + k * 8 + 4)));
+      let is_link = link.is_some_and(|(pos, _)| pos as usize == i);
+      let values = u32_at(b, layout.offsets_at + i * 4 + 4) != u32_at(b, layout.offsets_at + i * 4);
+      if is_link != (code == '[' as u32 || code == ']' as u32) || (is_link && values) {
+        return Err(FileError::Corrupt);
+      }
+      let Some((_, other)) = link.filter(|_| is_link) else {continue};
+      k += 1;
+      let paired = match code {
+        c if c == '[' as u32 => {
+          open.push((i, other));
+          other == OPEN || other as usize > i
+        },
+        _ => open.pop().is_some_and(|(start, end)| start == other as usize && end as usize == i),
+      };
+      if !paired {
+        return Err(FileError::Corrupt);
+      }
+    }
+    if k != layout.links || open.iter().any(|&(_, end)| end != OPEN) {
+      return Err(FileError::Corrupt);
+    }
+    for k in 0..layout.values {
+      let at = layout.values_at + k * VALUE;
+      let valid = match u32_at(b, at) {
+        NULL | INT | FLOAT | BOOL => true,
+        STRING => usize::try_from(u64_at(b, at + 8)).is_ok_and(|pos| string(pos, u32_at(b, at + 4) as usize).is_some()),
+        _ => false,
+      };
+      if !valid {
+        return Err(FileError::Corrupt);
+      }
+    }
+    Ok(TreeFile {bytes, meta, layout, names})
+  }
+
+  pub fn meta(&self) -> &TreeMeta {&self.meta}
+  pub fn len(&self) -> usize {self.layout.nodes}
+  pub fn is_empty(&self) -> bool {self.len() == 0}
+
+  fn symbol(&self, i: usize) -> Sym {
+    let code = u32_at(self.bytes.as_ref(), self.layout.symbols_at + i * 4);
+    match char::from_u32(code) {
+      Some(c) => Sym::from(c),
+      None => self.names[(code - FIRST_NAME) as usize],
+    }
+  }
+
+  fn link(&self, k: usize) -> (usize, u32) {
+    let at = self.layout.links_at + k * 8;
+    (u32_at(self.bytes.as_ref(), at) as usize, u32_at(self.bytes.as_ref(), at + 4))
+  }
+
+  fn link_node(&self, k: usize) -> FileNode<'_> {
+    match self.link(k) {
+      (_, OPEN) => FileNode::BranchStart(0),
+      (i, j) if j as usize > i => FileNode::BranchStart(j as usize),
+      (_, j) => FileNode::BranchEnd(j as usize),
+    }
+  }
+
+  fn leaf(&self, i: usize) -> FileNode<'_> {
+    let b = self.bytes.as_ref();
+    let start = u32_at(b, self.layout.offsets_at + i * 4) as usize;
+    let end = u32_at(b, self.layout.offsets_at + i * 4 + 4) as usize;
+    let at = self.layout.values_at;
+    FileNode::Leaf(self.symbol(i), FileValues {
+      values: &b[at + start * VALUE..at + end * VALUE],
+      strings: &b[self.layout.strings_at..],
+    })
+  }
+
+  pub fn node(&self, i: usize) -> FileNode<'_> {
+    // Búsqueda binaria en las ramas, ordenadas por posición
+    let (mut lo, mut hi) = (0, self.layout.links);
+    while lo < hi {
+      let mid = (lo + hi) / 2;
+      match self.link(mid).0 {
+        pos if pos == i => return self.link_node(mid),
+        pos if pos < i => lo = mid + 1,
+        _ => hi = mid,
+      }
+    }
+    self.leaf(i)
+  }
+
+  /// Goes through the branches along with the nodes, without looking them up.
+  pub fn nodes(&self) -> impl Iterator<Item = FileNode<'_>> {
+    let mut k = 0;
+    (0..self.len()).map(move |i| {
+      if k < self.layout.links && self.link(k).0 == i {
+        k += 1;
+        self.link_node(k - 1)
+      } else {
+        self.leaf(i)
+      }
+    })
+  }
+
+  /// Copies the word to a storage in memory, like `Tree` or `CompactTree`.
+  pub fn to_word<W: InstanceWord + Default>(&self) -> Result<W, FileError> {
+    let mut res = W::default();
+    for node in self.nodes() {
+      match node {
+        FileNode::BranchStart(_) => res.open_branch(),
+        FileNode::BranchEnd(_) => res.close_branch().map_err(|_| FileError::Corrupt)?,
+        FileNode::Leaf(symbol, values) => res.push_leaf(symbol, values),
+      }
+    }
+    Ok(res)
+  }
+}
+
+impl Iterator for FileValues<'_> {
+  type Item = Value;
+
+  fn next(&mut self) -> Option<Value> {
+    let (value, rest) = self.values.split_at_checked(VALUE)?;
+    self.values = rest;
+    let data = u64_at(value, 8);
+    Some(match u32_at(value, 0) {
+      INT => Value::Int(data as i64),
+      FLOAT => Value::Float(f64::from_bits(data)),
+      BOOL => Value::Bool(data != 0),
+      STRING => {
+        let s = &self.strings[data as usize..data as usize + u32_at(value, 4) as usize];
+        Value::String(String::from_utf8_lossy(s).into_owned())
+      },
+      _ => Value::Null,
+    })
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = self.values.len() / VALUE;
+    (len, Some(len))
+  }
+}
+
+impl ExactSizeIterator for FileValues<'_> {}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+  u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+  u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
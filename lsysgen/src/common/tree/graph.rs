@@ -0,0 +1,240 @@
+use std::fmt::Display;
+use std::io::{self, BufWriter, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::{Sym, Symbol};
+
+use crate::common::Value;
+use super::{InstanceWord, NodeRef};
+
+/// How a module of a graph follows another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+  /// The next module of the same axis.
+  Successor,
+  /// The first module of a branch that starts after the other one.
+  Lateral,
+}
+
+/// A module of a word, with where it is in it.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphNode<'t, S = Sym> {
+  pub position: usize,
+  pub symbol: S,
+  pub values: &'t [Value],
+}
+
+/// An edge between two nodes of a graph, by their index in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+  pub from: usize,
+  pub to: usize,
+  pub kind: EdgeKind,
+}
+
+/// The topology of a word as an explicit graph, to analyze it with other tools: a node per module, and an edge from
+/// every module to the ones that follow it, in its axis or in a branch. Branches that start before any module have
+/// no edge to them.
+#[derive(Debug, Clone)]
+pub struct Graph<'t, S = Sym> {
+  pub nodes: Vec<GraphNode<'t, S>>,
+  pub edges: Vec<Edge>,
+}
+
+/// A file format a graph can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+  /// Graphviz.
+  Dot,
+  GraphML,
+  /// A node-link JSON object, like the one of NetworkX and D3.
+  Json,
+}
+
+impl GraphFormat {
+  pub const ALL: [GraphFormat; 3] = [GraphFormat::Dot, GraphFormat::GraphML, GraphFormat::Json];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      GraphFormat::Dot => "dot",
+      GraphFormat::GraphML => "graphml",
+      GraphFormat::Json => "json",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.into_iter().find(|f| f.name() == name)
+  }
+
+  /// The format of a file by its extension, which is its name, or `gv` for DOT.
+  pub fn from_extension(ext: &str) -> Option<Self> {
+    if ext == "gv" {Some(GraphFormat::Dot)} else {Self::from_name(ext)}
+  }
+}
+
+impl<'t, S: Symbol> Graph<'t, S> {
+  pub fn new(word: &'t impl InstanceWord<S>) -> Self {
+    let mut graph = Graph {nodes: Vec::new(), edges: Vec::new()};
+    // El nodo del que sale la próxima arista, y su tipo
+    let mut last: Option<(usize, EdgeKind)> = None;
+    let mut stack = Vec::new();
+    for (position, node) in word.nodes().enumerate() {
+      match node {
+        NodeRef::BranchStart(_) => {
+          stack.push(last);
+          last = last.map(|(from, _)| (from, EdgeKind::Lateral));
+        },
+        NodeRef::BranchEnd(_) => last = stack.pop().flatten(),
+        NodeRef::Leaf(symbol, values) => {
+          let to = graph.nodes.len();
+          if let Some((from, kind)) = last {
+            graph.edges.push(Edge {from, to, kind});
+          }
+          graph.nodes.push(GraphNode {position, symbol, values});
+          last = Some((to, EdgeKind::Successor));
+        },
+      }
+    }
+    graph
+  }
+
+  pub fn write(&self, format: GraphFormat, out: impl Write) -> io::Result<()> {
+    match format {
+      GraphFormat::Dot => self.write_dot(out),
+      GraphFormat::GraphML => self.write_graphml(out),
+      GraphFormat::Json => self.write_json(out),
+    }
+  }
+
+  /// Writes a Graphviz digraph. Nodes are labeled with their module, and lateral edges are dashed.
+  pub fn write_dot(&self, out: impl Write) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    writeln!(out, "digraph tree {{")?;
+    for (i, node) in self.nodes.iter().enumerate() {
+      writeln!(out, "  n{} [label=\"{}\"];", i, escape_dot(&module(node)))?;
+    }
+    for edge in &self.edges {
+      match edge.kind {
+        EdgeKind::Successor => writeln!(out, "  n{} -> n{};", edge.from, edge.to)?,
+        EdgeKind::Lateral => writeln!(out, "  n{} -> n{} [style=dashed];", edge.from, edge.to)?,
+      }
+    }
+    writeln!(out, "}}")?;
+    out.flush()
+  }
+
+  /// Writes a GraphML document, with the symbol, the values and the position of every node, and the kind of every
+  /// edge.
+  pub fn write_graphml(&self, out: impl Write) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(out, r#"  <key id="symbol" for="node" attr.name="symbol" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="values" for="node" attr.name="values" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="position" for="node" attr.name="position" attr.type="long"/>"#)?;
+    writeln!(out, r#"  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <graph id="tree" edgedefault="directed">"#)?;
+    for (i, node) in self.nodes.iter().enumerate() {
+      write!(out, r#"    <node id="n{}"><data key="symbol">{}</data>"#, i, escape_xml(&node.symbol.to_string()))?;
+      if !node.values.is_empty() {
+        write!(out, r#"<data key="values">{}</data>"#, escape_xml(&values(node.values)))?;
+      }
+      writeln!(out, r#"<data key="position">{}</data></node>"#, node.position)?;
+    }
+    for edge in &self.edges {
+      writeln!(out, r#"    <edge source="n{}" target="n{}"><data key="kind">{}</data></edge>"#,
+        edge.from, edge.to, kind(edge.kind))?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    out.flush()
+  }
+
+  /// Writes a node-link JSON object, with a node per line: `{"directed": true, "nodes": [{"id": 0, "position": 0,
+  /// "symbol": "F", "values": [1.5]}], "links": [{"source": 0, "target": 1, "kind": "successor"}]}`.
+  pub fn write_json(&self, out: impl Write) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    writeln!(out, "{{\"directed\": true, \"nodes\": [")?;
+    for (i, node) in self.nodes.iter().enumerate() {
+      let values: Vec<String> = node.values.iter().map(json_value).collect();
+      let sep = if i + 1 < self.nodes.len() {","} else {""};
+      writeln!(out, "  {{\"id\": {}, \"position\": {}, \"symbol\": {}, \"values\": [{}]}}{}",
+        i, node.position, json_string(&node.symbol.to_string()), values.join(", "), sep)?;
+    }
+    writeln!(out, "], \"links\": [")?;
+    for (i, edge) in self.edges.iter().enumerate() {
+      let sep = if i + 1 < self.edges.len() {","} else {""};
+      writeln!(out, "  {{\"source\": {}, \"target\": {}, \"kind\": \"{}\"}}{}", edge.from, edge.to, kind(edge.kind), sep)?;
+    }
+    writeln!(out, "]}}")?;
+    out.flush()
+  }
+}
+
+fn kind(kind: EdgeKind) -> &'static str {
+  match kind {
+    EdgeKind::Successor => "successor",
+    EdgeKind::Lateral => "lateral",
+  }
+}
+
+fn values(values: &[Value]) -> String {
+  values.iter().map(Value::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// A module as it's written in a word, `F(1.5, 2)`.
+fn module<S: Display>(node: &GraphNode<S>) -> String {
+  if node.values.is_empty() {
+    node.symbol.to_string()
+  } else {
+    format!("{}({})", node.symbol, values(node.values))
+  }
+}
+
+fn escape_dot(s: &str) -> String {
+  s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(s: &str) -> String {
+  let mut res = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '&' => res.push_str("&amp;"),
+      '<' => res.push_str("&lt;"),
+      '>' => res.push_str("&gt;"),
+      '"' => res.push_str("&quot;"),
+      c => res.push(c),
+    }
+  }
+  res
+}
+
+fn json_string(s: &str) -> String {
+  let mut res = String::with_capacity(s.len() + 2);
+  res.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => res.push_str("\\\""),
+      '\\' => res.push_str("\\\\"),
+      '\n' => res.push_str("\\n"),
+      '\t' => res.push_str("\\t"),
+      c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+      c => res.push(c),
+    }
+  }
+  res.push('"');
+  res
+}
+
+/// A value in JSON. Infinities and NaN, which JSON doesn't have, are `null`.
+fn json_value(val: &Value) -> String {
+  match val {
+    Value::Int(i) => i.to_string(),
+    Value::Float(f) if f.is_finite() => format!("{:?}", f),
+    Value::Float(_) | Value::Null => "null".to_string(),
+    Value::Bool(b) => b.to_string(),
+    Value::String(s) => json_string(s),
+    val => json_string(&val.to_string()),
+  }
+}
@@ -1,4 +1,20 @@
 pub mod node;
+#[allow(clippy::module_inception)]
 mod tree;
+mod text;
+mod compact;
+mod stats;
+mod pattern;
+mod file;
+mod stream;
+mod diff;
+mod graph;
 
 pub use tree::*;
+pub use compact::CompactTree;
+pub use stats::TreeStats;
+pub use pattern::{Match, Pattern};
+pub use file::{save_tree, source_hash, write_tree, FileError, FileNode, FileValues, TreeFile, TreeMeta};
+pub use stream::{StreamNode, WordReader, WordSink, WordWriter};
+pub use diff::{DiffError, DiffSummary, Lineage, TreeDiff};
+pub use graph::{Edge, EdgeKind, Graph, GraphFormat, GraphNode};
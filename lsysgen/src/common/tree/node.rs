@@ -1,21 +1,23 @@
 use std::vec::Vec;
 
-use crate::common::{Value, Parameter};
+use lsd::Sym;
 
 #[derive(Debug, Clone)]
-pub enum Node<Ctx=context::Instance, Char=char> {
+pub enum Node<Ctx=context::Instance, Char=Sym> {
   BranchStart(usize),
   BranchEnd(usize),
   Leaf(NodeContent<Ctx, Char>),
 }
 
 #[derive(Debug, Clone)]
-pub struct NodeContent<Ctx=context::Instance, Char=char> {
+pub struct NodeContent<Ctx=context::Instance, Char=Sym> {
   pub character: Char,
   pub context: Ctx,
 }
 
 pub mod context {
+  use lsd::ast::normal::Expr;
+
   use crate::common::{Value, Parameter};
 
   pub trait Context {
@@ -33,7 +35,7 @@ pub mod context {
   /// Context for nodes in the right side of a rule
   #[derive(Debug, Clone)]
   pub struct RightSide {
-    pub args: Vec<Expr>,
+    pub args: Vec<Expr<'static>>,
   }
 
   /// Context for instance nodes
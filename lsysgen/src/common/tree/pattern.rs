@@ -0,0 +1,172 @@
+use std::str::FromStr;
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::Sym;
+use lsd::ast::grammar::{Node as WordNode, NodeKind};
+use lsd::ast::normal::ExprKind;
+
+use crate::common::{operators, Value};
+use crate::common::errors::Diagnostic;
+use super::node::{context, Node};
+use super::text::value;
+use super::Tree;
+
+/// A piece of a word to look for in a tree, written like the right context of a rule: `A[B]C` or `F(x)[+(25)]`.
+///
+/// It matches like a context: branches of the tree that aren't in the pattern are skipped, so `AC` is found in
+/// `A[B]C`, and a `]` of the pattern skips what's left of its branch, so `A[B]C` is found in `A[BD]C`. Arguments are
+/// either values, which must be equal to the ones of the node, or names, which take any value and are returned by
+/// `Tree::matches`. A symbol without arguments takes any.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+  nodes: Vec<PatternNode>,
+  /// Symbols skipped while matching, like the ones of the `ignore` setting.
+  ignored: Vec<Sym>,
+}
+
+#[derive(Debug, Clone)]
+enum PatternNode {
+  Open,
+  Close,
+  Leaf(Sym, Option<Vec<PatternArg>>),
+}
+
+#[derive(Debug, Clone)]
+enum PatternArg {
+  Name(String),
+  Value(Value),
+}
+
+/// Where a pattern was found, with the values taken by the names of its arguments.
+#[derive(Debug, Clone)]
+pub struct Match<'p, 't> {
+  pub start: usize,
+  pub bindings: Vec<(&'p str, &'t Value)>,
+}
+
+impl Match<'_, '_> {
+  pub fn get(&self, name: &str) -> Option<&Value> {
+    self.bindings.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+  }
+}
+
+impl Pattern {
+  /// Skips `symbols` while matching, except for the first node of the pattern.
+  pub fn ignoring(mut self, symbols: impl IntoIterator<Item = Sym>) -> Self {
+    self.ignored.extend(symbols);
+    self
+  }
+}
+
+impl FromStr for Pattern {
+  type Err = Vec<Diagnostic>;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let word = lsd::parse_word(s).map_err(|errors| errors.into_iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+    let mut nodes = Vec::new();
+    let mut errors = Vec::new();
+    add_nodes(&mut nodes, &word.0, &mut errors);
+    if errors.is_empty() {Ok(Pattern {nodes, ignored: Vec::new()})} else {Err(errors)}
+  }
+}
+
+fn add_nodes(pattern: &mut Vec<PatternNode>, nodes: &[WordNode<Sym>], errors: &mut Vec<Diagnostic>) {
+  for node in nodes {
+    match &node.kind {
+      NodeKind::Leaf(leaf) => {
+        let args = leaf.args.as_ref().map(|args| args.iter().filter_map(|arg| match (&arg.kind, value(arg)) {
+          (_, Some(val)) => Some(PatternArg::Value(val)),
+          (ExprKind::ID(name), None) => Some(PatternArg::Name(name.to_string())),
+          _ => {
+            errors.push(Diagnostic::error("arguments of a pattern must be names or values")
+              .with_code("W0003")
+              .with_primary(arg.span, ""));
+            None
+          },
+        }).collect());
+        pattern.push(PatternNode::Leaf(leaf.symbol, args));
+      },
+      NodeKind::Branch(nodes) => {
+        pattern.push(PatternNode::Open);
+        add_nodes(pattern, nodes, errors);
+        pattern.push(PatternNode::Close);
+      },
+      NodeKind::Expansion(_) | NodeKind::Block(_) => errors.push(Diagnostic::error("patterns can't have expansions or blocks")
+        .with_code("W0002")
+        .with_primary(node.span, "")),
+    }
+  }
+}
+
+impl Tree<context::Instance, Sym> {
+  /// Positions where `pattern` is found.
+  pub fn find(&self, pattern: &Pattern) -> Vec<usize> {
+    (0..self.len()).filter(|&i| self.match_at(i, pattern).is_some()).collect()
+  }
+
+  /// Every match of `pattern`, with the values of its names. Other constraints can be checked on them.
+  pub fn matches<'p>(&self, pattern: &'p Pattern) -> Vec<Match<'p, '_>> {
+    (0..self.len()).filter_map(|i| self.match_at(i, pattern)).collect()
+  }
+
+  /// Matches `pattern` with its first node at `start`.
+  pub fn match_at<'p>(&self, start: usize, pattern: &'p Pattern) -> Option<Match<'p, '_>> {
+    let mut res = Match {start, bindings: Vec::new()};
+    let mut i = start;
+    for (k, pnode) in pattern.nodes.iter().enumerate() {
+      if k > 0 {
+        i = self.skip(i, pattern, matches!(pnode, PatternNode::Leaf(..)))?;
+      }
+      match (pnode, self.get(i)?) {
+        (PatternNode::Open, Node::BranchStart(_)) => i += 1,
+        (PatternNode::Close, _) => i = self.branch_end(i)? + 1,
+        (PatternNode::Leaf(symbol, args), Node::Leaf(content)) if content.character == *symbol => {
+          let values = &content.context.values;
+          if let Some(args) = args {
+            if args.len() != values.len() {
+              return None;
+            }
+            for (arg, val) in args.iter().zip(values) {
+              match arg {
+                PatternArg::Name(name) => res.bindings.push((name.as_str(), val)),
+                PatternArg::Value(v) if operators::eq(v, val) => {},
+                PatternArg::Value(_) => return None,
+              }
+            }
+          }
+          i += 1;
+        },
+        _ => return None,
+      }
+    }
+    Some(res)
+  }
+
+  /// Skips the ignored symbols from `i` on and, before a symbol of the pattern, the branches too. Fails if a branch
+  /// doesn't end.
+  fn skip(&self, mut i: usize, pattern: &Pattern, branches: bool) -> Option<usize> {
+    while i < self.len() {
+      match self.node_at(i) {
+        Node::BranchStart(end) if branches && *end > i => i = end + 1,
+        Node::BranchStart(_) if branches => return None,
+        Node::Leaf(content) if pattern.ignored.contains(&content.character) => i += 1,
+        _ => break,
+      }
+    }
+    Some(i)
+  }
+
+  /// Position of the end of the branch that holds the node at `i`.
+  fn branch_end(&self, mut i: usize) -> Option<usize> {
+    while i < self.len() {
+      match self.node_at(i) {
+        Node::BranchStart(end) if *end > i => i = end + 1,
+        Node::BranchStart(_) => return None,
+        Node::BranchEnd(_) => return Some(i),
+        Node::Leaf(_) => i += 1,
+      }
+    }
+    None
+  }
+}
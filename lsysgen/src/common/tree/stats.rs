@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::vec::Vec;
+
+use super::node::Node;
+use super::Tree;
+
+/// Summary of the shape of a tree, from `Tree::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats<Char> {
+  pub nodes: usize,
+  pub leaves: usize,
+  pub branches: usize,
+  pub max_depth: usize,
+  pub longest_path: usize,
+  /// Leaves of every Strahler order, from order 1.
+  pub strahler: Vec<usize>,
+  pub symbols: BTreeMap<Char, usize>,
+}
+
+impl<Ctx, Char> Tree<Ctx, Char> {
+  /// How many times every symbol appears.
+  pub fn symbol_histogram(&self) -> BTreeMap<Char, usize> where Char: Ord + Clone {
+    let mut res = BTreeMap::new();
+    for node in self.iter() {
+      if let Node::Leaf(content) = node {
+        *res.entry(content.character.clone()).or_default() += 1;
+      }
+    }
+    res
+  }
+
+  pub fn leaf_count(&self) -> usize {
+    self.iter().filter(|node| matches!(node, Node::Leaf(_))).count()
+  }
+
+  pub fn branch_count(&self) -> usize {
+    self.iter().filter(|node| matches!(node, Node::BranchStart(_))).count()
+  }
+
+  /// How deep branches are nested: 0 if there are none.
+  pub fn max_depth(&self) -> usize {
+    let mut depth = 0usize;
+    let mut max = 0;
+    for node in self.iter() {
+      match node {
+        Node::BranchStart(_) => {
+          depth += 1;
+          max = max.max(depth);
+        },
+        Node::BranchEnd(_) => depth = depth.saturating_sub(1),
+        Node::Leaf(_) => {},
+      }
+    }
+    max
+  }
+
+  /// Number of leaves in the longest path from the first node to the tip of a branch.
+  pub fn longest_path(&self) -> usize {
+    let mut stack = Vec::new();
+    let mut len = 0;
+    let mut max = 0;
+    for node in self.iter() {
+      match node {
+        Node::BranchStart(_) => stack.push(len),
+        Node::BranchEnd(_) => len = stack.pop().unwrap_or(0),
+        Node::Leaf(_) => {
+          len += 1;
+          max = max.max(len);
+        },
+      }
+    }
+    max
+  }
+
+  /// Position of the start of the innermost branch that holds the node at `i`, or `None` if it's in the main axis.
+  /// For the start or the end of a branch, it's the branch that holds that one.
+  pub fn parent_branch(&self, i: usize) -> Option<usize> {
+    let mut k = match self.node_at(i) {
+      Node::BranchEnd(start) => *start,
+      _ => i,
+    };
+    while k > 0 {
+      k -= 1;
+      match self.node_at(k) {
+        Node::BranchStart(_) => return Some(k),
+        // Las ramas cerradas anteriores no contienen al nodo
+        Node::BranchEnd(start) => k = *start,
+        Node::Leaf(_) => {},
+      }
+    }
+    None
+  }
+
+  /// Positions of the branches that start right after the node at `i`, one after another: the lateral children of a
+  /// branch point.
+  pub fn children(&self, i: usize) -> Vec<usize> {
+    let mut res = Vec::new();
+    let mut k = i + 1;
+    while k < self.len() {
+      match self.node_at(k) {
+        // A branch still open ends at `BranchStart(0)`.
+        Node::BranchStart(end) if *end > k => {
+          res.push(k);
+          k = end + 1;
+        },
+        Node::BranchStart(_) => {
+          res.push(k);
+          break;
+        },
+        _ => break,
+      }
+    }
+    res
+  }
+
+  /// Number of leaves of every Horton-Strahler order, from order 1. Every branch is an axis, whose children are the
+  /// branches that start in it: an axis without children has order 1, and one whose children of the highest order
+  /// `n` are two or more has order `n + 1`, or `n` otherwise.
+  pub fn strahler_orders(&self) -> Vec<usize> {
+    /// An axis being walked: its leaves, and the highest order of its children with how many have it.
+    #[derive(Default)]
+    struct Axis {
+      leaves: usize,
+      max: usize,
+      count: usize,
+    }
+
+    impl Axis {
+      fn add_child(&mut self, order: usize) {
+        if order > self.max {
+          self.max = order;
+          self.count = 1;
+        } else if order == self.max {
+          self.count += 1;
+        }
+      }
+    }
+
+    /// Adds the leaves of an axis to its order, and returns it.
+    fn close(axis: Axis, res: &mut Vec<usize>) -> usize {
+      let order = match axis {
+        Axis {count: 0, ..} => 1,
+        Axis {max, count: 1, ..} => max,
+        Axis {max, ..} => max + 1,
+      };
+      if res.len() < order {
+        res.resize(order, 0);
+      }
+      res[order - 1] += axis.leaves;
+      order
+    }
+
+    let mut res = Vec::new();
+    let mut stack = vec![Axis::default()];
+    for node in self.iter() {
+      match node {
+        Node::BranchStart(_) => stack.push(Axis::default()),
+        Node::BranchEnd(_) if stack.len() > 1 => {
+          let order = close(stack.pop().unwrap(), &mut res);
+          stack.last_mut().unwrap().add_child(order);
+        },
+        Node::BranchEnd(_) => {},
+        Node::Leaf(_) => stack.last_mut().unwrap().leaves += 1,
+      }
+    }
+    // Las ramas que quedan abiertas se cierran al final
+    while let Some(axis) = stack.pop() {
+      let order = close(axis, &mut res);
+      if let Some(parent) = stack.last_mut() {
+        parent.add_child(order);
+      }
+    }
+    res
+  }
+
+  pub fn stats(&self) -> TreeStats<Char> where Char: Ord + Clone {
+    TreeStats {
+      nodes: self.len(),
+      leaves: self.leaf_count(),
+      branches: self.branch_count(),
+      max_depth: self.max_depth(),
+      longest_path: self.longest_path(),
+      strahler: self.strahler_orders(),
+      symbols: self.symbol_histogram(),
+    }
+  }
+}
+
+impl<Char: fmt::Display> fmt::Display for TreeStats<Char> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "nodes:         {}", self.nodes)?;
+    writeln!(f, "leaves:        {}", self.leaves)?;
+    writeln!(f, "branches:      {}", self.branches)?;
+    writeln!(f, "max depth:     {}", self.max_depth)?;
+    writeln!(f, "longest path:  {}", self.longest_path)?;
+    writeln!(f, "strahler orders:")?;
+    for (i, leaves) in self.strahler.iter().enumerate() {
+      writeln!(f, "  {:<12}{}", i + 1, leaves)?;
+    }
+    write!(f, "symbols:")?;
+    for (symbol, count) in &self.symbols {
+      write!(f, "\n  {:<12}{}", symbol.to_string(), count)?;
+    }
+    Ok(())
+  }
+}
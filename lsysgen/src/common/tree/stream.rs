@@ -0,0 +1,248 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::string::String;
+use std::vec::Vec;
+
+use lsd::{Sym, Symbol};
+
+use crate::common::Value;
+use super::node::context;
+use super::{CompactTree, InstanceWord, NodeRef, Tree, TreeError};
+
+const LEAF: u8 = 0;
+/// A leaf whose symbol is a name, written with it.
+const NAME: u8 = 1;
+const OPEN: u8 = 2;
+const CLOSE: u8 = 3;
+
+const NULL: u8 = 0;
+const INT: u8 = 1;
+const FLOAT: u8 = 2;
+const BOOL: u8 = 3;
+const STRING: u8 = 4;
+
+/// Receives a word node by node, while it's derived, so it doesn't have to be in memory as a whole. `Tree` and
+/// `CompactTree` are ones, and so is `WordWriter`, which writes the word to a file; a backend can take the nodes right
+/// away.
+pub trait WordSink<S = Sym> {
+  fn leaf(&mut self, symbol: S, values: &[Value]) -> io::Result<()>;
+  fn open_branch(&mut self) -> io::Result<()>;
+  fn close_branch(&mut self) -> io::Result<()>;
+
+  fn node(&mut self, node: StreamNode<S>) -> io::Result<()> {
+    match node {
+      StreamNode::BranchStart => self.open_branch(),
+      StreamNode::BranchEnd => self.close_branch(),
+      StreamNode::Leaf(symbol, values) => self.leaf(symbol, values),
+    }
+  }
+
+  /// Sends a whole word.
+  fn word(&mut self, word: &impl InstanceWord<S>) -> io::Result<()> where Self: Sized, S: Symbol {
+    word.nodes().try_for_each(|node| self.node(node.into()))
+  }
+}
+
+impl<S: Symbol> WordSink<S> for Tree<context::Instance, S> {
+  fn leaf(&mut self, symbol: S, values: &[Value]) -> io::Result<()> {
+    self.push_leaf(symbol, values.iter().cloned());
+    Ok(())
+  }
+
+  fn open_branch(&mut self) -> io::Result<()> {
+    Tree::open_branch(self);
+    Ok(())
+  }
+
+  fn close_branch(&mut self) -> io::Result<()> {
+    Tree::close_branch(self).map_err(unbalanced)
+  }
+}
+
+impl WordSink for CompactTree {
+  fn leaf(&mut self, symbol: Sym, values: &[Value]) -> io::Result<()> {
+    self.push_leaf(symbol, values.iter().cloned());
+    Ok(())
+  }
+
+  fn open_branch(&mut self) -> io::Result<()> {
+    InstanceWord::open_branch(self);
+    Ok(())
+  }
+
+  fn close_branch(&mut self) -> io::Result<()> {
+    InstanceWord::close_branch(self).map_err(unbalanced)
+  }
+}
+
+/// A node read by `WordReader`. Streams don't know where branches end, so they aren't given.
+#[derive(Debug, Clone, Copy)]
+pub enum StreamNode<'r, S = Sym> {
+  BranchStart,
+  BranchEnd,
+  Leaf(S, &'r [Value]),
+}
+
+impl<'t, S> From<NodeRef<'t, S>> for StreamNode<'t, S> {
+  fn from(node: NodeRef<'t, S>) -> Self {
+    match node {
+      NodeRef::BranchStart(_) => StreamNode::BranchStart,
+      NodeRef::BranchEnd(_) => StreamNode::BranchEnd,
+      NodeRef::Leaf(symbol, values) => StreamNode::Leaf(symbol, values),
+    }
+  }
+}
+
+/// Writes a word as a stream of nodes, which `WordReader` reads back in the same order. Unlike tree files, nothing
+/// has to be known before the first node, so words of any length can be written while they are derived. Symbols are
+/// written as the `Sym`s they stand for.
+pub struct WordWriter<W: Write> {
+  out: BufWriter<W>,
+}
+
+impl<W: Write> WordWriter<W> {
+  pub fn new(out: W) -> Self {
+    WordWriter {out: BufWriter::new(out)}
+  }
+
+  /// Writes what's left in the buffer and returns the output.
+  pub fn finish(self) -> io::Result<W> {
+    self.out.into_inner().map_err(|e| e.into_error())
+  }
+
+  fn value(&mut self, val: &Value) -> io::Result<()> {
+    match val {
+      Value::Null => self.out.write_all(&[NULL]),
+      Value::Int(i) => {
+        self.out.write_all(&[INT])?;
+        self.out.write_all(&i.to_le_bytes())
+      },
+      Value::Float(f) => {
+        self.out.write_all(&[FLOAT])?;
+        self.out.write_all(&f.to_le_bytes())
+      },
+      Value::Bool(b) => self.out.write_all(&[BOOL, *b as u8]),
+      Value::String(s) => {
+        self.out.write_all(&[STRING])?;
+        self.out.write_all(&(s.len() as u64).to_le_bytes())?;
+        self.out.write_all(s.as_bytes())
+      },
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} can't be written in a word", val))),
+    }
+  }
+}
+
+impl<W: Write, S: Symbol> WordSink<S> for WordWriter<W> {
+  fn leaf(&mut self, symbol: S, values: &[Value]) -> io::Result<()> {
+    let symbol = symbol.to_sym();
+    match symbol.as_char() {
+      Some(c) => {
+        self.out.write_all(&[LEAF])?;
+        self.out.write_all(&(c as u32).to_le_bytes())?;
+      },
+      None => symbol.with_str(|name| {
+        self.out.write_all(&[NAME])?;
+        self.out.write_all(&(name.len() as u32).to_le_bytes())?;
+        self.out.write_all(name.as_bytes())
+      })?,
+    }
+    self.out.write_all(&(values.len() as u32).to_le_bytes())?;
+    values.iter().try_for_each(|val| self.value(val))
+  }
+
+  fn open_branch(&mut self) -> io::Result<()> {
+    self.out.write_all(&[OPEN])
+  }
+
+  fn close_branch(&mut self) -> io::Result<()> {
+    self.out.write_all(&[CLOSE])
+  }
+}
+
+/// Reads a word written by `WordWriter` one node at a time, keeping only the values of the last one. It fails on
+/// symbols that aren't in the alphabet `S`.
+pub struct WordReader<R: Read, S = Sym> {
+  input: BufReader<R>,
+  values: Vec<Value>,
+  alphabet: PhantomData<fn() -> S>,
+}
+
+impl<R: Read, S: Symbol> WordReader<R, S> {
+  pub fn new(input: R) -> Self {
+    WordReader {input: BufReader::new(input), values: Vec::new(), alphabet: PhantomData}
+  }
+
+  /// The next node, or `None` at the end of the word.
+  pub fn next_node(&mut self) -> io::Result<Option<StreamNode<'_, S>>> {
+    let mut tag = [0];
+    if self.input.read(&mut tag)? == 0 {
+      return Ok(None);
+    }
+    let symbol = match tag[0] {
+      OPEN => return Ok(Some(StreamNode::BranchStart)),
+      CLOSE => return Ok(Some(StreamNode::BranchEnd)),
+      LEAF => char::from_u32(self.u32()?).map(Sym::from).ok_or_else(|| invalid("invalid symbol"))?,
+      NAME => {
+        let len = self.u32()? as usize;
+        Sym::new(&self.string(len)?)
+      },
+      _ => return Err(invalid("invalid node")),
+    };
+    let symbol = S::from_sym(symbol).ok_or_else(|| invalid(&format!("`{}` isn't a symbol of the alphabet", symbol)))?;
+    self.values.clear();
+    for _ in 0..self.u32()? {
+      let val = self.value()?;
+      self.values.push(val);
+    }
+    Ok(Some(StreamNode::Leaf(symbol, &self.values)))
+  }
+
+  /// Sends the rest of the word to `sink`.
+  pub fn copy_to(&mut self, sink: &mut impl WordSink<S>) -> io::Result<()> {
+    while let Some(node) = self.next_node()? {
+      sink.node(node)?;
+    }
+    Ok(())
+  }
+
+  fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    self.input.read_exact(&mut buf)?;
+    Ok(buf)
+  }
+
+  fn u32(&mut self) -> io::Result<u32> {
+    self.bytes().map(u32::from_le_bytes)
+  }
+
+  fn string(&mut self, len: usize) -> io::Result<String> {
+    let mut buf = Vec::new();
+    (&mut self.input).take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(buf).map_err(|_| invalid("invalid string"))
+  }
+
+  fn value(&mut self) -> io::Result<Value> {
+    Ok(match self.bytes::<1>()?[0] {
+      NULL => Value::Null,
+      INT => Value::Int(i64::from_le_bytes(self.bytes()?)),
+      FLOAT => Value::Float(f64::from_le_bytes(self.bytes()?)),
+      BOOL => Value::Bool(self.bytes::<1>()?[0] != 0),
+      STRING => {
+        let len = u64::from_le_bytes(self.bytes()?);
+        Value::String(self.string(usize::try_from(len).map_err(|_| invalid("invalid string"))?)?)
+      },
+      _ => return Err(invalid("invalid value")),
+    })
+  }
+}
+
+fn unbalanced(err: TreeError) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn invalid(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
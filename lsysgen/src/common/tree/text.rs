@@ -0,0 +1,120 @@
+use std::fmt;
+use std::str::FromStr;
+use std::vec::Vec;
+
+use lsd::Sym;
+use lsd::ast::grammar::{Node as WordNode, NodeKind};
+use lsd::ast::normal::{Expr, ExprKind};
+
+use crate::common::Value;
+use crate::common::errors::Diagnostic;
+use super::node::{context, Node, NodeContent};
+use super::Tree;
+
+/// Writes the tree as a word, `F[+F]F(1.5, 2)`, which `from_str` reads back. Values are written as LSD literals, so
+/// `1.0` stays a float and strings keep their escapes.
+///
+/// With `{:#}`, every branch starts a new line, indented by its depth.
+impl fmt::Display for Tree<context::Instance, Sym> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let pretty = f.alternate();
+    let mut depth = 0;
+    let mut prev = None;
+    for node in self.iter() {
+      match node {
+        Node::BranchStart(_) => {
+          depth += 1;
+          if pretty && prev.is_some() {
+            write!(f, "\n{:1$}", "", depth * 2)?;
+          }
+          prev = Some('[');
+          f.write_str("[")?;
+        },
+        Node::BranchEnd(_) => {
+          depth -= 1;
+          prev = Some(']');
+          f.write_str("]")?;
+        },
+        Node::Leaf(content) => {
+          let s = content.character;
+          if pretty && prev == Some(']') {
+            write!(f, "\n{:1$}", "", depth * 2)?;
+          }
+          if !s.is_bare() {
+            write!(f, "\"{}\"", s)?;
+          } else if prev.is_some_and(|prev| s.glues_to(prev)) {
+            write!(f, " {}", s)?;
+          } else {
+            write!(f, "{}", s)?;
+          }
+          prev = s.as_char().or(Some('"'));
+          if !content.context.values.is_empty() {
+            let values: Vec<String> = content.context.values.iter().map(Value::to_string).collect();
+            write!(f, "({})", values.join(", "))?;
+            prev = Some(')');
+          }
+        },
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Reads a word whose arguments are values, like the ones written by `Display`. Expressions, expansions and blocks
+/// need an L system to be evaluated, so they are errors.
+impl FromStr for Tree<context::Instance, Sym> {
+  type Err = Vec<Diagnostic>;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let word = lsd::parse_word(s).map_err(|errors| errors.into_iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+    let mut tree = Tree::new();
+    let mut errors = Vec::new();
+    add_nodes(&mut tree, &word.0, &mut errors);
+    if errors.is_empty() {Ok(tree)} else {Err(errors)}
+  }
+}
+
+fn add_nodes(tree: &mut Tree<context::Instance, Sym>, nodes: &[WordNode<Sym>], errors: &mut Vec<Diagnostic>) {
+  for node in nodes {
+    match &node.kind {
+      NodeKind::Leaf(leaf) => {
+        let mut content = NodeContent::new_instance(leaf.symbol);
+        for arg in leaf.args.iter().flatten() {
+          match value(arg) {
+            Some(val) => content.context.values.push(val),
+            None => errors.push(Diagnostic::error("arguments of an instance word must be values")
+              .with_code("W0001")
+              .with_primary(arg.span, "this has to be evaluated")),
+          }
+        }
+        tree.add_leaf(content);
+      },
+      NodeKind::Branch(nodes) => {
+        tree.open_branch();
+        add_nodes(tree, nodes, errors);
+        tree.close_branch().expect("the branch was just opened");
+      },
+      NodeKind::Expansion(_) | NodeKind::Block(_) => errors.push(Diagnostic::error("instance words can't have expansions or blocks")
+        .with_code("W0002")
+        .with_primary(node.span, "this has to be evaluated")),
+    }
+  }
+}
+
+/// The value of a literal, maybe with a sign.
+pub(super) fn value(e: &Expr) -> Option<Value> {
+  match &e.kind {
+    ExprKind::Int(i) => Some(Value::Int(*i)),
+    ExprKind::Float(f) => Some(Value::Float(*f)),
+    ExprKind::String(s) => Some(Value::String(s.to_string())),
+    ExprKind::Bool(b) => Some(Value::Bool(*b)),
+    ExprKind::Null => Some(Value::Null),
+    ExprKind::Plus(e) => value(e).filter(|v| matches!(v, Value::Int(_) | Value::Float(_))),
+    ExprKind::Minus(e) => match value(e)? {
+      Value::Int(i) => Some(Value::Int(-i)),
+      Value::Float(f) => Some(Value::Float(-f)),
+      _ => None,
+    },
+    _ => None,
+  }
+}
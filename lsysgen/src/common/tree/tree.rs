@@ -1,25 +1,87 @@
+use std::fmt;
+use std::ops::Range;
 use std::vec::Vec;
 use std::iter::{Iterator, DoubleEndedIterator};
 
+use lsd::{Sym, Symbol};
+
+use crate::common::Value;
 use super::node::*;
 
 #[derive(Debug, Clone)]
-pub struct Tree<Ctx=context::Instance, Char=char> {
+pub struct Tree<Ctx=context::Instance, Char=Sym> {
   nodes: Vec<Node<Ctx, Char>>,
   open_branches: Vec<usize>,
 }
 
-pub struct TreeIterator<Ctx, Char=char> {
-  tree: &Tree<Ctx, Char>,
+pub struct TreeIterator<'t, Ctx, Char=Sym> {
+  tree: &'t Tree<Ctx, Char>,
   idx: usize,
 }
 
-pub struct TreeBranchIterator<Ctx, Char=char> {
-  tree: &Tree<Ctx, Char>,
+pub struct TreeBranchIterator<'t, Ctx, Char=Sym> {
+  tree: &'t Tree<Ctx, Char>,
   idx: usize,
   depth: i32,
 }
 
+/// Why an edit of a tree failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeError {
+  /// A branch was closed, but none was open.
+  NoOpenBranch,
+  /// A tree with open branches was inserted, or a range with only one end of a branch was removed.
+  Unbalanced,
+  /// The position is past the end of the tree.
+  OutOfBounds(usize),
+  /// The node at the position isn't a leaf.
+  NotALeaf(usize),
+  /// The node at the position isn't the start or the end of a branch.
+  NotABranch(usize),
+}
+
+impl fmt::Display for TreeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::NoOpenBranch => write!(f, "there is no open branch to close"),
+      Self::Unbalanced => write!(f, "unbalanced branches"),
+      Self::OutOfBounds(i) => write!(f, "position {} is out of the tree", i),
+      Self::NotALeaf(i) => write!(f, "node {} isn't a leaf", i),
+      Self::NotABranch(i) => write!(f, "node {} isn't the start or the end of a branch", i),
+    }
+  }
+}
+
+/// A node of an instance word, borrowed from the storage that holds it.
+#[derive(Debug, Clone, Copy)]
+pub enum NodeRef<'t, S = Sym> {
+  BranchStart(usize),
+  BranchEnd(usize),
+  Leaf(S, &'t [Value]),
+}
+
+/// Storage of an instance word. `Tree` keeps every node by itself, which makes it easy to edit, and `CompactTree`
+/// keeps symbols and values in flat arrays, which takes much less memory for big words.
+///
+/// Positions of branch ends work as in `Node`: a branch that is still open starts at `BranchStart(0)`. A `Tree` can
+/// hold any alphabet, while the other storages hold `Sym`s.
+pub trait InstanceWord<S: Symbol = Sym> {
+  fn len(&self) -> usize;
+  fn is_empty(&self) -> bool {self.len() == 0}
+  fn node(&self, i: usize) -> NodeRef<'_, S>;
+  fn nodes(&self) -> impl Iterator<Item = NodeRef<'_, S>> {(0..self.len()).map(|i| self.node(i))}
+
+  fn push_leaf(&mut self, symbol: S, values: impl IntoIterator<Item = Value>);
+  fn open_branch(&mut self);
+  fn close_branch(&mut self) -> Result<(), TreeError>;
+}
+
+impl<Ctx, Char> Default for Tree<Ctx, Char> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl<Ctx, Char> Tree<Ctx, Char> {
   pub fn new() -> Self {
     Tree{
@@ -29,6 +91,13 @@ impl<Ctx, Char> Tree<Ctx, Char> {
   }
 
   pub fn node_at(&self, i: usize) -> &Node<Ctx, Char> {&self.nodes[i]}
+  pub fn get(&self, i: usize) -> Option<&Node<Ctx, Char>> {self.nodes.get(i)}
+
+  pub fn len(&self) -> usize {self.nodes.len()}
+  pub fn is_empty(&self) -> bool {self.nodes.is_empty()}
+
+  /// Whether every open branch has been closed.
+  pub fn is_balanced(&self) -> bool {self.open_branches.is_empty()}
 
   pub fn add_leaf(&mut self, content: NodeContent<Ctx, Char>) {self.nodes.push(Node::Leaf(content));}
 
@@ -38,27 +107,147 @@ impl<Ctx, Char> Tree<Ctx, Char> {
     self.open_branches.push(i);
   }
 
-  pub fn close_branch(&mut self) {
-    match self.open_branches.pop() {
-      Some(last_open_branch) => {
-        let i = self.nodes.len();
-        self.nodes.push(Node::BranchEnd(last_open_branch));
-        self.nodes[last_open_branch] = Node::BranchStart(i);
-      },
-    };
+  pub fn close_branch(&mut self) -> Result<(), TreeError> {
+    let last_open_branch = self.open_branches.pop().ok_or(TreeError::NoOpenBranch)?;
+    let i = self.nodes.len();
+    self.nodes.push(Node::BranchEnd(last_open_branch));
+    self.nodes[last_open_branch] = Node::BranchStart(i);
+    Ok(())
   }
 
-  pub fn is_left_side()  -> bool {Ctx::is_left_side() }
-  pub fn is_right_side() -> bool {Ctx::is_right_side()}
-  pub fn is_instance()   -> bool {Ctx::is_instance()  }
+  /// Inserts the nodes of `other` before the node at `at`. `other` can't have open branches, but this tree can.
+  pub fn splice(&mut self, at: usize, other: Tree<Ctx, Char>) -> Result<(), TreeError> {
+    if at > self.nodes.len() {
+      return Err(TreeError::OutOfBounds(at));
+    }
+    if !other.is_balanced() {
+      return Err(TreeError::Unbalanced);
+    }
+    let n = other.nodes.len();
+    self.shift(at, |j| j + n);
+    self.nodes.splice(at..at, other.nodes.into_iter().map(|node| node.moved(|j| j + at)));
+    Ok(())
+  }
+
+  /// Adds the nodes of `other` at the end of this tree.
+  pub fn append(&mut self, other: Tree<Ctx, Char>) -> Result<(), TreeError> {
+    self.splice(self.nodes.len(), other)
+  }
+
+  /// Removes the nodes in `range` and returns them as a tree. Every branch has to be either inside the range or
+  /// outside of it.
+  pub fn remove_range(&mut self, range: Range<usize>) -> Result<Tree<Ctx, Char>, TreeError> {
+    let Range {start, end} = range;
+    if start > end || end > self.nodes.len() {
+      return Err(TreeError::OutOfBounds(end));
+    }
+    let inside = |j: usize| (start..end).contains(&j);
+    let balanced = self.nodes[start..end].iter().enumerate().all(|(i, node)| match node {
+      Node::BranchStart(j) | Node::BranchEnd(j) => inside(*j) && !self.open_branches.contains(&(start + i)),
+      Node::Leaf(_) => true,
+    });
+    if !balanced {
+      return Err(TreeError::Unbalanced);
+    }
+
+    let n = end - start;
+    self.shift(end, |j| j - n);
+    let removed = self.nodes.drain(start..end).map(|node| node.moved(|j| j - start)).collect();
+    Ok(Tree {nodes: removed, open_branches: Vec::new()})
+  }
+
+  /// Removes the leaf at `i`.
+  pub fn remove_leaf(&mut self, i: usize) -> Result<NodeContent<Ctx, Char>, TreeError> {
+    self.leaf_check(i)?;
+    match self.remove_range(i..i + 1)?.nodes.pop() {
+      Some(Node::Leaf(content)) => Ok(content),
+      _ => unreachable!(),
+    }
+  }
+
+  /// Removes a whole branch, given the position of its start or its end, and returns it with its brackets.
+  pub fn remove_branch(&mut self, i: usize) -> Result<Tree<Ctx, Char>, TreeError> {
+    let range = self.branch_range(i)?;
+    self.remove_range(range)
+  }
+
+  /// Replaces the leaf at `i` with the nodes of `other`, and returns the leaf.
+  pub fn replace_leaf(&mut self, i: usize, other: Tree<Ctx, Char>) -> Result<NodeContent<Ctx, Char>, TreeError> {
+    self.leaf_check(i)?;
+    if !other.is_balanced() {
+      return Err(TreeError::Unbalanced);
+    }
+    let leaf = self.remove_leaf(i)?;
+    self.splice(i, other)?;
+    Ok(leaf)
+  }
 
-  pub fn iter(&self) -> TreeIterator<Ctx, Char> {
+  /// Removes the nodes from `i` to the end of its branch, as the cut operator does, and returns them.
+  pub fn cut(&mut self, i: usize) -> Result<Tree<Ctx, Char>, TreeError> {
+    if i > self.nodes.len() {
+      return Err(TreeError::OutOfBounds(i));
+    }
+    let mut depth = 0usize;
+    let mut end = i;
+    while end < self.nodes.len() {
+      match self.nodes[end] {
+        Node::BranchStart(_) => depth += 1,
+        Node::BranchEnd(_) if depth == 0 => break,
+        Node::BranchEnd(_) => depth -= 1,
+        Node::Leaf(_) => {},
+      }
+      end += 1;
+    }
+    self.remove_range(i..end)
+  }
+
+  /// Positions of the nodes of the branch that starts or ends at `i`, brackets included.
+  pub fn branch_range(&self, i: usize) -> Result<Range<usize>, TreeError> {
+    if self.open_branches.contains(&i) {
+      return Err(TreeError::Unbalanced);
+    }
+    match self.nodes.get(i) {
+      Some(Node::BranchStart(end)) => Ok(i..end + 1),
+      Some(Node::BranchEnd(start)) => Ok(*start..i + 1),
+      Some(Node::Leaf(_)) => Err(TreeError::NotABranch(i)),
+      None => Err(TreeError::OutOfBounds(i)),
+    }
+  }
+
+  fn leaf_check(&self, i: usize) -> Result<(), TreeError> {
+    match self.nodes.get(i) {
+      Some(Node::Leaf(_)) => Ok(()),
+      Some(_) => Err(TreeError::NotALeaf(i)),
+      None => Err(TreeError::OutOfBounds(i)),
+    }
+  }
+
+  /// Applies `f` to the positions from `from` on, in the branch nodes and the open branches. Only the nodes from
+  /// `from` on and the starts of the branches around them are touched, so editing near the end of a tree is cheap.
+  /// Open branches don't know where they end yet, so their starts are left as they are.
+  fn shift(&mut self, from: usize, f: impl Fn(usize) -> usize) {
+    for i in from..self.nodes.len() {
+      match &mut self.nodes[i] {
+        // Un inicio de rama cerrada nunca apunta a 0, así que `BranchStart(0)` es una rama abierta
+        Node::BranchStart(j) if *j != 0 => *j = f(*j),
+        Node::BranchEnd(j) if *j >= from => *j = f(*j),
+        // La rama empieza antes de `from`: no se mueve, pero su final sí
+        &mut Node::BranchEnd(start) => self.nodes[start] = Node::BranchStart(f(i)),
+        _ => {},
+      }
+    }
+    for b in self.open_branches.iter_mut().filter(|b| **b >= from) {
+      *b = f(*b);
+    }
+  }
+
+  pub fn iter(&self) -> TreeIterator<'_, Ctx, Char> {
     TreeIterator{
       tree: self,
       idx: 0 //from.unwrap_or(0)
     }
   }
-  pub fn branch_iter(&self, from: usize) -> TreeBranchIterator<Ctx, Char> {
+  pub fn branch_iter(&self, from: usize) -> TreeBranchIterator<'_, Ctx, Char> {
     TreeBranchIterator{
       tree: self,
       idx: from, //from.unwrap_or(0)
@@ -67,21 +256,54 @@ impl<Ctx, Char> Tree<Ctx, Char> {
   }
 }
 
-impl<Ctx, Char> Iterator for TreeIterator<Ctx, Char> {
-  type Item = &Node<Ctx, Char>;
+impl<S: Symbol> InstanceWord<S> for Tree<context::Instance, S> {
+  fn len(&self) -> usize {self.nodes.len()}
+
+  fn node(&self, i: usize) -> NodeRef<'_, S> {
+    match &self.nodes[i] {
+      Node::BranchStart(j) => NodeRef::BranchStart(*j),
+      Node::BranchEnd(j) => NodeRef::BranchEnd(*j),
+      Node::Leaf(content) => NodeRef::Leaf(content.character.clone(), &content.context.values),
+    }
+  }
+
+  fn push_leaf(&mut self, symbol: S, values: impl IntoIterator<Item = Value>) {
+    self.add_leaf(NodeContent {character: symbol, context: context::Instance {values: values.into_iter().collect()}});
+  }
+
+  fn open_branch(&mut self) {Tree::open_branch(self)}
+  fn close_branch(&mut self) -> Result<(), TreeError> {Tree::close_branch(self)}
+}
+
+impl<Ctx: context::Context, Char> Tree<Ctx, Char> {
+  pub fn is_left_side()  -> bool {Ctx::is_left_side() }
+  pub fn is_right_side() -> bool {Ctx::is_right_side()}
+  pub fn is_instance()   -> bool {Ctx::is_instance()  }
+}
+
+impl<Ctx, Char> Node<Ctx, Char> {
+  /// The same node, with the position of the other end of its branch changed by `f`.
+  fn moved(self, f: impl Fn(usize) -> usize) -> Self {
+    match self {
+      Node::BranchStart(j) => Node::BranchStart(f(j)),
+      Node::BranchEnd(j) => Node::BranchEnd(f(j)),
+      leaf => leaf,
+    }
+  }
+}
+
+impl<'t, Ctx, Char> Iterator for TreeIterator<'t, Ctx, Char> {
+  type Item = &'t Node<Ctx, Char>;
 
   fn next(&mut self) -> Option<Self::Item> {
+    let node = self.tree.nodes.get(self.idx)?;
     self.idx += 1;
-    if self.idx < self.tree.nodes.len() {
-      Some(self.tree.node_at(self.idx))
-    } else {
-      None
-    }
+    Some(node)
   }
 }
 
-impl<Ctx, Char> Iterator for TreeBranchIterator<Ctx, Char> {
-  type Item = &Node<Ctx, Char>;
+impl<'t, Ctx, Char> Iterator for TreeBranchIterator<'t, Ctx, Char> {
+  type Item = &'t Node<Ctx, Char>;
 
   fn next(&mut self) -> Option<Self::Item> {
     // Si no existe siguiente nodo, ya hemos terminado
@@ -95,22 +317,20 @@ impl<Ctx, Char> Iterator for TreeBranchIterator<Ctx, Char> {
     }
 
     self.idx += 1;
-    match self.tree.node_at(self.idx) {
+    let node = self.tree.node_at(self.idx);
+    match node {
       Node::BranchStart(i) => {
-        self.idx = i; // Ir al final de la rama (para el siguiente)
+        self.idx = *i; // Ir al final de la rama (para el siguiente)
         self.depth += 1;
-        Some(Node::BranchStart(i))
       },
-      Node::BranchEnd(i) => {
-        self.depth -= 1;
-        Some(Node::BranchEnd(i))
-      },
-      x => Some(x)
+      Node::BranchEnd(_) => self.depth -= 1,
+      Node::Leaf(_) => {},
     }
+    Some(node)
   }
 }
 
-impl<Ctx, Char> DoubleEndedIterator for TreeBranchIterator<Ctx, Char> {
+impl<Ctx, Char> DoubleEndedIterator for TreeBranchIterator<'_, Ctx, Char> {
   fn next_back(&mut self) -> Option<Self::Item> {
     // Si estamos en el primer nodo, no existe anterior nodo, por lo que ya hemos terminado
     if self.idx == 0 {
@@ -118,17 +338,15 @@ impl<Ctx, Char> DoubleEndedIterator for TreeBranchIterator<Ctx, Char> {
     }
 
     self.idx -= 1;
-    match self.tree.node_at(self.idx) {
-      Node::BranchStart(i) => {
-        self.depth += 1;
-        Some(Node::BranchStart(i))
-      },
+    let node = self.tree.node_at(self.idx);
+    match node {
+      Node::BranchStart(_) => self.depth += 1,
       Node::BranchEnd(i) => {
-        self.idx = i; // Ir al principio de la rama (para el siguiente)
+        self.idx = *i; // Ir al principio de la rama (para el siguiente)
         self.depth -= 1;
-        Some(Node::BranchEnd(i))
       },
-      x => Some(x)
+      Node::Leaf(_) => {},
     }
+    Some(node)
   }
 }
@@ -1,18 +1,29 @@
 use std::string::String;
 use std::vec::Vec;
 use std::collections::HashMap;
+use std::rc::Rc;
 
+use lsd::Sym;
+use lsd::ast::normal::Expr;
+use lsd::source::Span;
+
+use super::errors::EvalError;
+use super::expr::ExpressionEvaluator;
 use super::lsystem::LSystem;
+use super::tree::{Tree, TreeError};
+use super::tree::node::context;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Parameter {
   name: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Function {
   params: Vec<Parameter>,
-  expr: Expr,
+  expr: Expr<'static>,
+  /// Where the function is defined.
+  span: Span,
   //ctx: ???,
 }
 
@@ -22,28 +33,63 @@ pub enum Value {
   Float(f64),
   Bool(bool),
   String(String),
-  Function(&Function),
-  LSystem(&LSystem),
+  Function(Rc<Function>),
+  LSystem(Rc<LSystem<Sym>>),
+  Word(Word),
   Null,
   Error,
 }
 
+/// A word built at runtime by LSD code.
+///
+/// Words written inside rules keep their arguments as expressions until they are derived, while words built by
+/// evaluating code (for example, a function called from the axiom) already hold their values.
 #[derive(Debug, Clone)]
+pub enum Word {
+  RightSide(Tree<context::RightSide>),
+  Instance(Tree<context::Instance>),
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Scope {
-  parent: Option<&mut Scope>,
+  parent: Option<Rc<Scope>>,
   mapping: HashMap<String, Value>,
 }
 
+impl Parameter {
+  pub fn new(name: impl Into<String>) -> Self {
+    Parameter {name: name.into()}
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
+
 impl Function {
-  pub fn call(&self, args: Option<&Vec<Value>>, scope: &Scope, ee: &ExpressionEvaluator) -> Value {
-    let mut param_mapping = scope.clone();
-    if self.params != None && (args.is_none() && self.params.len() > 0) || (args.is_some() && self.params.len() != args.unwrap().len()) {
-      return Value::Error;
+  pub fn new(params: Vec<Parameter>, expr: Expr<'static>, span: Span) -> Self {
+    Function {params, expr, span}
+  }
+
+  pub fn params(&self) -> &[Parameter] {
+    &self.params
+  }
+
+  pub fn span(&self) -> Span {
+    self.span
+  }
+
+  /// Evaluates the function with its parameters bound to `args`, in a copy of `scope`. `call` is where it's called
+  /// from, for errors.
+  pub fn call(&self, args: &[Value], scope: &Scope, call: Span, ee: &ExpressionEvaluator) -> Result<Value, EvalError> {
+    if self.params.len() != args.len() {
+      return Err(EvalError::WrongArgCount {expected: self.params.len(), found: args.len(), call, def: Some(self.span)});
     }
-    for (param, arg) in (0..args.unwrap().len()).map(|i| (self.params[i], args.unwrap()[i])) {
-      param_mapping.set(param.name, arg);
+    let mut param_mapping = scope.clone();
+    for (param, arg) in self.params.iter().zip(args) {
+      param_mapping.set(param.name.clone(), arg.clone());
     }
-    ee.eval(self.expr, &param_mapping)
+    ee.eval(&self.expr, &param_mapping)
   }
 }
 
@@ -56,13 +102,13 @@ impl std::fmt::Display for Parameter {
 impl std::fmt::Display for Function {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     let mut sparams = String::new();
-    for (i, &param) in self.params.iter().enumerate() {
+    for (i, param) in self.params.iter().enumerate() {
       if i != 0 {
         sparams += ", ";
       }
       sparams += param.name.as_str();
     }
-    write!(f, "({}) -> {}", sparams, self.expr.getText())
+    write!(f, "({}) -> {}", sparams, lsd::format_expr(&self.expr))
   }
 }
 
@@ -70,28 +116,143 @@ impl std::fmt::Display for Value {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
       Self::Int(i) => write!(f, "{}", i),
-      Self::Float(fl) => write!(f, "{}", fl),
+      // Como en LSD, para que se pueda volver a leer
+      Self::Float(fl) if fl.is_nan() => write!(f, "NaN"),
+      Self::Float(fl) if fl.is_infinite() => write!(f, "{}", if *fl > 0.0 {"Inf"} else {"-Inf"}),
+      Self::Float(fl) => write!(f, "{:?}", fl),
       Self::Bool(b) => write!(f, "{}", b),
-      Self::String(s) => write!(f, "\"{}\"", s),
+      Self::String(s) => write!(f, "{}", lsd::quote(s)),
       Self::Function(fun) => write!(f, "{}", fun),
       Self::LSystem(lsystem) => write!(f, "{}", lsystem),
-      Self::Null => write!(f, "Null"),
+      Self::Word(word) => write!(f, "{}", word),
+      Self::Null => write!(f, "null"),
       Self::Error => write!(f, "Error"),
     }
   }
 }
 
+impl Value {
+  /// Name of the type of the value, for error messages.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Self::Int(_) => "int",
+      Self::Float(_) => "float",
+      Self::Bool(_) => "bool",
+      Self::String(_) => "string",
+      Self::Function(_) => "function",
+      Self::LSystem(_) => "L system",
+      Self::Word(_) => "word",
+      Self::Null => "null",
+      Self::Error => "error",
+    }
+  }
+}
+
+impl std::fmt::Display for Word {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::RightSide(tree) => write!(f, "Word({} nodes)", tree.len()),
+      Self::Instance(tree) => write!(f, "`{}`", tree),
+    }
+  }
+}
+
+impl Word {
+  /// Number of nodes of this word, counting both ends of every branch.
+  pub fn len(&self) -> usize {
+    match self {
+      Self::RightSide(tree) => tree.len(),
+      Self::Instance(tree) => tree.len(),
+    }
+  }
+
+  /// Returns whether this word has no nodes.
+  pub fn is_empty(&self) -> bool {
+    match self {
+      Self::RightSide(tree) => tree.len() == 0,
+      Self::Instance(tree) => tree.len() == 0,
+    }
+  }
+
+  /// Returns whether every branch of this word is closed.
+  pub fn is_balanced(&self) -> bool {
+    match self {
+      Self::RightSide(tree) => tree.is_balanced(),
+      Self::Instance(tree) => tree.is_balanced(),
+    }
+  }
+
+  /// Splices this word at the end of an instance word, as done when an axiom or a rule's right side expands a word
+  /// value.
+  ///
+  /// Returns `false` if this word still has unevaluated arguments, or if it has open branches.
+  pub fn splice_into(&self, dest: &mut Tree<context::Instance>) -> bool {
+    match self {
+      Self::RightSide(_) => false,
+      Self::Instance(tree) => dest.append(tree.clone()).is_ok(),
+    }
+  }
+
+  /// Repeats this word `n` times, like a loop writing the same motif over and over. Fails if the word has open
+  /// branches.
+  pub fn repeat(&self, n: usize) -> Result<Word, TreeError> {
+    Ok(match self {
+      Self::RightSide(tree) => Self::RightSide(repeat(tree, n)?),
+      Self::Instance(tree) => Self::Instance(repeat(tree, n)?),
+    })
+  }
+
+  /// Concatenates two words. Returns `None` if one of them is instanced and the other one is not, or if the second
+  /// one has open branches.
+  pub fn concat(&self, other: &Word) -> Option<Word> {
+    match (self, other) {
+      (Self::RightSide(a), Self::RightSide(b)) => {
+        let mut res = a.clone();
+        res.append(b.clone()).ok()?;
+        Some(Self::RightSide(res))
+      },
+      (Self::Instance(a), Self::Instance(b)) => {
+        let mut res = a.clone();
+        res.append(b.clone()).ok()?;
+        Some(Self::Instance(res))
+      },
+      _ => None,
+    }
+  }
+}
+
+/// `tree` written `n` times.
+fn repeat<Ctx: Clone>(tree: &Tree<Ctx>, n: usize) -> Result<Tree<Ctx>, TreeError> {
+  if !tree.is_balanced() {
+    return Err(TreeError::Unbalanced);
+  }
+  let mut res = Tree::new();
+  for _ in 0..n {
+    res.append(tree.clone())?;
+  }
+  Ok(res)
+}
+
 impl Scope {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// An empty scope whose variables hide the ones of `parent`.
+  pub fn child(parent: Rc<Scope>) -> Self {
+    Scope {parent: Some(parent), mapping: HashMap::new()}
+  }
+
   /// Sets the value of a variable in this scope. If the variable doesn't exist in this scope, it's created.
   pub fn set(&mut self, var: String, val: Value) {
     self.mapping.insert(var, val);
   }
 
   /// Recursively get variable's value.
-  pub fn get (&self, var: String) -> Option<&Value> {
-    match self.mapping.get(&var) {
+  pub fn get (&self, var: &str) -> Option<&Value> {
+    match self.mapping.get(var) {
       Some(val) => Some(val),
-      None => match self.parent {
+      None => match &self.parent {
         Some(scope) => scope.get(var),
         None => None
       }
@@ -99,11 +260,8 @@ impl Scope {
   }
 
   /// Returns whether this scope (not recursively) has that variable in it.
-  pub fn has (&self, var: String) -> bool {
-    match self.mapping.get(&var) {
-      Some(_) => true,
-      None => false
-    }
+  pub fn has (&self, var: &str) -> bool {
+    self.mapping.contains_key(var)
   }
 
   /// Merges env into self (without env's ancestors).
@@ -0,0 +1,143 @@
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use lsd::Symbol;
+
+use crate::common::{Scope, Value};
+use crate::common::errors::{DeriveError, EvalError};
+use crate::common::expr::ExpressionEvaluator;
+use crate::common::tree::{Tree, TreeDiff};
+use crate::common::tree::node::{context, Node, NodeContent};
+use super::rule::Rule;
+use super::table::Table;
+
+/// Derives words with the rules of a table, rewriting every leaf at once, as in a context-free L system. Leaves that
+/// no rule applies to, and branches, stay as they are.
+#[derive(Debug, Clone)]
+pub struct Derivator<T> {
+  ee: ExpressionEvaluator,
+  alphabet: PhantomData<fn(T)>,
+}
+
+impl<T> Default for Derivator<T> {
+  fn default() -> Self {
+    Derivator {ee: ExpressionEvaluator::new(), alphabet: PhantomData}
+  }
+}
+
+impl<T: Symbol> Derivator<T> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Derives `word` once with the rules of `table`. Conditions and right sides of the rules are evaluated in a scope
+  /// inside `scope`, with the values of the leaf bound to the parameters of the rule.
+  ///
+  /// A leaf that can't be rewritten doesn't stop the derivation, so every error of the step is returned, each one
+  /// only once even if many leaves raise it.
+  pub fn step(&self, table: &Table<T>, word: &Tree<context::Instance, T>, scope: &Scope)
+    -> Result<Tree<context::Instance, T>, Vec<DeriveError>> {
+    self.derive_into(table, word, scope, None)
+  }
+
+  /// Like `step`, but also returns where every node of `word` went, so the lineage of the nodes can be followed
+  /// across iterations. Leaves that no rule applies to and branches are kept; rewritten leaves aren't, even if their
+  /// successor is the same.
+  pub fn step_with_diff(&self, table: &Table<T>, word: &Tree<context::Instance, T>, scope: &Scope)
+    -> Result<(Tree<context::Instance, T>, TreeDiff), Vec<DeriveError>> {
+    let mut diff = TreeDiff::new();
+    let res = self.derive_into(table, word, scope, Some(&mut diff))?;
+    Ok((res, diff))
+  }
+
+  fn derive_into(&self, table: &Table<T>, word: &Tree<context::Instance, T>, scope: &Scope,
+                 mut diff: Option<&mut TreeDiff>) -> Result<Tree<context::Instance, T>, Vec<DeriveError>> {
+    let globals = Rc::new(scope.clone());
+    let mut res = Tree::new();
+    let mut errors = Vec::new();
+    for node in word.iter() {
+      let start = res.len();
+      let outcome = match node {
+        Node::BranchStart(_) => {
+          res.open_branch();
+          Ok(true)
+        },
+        Node::BranchEnd(_) => match res.close_branch() {
+          Ok(()) => Ok(true),
+          Err(_) => Err(DeriveError::UnbalancedBranch(Default::default())),
+        },
+        Node::Leaf(leaf) => match self.rewrite(table, &leaf.character, &leaf.context.values, &globals, &mut res) {
+          Ok(rewritten) => {
+            if !rewritten {
+              res.add_leaf(leaf.clone());
+            }
+            Ok(!rewritten)
+          },
+          Err(err) => Err(err),
+        },
+      };
+      match outcome {
+        // Tras un error la palabra no se devuelve, y puede tener parte de un sucesor, así que el diff ya no sirve
+        Ok(kept) => if let (Some(diff), true) = (diff.as_deref_mut(), errors.is_empty()) {
+          diff.push(start..res.len(), kept).expect("the successors of a step follow each other");
+        },
+        Err(err) => if !errors.contains(&err) {
+          errors.push(err);
+        },
+      }
+    }
+    if errors.is_empty() {Ok(res)} else {Err(errors)}
+  }
+
+  /// The successor of a leaf with the rules of `table`, chosen as in `step`, or `None` if no rule applies to it.
+  /// `globals` is the scope of the rules; keeping it in an `Rc` lets a `StreamDeriver` share it between leaves.
+  pub fn successor_of(&self, table: &Table<T>, character: &T, values: &[Value], globals: &Rc<Scope>)
+    -> Result<Option<Tree<context::Instance, T>>, DeriveError> {
+    let mut res = Tree::new();
+    Ok(self.rewrite(table, character, values, globals, &mut res)?.then_some(res))
+  }
+
+  /// Adds the successor of a leaf to `res`, if a rule applies to it.
+  fn rewrite(&self, table: &Table<T>, character: &T, values: &[Value], globals: &Rc<Scope>,
+             res: &mut Tree<context::Instance, T>) -> Result<bool, DeriveError> {
+    for rule in table.rules_for(character) {
+      if !rule.params.is_empty() && rule.params.len() != values.len() {
+        return Err(DeriveError::ArgMismatch {rule: rule.span, expected: rule.params.len(), found: values.len()});
+      }
+      let mut scope = Scope::child(Rc::clone(globals));
+      for (param, val) in rule.params.iter().zip(values) {
+        scope.set(param.name().to_string(), val.clone());
+      }
+      if let Some(cond) = &rule.condition {
+        match self.ee.eval(cond, &scope)? {
+          Value::Bool(true) => {},
+          Value::Bool(false) => continue,
+          val => return Err(EvalError::TypeMismatch(":", vec![val.type_name()], cond.span).into()),
+        }
+      }
+      self.successor(rule, &scope, res)?;
+      return Ok(true);
+    }
+    Ok(false)
+  }
+
+  /// Adds the right side of `rule` to `res`, with its arguments evaluated in `scope`.
+  fn successor(&self, rule: &Rule<T>, scope: &Scope, res: &mut Tree<context::Instance, T>) -> Result<(), DeriveError> {
+    // Una rama abierta en la parte derecha quedaría cerrada por la palabra que la rodea
+    if !rule.right_side.is_balanced() {
+      return Err(DeriveError::UnbalancedBranch(rule.span));
+    }
+    for node in rule.right_side.iter() {
+      match node {
+        Node::BranchStart(_) => res.open_branch(),
+        Node::BranchEnd(_) => res.close_branch().map_err(|_| DeriveError::UnbalancedBranch(rule.span))?,
+        Node::Leaf(content) => {
+          let values = content.context.args.iter().map(|arg| self.ee.eval(arg, scope)).collect::<Result<_, _>>()?;
+          res.add_leaf(NodeContent {character: content.character.clone(), context: context::Instance {values}});
+        },
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,9 @@
+mod spill;
+mod rule;
+mod table;
+mod derivator;
+
+pub use spill::StreamDeriver;
+pub use rule::Rule;
+pub use table::Table;
+pub use derivator::Derivator;
@@ -0,0 +1,42 @@
+use std::vec::Vec;
+
+use lsd::ast::normal::Expr;
+use lsd::source::Span;
+
+use crate::common::Parameter;
+use crate::common::tree::Tree;
+use crate::common::tree::node::context;
+
+/// A production rule, `A(x, y) : x < y -> B(x + 1)[C]`. It rewrites the leaves with its symbol whose values make the
+/// condition true, with the values bound to the parameters, by the right side with its arguments evaluated.
+#[derive(Debug, Clone)]
+pub struct Rule<T> {
+  pub symbol: T,
+  /// A rule without parameters rewrites leaves with any values.
+  pub params: Vec<Parameter>,
+  pub condition: Option<Expr<'static>>,
+  pub right_side: Tree<context::RightSide, T>,
+  /// Where the rule is defined. Rules built by code have an empty span.
+  pub span: Span,
+}
+
+impl<T> Rule<T> {
+  pub fn new(symbol: T, right_side: Tree<context::RightSide, T>) -> Self {
+    Rule {symbol, params: Vec::new(), condition: None, right_side, span: Span::default()}
+  }
+
+  pub fn with_params<S: Into<String>>(mut self, params: impl IntoIterator<Item = S>) -> Self {
+    self.params = params.into_iter().map(Parameter::new).collect();
+    self
+  }
+
+  pub fn with_condition(mut self, condition: Expr<'static>) -> Self {
+    self.condition = Some(condition);
+    self
+  }
+
+  pub fn with_span(mut self, span: Span) -> Self {
+    self.span = span;
+    self
+  }
+}
@@ -0,0 +1,139 @@
+use std::fs::{self, File};
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lsd::{Sym, Symbol};
+
+use crate::common::Value;
+use crate::common::tree::{InstanceWord, StreamNode, TreeDiff, WordReader, WordSink, WordWriter};
+
+/// Derives a context-free L system whose words don't fit in memory. Every iteration reads the word of the previous
+/// one from a file, a node at a time, and writes the next one to another file; the last one goes to a `WordSink`,
+/// which can be a backend that draws it as it comes. Memory use doesn't depend on the length of the words.
+///
+/// Rules are a function that takes a leaf and sends its successor to a sink, returning `true`, or returns `false`
+/// when no rule applies and the leaf stays as it is. Branches stay as they are. The symbols of the words can be of any
+/// alphabet `S`, not only `Sym`. `LSystem::derive_to` takes them from a `Table`, through `Derivator::successor_of`.
+pub struct StreamDeriver<R, S = Sym> {
+  rules: R,
+  /// Where the words of the iterations are written.
+  dir: PathBuf,
+  alphabet: PhantomData<fn(S)>,
+}
+
+/// Numbers the spill files of the process.
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// A file with the word of an iteration, removed when it's no longer needed.
+struct Spill(PathBuf);
+
+impl Spill {
+  fn new(dir: &Path) -> Self {
+    let n = SPILLS.fetch_add(1, Ordering::Relaxed);
+    Spill(dir.join(format!("lsysgen-{}-{}.word", std::process::id(), n)))
+  }
+}
+
+impl Drop for Spill {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.0);
+  }
+}
+
+impl<R, S: Symbol> StreamDeriver<R, S> where R: FnMut(S, &[Value], &mut dyn WordSink<S>) -> io::Result<bool> {
+  /// Writes the words to the temporary directory of the system.
+  pub fn new(rules: R) -> Self {
+    StreamDeriver {rules, dir: std::env::temp_dir(), alphabet: PhantomData}
+  }
+
+  pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    self.dir = dir.into();
+    self
+  }
+
+  /// Derives `axiom` `iterations` times and sends the result to `sink`.
+  pub fn derive(&mut self, axiom: &impl InstanceWord<S>, iterations: usize, sink: &mut impl WordSink<S>) -> io::Result<()> {
+    if iterations == 0 {
+      return sink.word(axiom);
+    }
+    let mut prev: Option<Spill> = None;
+    for i in 0..iterations {
+      let next = (i + 1 < iterations).then(|| Spill::new(&self.dir));
+      let mut writer = match &next {
+        Some(spill) => Some(WordWriter::new(File::create(&spill.0)?)),
+        None => None,
+      };
+      let out: &mut dyn WordSink<S> = match &mut writer {
+        Some(writer) => writer,
+        None => sink,
+      };
+      match &prev {
+        None => axiom.nodes().try_for_each(|node| self.rewrite(node.into(), out).map(|_| ()))?,
+        Some(spill) => {
+          let mut reader = WordReader::new(File::open(&spill.0)?);
+          while let Some(node) = reader.next_node()? {
+            self.rewrite(node, out)?;
+          }
+        },
+      }
+      if let Some(writer) = writer {
+        writer.finish()?;
+      }
+      prev = next;
+    }
+    Ok(())
+  }
+
+  /// Derives `word` once in memory and sends the result to `out`. The diff tells where every node went, and gives the
+  /// lineage IDs of the result with `Lineage::next`.
+  pub fn step(&mut self, word: &impl InstanceWord<S>, out: &mut impl WordSink<S>) -> io::Result<TreeDiff> {
+    let mut out = Counted {sink: out, count: 0};
+    let mut diff = TreeDiff::new();
+    for node in word.nodes() {
+      let start = out.count;
+      let rewritten = self.rewrite(node.into(), &mut out)?;
+      // Una regla que escribe nodos y dice que no se aplicó deja un nodo conservado con más de una copia
+      diff.push(start..out.count, !rewritten).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    }
+    Ok(diff)
+  }
+
+  /// Sends the successor of a node to `out`, and tells whether a rule rewrote it.
+  fn rewrite(&mut self, node: StreamNode<S>, out: &mut dyn WordSink<S>) -> io::Result<bool> {
+    match node {
+      StreamNode::Leaf(symbol, values) => {
+        if (self.rules)(symbol.clone(), values, out)? {
+          Ok(true)
+        } else {
+          out.leaf(symbol, values).map(|_| false)
+        }
+      },
+      node => out.node(node).map(|_| false),
+    }
+  }
+}
+
+/// Counts the nodes sent to a sink.
+struct Counted<'s, W> {
+  sink: &'s mut W,
+  count: usize,
+}
+
+impl<S, W: WordSink<S>> WordSink<S> for Counted<'_, W> {
+  fn leaf(&mut self, symbol: S, values: &[Value]) -> io::Result<()> {
+    self.count += 1;
+    self.sink.leaf(symbol, values)
+  }
+
+  fn open_branch(&mut self) -> io::Result<()> {
+    self.count += 1;
+    self.sink.open_branch()
+  }
+
+  fn close_branch(&mut self) -> io::Result<()> {
+    self.count += 1;
+    self.sink.close_branch()
+  }
+}
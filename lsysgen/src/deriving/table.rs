@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::vec::Vec;
+
+use lsd::Symbol;
+
+use super::rule::Rule;
+
+/// A table of production rules. The rules of a symbol are tried in the order they were added, and the first one that
+/// applies to a leaf rewrites it.
+#[derive(Debug, Clone)]
+pub struct Table<T> {
+  rules: Vec<Rule<T>>,
+  /// Positions of the rules of every symbol.
+  by_symbol: HashMap<T, Vec<usize>>,
+}
+
+impl<T> Default for Table<T> {
+  fn default() -> Self {
+    Table {rules: Vec::new(), by_symbol: HashMap::new()}
+  }
+}
+
+impl<T: Symbol> Table<T> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn push(&mut self, rule: Rule<T>) {
+    self.by_symbol.entry(rule.symbol.clone()).or_default().push(self.rules.len());
+    self.rules.push(rule);
+  }
+
+  pub fn with_rule(mut self, rule: Rule<T>) -> Self {
+    self.push(rule);
+    self
+  }
+
+  pub fn rules(&self) -> &[Rule<T>] {&self.rules}
+  pub fn len(&self) -> usize {self.rules.len()}
+  pub fn is_empty(&self) -> bool {self.rules.is_empty()}
+
+  /// The rules that rewrite `symbol`, in order.
+  pub fn rules_for(&self, symbol: &T) -> impl Iterator<Item = &Rule<T>> {
+    self.by_symbol.get(symbol).into_iter().flatten().map(|&i| &self.rules[i])
+  }
+}
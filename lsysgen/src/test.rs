@@ -0,0 +1,832 @@
+use std::fmt;
+use std::str::FromStr;
+
+use lsd::{parse_expr, parse_word, Sym, Symbol};
+
+use crate::common::{operators, Scope, Value, Word};
+use crate::common::errors::{Diagnostic, EvalError};
+use crate::common::expr::ExpressionEvaluator;
+use crate::common::builder::LSystemBuilder;
+use crate::common::lsystem::{Arg, LSystem};
+use crate::common::module::ModuleLoader;
+use crate::common::tree::*;
+use crate::common::tree::node::*;
+use crate::deriving::{Rule, StreamDeriver, Table};
+
+/// The algae of Lindenmayer, with an alphabet of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Algae {A, B}
+
+impl fmt::Display for Algae {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {Algae::A => "A", Algae::B => "B"})
+  }
+}
+
+impl FromStr for Algae {
+  type Err = ();
+  fn from_str(s: &str) -> Result<Self, ()> {
+    match s {"A" => Ok(Algae::A), "B" => Ok(Algae::B), _ => Err(())}
+  }
+}
+
+impl Symbol for Algae {}
+
+/// A word without values, with a symbol per character.
+fn word<T: Symbol, Ctx>(s: &str, leaf: fn(T) -> NodeContent<Ctx, T>) -> Tree<Ctx, T> {
+  let mut tree = Tree::new();
+  for c in s.chars() {
+    match c {
+      '[' => tree.open_branch(),
+      ']' => tree.close_branch().unwrap(),
+      c => tree.add_leaf(leaf(T::from_str(&c.to_string()).ok().unwrap())),
+    }
+  }
+  tree
+}
+
+fn text<Ctx, T: fmt::Display>(tree: &Tree<Ctx, T>) -> String {
+  tree.iter().map(|node| match node {
+    Node::BranchStart(_) => "[".to_string(),
+    Node::BranchEnd(_) => "]".to_string(),
+    Node::Leaf(content) => content.character.to_string(),
+  }).collect()
+}
+
+/// Writes an instance word with the values of its leaves.
+fn show(tree: &Tree) -> String {
+  tree.iter().map(|node| match node {
+    Node::BranchStart(_) => "[".to_string(),
+    Node::BranchEnd(_) => "]".to_string(),
+    Node::Leaf(content) if content.context.values.is_empty() => content.character.to_string(),
+    Node::Leaf(content) => {
+      let values: Vec<_> = content.context.values.iter().map(|v| v.to_string()).collect();
+      format!("{}({})", content.character, values.join(", "))
+    },
+  }).collect()
+}
+
+fn instance(s: &str) -> Value {
+  Value::Word(Word::Instance(word(s, NodeContent::new_instance)))
+}
+
+/// A word of `Sym`s without values.
+fn t(s: &str) -> Tree {
+  word(s, NodeContent::new_instance)
+}
+
+/// Writes a tree, checking that both ends of every branch point to each other.
+fn checked(tree: &Tree) -> String {
+  for (i, node) in tree.iter().enumerate() {
+    match node {
+      Node::BranchStart(0) => {},
+      Node::BranchStart(j) => assert!(matches!(tree.node_at(*j), Node::BranchEnd(k) if *k == i), "start {} -> {}", i, j),
+      Node::BranchEnd(j) => assert!(matches!(tree.node_at(*j), Node::BranchStart(k) if *k == i), "end {} -> {}", i, j),
+      Node::Leaf(_) => {},
+    }
+  }
+  text(tree)
+}
+
+#[test]
+fn edit() {
+  let mut a = t("F[+F]F[-F[G]]F");
+  a.splice(2, t("X[Y]")).unwrap();
+  assert_eq!(checked(&a), "F[X[Y]+F]F[-F[G]]F");
+  a.splice(0, t("[A]")).unwrap();
+  assert_eq!(checked(&a), "[A]F[X[Y]+F]F[-F[G]]F");
+  assert_eq!(checked(&a.remove_branch(4).unwrap()), "[X[Y]+F]");
+  assert_eq!(checked(&a), "[A]FF[-F[G]]F");
+  assert_eq!(a.remove_range(4..6).unwrap_err(), TreeError::Unbalanced);
+  assert_eq!(checked(&a.remove_branch(11).unwrap()), "[-F[G]]");
+  assert_eq!(checked(&a), "[A]FFF");
+  a.replace_leaf(3, t("B[C]D")).unwrap();
+  assert_eq!(checked(&a), "[A]B[C]DFF");
+  assert_eq!(a.replace_leaf(0, t("X")).unwrap_err(), TreeError::NotALeaf(0));
+  assert_eq!(a.splice(20, t("X")).unwrap_err(), TreeError::OutOfBounds(20));
+  a.append(t("[Q]")).unwrap();
+  assert_eq!(checked(&a), "[A]B[C]DFF[Q]");
+
+  let mut b = t("A[BC[D]E]F");
+  assert_eq!(checked(&b.cut(3).unwrap()), "C[D]E");
+  assert_eq!(checked(&b), "A[B]F");
+  assert_eq!(checked(&b.cut(1).unwrap()), "[B]F");
+  assert_eq!(checked(&b), "A");
+  assert_eq!(b.remove_leaf(0).unwrap().character, Sym::from('A'));
+  assert!(b.is_empty());
+
+  // Ramas abiertas
+  let mut c = t("A[B");
+  c.append(t("")).unwrap();
+  let mut open = Tree::new();
+  open.open_branch();
+  assert_eq!(c.append(open).unwrap_err(), TreeError::Unbalanced);
+  c.splice(0, t("[X]")).unwrap();
+  c.splice(5, t("[Y]")).unwrap();
+  c.add_leaf(NodeContent::new_instance(Sym::from('C')));
+  c.close_branch().unwrap();
+  assert_eq!(checked(&c), "[X]A[[Y]BC]");
+  assert_eq!(c.close_branch().unwrap_err(), TreeError::NoOpenBranch);
+  let mut d = t("A[B");
+  assert_eq!(d.remove_range(1..3).unwrap_err(), TreeError::Unbalanced);
+  assert_eq!(d.remove_branch(1).unwrap_err(), TreeError::Unbalanced);
+  d.remove_leaf(0).unwrap();
+  d.close_branch().unwrap();
+  assert_eq!(checked(&d), "[B]");
+}
+
+#[test]
+fn text_round_trip() {
+  let src = "F[+F]F(1.5, 2)[-F(-3)[\"Apex\"(\"a\", true, null)]F]F";
+  let tree: Tree = src.parse().unwrap();
+  assert_eq!(tree.to_string(), src);
+  assert_eq!(format!("{:#}", tree), "F\n  [+F]\nF(1.5, 2)\n  [-F(-3)\n    [\"Apex\"(\"a\", true, null)]\n  F]\nF");
+  assert_eq!(checked(&tree), "F[+F]F[-F[Apex]F]F");
+  assert_eq!("/ /(1)@ a".parse::<Tree>().unwrap().to_string(), "/ /(1)@ a");
+
+  // Los números siguen siendo del mismo tipo al volver a leerlos
+  let mut tree = Tree::new();
+  let floats = [1.0, -0.5, 1e300, -0.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN].map(Value::Float).to_vec();
+  tree.add_leaf(NodeContent {character: Sym::from('F'), context: context::Instance {values: floats}});
+  let values = vec![Value::Int(-7), Value::String("say \"hi\"\n\\".to_string())];
+  tree.add_leaf(NodeContent {character: Sym::from('G'), context: context::Instance {values}});
+  let src = tree.to_string();
+  assert_eq!(src, r#"F(1.0, -0.5, 1e300, -0.0, Inf, -Inf, NaN)G(-7, "say \"hi\"\n\\")"#);
+  let back: Tree = src.parse().unwrap();
+  assert_eq!(back.to_string(), src);
+  let types = |i| match back.node_at(i) {
+    Node::Leaf(content) => content.context.values.iter().map(Value::type_name).collect::<Vec<_>>(),
+    _ => unreachable!(),
+  };
+  assert_eq!(types(0), ["float"; 7]);
+  assert_eq!(types(1), ["int", "string"]);
+
+  let errors = "F(x + 1)@w{ }".parse::<Tree>().unwrap_err();
+  assert_eq!(errors.iter().map(|d| d.code.unwrap()).collect::<Vec<_>>(), ["W0001", "W0002", "W0002"]);
+  assert_eq!("F[".parse::<Tree>().unwrap_err()[0].code, Some("P0003"));
+}
+
+#[test]
+fn enum_alphabet() {
+  let rules = Table::new()
+    .with_rule(Rule::new(Algae::A, word("A[B]", NodeContent::new_right)))
+    .with_rule(Rule::new(Algae::B, word("A", NodeContent::new_right)));
+  let mut algae = LSystem::new("algae", word("A", NodeContent::new_instance)).with_rules(rules).with_iterations(4);
+  assert_eq!(text(algae.derive()), "A[B][A][A[B]][A[B][A]]");
+  assert_eq!(algae.iteration(), 4);
+  assert!(!algae.errors().has_errors());
+  // Ya ha llegado a las iteraciones que tiene, pero se puede seguir paso a paso
+  assert!(algae.step());
+  assert_eq!(text(algae.current()).matches(char::is_alphabetic).count(), 13);
+  algae.reset();
+  assert_eq!((text(algae.current()), algae.iteration()), ("A".to_string(), 0));
+
+  let coding = Table::new().with_rule(Rule::new(Algae::B, word("", NodeContent::new_right)));
+  let mut algae = algae.with_coding_rules(coding).with_iterations(2);
+  algae.derive();
+  assert_eq!(text(&algae.encoded().unwrap()), "A[][A]");
+  assert_eq!(algae.to_string(), "LSystem(algae)");
+}
+
+#[test]
+fn word_values() {
+  let text_of = |val: Value| match val {
+    Value::Word(Word::Instance(tree)) => text(&tree),
+    Value::Word(Word::RightSide(tree)) => text(&tree),
+    val => panic!("{} isn't a word", val),
+  };
+  let petals = operators::mul(&instance("[+F]"), &Value::Int(3));
+  assert_eq!(text_of(operators::add(&instance("F"), &petals)), "F[+F][+F][+F]");
+  assert_eq!(text_of(operators::mul(&Value::Int(2), &instance("F[-F]"))), "F[-F]F[-F]");
+  assert_eq!(text_of(operators::mul(&instance("F"), &Value::Int(0))), "");
+  assert!(matches!(operators::mul(&instance("F"), &Value::Int(-1)), Value::Error));
+  assert!(matches!(operators::mul(&instance("F[+F]"), &Value::Int(i64::MAX)), Value::Error));
+  assert!(matches!(operators::rem(&Value::Int(-7), &Value::Int(3)), Value::Int(2)));
+  assert!(matches!(operators::rem(&Value::Int(7), &Value::Int(-3)), Value::Int(1)));
+
+  // Las palabras de reglas aún no tienen valores, así que no se mezclan con las instanciadas
+  let right = Value::Word(Word::RightSide(word("F[+F]", NodeContent::new_right)));
+  assert_eq!(text_of(operators::add(&right, &right)), "F[+F]F[+F]");
+  assert!(matches!(operators::add(&right, &instance("F")), Value::Error));
+
+  let open = Word::Instance(word("F[+F", NodeContent::new_instance));
+  assert!(!open.is_balanced());
+  assert_eq!(open.repeat(2).unwrap_err(), TreeError::Unbalanced);
+  assert!(matches!(operators::mul(&Value::Word(open.clone()), &Value::Int(2)), Value::Error));
+  assert!(matches!(operators::add(&instance("F"), &Value::Word(open.clone())), Value::Error));
+  let mut axiom = word("A", NodeContent::new_instance);
+  assert!(!open.splice_into(&mut axiom));
+  assert!(Word::Instance(word("[B]", NodeContent::new_instance)).splice_into(&mut axiom));
+  assert_eq!(text(&axiom), "A[B]");
+}
+
+#[test]
+fn word_expansions() {
+  let ee = ExpressionEvaluator::new();
+  let mut scope = Scope::new();
+  let petals = ee.eval(&parse_expr("fn(n) -> `[+F]` * n").unwrap(), &scope).unwrap();
+  scope.set("petals".to_string(), petals);
+  let leaf = ee.eval(&parse_expr("`L(1 / 2, x)`").unwrap(), &scope).unwrap_err();
+  assert!(matches!(leaf, EvalError::UndefinedVariable(name, _) if name == "x"));
+  scope.set("x".to_string(), Value::Int(3));
+  let leaf = match ee.eval(&parse_expr("`L(1 / 2, x)`").unwrap(), &scope).unwrap() {
+    Value::Word(Word::Instance(tree)) => tree,
+    val => panic!("{} isn't an instance word", val),
+  };
+  assert_eq!(show(&leaf), "L(0.5, 3)");
+  scope.set("leaf".to_string(), Value::Word(Word::Instance(leaf)));
+
+  let axiom = ee.eval_word(&parse_word("F@petals(3)@leaf").unwrap(), &scope).unwrap();
+  assert_eq!(show(&axiom), "F[+F][+F][+F]L(0.5, 3)");
+  // En una regla, las expansiones se evalúan al construirla y los argumentos al derivar
+  let right = ee.right_side(&parse_word("F(x + 1)@petals(2)@leaf").unwrap(), &scope).unwrap();
+  let rules = Table::new().with_rule(Rule::new(Sym::from('X'), right).with_params(["x"]));
+  let mut flower = LSystem::new("flower", ee.eval_word(&parse_word("X(1)").unwrap(), &scope).unwrap())
+    .with_rules(rules)
+    .with_iterations(1);
+  assert_eq!(show(flower.derive()), "F(2)[+F][+F]L(0.5, 3)");
+
+  let err = |src| ee.eval_word(&parse_word(src).unwrap(), &scope).unwrap_err();
+  assert!(matches!(err("F@nothing"), EvalError::UndefinedVariable(name, _) if name == "nothing"));
+  assert!(matches!(err("F@petals"), EvalError::TypeMismatch("@", types, _) if types == ["function"]));
+  assert!(matches!(err("F@leaf(1)"), EvalError::NotCallable(_)));
+  assert!(matches!(err("F@petals(-1)"), EvalError::TypeMismatch("*", types, _) if types == ["word", "int"]));
+
+  scope.set("open".to_string(), Value::Word(Word::Instance(word("[F", NodeContent::new_instance))));
+  let err = ee.eval(&parse_expr("open * 2").unwrap(), &scope).unwrap_err();
+  assert!(matches!(err, EvalError::UnbalancedWord(_)));
+}
+
+#[test]
+fn derivation_errors() {
+  let ee = ExpressionEvaluator::new();
+  let mut scope = Scope::new();
+  scope.set("one".to_string(), Value::Int(1));
+  let id = ee.eval(&parse_expr("fn(a) -> a").unwrap(), &scope).unwrap();
+  scope.set("id".to_string(), id);
+  assert_eq!(scope.get("id").unwrap().to_string(), "(a) -> a");
+  let code = |src: &str| Diagnostic::from(ee.eval(&parse_expr(src).unwrap(), &scope).unwrap_err()).code.unwrap();
+  assert_eq!(code("y"), "E0001");
+  assert_eq!(code("1 + true"), "E0002");
+  assert_eq!(code("id(1, 2)"), "E0003");
+  assert_eq!(code("1 / 0"), "E0004");
+  assert_eq!(code("one(2)"), "E0005");
+  assert_eq!(code("9223372036854775807 + one"), "E0006");
+  assert_eq!(code("[1, 2]"), "E0007");
+  assert_eq!(code("`F` * 100000000000"), "E0009");
+
+  let rule = |sym: char, params: &[&str], right: &str| {
+    let right = ee.right_side(&parse_word(right).unwrap(), &scope).unwrap();
+    Rule::new(Sym::from(sym), right).with_params(params.iter().copied())
+  };
+  let rules = Table::new()
+    .with_rule(rule('F', &["x"], "F(y)"))
+    .with_rule(rule('G', &["a", "b"], "G"))
+    .with_rule(rule('H', &["x"], "H").with_condition(parse_expr("x").unwrap().into_owned()));
+  let axiom = ee.eval_word(&parse_word("F(1)G(1)[H(1)F(2)]").unwrap(), &scope).unwrap();
+  let mut plant = LSystem::new("plant", axiom).with_rules(rules).with_iterations(3);
+  // Se informa de todos los errores del paso, pero de cada uno una sola vez
+  assert_eq!(show(plant.derive()), "F(1)G(1)[H(1)F(2)]");
+  assert_eq!(plant.iteration(), 0);
+  let codes: Vec<_> = plant.errors().diagnostics().iter().map(|d| d.code.unwrap()).collect();
+  assert_eq!(codes, ["E0001", "D0002", "E0002"]);
+
+  let choose = match ee.eval(&parse_expr("fn(i) -> if i < 1 then \"main\" else \"other\"").unwrap(), &scope) {
+    Ok(Value::Function(f)) => f,
+    val => panic!("{:?}", val),
+  };
+  let rules = Table::new().with_rule(rule('F', &[], "FF"));
+  let mut plant = LSystem::new("plant", ee.eval_word(&parse_word("F").unwrap(), &scope).unwrap())
+    .with_table("main", rules)
+    .with_table_func(choose)
+    .with_iterations(2);
+  assert_eq!(show(plant.derive()), "FF");
+  assert_eq!(plant.errors().diagnostics()[0].to_string(), "error[D0001]: unknown rules table `other`");
+
+  let coding = Table::new().with_rule(rule('F', &[], "G"));
+  let plant = plant.with_coding_rules(coding);
+  assert_eq!(show(&plant.encoded().unwrap()), "GG");
+  assert_eq!(plant.to_string(), "LSystem(plant)");
+}
+
+#[test]
+fn build() {
+  let src = "let growth = 2
+fn pick(i) {return if i % 2 == 0 then \"grow\" else \"mark\"}
+
+lsys plant(n = growth + 1) {
+  set iterations = n
+  set angle = 30
+  set tables = pick
+  axiom A
+  table grow {
+    A -> AB
+  }
+  table mark {
+    A -> AC
+  }
+  rules {
+    C => D
+  }
+}
+
+lsys bad {
+  set iterations = -1
+  set speed = 2
+  x = 1
+}
+";
+  let mut loader = ModuleLoader::new();
+  let id = loader.load_source("plant.lsd", src.to_string());
+  assert!(!loader.errors().has_errors(), "{}", loader.errors().render(loader.source_map(), false));
+  let decl = loader.choose_lsystem(id, Some("plant")).unwrap();
+
+  let mut builder = LSystemBuilder::new(&loader);
+  let mut plant = builder.build(decl, decl.bind(vec![]).unwrap()).unwrap();
+  assert_eq!(plant.iterations(), 3);
+  assert_eq!(plant.settings().angle, 30.0);
+  // Las tablas se alternan: grow, mark, grow
+  assert_eq!(show(plant.derive()), "ABCB");
+  assert!(!plant.errors().has_errors());
+  assert_eq!(show(&plant.encoded().unwrap()), "ABDB");
+  assert!(plant.lineage().is_none() && plant.last_diff().is_none());
+
+  // Con cada tabla, los nodos que no se reescriben conservan su ID, y los nuevos recuerdan de cuál vienen
+  let mut plant = plant.with_lineage();
+  plant.reset();
+  assert!(plant.step() && plant.step());
+  assert_eq!(show(plant.current()), "ACB");
+  assert_eq!(plant.last_diff().unwrap().iter().collect::<Vec<_>>(), [(0, 0..2), (1, 2..3)]);
+  assert!(plant.step());
+  let diff = plant.last_diff().unwrap();
+  assert_eq!(diff.summary(), DiffSummary {kept: 2, deleted: 1, inserted: 2});
+  let lineage = plant.lineage().unwrap();
+  assert_eq!((0..4).map(|i| lineage.id(i)).collect::<Vec<_>>(), [5, 6, 4, 2]);
+  assert_eq!((0..4).map(|i| lineage.origin(i)).collect::<Vec<_>>(), [3, 3, 4, 2].map(Some));
+  plant.reset();
+  assert_eq!((plant.lineage().unwrap().len(), plant.last_diff().is_none()), (1, true));
+
+  let args = decl.bind(vec![Arg {name: None, value: Value::Int(1)}]).unwrap();
+  assert_eq!(show(builder.build(decl, args).unwrap().derive()), "AB");
+  assert!(builder.errors().diagnostics().is_empty());
+
+  // Los ajustes desconocidos solo son avisos
+  let decl = loader.choose_lsystem(id, Some("bad")).unwrap();
+  assert!(builder.build(decl, decl.bind(vec![]).unwrap()).is_none());
+  let codes: Vec<_> = builder.errors().diagnostics().iter().map(|d| (d.code.unwrap(), d.is_error())).collect();
+  assert_eq!(codes, [("B0003", true), ("B0004", false), ("E0001", true), ("B0002", true)]);
+}
+
+#[test]
+fn build_imports() {
+  let dir = std::env::temp_dir().join(format!("lsysgen-import-test-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("leaves.lsd"), "let leaf = `L[+F]`\nlet angle = 22.5\n").unwrap();
+  std::fs::write(dir.join("shapes.lsd"), "fn stem(n) {return `F` * n}\n").unwrap();
+  let src = "import leaves as lv
+from shapes import stem
+
+lsys plant {
+  set iterations = 2
+  set angle = lv.angle
+  axiom A
+  rules {
+    A -> B@lv.leaf
+    B -> @stem(2)
+  }
+}
+";
+  let mut loader = ModuleLoader::new();
+  let id = loader.load_source(dir.join("plant.lsd"), src.to_string());
+  std::fs::remove_dir_all(&dir).unwrap();
+  assert!(!loader.errors().has_errors(), "{}", loader.errors().render(loader.source_map(), false));
+
+  let decl = loader.choose_lsystem(id, None).unwrap();
+  let mut builder = LSystemBuilder::new(&loader);
+  let mut plant = builder.build(decl, vec![]).unwrap();
+  assert_eq!(plant.settings().angle, 22.5);
+  assert_eq!(show(plant.derive()), "FFL[+F]");
+  assert!(!plant.errors().has_errors());
+}
+
+#[test]
+fn compact() {
+  fn nodes(word: &impl InstanceWord) -> Vec<String> {
+    word.nodes().map(|node| format!("{:?}", node)).collect()
+  }
+  let trees = ["F(1)[+(2, 3.5)[X]A]F[B]", "", "[[A]]B(\"b\", null)"].map(|src| src.parse::<Tree>().unwrap());
+  // Una rama abierta también se copia
+  for tree in trees.iter().chain([&t("F[A[B")]) {
+    let compact = CompactTree::from(tree);
+    assert_eq!(compact.len(), tree.len());
+    assert_eq!(nodes(&compact), nodes(tree));
+    assert_eq!(checked(&Tree::from(&compact)), checked(tree));
+  }
+
+  let mut compact = CompactTree::new();
+  compact.push_leaf(Sym::from('A'), [Value::Int(1)]);
+  InstanceWord::open_branch(&mut compact);
+  assert!(matches!(compact.node(1), NodeRef::BranchStart(0)));
+  compact.push_leaf(Sym::from('B'), []);
+  InstanceWord::close_branch(&mut compact).unwrap();
+  assert!(matches!(compact.node(1), NodeRef::BranchStart(3)));
+  assert!(matches!(compact.node(3), NodeRef::BranchEnd(1)));
+  assert_eq!(InstanceWord::close_branch(&mut compact).unwrap_err(), TreeError::NoOpenBranch);
+  assert_eq!(Tree::from(&compact).to_string(), "A(1)[B]");
+}
+
+#[test]
+fn stats() {
+  // 0F 1[ 2A 3] 4[ 5B 6[ 7C 8] 9[ 10D 11] 12E 13] 14F 15[ 16G 17]
+  let tree = t("F[A][B[C][D]E]F[G]");
+  assert_eq!(tree.leaf_count(), 8);
+  assert_eq!(tree.branch_count(), 5);
+  assert_eq!(tree.max_depth(), 2);
+  assert_eq!(tree.longest_path(), 3);
+  assert_eq!(tree.parent_branch(0), None);
+  assert_eq!(tree.parent_branch(2), Some(1));
+  assert_eq!(tree.parent_branch(9), Some(4));
+  assert_eq!(tree.parent_branch(10), Some(9));
+  assert_eq!(tree.parent_branch(11), Some(4));
+  assert_eq!(tree.parent_branch(12), Some(4));
+  assert_eq!(tree.parent_branch(14), None);
+  assert_eq!(tree.children(0), [1, 4]);
+  assert_eq!(tree.children(5), [6, 9]);
+  assert_eq!(tree.children(14), [15]);
+  assert!(tree.children(2).is_empty());
+  // A, C, D y G son de orden 1; B tiene dos hijos de orden 1, así que es de orden 2, y el eje principal también
+  assert_eq!(tree.strahler_orders(), [4, 4]);
+
+  let stats = tree.stats();
+  assert_eq!((stats.nodes, stats.leaves, stats.branches), (18, 8, 5));
+  assert_eq!(stats.symbols[&Sym::from('F')], 2);
+  assert_eq!(stats.symbols.values().sum::<usize>(), 8);
+  assert!(stats.to_string().starts_with("nodes:         18\nleaves:        8\n"));
+
+  let empty = t("").stats();
+  assert_eq!((empty.nodes, empty.leaves, empty.branches, empty.max_depth, empty.longest_path), (0, 0, 0, 0, 0));
+  assert!(empty.symbols.is_empty());
+}
+
+#[test]
+fn pattern() {
+  // 0A 1[ 2B 3] 4C 5A 6[ 7B 8D 9] 10C 11A 12[ 13X 14] 15[ 16B 17] 18C 19A 20[ 21B 22] 23C 24A 25C 26F 27[ 28A 29[ 30B
+  // 31] 32C 33]
+  let tree: Tree = "A[B]C A[BD]C A[X][B]C A(1)[B(2)]C(3) AC F[A[B]C]".parse().unwrap();
+  let p = |s: &str| s.parse::<Pattern>().unwrap();
+  // Las ramas que no están en el patrón se saltan antes de un símbolo...
+  assert_eq!(tree.find(&p("AC")), [0, 5, 11, 19, 24, 28]);
+  // pero no las que están antes de una rama del patrón
+  assert_eq!(tree.find(&p("A[B]C")), [0, 5, 19, 28]);
+  assert_eq!(tree.find(&p("CF")), [25]);
+  assert!(tree.find(&p("XB")).is_empty());
+  // Un `]` del patrón se salta lo que queda de la rama
+  assert_eq!(tree.find(&p("[B]")), [1, 6, 15, 20, 29]);
+  assert_eq!(tree.find(&p("[B]C")), [1, 6, 15, 20, 29]);
+  assert!(tree.find(&p("[B]D")).is_empty());
+  // Valores y nombres
+  assert_eq!(tree.find(&p("A(1)[B(2.0)]")), [19]);
+  assert!(tree.find(&p("A(2)")).is_empty());
+  assert_eq!(tree.find(&p("A(x)[B(y)]C(z)")), [19]);
+  let pattern = p("A(x)[B]C(z)");
+  let found = tree.matches(&pattern);
+  assert_eq!(found.len(), 1);
+  assert_eq!(found[0].get("x").map(Value::to_string), Some("1".to_string()));
+  assert_eq!(found[0].get("z").map(Value::to_string), Some("3".to_string()));
+  assert!(found[0].get("y").is_none());
+  assert!(tree.match_at(0, &pattern).is_none());
+
+  let tree: Tree = "A+B-C".parse().unwrap();
+  assert!(tree.find(&p("ABC")).is_empty());
+  assert_eq!(tree.find(&p("ABC").ignoring([Sym::from('+'), Sym::from('-')])), [0]);
+  assert_eq!("A(x + 1)".parse::<Pattern>().unwrap_err()[0].code, Some("W0003"));
+}
+
+#[test]
+fn file() {
+  let tree: Tree = r#"F(1, -2.5)[+(true)"Apex"("a", null)]["Bud"]"Apex"(3)A"#.parse().unwrap();
+  let meta = TreeMeta {seed: 42, iteration: 7, source_hash: source_hash("axiom F;")};
+  let mut bytes = Vec::new();
+  write_tree(&mut bytes, &tree, &meta).unwrap();
+  let file = TreeFile::from_bytes(bytes.clone()).unwrap();
+  assert_eq!((file.meta(), file.len()), (&meta, tree.len()));
+  assert_eq!(checked(&file.to_word::<Tree>().unwrap()), checked(&tree));
+  assert_eq!(Tree::from(&file.to_word::<CompactTree>().unwrap()).to_string(), tree.to_string());
+  for i in 0..file.len() {
+    match (file.node(i), tree.node(i)) {
+      (FileNode::BranchStart(a), NodeRef::BranchStart(b)) | (FileNode::BranchEnd(a), NodeRef::BranchEnd(b)) => {
+        assert_eq!(a, b)
+      },
+      (FileNode::Leaf(a, va), NodeRef::Leaf(b, vb)) => {
+        assert_eq!(a, b);
+        assert_eq!(va.map(|v| v.to_string()).collect::<Vec<_>>(), vb.iter().map(Value::to_string).collect::<Vec<_>>());
+      },
+      _ => panic!("node {}", i),
+    }
+  }
+
+  let path = std::env::temp_dir().join(format!("lsysgen-test-{}.tree", std::process::id()));
+  save_tree(&path, &t("A[B[C"), &meta).unwrap();
+  let open = TreeFile::open(&path).unwrap().to_word::<Tree>().unwrap();
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!((checked(&open), open.is_balanced()), ("A[B[C".to_string(), false));
+  let mut empty = Vec::new();
+  write_tree(&mut empty, &t(""), &TreeMeta::default()).unwrap();
+  assert!(TreeFile::from_bytes(empty).unwrap().is_empty());
+
+  assert!(matches!(TreeFile::from_bytes(b"F[+F]F".to_vec()), Err(FileError::NotATreeFile)));
+  assert!(matches!(TreeFile::from_bytes(&bytes[..bytes.len() - 1]), Err(FileError::Corrupt)));
+  let mut other = bytes.clone();
+  other[8] = 2;
+  assert!(matches!(TreeFile::from_bytes(other), Err(FileError::Version(2))));
+
+  // `A[B]C` tiene los símbolos desde el byte 80, sus valores desde el 100 y sus ramas, `(1, 3)` y `(3, 1)`, desde el 124
+  let mut bytes = Vec::new();
+  write_tree(&mut bytes, &t("A[B]C"), &meta).unwrap();
+  assert!(TreeFile::from_bytes(bytes.clone()).is_ok());
+  let corrupt = |bytes: &[u8], at: usize, data: u32| {
+    let mut bad = bytes.to_vec();
+    bad[at..at + 4].copy_from_slice(&data.to_le_bytes());
+    matches!(TreeFile::from_bytes(bad), Err(FileError::Corrupt))
+  };
+  assert!(corrupt(&bytes, 80, 0x11_0000), "unknown name");
+  assert!(corrupt(&bytes, 80, ']' as u32), "leaf with a branch code");
+  assert!(corrupt(&bytes, 84, 'X' as u32), "branch with a leaf code");
+  assert!(corrupt(&bytes, 128, 4), "start paired with a leaf");
+  assert!(corrupt(&bytes, 140 - 4, 0), "end paired with a leaf");
+  assert!(corrupt(&bytes, 128, u32::MAX), "open branch that ends");
+  assert!(corrupt(&bytes, 132, 2), "branches out of order");
+  // Los valores de `A(1)[B]` empiezan en los bytes 96, 100... y los de `[` pasan a ser el de `A`
+  let mut bytes = Vec::new();
+  write_tree(&mut bytes, &"A(1)[B]".parse::<Tree>().unwrap(), &meta).unwrap();
+  assert!(TreeFile::from_bytes(bytes.clone()).is_ok());
+  assert!(corrupt(&bytes, 100, 0), "values in a branch");
+}
+
+#[test]
+fn stream() {
+  let tree: Tree = r#"F(1, -2.5)[+(true)"Apex"("a", null)]["Bud"]"Apex"(3)A"#.parse().unwrap();
+  let mut writer = WordWriter::new(Vec::new());
+  writer.word(&tree).unwrap();
+  let bytes = writer.finish().unwrap();
+  let mut back = Tree::new();
+  WordReader::new(&bytes[..]).copy_to(&mut back).unwrap();
+  assert_eq!(checked(&back), checked(&tree));
+  assert_eq!(back.to_string(), tree.to_string());
+  assert!(WordReader::new(&bytes[..bytes.len() - 3]).copy_to(&mut t("")).is_err());
+
+  // F → F[+F]F y X(n) → F(n + 1). Las reglas miran cuántas palabras hay en disco mientras se deriva.
+  let dir = std::env::temp_dir().join(format!("lsysgen-stream-test-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let spills = std::cell::Cell::new(0);
+  let f = Sym::from('F');
+  let mut deriver = StreamDeriver::new(|symbol: Sym, values: &[Value], out: &mut dyn WordSink| {
+    spills.set(spills.get().max(std::fs::read_dir(&dir)?.count()));
+    if symbol == 'F' {
+      out.leaf(f, values)?;
+      out.open_branch()?;
+      out.leaf(Sym::from('+'), &[])?;
+      out.leaf(f, values)?;
+      out.close_branch()?;
+      out.leaf(f, values)?;
+      Ok(true)
+    } else if symbol == 'X' {
+      let n = match values {[Value::Int(n)] => *n, _ => 0};
+      out.leaf(f, &[Value::Int(n + 1)])?;
+      Ok(true)
+    } else {
+      Ok(false)
+    }
+  }).spill_dir(&dir);
+  let axiom = t("X");
+  let mut res = Tree::new();
+  deriver.derive(&axiom, 0, &mut res).unwrap();
+  assert_eq!(res.to_string(), "X");
+  assert_eq!(spills.get(), 0);
+
+  let mut res = CompactTree::new();
+  deriver.derive(&"X(1)".parse::<Tree>().unwrap(), 3, &mut res).unwrap();
+  assert_eq!(Tree::from(&res).to_string(), "F(2)[+F(2)]F(2)[+F(2)[+F(2)]F(2)]F(2)[+F(2)]F(2)");
+  // Como mucho están la palabra que se lee y la que se escribe
+  assert_eq!(spills.get(), 2);
+
+  // Derivar por disco da lo mismo que derivar en memoria
+  let mut in_memory = axiom.clone();
+  for _ in 0..5 {
+    let mut next = Tree::new();
+    deriver.step(&in_memory, &mut next).unwrap();
+    in_memory = next;
+  }
+  let mut spilled = Tree::new();
+  deriver.derive(&axiom, 5, &mut spilled).unwrap();
+  assert_eq!(checked(&spilled), checked(&in_memory));
+  assert_eq!(spilled.to_string(), in_memory.to_string());
+
+  /// Counts the leaves without keeping them.
+  struct Count(usize);
+  impl WordSink for Count {
+    fn leaf(&mut self, _: Sym, _: &[Value]) -> std::io::Result<()> {
+      self.0 += 1;
+      Ok(())
+    }
+    fn open_branch(&mut self) -> std::io::Result<()> {Ok(())}
+    fn close_branch(&mut self) -> std::io::Result<()> {Ok(())}
+  }
+  let mut count = Count(0);
+  deriver.derive(&axiom, 10, &mut count).unwrap();
+  // Tras el primer paso hay una F, y cada paso cambia cada F por tres y un +
+  assert_eq!(count.0, 3usize.pow(9) + (3usize.pow(9) - 1) / 2);
+
+  // Un L system escrito en LSD se deriva por disco con las reglas de su tabla, igual que en memoria
+  let src = "fn pick(i) {return if i < 1 then \"a\" else \"b\"}
+lsys plant {
+  set iterations = 4
+  axiom X(1)
+  rules {
+    X(n) -> F(n + 1)
+    F(x) : x < 4 -> F(x + 1)[+F(x)]
+  }
+}
+
+lsys mixed {
+  set iterations = 2
+  set tables = pick
+  axiom A
+  table a {
+    A -> B
+  }
+  table b {
+    B -> A
+  }
+}
+
+lsys broken {
+  set iterations = 1
+  axiom F
+  rules {
+    F -> F(y)
+  }
+}
+";
+  let mut loader = ModuleLoader::new();
+  let id = loader.load_source("stream.lsd", src.to_string());
+  let mut builder = LSystemBuilder::new(&loader);
+  let mut build = |name| {
+    let decl = loader.choose_lsystem(id, Some(name)).unwrap();
+    builder.build(decl, decl.bind(vec![]).unwrap()).unwrap().with_spill_dir(&dir)
+  };
+  let mut plant = build("plant");
+  let mut streamed = Tree::new();
+  assert!(plant.derive_to(&mut streamed).unwrap());
+  assert_eq!((plant.iteration(), show(plant.current())), (0, "X(1)".to_string()));
+  assert_eq!(show(&streamed), show(plant.derive()));
+  assert_eq!(show(&streamed), "F(4)[+F(4)[+F(3)]][+F(4)[+F(3)][+F(3)[+F(2)]]]");
+
+  // Sin tablas que cambien ni errores al reescribir
+  let mut mixed = build("mixed");
+  assert!(!mixed.derive_to(&mut Tree::new()).unwrap());
+  let mut broken = build("broken");
+  assert!(!broken.derive_to(&mut Tree::new()).unwrap());
+  let codes = [&mixed, &broken].map(|l| l.errors().diagnostics()[0].code.unwrap());
+  assert_eq!(codes, ["D0004", "E0001"]);
+  assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+  std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn diff() {
+  // A → AB, B → A, C → ε
+  let mut deriver = StreamDeriver::new(|symbol: Sym, _: &[Value], out: &mut dyn WordSink| {
+    match symbol.as_char() {
+      Some('A') => {
+        out.leaf(Sym::from('A'), &[])?;
+        out.leaf(Sym::from('B'), &[])?;
+      },
+      Some('B') => out.leaf(Sym::from('A'), &[])?,
+      Some('C') => {},
+      _ => return Ok(false),
+    }
+    Ok(true)
+  });
+  let axiom = t("A[BC]F");
+  let mut next = Tree::new();
+  let diff = deriver.step(&axiom, &mut next).unwrap();
+  assert_eq!(next.to_string(), "AB[A]F");
+  assert_eq!(diff.iter().collect::<Vec<_>>(), [(0, 0..2), (1, 2..3), (2, 3..4), (3, 4..4), (4, 4..5), (5, 5..6)]);
+  assert_eq!([0, 1, 3, 5, 6].map(|j| diff.source(j)), [Some(0), Some(0), Some(2), Some(5), None]);
+  assert!(diff.is_kept(1) && !diff.is_kept(2) && !diff.is_kept(3) && diff.is_kept(5));
+  assert_eq!(diff.summary(), DiffSummary {kept: 3, deleted: 3, inserted: 3});
+  assert_eq!(diff.summary().to_string(), "3 kept, 3 deleted, 3 inserted");
+
+  // Los nodos conservados mantienen su ID en todas las iteraciones, y los nuevos recuerdan de cuál vienen
+  let lineage = Lineage::new(axiom.len());
+  let second = lineage.next(&diff);
+  assert_eq!((0..6).map(|i| second.id(i)).collect::<Vec<_>>(), [6, 7, 1, 8, 4, 5]);
+  assert_eq!((0..6).map(|i| second.origin(i)).collect::<Vec<_>>(), [0, 0, 1, 2, 4, 5].map(Some));
+  assert_eq!(second.position(5), Some(5));
+  let mut third_word = Tree::new();
+  let diff = deriver.step(&next, &mut third_word).unwrap();
+  assert_eq!(third_word.to_string(), "ABA[AB]F");
+  let third = second.next(&diff);
+  assert_eq!((third.len(), third.id(3), third.id(7)), (8, 1, 5));
+  assert_eq!((third.origin(0), third.origin(2), third.origin(4)), (Some(6), Some(7), Some(8)));
+  assert_eq!(third.position(8), None);
+
+  // Una regla que escribe nodos y dice que no se aplicó
+  let mut wrong = StreamDeriver::new(|symbol: Sym, values: &[Value], out: &mut dyn WordSink| {
+    out.leaf(symbol, values)?;
+    Ok(false)
+  });
+  let err = wrong.step(&axiom, &mut Tree::new()).unwrap_err();
+  assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+  let mut diff = TreeDiff::new();
+  assert_eq!(diff.push(1..2, false), Err(DiffError::Gap(1..2)));
+  assert_eq!(diff.push(0..2, true), Err(DiffError::NotACopy(0..2)));
+  diff.push(0..0, false).unwrap();
+  assert_eq!(diff.source_len(), 1);
+
+  let between = |a: &str, b: &str| TreeDiff::between(&t(a), &t(b)).iter().collect::<Vec<_>>();
+  assert_eq!(between("ABC", "AXC"), [(0, 0..1), (1, 1..2), (2, 2..3)]);
+  assert_eq!(between("AB", "ABXY"), [(0, 0..1), (1, 1..4)]);
+  assert_eq!(between("A", "XA"), [(0, 0..2)]);
+  assert_eq!(between("ABC", ""), [(0, 0..0), (1, 0..0), (2, 0..0)]);
+  assert!(between("", "AB").is_empty());
+  let diff = TreeDiff::between(&t("A[B]C"), &t("A[XB]C"));
+  assert_eq!(diff.summary(), DiffSummary {kept: 4, deleted: 1, inserted: 2});
+  assert!(!diff.is_kept(1) && diff.is_kept(2));
+  let same = TreeDiff::between(&third_word, &third_word);
+  assert_eq!(same.summary(), DiffSummary {kept: 8, deleted: 0, inserted: 0});
+  // Los valores también cuentan, y lo que se añade entre dos nodos conservados viene del primero
+  let diff = TreeDiff::between(&"F(1)F(2)".parse::<Tree>().unwrap(), &"F(1)F(2.0)F(2)".parse::<Tree>().unwrap());
+  assert_eq!(diff.iter().collect::<Vec<_>>(), [(0, 0..2), (1, 2..3)]);
+  assert_eq!(diff.summary(), DiffSummary {kept: 1, deleted: 1, inserted: 2});
+
+  // Entre dos árboles cualesquiera, el diff cubre el destino y conserva nodos iguales
+  for (a, b, kept) in [("F[+F]F[-F]F", "F[+F[-F]]FF", 7), ("[A][B]", "[B][A]", 4), ("ABCABBA", "CBABAC", 2)] {
+    let diff = TreeDiff::between(&t(a), &t(b));
+    assert_eq!((diff.source_len(), diff.target_len(), diff.summary().kept), (a.len(), b.len(), kept));
+    for (i, target) in diff.iter().filter(|(i, _)| diff.is_kept(*i)) {
+      assert_eq!(a[i..i + 1], b[target]);
+    }
+  }
+}
+
+#[test]
+fn graph() {
+  // La rama que empieza antes de cualquier módulo no tiene aristas que lleguen a ella
+  let tree: Tree = r#"[X]F[+(2.5)"A<"("a\"b")]F(1)"#.parse().unwrap();
+  let nodes = Graph::new(&tree).nodes.iter().map(|n| (n.position, n.symbol.to_string())).collect::<Vec<_>>();
+  assert_eq!(nodes, [(1, "X"), (3, "F"), (5, "+"), (6, "A<"), (8, "F")].map(|(i, s)| (i, s.to_string())));
+  let edges = Graph::new(&tree).edges.iter().map(|e| (e.from, e.to, e.kind)).collect::<Vec<_>>();
+  assert_eq!(edges, [(1, 2, EdgeKind::Lateral), (2, 3, EdgeKind::Successor), (1, 4, EdgeKind::Successor)]);
+
+  // 0F 1[ 2+ 3A< 4] 5F
+  let tree: Tree = r#"F[+(2.5)"A<"("a\"b")]F(1)"#.parse().unwrap();
+  let graph = Graph::new(&tree);
+  let write = |format| {
+    let mut out = Vec::new();
+    graph.write(format, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+  };
+  assert_eq!(write(GraphFormat::Dot), r#"digraph tree {
+  n0 [label="F"];
+  n1 [label="+(2.5)"];
+  n2 [label="A<(\"a\\\"b\")"];
+  n3 [label="F(1)"];
+  n0 -> n1 [style=dashed];
+  n1 -> n2;
+  n0 -> n3;
+}
+"#);
+  assert_eq!(write(GraphFormat::GraphML), r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="symbol" for="node" attr.name="symbol" attr.type="string"/>
+  <key id="values" for="node" attr.name="values" attr.type="string"/>
+  <key id="position" for="node" attr.name="position" attr.type="long"/>
+  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>
+  <graph id="tree" edgedefault="directed">
+    <node id="n0"><data key="symbol">F</data><data key="position">0</data></node>
+    <node id="n1"><data key="symbol">+</data><data key="values">2.5</data><data key="position">2</data></node>
+    <node id="n2"><data key="symbol">A&lt;</data><data key="values">&quot;a\&quot;b&quot;</data><data key="position">3</data></node>
+    <node id="n3"><data key="symbol">F</data><data key="values">1</data><data key="position">5</data></node>
+    <edge source="n0" target="n1"><data key="kind">lateral</data></edge>
+    <edge source="n1" target="n2"><data key="kind">successor</data></edge>
+    <edge source="n0" target="n3"><data key="kind">successor</data></edge>
+  </graph>
+</graphml>
+"#);
+  assert_eq!(write(GraphFormat::Json), r#"{"directed": true, "nodes": [
+  {"id": 0, "position": 0, "symbol": "F", "values": []},
+  {"id": 1, "position": 2, "symbol": "+", "values": [2.5]},
+  {"id": 2, "position": 3, "symbol": "A<", "values": ["a\"b"]},
+  {"id": 3, "position": 5, "symbol": "F", "values": [1]}
+], "links": [
+  {"source": 0, "target": 1, "kind": "lateral"},
+  {"source": 1, "target": 2, "kind": "successor"},
+  {"source": 0, "target": 3, "kind": "successor"}
+]}
+"#);
+  assert_eq!(GraphFormat::from_extension("gv"), Some(GraphFormat::Dot));
+  assert_eq!(GraphFormat::from_name("graphml"), Some(GraphFormat::GraphML));
+  assert_eq!(GraphFormat::from_name("svg"), None);
+}